@@ -12,7 +12,9 @@
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::range_plus_one))]
 
 use anyhow::{bail, Context as _, Error};
+use config::{MuxCompression, MuxCompressionAlgorithm, PortForward};
 use leb128;
+use mux::client::{AuthPermission, ClientId, ClientInfo, CompressionStats};
 use mux::domain::DomainId;
 use mux::pane::PaneId;
 use mux::renderable::{RenderableDimensions, StableCursorPosition};
@@ -23,14 +25,17 @@ use rangeset::*;
 use serde::{Deserialize, Serialize};
 use smol::io::AsyncWriteExt;
 use smol::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-use std::io::Cursor;
+use std::io::{Cursor, Write as _};
 use std::ops::Range;
 use std::sync::Arc;
+use termwiz::cell::Cell;
 use termwiz::hyperlink::Hyperlink;
+use termwiz::image::{ImageCell, ImageData, TextureCoordinate};
 use termwiz::surface::Line;
 use varbincode;
-use wezterm_term::{ClipboardSelection, StableRowIndex};
+use wezterm_term::{Alert, ClipboardSelection, StableRowIndex};
 
 /// Returns the encoded length of the leb128 representation of value
 fn encoded_length(value: u64) -> usize {
@@ -99,20 +104,6 @@ fn encode_raw<W: std::io::Write>(
     Ok(buffer.len())
 }
 
-async fn encode_raw_async<W: Unpin + AsyncWriteExt>(
-    ident: u64,
-    serial: u64,
-    data: &[u8],
-    is_compressed: bool,
-    w: &mut W,
-) -> anyhow::Result<usize> {
-    let buffer = encode_raw_as_vec(ident, serial, data, is_compressed)?;
-    w.write_all(&buffer)
-        .await
-        .context("writing pdu data buffer")?;
-    Ok(buffer.len())
-}
-
 /// Read a single leb128 encoded value from the stream
 async fn read_u64_async<R>(r: &mut R) -> anyhow::Result<u64>
 where
@@ -261,49 +252,249 @@ pub struct DecodedPdu {
     pub pdu: Pdu,
 }
 
-/// If the serialized size is larger than this, then we'll consider compressing it
-const COMPRESS_THRESH: usize = 32;
+/// Once we've sampled this much line data, we'll train a dictionary
+const DICTIONARY_SAMPLE_BYTES: usize = 256 * 1024;
+/// ... but we need at least this many samples for training to be useful
+const DICTIONARY_MIN_SAMPLES: usize = 32;
+const DICTIONARY_MAX_SIZE: usize = 16 * 1024;
+/// The most that a single compressed PDU may expand to.  This guards
+/// against a peer sending a small frame that decompresses to an
+/// enormous amount of data.
+const MAX_DECOMPRESSED_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Read the whole of `decoder`, failing if it produces more
+/// than `limit` bytes
+fn read_bounded<R: std::io::Read>(decoder: R, limit: u64) -> Result<Vec<u8>, Error> {
+    let mut decompressed = Vec::new();
+    std::io::Read::read_to_end(
+        &mut std::io::Read::take(decoder, limit + 1),
+        &mut decompressed,
+    )?;
+    if decompressed.len() as u64 > limit {
+        bail!("compressed PDU expands to more than {} bytes", limit);
+    }
+    Ok(decompressed)
+}
 
-fn serialize<T: serde::Serialize>(t: &T) -> Result<(Vec<u8>, bool), Error> {
-    let mut uncompressed = Vec::new();
-    let mut encode = varbincode::Serializer::new(&mut uncompressed);
-    t.serialize(&mut encode)?;
+/// The compression settings that are in effect until the peers have
+/// negotiated something else.  Nothing is compressed, which every
+/// version of the protocol is able to decode, so a peer that is too
+/// old to negotiate compression simply receives uncompressed PDUs.
+fn initial_compression() -> MuxCompression {
+    MuxCompression {
+        algorithm: MuxCompressionAlgorithm::None,
+        level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        threshold: 0,
+        dictionary: false,
+    }
+}
 
-    if uncompressed.len() <= COMPRESS_THRESH {
-        return Ok((uncompressed, false));
+/// Compute the compression settings to use for a connection, given
+/// the settings `requested` by the client and those configured
+/// `locally` on the server.
+pub fn negotiate_compression(
+    requested: &MuxCompression,
+    locally: &MuxCompression,
+) -> MuxCompression {
+    let algorithm = match (requested.algorithm, locally.algorithm) {
+        (MuxCompressionAlgorithm::Zstd, MuxCompressionAlgorithm::Zstd) => {
+            MuxCompressionAlgorithm::Zstd
+        }
+        _ => MuxCompressionAlgorithm::None,
+    };
+    MuxCompression {
+        algorithm,
+        // Levels above 19 need a lot of memory on both ends
+        level: requested.level.max(1).min(19),
+        threshold: requested.threshold,
+        dictionary: algorithm == MuxCompressionAlgorithm::Zstd
+            && requested.dictionary
+            && locally.dictionary,
     }
-    // It's a little heavy; let's try compressing it
-    let mut compressed = Vec::new();
-    let mut compress = zstd::Encoder::new(&mut compressed, zstd::DEFAULT_COMPRESSION_LEVEL)?;
-    let mut encode = varbincode::Serializer::new(&mut compress);
-    t.serialize(&mut encode)?;
-    drop(encode);
-    compress.finish()?;
+}
 
-    log::debug!(
-        "serialized+compress len {} vs {}",
-        compressed.len(),
-        uncompressed.len()
-    );
+/// Holds the per-connection state that influences how PDUs are
+/// encoded and decoded.
+/// Compression settings and dictionaries change when the
+/// `NegotiateCompressionResponse` and `SetCompressionDictionary` PDUs
+/// pass through the codec, so both ends of the connection switch at
+/// the same point in the stream.
+pub struct CodecState {
+    compression: MuxCompression,
+    /// Applied when compressing the PDUs that we send
+    encode_dictionary: Option<Vec<u8>>,
+    /// Applied when decompressing the PDUs that we receive
+    decode_dictionary: Option<Vec<u8>>,
+    /// Line data that we've sent, used to train the encode_dictionary
+    samples: Option<Vec<Vec<u8>>>,
+    sample_bytes: usize,
+    stats: CompressionStats,
+}
 
-    if compressed.len() < uncompressed.len() {
-        Ok((compressed, true))
-    } else {
-        Ok((uncompressed, false))
+impl Default for CodecState {
+    fn default() -> Self {
+        Self {
+            compression: initial_compression(),
+            encode_dictionary: None,
+            decode_dictionary: None,
+            samples: None,
+            sample_bytes: 0,
+            stats: CompressionStats::default(),
+        }
     }
 }
 
-fn deserialize<T: serde::de::DeserializeOwned, R: std::io::Read>(
-    mut r: R,
-    is_compressed: bool,
-) -> Result<T, Error> {
-    if is_compressed {
-        let mut decompress = zstd::Decoder::new(r)?;
-        let mut decode = varbincode::Deserializer::new(&mut decompress);
-        serde::Deserialize::deserialize(&mut decode).map_err(Into::into)
-    } else {
-        let mut decode = varbincode::Deserializer::new(&mut r);
-        serde::Deserialize::deserialize(&mut decode).map_err(Into::into)
+impl CodecState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compression(&self) -> MuxCompression {
+        self.compression
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+
+    fn set_compression(&mut self, compression: MuxCompression) {
+        log::debug!("using compression settings {:?}", compression);
+        self.compression = compression;
+        self.sample_bytes = 0;
+        self.samples = if compression.dictionary && self.encode_dictionary.is_none() {
+            Some(vec![])
+        } else {
+            None
+        };
+    }
+
+    /// Update our state to reflect a PDU that we just sent
+    fn pdu_sent(&mut self, pdu: &Pdu) {
+        if let Pdu::NegotiateCompressionResponse(resp) = pdu {
+            self.set_compression(resp.compression);
+        }
+    }
+
+    /// Update our state to reflect a PDU that we just received
+    fn pdu_received(&mut self, pdu: &Pdu) {
+        match pdu {
+            Pdu::NegotiateCompressionResponse(resp) => self.set_compression(resp.compression),
+            Pdu::SetCompressionDictionary(dict) => {
+                self.decode_dictionary.replace(dict.dictionary.clone());
+            }
+            _ => {}
+        }
+    }
+
+    fn add_sample(&mut self, data: &[u8]) {
+        if let Some(samples) = self.samples.as_mut() {
+            samples.push(data.to_vec());
+            self.sample_bytes += data.len();
+        }
+    }
+
+    /// If we've collected enough samples, train a dictionary from them.
+    /// Sampling stops after the first attempt, whether it succeeds or not.
+    fn take_trained_dictionary(&mut self) -> Option<Vec<u8>> {
+        let ready = match self.samples.as_ref() {
+            Some(samples) => {
+                self.sample_bytes >= DICTIONARY_SAMPLE_BYTES
+                    && samples.len() >= DICTIONARY_MIN_SAMPLES
+            }
+            None => false,
+        };
+        if !ready {
+            return None;
+        }
+        let samples = self.samples.take()?;
+        match zstd::dict::from_samples(&samples, DICTIONARY_MAX_SIZE) {
+            Ok(dictionary) => {
+                log::debug!(
+                    "trained {} byte dictionary from {} samples ({} bytes)",
+                    dictionary.len(),
+                    samples.len(),
+                    self.sample_bytes
+                );
+                Some(dictionary)
+            }
+            Err(err) => {
+                log::warn!("failed to train compression dictionary: {:#}", err);
+                None
+            }
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut compressed = Vec::new();
+        let level = self.compression.level;
+        let mut compress = match self.encode_dictionary.as_ref() {
+            Some(dictionary) => zstd::Encoder::with_dictionary(&mut compressed, level, dictionary)?,
+            None => zstd::Encoder::new(&mut compressed, level)?,
+        };
+        compress.write_all(data)?;
+        compress.finish()?;
+        Ok(compressed)
+    }
+
+    fn serialize<T: serde::Serialize>(
+        &mut self,
+        t: &T,
+        is_line_data: bool,
+    ) -> Result<(Vec<u8>, bool), Error> {
+        let mut uncompressed = Vec::new();
+        let mut encode = varbincode::Serializer::new(&mut uncompressed);
+        t.serialize(&mut encode)?;
+
+        if is_line_data {
+            self.add_sample(&uncompressed);
+        }
+        self.stats.raw_bytes_sent += uncompressed.len() as u64;
+
+        let (data, is_compressed) = if self.compression.algorithm == MuxCompressionAlgorithm::None
+            || uncompressed.len() <= self.compression.threshold
+        {
+            (uncompressed, false)
+        } else {
+            // It's a little heavy; let's try compressing it
+            let compressed = self.compress(&uncompressed)?;
+            log::debug!(
+                "serialized+compress len {} vs {}",
+                compressed.len(),
+                uncompressed.len()
+            );
+            if compressed.len() < uncompressed.len() {
+                (compressed, true)
+            } else {
+                (uncompressed, false)
+            }
+        };
+
+        self.stats.compressed_bytes_sent += data.len() as u64;
+        Ok((data, is_compressed))
+    }
+
+    fn deserialize<T: serde::de::DeserializeOwned>(
+        &mut self,
+        data: &[u8],
+        is_compressed: bool,
+    ) -> Result<T, Error> {
+        self.stats.compressed_bytes_received += data.len() as u64;
+        if is_compressed {
+            let decompressed = match self.decode_dictionary.as_ref() {
+                Some(dictionary) => read_bounded(
+                    zstd::Decoder::with_dictionary(data, dictionary)?,
+                    MAX_DECOMPRESSED_SIZE,
+                )?,
+                None => read_bounded(zstd::Decoder::new(data)?, MAX_DECOMPRESSED_SIZE)?,
+            };
+            self.stats.raw_bytes_received += decompressed.len() as u64;
+            let mut decode = varbincode::Deserializer::new(decompressed.as_slice());
+            serde::Deserialize::deserialize(&mut decode).map_err(Into::into)
+        } else {
+            self.stats.raw_bytes_received += data.len() as u64;
+            let mut decode = varbincode::Deserializer::new(data);
+            serde::Deserialize::deserialize(&mut decode).map_err(Into::into)
+        }
     }
 }
 
@@ -318,69 +509,38 @@ macro_rules! pdu {
         }
 
         impl Pdu {
-            pub fn encode<W: std::io::Write>(&self, w: W, serial: u64) -> Result<(), Error> {
+            fn name(&self) -> &'static str {
                 match self {
-                    Pdu::Invalid{..} => bail!("attempted to serialize Pdu::Invalid"),
+                    Pdu::Invalid{..} => "??",
                     $(
-                        Pdu::$name(s) => {
-                            let (data, is_compressed) = serialize(s)?;
-                            let encoded_size = encode_raw($vers, serial, &data, is_compressed, w)?;
-                            metrics::histogram!("pdu.size", encoded_size as f64, "pdu" => stringify!($name));
-                            Ok(())
-                        }
-                    ,)*
+                        Pdu::$name(_) => stringify!($name),
+                    )*
                 }
             }
 
-            pub async fn encode_async<W: Unpin + AsyncWriteExt>(&self, w: &mut W, serial: u64) -> Result<(), Error> {
+            /// Serialize the body of the PDU, returning its ident,
+            /// the data and whether that data is compressed
+            fn serialize_with(&self, state: &mut CodecState) -> Result<(u64, Vec<u8>, bool), Error> {
+                let is_line_data = self.is_line_data();
                 match self {
                     Pdu::Invalid{..} => bail!("attempted to serialize Pdu::Invalid"),
                     $(
                         Pdu::$name(s) => {
-                            let (data, is_compressed) = serialize(s)?;
-                            let encoded_size = encode_raw_async($vers, serial, &data, is_compressed, w).await?;
-                            metrics::histogram!("pdu.size", encoded_size as f64, "pdu" => stringify!($name));
-                            Ok(())
+                            let (data, is_compressed) = state.serialize(s, is_line_data)?;
+                            Ok(($vers, data, is_compressed))
                         }
                     ,)*
                 }
             }
 
-            pub fn decode<R: std::io::Read>(r: R) -> Result<DecodedPdu, Error> {
-                let decoded = decode_raw(r).context("decoding a PDU")?;
+            fn deserialize_with(decoded: Decoded, state: &mut CodecState) -> Result<DecodedPdu, Error> {
                 match decoded.ident {
                     $(
                         $vers => {
                             metrics::histogram!("pdu.size", decoded.data.len() as f64, "pdu" => stringify!($name));
                             Ok(DecodedPdu {
                                 serial: decoded.serial,
-                                pdu: Pdu::$name(deserialize(decoded.data.as_slice(), decoded.is_compressed)?)
-                            })
-                        }
-                    ,)*
-                    _ => {
-                        metrics::histogram!("pdu.size", decoded.data.len() as f64, "pdu" => "??");
-                        Ok(DecodedPdu {
-                            serial: decoded.serial,
-                            pdu: Pdu::Invalid{ident:decoded.ident}
-                        })
-                    }
-                }
-            }
-
-            pub async fn decode_async<R>(r: &mut R) -> Result<DecodedPdu, Error>
-                where R: std::marker::Unpin,
-                      R: AsyncRead,
-                      R: std::fmt::Debug
-            {
-                let decoded = decode_raw_async(r).await.context("decoding a PDU")?;
-                match decoded.ident {
-                    $(
-                        $vers => {
-                            metrics::histogram!("pdu.size", decoded.data.len() as f64, "pdu" => stringify!($name));
-                            Ok(DecodedPdu {
-                                serial: decoded.serial,
-                                pdu: Pdu::$name(deserialize(decoded.data.as_slice(), decoded.is_compressed)?)
+                                pdu: Pdu::$name(state.deserialize(decoded.data.as_slice(), decoded.is_compressed)?)
                             })
                         }
                     ,)*
@@ -398,9 +558,24 @@ macro_rules! pdu {
 }
 
 /// The overall version of the codec.
-/// This must be bumped when backwards incompatible changes
-/// are made to the types and protocol.
-pub const CODEC_VERSION: usize = 7;
+/// This must be bumped when changes are made to the types and protocol.
+pub const CODEC_VERSION: usize = 21;
+
+/// The oldest codec version that we are able to talk to.
+/// Adding PDUs only requires bumping CODEC_VERSION, as long as we
+/// check that the peer's version supports them before sending them.
+/// This must be bumped when backwards incompatible changes are made
+/// to the existing types and protocol.
+pub const MIN_CODEC_VERSION: usize = 21;
+
+/// The codec version that introduced NegotiateCompression
+pub const NEGOTIATE_COMPRESSION_CODEC_VERSION: usize = 13;
+
+/// Returns true if we are able to talk to a peer whose codec
+/// version is `codec_vers`
+pub fn is_compatible_codec_version(codec_vers: usize) -> bool {
+    (MIN_CODEC_VERSION..=CODEC_VERSION).contains(&codec_vers)
+}
 
 // Defines the Pdu enum.
 // Each struct has an explicit identifying number.
@@ -434,9 +609,155 @@ pdu! {
     SearchScrollbackResponse: 32,
     SetPaneZoomed: 33,
     SplitPane: 34,
+    TabTreeChanged: 35,
+    TabRemoved: 36,
+    PaneRemoved: 37,
+    PaneMetadataChanged: 38,
+    ActivePaneChanged: 39,
+    PaneZoomChanged: 40,
+    SetClientId: 41,
+    GetClientList: 42,
+    GetClientListResponse: 43,
+    NegotiateCompression: 44,
+    NegotiateCompressionResponse: 45,
+    SetCompressionDictionary: 46,
+    NotifyAlert: 47,
+    OpenFileTransfer: 48,
+    OpenFileTransferResponse: 49,
+    WriteFileChunk: 50,
+    ReadFileChunk: 51,
+    ReadFileChunkResponse: 52,
+    CloseFileTransfer: 53,
+    AddPortForward: 54,
+    AddPortForwardResponse: 55,
+    RemovePortForward: 56,
+    PortForwardRemoved: 57,
+    OpenTcpStream: 58,
+    TcpStreamOpened: 59,
+    TcpStreamData: 60,
+    CloseTcpStream: 61,
+    Authenticate: 62,
+    AuthenticateResponse: 63,
+    CreateAuthToken: 64,
+    AuthTokenResponse: 65,
+    RotateAuthToken: 66,
+    RevokeAuthToken: 67,
+    ListAuthTokens: 68,
+    ListAuthTokensResponse: 69,
+    TcpStreamAck: 70,
 }
 
 impl Pdu {
+    /// Encode the PDU, along with any control PDUs that the codec
+    /// state needs to send to the peer as a consequence.
+    fn encode_as_vec(&self, serial: u64, state: &mut CodecState) -> Result<Vec<u8>, Error> {
+        let (ident, data, is_compressed) = self.serialize_with(state)?;
+        let mut buffer = encode_raw_as_vec(ident, serial, &data, is_compressed)?;
+        metrics::histogram!("pdu.size", buffer.len() as f64, "pdu" => self.name());
+        state.pdu_sent(self);
+
+        if let Some(dictionary) = state.take_trained_dictionary() {
+            // Send the dictionary using the current settings, then
+            // start using it for everything that follows
+            let pdu = Pdu::SetCompressionDictionary(SetCompressionDictionary {
+                dictionary: dictionary.clone(),
+            });
+            buffer.extend_from_slice(&pdu.encode_as_vec(0, state)?);
+            state.encode_dictionary.replace(dictionary);
+        }
+
+        Ok(buffer)
+    }
+
+    /// Encode using the default codec settings
+    pub fn encode<W: std::io::Write>(&self, w: W, serial: u64) -> Result<(), Error> {
+        self.encode_with(w, serial, &mut CodecState::default())
+    }
+
+    pub fn encode_with<W: std::io::Write>(
+        &self,
+        mut w: W,
+        serial: u64,
+        state: &mut CodecState,
+    ) -> Result<(), Error> {
+        let buffer = self.encode_as_vec(serial, state)?;
+        w.write_all(&buffer).context("writing pdu data buffer")?;
+        Ok(())
+    }
+
+    /// Encode using the default codec settings
+    pub async fn encode_async<W: Unpin + AsyncWriteExt>(
+        &self,
+        w: &mut W,
+        serial: u64,
+    ) -> Result<(), Error> {
+        self.encode_async_with(w, serial, &mut CodecState::default())
+            .await
+    }
+
+    pub async fn encode_async_with<W: Unpin + AsyncWriteExt>(
+        &self,
+        w: &mut W,
+        serial: u64,
+        state: &mut CodecState,
+    ) -> Result<(), Error> {
+        let buffer = self.encode_as_vec(serial, state)?;
+        w.write_all(&buffer)
+            .await
+            .context("writing pdu data buffer")?;
+        Ok(())
+    }
+
+    /// Decode using the default codec settings
+    pub fn decode<R: std::io::Read>(r: R) -> Result<DecodedPdu, Error> {
+        Self::decode_with(r, &mut CodecState::default())
+    }
+
+    pub fn decode_with<R: std::io::Read>(
+        r: R,
+        state: &mut CodecState,
+    ) -> Result<DecodedPdu, Error> {
+        let decoded = decode_raw(r).context("decoding a PDU")?;
+        let decoded = Self::deserialize_with(decoded, state)?;
+        state.pdu_received(&decoded.pdu);
+        Ok(decoded)
+    }
+
+    /// Decode using the default codec settings
+    pub async fn decode_async<R>(r: &mut R) -> Result<DecodedPdu, Error>
+    where
+        R: std::marker::Unpin,
+        R: AsyncRead,
+        R: std::fmt::Debug,
+    {
+        Self::decode_async_with(r, &mut CodecState::default()).await
+    }
+
+    pub async fn decode_async_with<R>(
+        r: &mut R,
+        state: &mut CodecState,
+    ) -> Result<DecodedPdu, Error>
+    where
+        R: std::marker::Unpin,
+        R: AsyncRead,
+        R: std::fmt::Debug,
+    {
+        let decoded = decode_raw_async(r).await.context("decoding a PDU")?;
+        let decoded = Self::deserialize_with(decoded, state)?;
+        state.pdu_received(&decoded.pdu);
+        Ok(decoded)
+    }
+
+    /// Returns true if this PDU carries line data; these make
+    /// up the bulk of the traffic and are used to train the
+    /// compression dictionary.
+    fn is_line_data(&self) -> bool {
+        matches!(
+            self,
+            Pdu::GetLinesResponse(_) | Pdu::GetPaneRenderChangesResponse(_)
+        )
+    }
+
     pub fn stream_decode(buffer: &mut Vec<u8>) -> anyhow::Result<Option<DecodedPdu>> {
         let mut cursor = Cursor::new(buffer.as_slice());
         match Self::decode(&mut cursor) {
@@ -509,9 +830,38 @@ impl Pdu {
                 Some(*pane_id)
             }
             Pdu::SetClipboard(SetClipboard { pane_id, .. }) => Some(*pane_id),
+            Pdu::PaneMetadataChanged(PaneMetadataChanged { pane_id, .. }) => Some(*pane_id),
+            Pdu::NotifyAlert(NotifyAlert { pane_id, .. }) => Some(*pane_id),
             _ => None,
         }
     }
+
+    /// Returns true if this is a unilateral notification about a change
+    /// to the tab/pane topology of the server, rather than to the content
+    /// of a specific pane.
+    pub fn is_topology_change(&self) -> bool {
+        matches!(
+            self,
+            Pdu::TabTreeChanged(_)
+                | Pdu::TabRemoved(_)
+                | Pdu::PaneRemoved(_)
+                | Pdu::ActivePaneChanged(_)
+                | Pdu::PaneZoomChanged(_)
+        )
+    }
+
+    /// Returns true if this is a unilateral notification about
+    /// a forwarded TCP port or one of its connections
+    pub fn is_port_forward(&self) -> bool {
+        matches!(
+            self,
+            Pdu::PortForwardRemoved(_)
+                | Pdu::TcpStreamOpened(_)
+                | Pdu::TcpStreamData(_)
+                | Pdu::TcpStreamAck(_)
+                | Pdu::CloseTcpStream(_)
+        )
+    }
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
@@ -531,6 +881,28 @@ pub struct GetCodecVersionResponse {
     pub version_string: String,
 }
 
+/// Sent by the client after the version check to propose the
+/// compression settings for the remainder of the connection
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct NegotiateCompression {
+    pub compression: MuxCompression,
+}
+
+/// The settings that both ends use for the PDUs that follow
+/// this response
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct NegotiateCompressionResponse {
+    pub compression: MuxCompression,
+}
+
+/// A zstd dictionary that the sender will use to compress the
+/// PDUs that follow this one.  This is handled by the codec
+/// and requires no response.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct SetCompressionDictionary {
+    pub dictionary: Vec<u8>,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct Ping {}
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
@@ -651,6 +1023,14 @@ pub struct SetClipboard {
     pub selection: ClipboardSelection,
 }
 
+/// Sent unilaterally by the server when a pane raises an alert,
+/// such as a bell or a system notification
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct NotifyAlert {
+    pub pane_id: PaneId,
+    pub alert: Alert,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct Resize {
     pub containing_tab_id: TabId,
@@ -680,6 +1060,7 @@ pub struct LivenessResponse {
 pub struct GetPaneRenderChangesResponse {
     pub pane_id: PaneId,
     pub mouse_grabbed: bool,
+    pub alt_screen_active: bool,
     pub cursor_position: StableCursorPosition,
     pub dimensions: RenderableDimensions,
     pub dirty_lines: Vec<Range<StableRowIndex>>,
@@ -700,6 +1081,8 @@ pub struct GetLines {
 
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 struct CellCoordinates {
+    /// Indexes the runs of cells in the SerializedLines; see
+    /// `SerializedLines::cell_runs_mut`
     line_idx: usize,
     cols: Range<usize>,
 }
@@ -710,39 +1093,313 @@ struct LineHyperlink {
     coords: Vec<CellCoordinates>,
 }
 
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+struct ImageCoordinates {
+    line_idx: usize,
+    col: usize,
+    top_left: TextureCoordinate,
+    bottom_right: TextureCoordinate,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+struct LineImage {
+    data: ImageData,
+    coords: Vec<ImageCoordinates>,
+}
+
+/// A run of cells that replaces the cells starting at `start`
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+struct CellPatch {
+    start: usize,
+    cells: Vec<Cell>,
+}
+
+/// Describes the changes to a line relative to a version of that
+/// line that was previously sent to the recipient
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+struct LineDelta {
+    stable_row: StableRowIndex,
+    /// The version of the line that the patches apply to
+    base: u64,
+    /// The version of the line once the patches have been applied
+    version: u64,
+    /// The number of cells in the updated line
+    len: usize,
+    patches: Vec<CellPatch>,
+}
+
+impl LineDelta {
+    fn apply(self, mut line: Line) -> Line {
+        // Implicit hyperlinks are a local decoration that the sender
+        // doesn't know about
+        line.invalidate_implicit_hyperlinks();
+        line.resize(self.len);
+        let cells = line.cells_mut();
+        for patch in self.patches {
+            for (idx, cell) in patch.cells.into_iter().enumerate() {
+                if let Some(dest) = cells.get_mut(patch.start + idx) {
+                    *dest = cell;
+                }
+            }
+        }
+        line
+    }
+}
+
+/// If fewer than this many cells separate two changed runs of cells,
+/// then we send them as a single patch, as the overhead of a separate
+/// patch is greater than the cost of sending the unchanged cells
+const PATCH_MERGE_GAP: usize = 4;
+
+/// Compute the patches that turn `base` into `line`.
+/// Returns None if a delta isn't worthwhile.
+fn compute_patches(base: &Line, line: &Line) -> Option<Vec<CellPatch>> {
+    if base.line_size() != line.line_size()
+        || base.is_bidi_enabled() != line.is_bidi_enabled()
+        || base.has_hyperlink() != line.has_hyperlink()
+    {
+        return None;
+    }
+
+    let base_cells = base.cells();
+    let cells = line.cells();
+    let differs = |idx: usize| base_cells.get(idx) != Some(&cells[idx]);
+
+    let mut patches = vec![];
+    let mut patched_cells = 0;
+    let mut idx = 0;
+    while idx < cells.len() {
+        if !differs(idx) {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        let mut end = idx + 1;
+        let mut probe = end;
+        while probe < cells.len() && probe - end < PATCH_MERGE_GAP {
+            if differs(probe) {
+                end = probe + 1;
+            }
+            probe += 1;
+        }
+        patched_cells += end - start;
+        patches.push(CellPatch {
+            start,
+            cells: cells[start..end].to_vec(),
+        });
+        idx = end;
+    }
+
+    if patched_cells * 2 > cells.len() {
+        None
+    } else {
+        Some(patches)
+    }
+}
+
+/// Remembers the version of each line that was most recently sent to
+/// the peer, so that subsequent changes to that line can be sent as a
+/// delta rather than as a whole line.
+/// The sender must keep one of these for each pane of each connection.
+#[derive(Default, Debug)]
+pub struct LineBaselines {
+    lines: HashMap<StableRowIndex, (u64, Line)>,
+    next_version: u64,
+}
+
+impl LineBaselines {
+    fn record(&mut self, stable_row: StableRowIndex, line: &Line) -> u64 {
+        self.next_version += 1;
+        let version = self.next_version;
+        self.lines.insert(stable_row, (version, line.clone()));
+        version
+    }
+
+    /// Prepare lines for sending, encoding lines that were previously
+    /// sent as deltas where that is worthwhile
+    pub fn serialize(&mut self, lines: Vec<(StableRowIndex, Line)>) -> SerializedLines {
+        let mut full = vec![];
+        let mut versions = vec![];
+        let mut deltas = vec![];
+        let mut seen = HashSet::new();
+
+        for (stable_row, line) in lines {
+            if !seen.insert(stable_row) {
+                continue;
+            }
+            let delta = match self.lines.get(&stable_row) {
+                Some((base, prior)) if *prior == line => Some(LineDelta {
+                    stable_row,
+                    base: *base,
+                    version: *base,
+                    len: line.cells().len(),
+                    patches: vec![],
+                }),
+                Some((base, prior)) => compute_patches(prior, &line).map(|patches| LineDelta {
+                    stable_row,
+                    base: *base,
+                    version: 0,
+                    len: line.cells().len(),
+                    patches,
+                }),
+                None => None,
+            };
+
+            match delta {
+                Some(mut delta) => {
+                    if delta.version == 0 {
+                        delta.version = self.record(stable_row, &line);
+                    }
+                    deltas.push(delta);
+                }
+                None => {
+                    versions.push(self.record(stable_row, &line));
+                    full.push((stable_row, line));
+                }
+            }
+        }
+
+        SerializedLines::build(full, versions, deltas)
+    }
+
+    /// Prepare lines for sending in their entirety; this is used when
+    /// the recipient has explicitly asked for the lines
+    pub fn serialize_full(&mut self, lines: Vec<(StableRowIndex, Line)>) -> SerializedLines {
+        let versions = lines
+            .iter()
+            .map(|(stable_row, line)| self.record(*stable_row, line))
+            .collect();
+        SerializedLines::build(lines, versions, vec![])
+    }
+
+    /// Forget the lines outside of `rows`; they are unlikely
+    /// to change and we don't want to hold on to them indefinitely
+    pub fn retain(&mut self, rows: Range<StableRowIndex>) {
+        self.lines.retain(|stable_row, _| rows.contains(stable_row));
+    }
+}
+
+/// The lines decoded from SerializedLines
+#[derive(Debug, Default)]
+pub struct DecodedLines {
+    /// The line data along with the version of each line.  The version
+    /// is zero if the sender isn't tracking versions for the line.
+    pub lines: Vec<(StableRowIndex, Line, u64)>,
+    /// Rows that were sent as a delta against a version that the
+    /// recipient no longer has; these need to be fetched in full
+    pub missing: Vec<StableRowIndex>,
+}
+
 /// What's all this?
 /// Cells hold references to Arc<Hyperlink> and it is important to us to
 /// maintain identity of the hyperlinks in the individual cells, while also
 /// only sending a single copy of the associated URL.
-/// This section of code extracts the hyperlinks from the cells and builds
-/// up a mapping that can be used to restore the identity when the `lines()`
-/// method is called.
+/// The same is true of images, which are considerably larger.
+/// This section of code extracts the hyperlinks and images from the cells
+/// and builds up a mapping that can be used to restore the identity when
+/// the `lines()` method is called.
+/// Lines may also be sent as a delta against a version of the line that the
+/// recipient already has; see `LineBaselines`.
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct SerializedLines {
     lines: Vec<(StableRowIndex, Line)>,
+    /// The version of each of the `lines`; may be empty if the
+    /// sender isn't tracking versions
+    versions: Vec<u64>,
+    deltas: Vec<LineDelta>,
     hyperlinks: Vec<LineHyperlink>,
-    // TODO: image references
+    images: Vec<LineImage>,
 }
 
 impl SerializedLines {
+    /// Return the full lines.  Any deltas are ignored, so this is
+    /// only suitable when the sender isn't using `LineBaselines`.
     pub fn lines(self) -> Vec<(StableRowIndex, Line)> {
         self.into()
     }
-}
 
-impl From<Vec<(StableRowIndex, Line)>> for SerializedLines {
-    fn from(mut lines: Vec<(StableRowIndex, Line)>) -> Self {
-        let mut hyperlinks = vec![];
+    /// Return the lines, applying deltas to the versions of the lines
+    /// returned by `base`, which is passed the row and the version that
+    /// is required.
+    pub fn decode_lines<F>(self, mut base: F) -> DecodedLines
+    where
+        F: FnMut(StableRowIndex, u64) -> Option<Line>,
+    {
+        let (lines, versions, deltas) = self.restore();
+        let mut result = DecodedLines::default();
+
+        for (idx, (stable_row, line)) in lines.into_iter().enumerate() {
+            let version = versions.get(idx).cloned().unwrap_or(0);
+            result.lines.push((stable_row, line, version));
+        }
 
-        for (line_idx, (_, line)) in lines.iter_mut().enumerate() {
+        for delta in deltas {
+            match base(delta.stable_row, delta.base) {
+                Some(line) => {
+                    let stable_row = delta.stable_row;
+                    let version = delta.version;
+                    result.lines.push((stable_row, delta.apply(line), version));
+                }
+                None => result.missing.push(delta.stable_row),
+            }
+        }
+
+        result
+    }
+
+    /// Returns the runs of cells that may hold hyperlinks or images;
+    /// the full lines, followed by the patches for the deltas.
+    fn cell_runs_mut<'a>(
+        lines: &'a mut [(StableRowIndex, Line)],
+        deltas: &'a mut [LineDelta],
+    ) -> Vec<&'a mut [Cell]> {
+        let mut runs = vec![];
+        for (_, line) in lines.iter_mut() {
+            runs.push(line.cells_mut_for_attr_changes_only());
+        }
+        for delta in deltas.iter_mut() {
+            for patch in delta.patches.iter_mut() {
+                runs.push(patch.cells.as_mut_slice());
+            }
+        }
+        runs
+    }
+
+    fn build(
+        mut lines: Vec<(StableRowIndex, Line)>,
+        versions: Vec<u64>,
+        mut deltas: Vec<LineDelta>,
+    ) -> Self {
+        let mut hyperlinks: Vec<LineHyperlink> = vec![];
+        let mut interned_links: Vec<Arc<Hyperlink>> = vec![];
+        let mut images: Vec<LineImage> = vec![];
+        let mut interned_images: Vec<Arc<ImageData>> = vec![];
+
+        let mut add_link = |link: Arc<Hyperlink>, line_idx: usize, cols: Range<usize>| {
+            let coords = CellCoordinates { line_idx, cols };
+            match interned_links
+                .iter()
+                .position(|l| Arc::ptr_eq(l, &link) || **l == *link)
+            {
+                Some(idx) => hyperlinks[idx].coords.push(coords),
+                None => {
+                    hyperlinks.push(LineHyperlink {
+                        link: (*link).clone(),
+                        coords: vec![coords],
+                    });
+                    interned_links.push(link);
+                }
+            }
+        };
+
+        for (line_idx, cells) in Self::cell_runs_mut(&mut lines, &mut deltas)
+            .into_iter()
+            .enumerate()
+        {
             let mut current_link: Option<Arc<Hyperlink>> = None;
             let mut current_range = 0..0;
 
-            for (x, cell) in line
-                .cells_mut_for_attr_changes_only()
-                .iter_mut()
-                .enumerate()
-            {
+            for (x, cell) in cells.iter_mut().enumerate() {
                 // Unset the hyperlink on the cell, if any, and record that
                 // in the hyperlinks data for later restoration.
                 if let Some(link) = cell.attrs_mut().hyperlink().map(Arc::clone) {
@@ -754,13 +1411,7 @@ impl From<Vec<(StableRowIndex, Line)>> for SerializedLines {
                         }
                         Some(prior) => {
                             // It's a different URL, push the current data and start a new one
-                            hyperlinks.push(LineHyperlink {
-                                link: (**prior).clone(),
-                                coords: vec![CellCoordinates {
-                                    line_idx,
-                                    cols: current_range,
-                                }],
-                            });
+                            add_link(Arc::clone(prior), line_idx, current_range);
                             current_range = x..x + 1;
                             current_link = Some(link);
                         }
@@ -772,61 +1423,105 @@ impl From<Vec<(StableRowIndex, Line)>> for SerializedLines {
                     }
                 } else if let Some(link) = current_link.take() {
                     // Wrap up a prior streak
-                    hyperlinks.push(LineHyperlink {
-                        link: (*link).clone(),
-                        coords: vec![CellCoordinates {
-                            line_idx,
-                            cols: current_range,
-                        }],
-                    });
+                    add_link(link, line_idx, current_range);
                     current_range = 0..0;
                 }
 
-                // TODO: something smart for image cells
+                // Likewise for images, which are recorded per cell as
+                // each cell has its own texture coordinates
+                if let Some(image) = cell.attrs().image().cloned() {
+                    cell.attrs_mut().set_image(None);
+                    let coords = ImageCoordinates {
+                        line_idx,
+                        col: x,
+                        top_left: image.top_left(),
+                        bottom_right: image.bottom_right(),
+                    };
+                    let data = image.image_data();
+                    match interned_images
+                        .iter()
+                        .position(|d| Arc::ptr_eq(d, data) || d.id() == data.id())
+                    {
+                        Some(idx) => images[idx].coords.push(coords),
+                        None => {
+                            images.push(LineImage {
+                                data: (**data).clone(),
+                                coords: vec![coords],
+                            });
+                            interned_images.push(Arc::clone(data));
+                        }
+                    }
+                }
             }
             if let Some(link) = current_link.take() {
                 // Wrap up final streak
-                hyperlinks.push(LineHyperlink {
-                    link: (*link).clone(),
-                    coords: vec![CellCoordinates {
-                        line_idx,
-                        cols: current_range,
-                    }],
-                });
+                add_link(link, line_idx, current_range);
             }
         }
 
-        Self { lines, hyperlinks }
+        Self {
+            lines,
+            versions,
+            deltas,
+            hyperlinks,
+            images,
+        }
     }
-}
 
-/// Reconsitute hyperlinks or other attributes that were decomposed for
-/// serialization, and return the line data.
-impl Into<Vec<(StableRowIndex, Line)>> for SerializedLines {
-    fn into(self) -> Vec<(StableRowIndex, Line)> {
-        if self.hyperlinks.is_empty() {
-            self.lines
-        } else {
-            let mut lines = self.lines;
+    /// Reconstitute hyperlinks and images that were decomposed for
+    /// serialization
+    fn restore(mut self) -> (Vec<(StableRowIndex, Line)>, Vec<u64>, Vec<LineDelta>) {
+        if !self.hyperlinks.is_empty() || !self.images.is_empty() {
+            let mut runs = Self::cell_runs_mut(&mut self.lines, &mut self.deltas);
 
             for link in self.hyperlinks {
                 let url = Arc::new(link.link);
 
                 for coord in link.coords {
-                    if let Some((_, line)) = lines.get_mut(coord.line_idx) {
-                        if let Some(cells) =
-                            line.cells_mut_for_attr_changes_only().get_mut(coord.cols)
-                        {
-                            for cell in cells {
-                                cell.attrs_mut().set_hyperlink(Some(Arc::clone(&url)));
-                            }
+                    if let Some(cells) = runs
+                        .get_mut(coord.line_idx)
+                        .and_then(|cells| cells.get_mut(coord.cols))
+                    {
+                        for cell in cells {
+                            cell.attrs_mut().set_hyperlink(Some(Arc::clone(&url)));
                         }
                     }
                 }
             }
 
-            lines
+            for image in self.images {
+                let data = Arc::new(image.data);
+
+                for coord in image.coords {
+                    if let Some(cell) = runs
+                        .get_mut(coord.line_idx)
+                        .and_then(|cells| cells.get_mut(coord.col))
+                    {
+                        cell.attrs_mut().set_image(Some(Box::new(ImageCell::new(
+                            coord.top_left,
+                            coord.bottom_right,
+                            Arc::clone(&data),
+                        ))));
+                    }
+                }
+            }
         }
+
+        (self.lines, self.versions, self.deltas)
+    }
+}
+
+impl From<Vec<(StableRowIndex, Line)>> for SerializedLines {
+    fn from(lines: Vec<(StableRowIndex, Line)>) -> Self {
+        Self::build(lines, vec![], vec![])
+    }
+}
+
+/// Reconsitute hyperlinks or other attributes that were decomposed for
+/// serialization, and return the line data.
+impl Into<Vec<(StableRowIndex, Line)>> for SerializedLines {
+    fn into(self) -> Vec<(StableRowIndex, Line)> {
+        self.restore().0
     }
 }
 
@@ -836,6 +1531,245 @@ pub struct GetLinesResponse {
     pub lines: SerializedLines,
 }
 
+/// Sent unilaterally by the server when a tab was added to a window
+/// or when its split tree changed.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct TabTreeChanged {
+    pub tab_id: TabId,
+    pub window_id: WindowId,
+    pub root: PaneNode,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct TabRemoved {
+    pub tab_id: TabId,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PaneRemoved {
+    pub pane_id: PaneId,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PaneMetadataChanged {
+    pub pane_id: PaneId,
+    pub title: String,
+    pub working_dir: Option<SerdeUrl>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ActivePaneChanged {
+    pub tab_id: TabId,
+    pub pane_id: PaneId,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PaneZoomChanged {
+    pub tab_id: TabId,
+    pub pane_id: PaneId,
+    pub zoomed: bool,
+}
+
+/// Sent by a GUI client after it has verified the codec version,
+/// to identify itself to the server
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct SetClientId {
+    pub client_id: ClientId,
+    /// If true, the server will reject input and resize requests
+    /// from this client
+    pub read_only: bool,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GetClientList {}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct GetClientListResponse {
+    pub clients: Vec<ClientInfo>,
+}
+
+/// The size of the chunks in which file data is transferred
+pub const FILE_TRANSFER_CHUNK_SIZE: usize = 128 * 1024;
+
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub enum FileTransferMode {
+    /// Send a file of the specified size to the server
+    Upload { size: u64 },
+    /// Fetch a file from the server
+    Download,
+}
+
+/// Begin transferring a file.  Relative paths are resolved against
+/// the home directory of the user running the server.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct OpenFileTransfer {
+    pub path: String,
+    pub mode: FileTransferMode,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct OpenFileTransferResponse {
+    pub transfer_id: u64,
+    /// The size of the file being transferred
+    pub size: u64,
+}
+
+/// Append data to a file being uploaded
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct WriteFileChunk {
+    pub transfer_id: u64,
+    pub data: Vec<u8>,
+}
+
+/// Read the next chunk of a file being downloaded
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ReadFileChunk {
+    pub transfer_id: u64,
+    pub max_len: usize,
+}
+
+/// The data is empty once the end of the file has been reached
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ReadFileChunkResponse {
+    pub data: Vec<u8>,
+}
+
+/// Finish a transfer.  An upload only replaces the destination
+/// file if `complete` is true; otherwise it is discarded.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct CloseFileTransfer {
+    pub transfer_id: u64,
+    pub complete: bool,
+}
+
+/// Ask the server to take part in a port forward on behalf of this
+/// client.  For a RemoteToLocal forward the server starts listening
+/// on the remote address; for LocalToRemote the client listens and
+/// uses OpenTcpStream for each connection that it accepts.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct AddPortForward {
+    pub forward: PortForward,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct AddPortForwardResponse {
+    pub forward_id: u64,
+}
+
+/// Cancel a port forward.  This may be sent by any client, not just
+/// the one that owns the forward, which is sent PortForwardRemoved.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct RemovePortForward {
+    pub forward_id: u64,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct PortForwardRemoved {
+    pub forward_id: u64,
+}
+
+/// Sent by the client when it accepts a connection for a LocalToRemote
+/// forward; the server connects to the remote address of the forward.
+/// The side that accepts a connection allocates its stream id.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct OpenTcpStream {
+    pub forward_id: u64,
+    pub stream_id: u64,
+}
+
+/// Sent by the server when it accepts a connection for a RemoteToLocal
+/// forward; the client connects to the local port of the forward.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct TcpStreamOpened {
+    pub forward_id: u64,
+    pub stream_id: u64,
+}
+
+/// Data read from one end of a forwarded connection.  This is sent
+/// in both directions as a unilateral PDU, without a response.
+/// No more than `mux::portforward::RELAY_WINDOW_SIZE` bytes are sent
+/// for a stream before the peer acknowledges them with TcpStreamAck.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct TcpStreamData {
+    pub stream_id: u64,
+    pub data: Vec<u8>,
+}
+
+/// Acknowledges that `len` bytes of TcpStreamData have been written
+/// to the socket for the stream, so that the sender may send more.
+/// This is sent in both directions as a unilateral PDU.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct TcpStreamAck {
+    pub stream_id: u64,
+    pub len: u64,
+}
+
+/// The sender has no more data for the stream.  This is sent in
+/// both directions as a unilateral PDU, and the stream is finished
+/// once both ends have sent it.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct CloseTcpStream {
+    pub stream_id: u64,
+}
+
+/// Present a token to the server.  A server that requires clients to
+/// authenticate rejects everything other than this and GetCodecVersion
+/// until the client has done so.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct Authenticate {
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct AuthenticateResponse {
+    /// The name of the token
+    pub name: String,
+    pub permission: AuthPermission,
+}
+
+/// Generate a new token.  The secret is only ever revealed in the
+/// response; the server retains just a hash of it.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct CreateAuthToken {
+    pub name: String,
+    pub permission: AuthPermission,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct AuthTokenResponse {
+    pub name: String,
+    /// The secret to present in Authenticate
+    pub token: String,
+}
+
+/// Replace the secret of an existing token, keeping its permission.
+/// Connections that authenticated with the old secret are unaffected.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct RotateAuthToken {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct RevokeAuthToken {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ListAuthTokens {}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct AuthTokenInfo {
+    pub name: String,
+    pub permission: AuthPermission,
+    pub created: std::time::SystemTime,
+    pub last_used: Option<std::time::SystemTime>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct ListAuthTokensResponse {
+    pub tokens: Vec<AuthTokenInfo>,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 pub struct SearchScrollbackRequest {
     pub pane_id: PaneId,
@@ -850,6 +1784,7 @@ pub struct SearchScrollbackResponse {
 #[cfg(test)]
 mod test {
     use super::*;
+    use termwiz::cell::CellAttributes;
 
     #[test]
     fn test_frame() {
@@ -955,6 +1890,286 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_port_forward_pdus() {
+        let pdus = vec![
+            Pdu::AddPortForward(AddPortForward {
+                forward: PortForward {
+                    local: 8080,
+                    remote: "localhost:80".to_string(),
+                    direction: config::PortForwardDirection::RemoteToLocal,
+                },
+            }),
+            Pdu::TcpStreamOpened(TcpStreamOpened {
+                forward_id: 1,
+                stream_id: 2,
+            }),
+            Pdu::TcpStreamData(TcpStreamData {
+                stream_id: 2,
+                data: b"hello".to_vec(),
+            }),
+            Pdu::TcpStreamAck(TcpStreamAck {
+                stream_id: 2,
+                len: 5,
+            }),
+            Pdu::CloseTcpStream(CloseTcpStream { stream_id: 2 }),
+        ];
+        for pdu in pdus {
+            let mut encoded = Vec::new();
+            pdu.encode(&mut encoded, 0).unwrap();
+            let decoded = Pdu::decode(encoded.as_slice()).unwrap().pdu;
+            assert_eq!(
+                decoded.is_port_forward(),
+                !matches!(pdu, Pdu::AddPortForward(_))
+            );
+            assert_eq!(decoded, pdu);
+        }
+    }
+
+    #[test]
+    fn test_auth_pdus() {
+        let pdus = vec![
+            Pdu::Authenticate(Authenticate {
+                token: "wzt_0123".to_string(),
+            }),
+            Pdu::AuthenticateResponse(AuthenticateResponse {
+                name: "laptop".to_string(),
+                permission: AuthPermission::NoSpawn,
+            }),
+            Pdu::ListAuthTokensResponse(ListAuthTokensResponse {
+                tokens: vec![AuthTokenInfo {
+                    name: "laptop".to_string(),
+                    permission: AuthPermission::ReadOnly,
+                    created: std::time::UNIX_EPOCH,
+                    last_used: None,
+                }],
+            }),
+        ];
+        for pdu in pdus {
+            let mut encoded = Vec::new();
+            pdu.encode(&mut encoded, 0).unwrap();
+            assert_eq!(Pdu::decode(encoded.as_slice()).unwrap().pdu, pdu);
+        }
+    }
+
+    #[test]
+    fn test_negotiate_compression() {
+        let requested = MuxCompression {
+            algorithm: MuxCompressionAlgorithm::Zstd,
+            level: 40,
+            threshold: 128,
+            dictionary: true,
+        };
+        let server = MuxCompression {
+            dictionary: false,
+            ..MuxCompression::default()
+        };
+        assert_eq!(
+            negotiate_compression(&requested, &server),
+            MuxCompression {
+                algorithm: MuxCompressionAlgorithm::Zstd,
+                level: 19,
+                threshold: 128,
+                dictionary: false,
+            }
+        );
+
+        let server = MuxCompression {
+            algorithm: MuxCompressionAlgorithm::None,
+            ..MuxCompression::default()
+        };
+        let agreed = negotiate_compression(&requested, &server);
+        assert_eq!(agreed.algorithm, MuxCompressionAlgorithm::None);
+        assert!(!agreed.dictionary);
+    }
+
+    #[test]
+    fn test_negotiated_compression_roundtrip() {
+        let mut server = CodecState::new();
+        let mut client = CodecState::new();
+
+        let compression = MuxCompression {
+            algorithm: MuxCompressionAlgorithm::None,
+            level: 1,
+            threshold: 32,
+            dictionary: false,
+        };
+        let mut encoded = Vec::new();
+        Pdu::NegotiateCompressionResponse(NegotiateCompressionResponse { compression })
+            .encode_with(&mut encoded, 1, &mut server)
+            .unwrap();
+        Pdu::decode_with(encoded.as_slice(), &mut client).unwrap();
+        assert_eq!(server.compression(), compression);
+        assert_eq!(client.compression(), compression);
+
+        // With compression turned off, even a large and very
+        // compressible PDU is sent as-is
+        let lines: Vec<(StableRowIndex, Line)> = (0..24)
+            .map(|row| (row, Line::from_text(&"x".repeat(80), &Default::default())))
+            .collect();
+        let pdu = Pdu::GetLinesResponse(GetLinesResponse {
+            pane_id: 0,
+            lines: lines.into(),
+        });
+        let mut encoded = Vec::new();
+        pdu.encode_with(&mut encoded, 2, &mut server).unwrap();
+        let decoded = Pdu::decode_with(encoded.as_slice(), &mut client).unwrap();
+        assert_eq!(decoded.pdu, pdu);
+
+        let stats = server.stats();
+        assert_eq!(stats.raw_bytes_sent, stats.compressed_bytes_sent);
+        assert_eq!(client.stats().raw_bytes_received, stats.raw_bytes_sent);
+    }
+
+    #[test]
+    fn test_uncompressed_until_negotiated() {
+        // A peer that is too old to negotiate compression never sends
+        // NegotiateCompression, so the connection stays uncompressed
+        let mut server = CodecState::new();
+        let lines: Vec<(StableRowIndex, Line)> = (0..24)
+            .map(|row| (row, Line::from_text(&"x".repeat(80), &Default::default())))
+            .collect();
+        let pdu = Pdu::GetLinesResponse(GetLinesResponse {
+            pane_id: 0,
+            lines: lines.into(),
+        });
+        let mut encoded = Vec::new();
+        pdu.encode_with(&mut encoded, 1, &mut server).unwrap();
+
+        let decoded = decode_raw(encoded.as_slice()).unwrap();
+        assert!(!decoded.is_compressed);
+        assert_eq!(Pdu::decode(encoded.as_slice()).unwrap().pdu, pdu);
+        assert!(!is_compatible_codec_version(MIN_CODEC_VERSION - 1));
+        assert!(is_compatible_codec_version(CODEC_VERSION));
+    }
+
+    fn roundtrip_lines(lines: SerializedLines) -> SerializedLines {
+        let data = varbincode::serialize(&lines).unwrap();
+        varbincode::deserialize(data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_line_deltas() {
+        let attrs = CellAttributes::default();
+        let mut baselines = LineBaselines::default();
+        let mut client: HashMap<StableRowIndex, (u64, Line)> = HashMap::new();
+
+        let mut apply = |lines: SerializedLines| {
+            let decoded =
+                roundtrip_lines(lines).decode_lines(|row, version| match client.get(&row) {
+                    Some((v, line)) if *v == version => Some(line.clone()),
+                    _ => None,
+                });
+            for (row, line, version) in decoded.lines.iter() {
+                client.insert(*row, (*version, line.clone()));
+            }
+            (decoded, client.clone())
+        };
+
+        let first = vec![
+            (0, Line::from_text(&"a".repeat(80), &attrs)),
+            (1, Line::from_text(&"b".repeat(80), &attrs)),
+        ];
+        let serialized = baselines.serialize(first.clone());
+        assert!(serialized.deltas.is_empty());
+        let (decoded, _) = apply(serialized);
+        assert!(decoded.missing.is_empty());
+        assert_eq!(decoded.lines.len(), 2);
+
+        // A small change to one line is sent as a patch, and the
+        // unchanged line is sent without any cell data
+        let mut changed = first[0].1.clone();
+        changed.set_cell(3, Cell::new('X', attrs.clone()));
+        changed.set_cell(5, Cell::new('Y', attrs.clone()));
+        let serialized = baselines.serialize(vec![(0, changed.clone()), first[1].clone()]);
+        assert!(serialized.lines.is_empty());
+        assert_eq!(serialized.deltas.len(), 2);
+        assert_eq!(serialized.deltas[0].patches.len(), 1);
+        assert_eq!(serialized.deltas[0].patches[0].cells.len(), 3);
+        assert!(serialized.deltas[1].patches.is_empty());
+        let (decoded, client_lines) = apply(serialized);
+        assert!(decoded.missing.is_empty());
+        assert_eq!(client_lines[&0].1, changed);
+        assert_eq!(client_lines[&1].1, first[1].1);
+
+        // A line that has mostly changed is sent in full
+        let replaced = Line::from_text(&"c".repeat(80), &attrs);
+        let serialized = baselines.serialize(vec![(1, replaced.clone())]);
+        assert_eq!(serialized.lines.len(), 1);
+        assert!(serialized.deltas.is_empty());
+        let (_, client_lines) = apply(serialized);
+        assert_eq!(client_lines[&1].1, replaced);
+
+        // If the recipient doesn't have the base version, the row
+        // is reported as missing
+        let mut other = changed.clone();
+        other.set_cell(0, Cell::new('Z', attrs.clone()));
+        let decoded =
+            roundtrip_lines(baselines.serialize(vec![(0, other)])).decode_lines(|_, _| None);
+        assert!(decoded.lines.is_empty());
+        assert_eq!(decoded.missing, vec![0]);
+    }
+
+    #[test]
+    fn test_interning() {
+        let link = Arc::new(Hyperlink::new("https://example.com"));
+        let mut attrs = CellAttributes::default();
+        attrs.set_hyperlink(Some(Arc::clone(&link)));
+
+        let image = Arc::new(ImageData::with_raw_data(vec![1, 2, 3, 4]));
+        let mut image_attrs = CellAttributes::default();
+        image_attrs.set_image(Some(Box::new(ImageCell::new(
+            TextureCoordinate::new_f32(0., 0.),
+            TextureCoordinate::new_f32(0.5, 1.),
+            Arc::clone(&image),
+        ))));
+        let mut image_attrs2 = CellAttributes::default();
+        image_attrs2.set_image(Some(Box::new(ImageCell::new(
+            TextureCoordinate::new_f32(0.5, 0.),
+            TextureCoordinate::new_f32(1., 1.),
+            Arc::clone(&image),
+        ))));
+
+        let lines: Vec<(StableRowIndex, Line)> = (0..3)
+            .map(|row| {
+                let mut line = Line::from_text("hello world", &attrs);
+                line.set_cell(8, Cell::new(' ', image_attrs.clone()));
+                line.set_cell(9, Cell::new(' ', image_attrs2.clone()));
+                (row, line)
+            })
+            .collect();
+
+        let serialized = SerializedLines::from(lines.clone());
+        assert_eq!(serialized.hyperlinks.len(), 1);
+        assert_eq!(serialized.images.len(), 1);
+        assert_eq!(serialized.images[0].coords.len(), 6);
+
+        let restored = roundtrip_lines(serialized).lines();
+        assert_eq!(restored, lines);
+
+        // Identity is preserved across cells and lines
+        let first = restored[0].1.cells()[0].attrs().hyperlink().unwrap();
+        let last = restored[2].1.cells()[4].attrs().hyperlink().unwrap();
+        assert!(Arc::ptr_eq(first, last));
+        let first = restored[0].1.cells()[8].attrs().image().unwrap();
+        let last = restored[2].1.cells()[9].attrs().image().unwrap();
+        assert!(Arc::ptr_eq(first.image_data(), last.image_data()));
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let data = vec![0u8; 4096];
+        let mut compressed = Vec::new();
+        let mut encoder = zstd::Encoder::new(&mut compressed, 3).unwrap();
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap();
+
+        let decoded =
+            read_bounded(zstd::Decoder::new(compressed.as_slice()).unwrap(), 4096).unwrap();
+        assert_eq!(decoded, data);
+        assert!(read_bounded(zstd::Decoder::new(compressed.as_slice()).unwrap(), 1024).is_err());
+    }
+
     #[test]
     fn test_bogus_pdu() {
         let mut encoded = Vec::new();
@@ -38,6 +38,8 @@ pub struct Palette {
     pub scrollbar_thumb: Option<RgbColor>,
     /// The color of the split line between panes
     pub split: Option<RgbColor>,
+    /// The color that the visual bell fades to
+    pub visual_bell: Option<RgbColor>,
 }
 impl_lua_conversion!(Palette);
 
@@ -60,6 +62,7 @@ impl From<Palette> for wezterm_term::color::ColorPalette {
         apply_color!(selection_bg);
         apply_color!(scrollbar_thumb);
         apply_color!(split);
+        apply_color!(visual_bell);
 
         if let Some(ansi) = cfg.ansi {
             for (idx, col) in ansi.iter().enumerate() {
@@ -37,7 +37,102 @@ impl Default for FontAntiAliasing {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+/// A font weight on the CSS and OpenType scale, where 400 is
+/// regular and 700 is bold.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FontWeight(pub u16);
+
+impl FontWeight {
+    pub const THIN: Self = Self(100);
+    pub const EXTRA_LIGHT: Self = Self(200);
+    pub const LIGHT: Self = Self(300);
+    pub const REGULAR: Self = Self(400);
+    pub const MEDIUM: Self = Self(500);
+    pub const SEMI_BOLD: Self = Self(600);
+    pub const BOLD: Self = Self(700);
+    pub const EXTRA_BOLD: Self = Self(800);
+    pub const BLACK: Self = Self(900);
+
+    /// Returns the weight to use for bold text that is
+    /// rendered relative to this weight
+    pub fn bolder(self) -> Self {
+        if self < Self::BOLD {
+            Self::BOLD
+        } else {
+            Self::BLACK
+        }
+    }
+}
+
+impl Default for FontWeight {
+    fn default() -> Self {
+        Self::REGULAR
+    }
+}
+
+/// The width of a font face, corresponding to the `usWidthClass`
+/// field of the OpenType OS/2 table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FontStretch {
+    UltraCondensed,
+    ExtraCondensed,
+    Condensed,
+    SemiCondensed,
+    Normal,
+    SemiExpanded,
+    Expanded,
+    ExtraExpanded,
+    UltraExpanded,
+}
+impl_lua_conversion!(FontStretch);
+
+impl Default for FontStretch {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl FontStretch {
+    const ALL: [Self; 9] = [
+        Self::UltraCondensed,
+        Self::ExtraCondensed,
+        Self::Condensed,
+        Self::SemiCondensed,
+        Self::Normal,
+        Self::SemiExpanded,
+        Self::Expanded,
+        Self::ExtraExpanded,
+        Self::UltraExpanded,
+    ];
+
+    /// Returns the OS/2 `usWidthClass` value, in the range 1-9
+    pub fn to_opentype_width(self) -> u16 {
+        self as u16 + 1
+    }
+
+    pub fn from_opentype_width(width: u16) -> Self {
+        Self::ALL[(width.clamp(1, 9) - 1) as usize]
+    }
+
+    /// Returns the width as a percentage of the normal width,
+    /// which is the unit used by the `wdth` variation axis
+    pub fn to_percent(self) -> f64 {
+        match self {
+            Self::UltraCondensed => 50.,
+            Self::ExtraCondensed => 62.5,
+            Self::Condensed => 75.,
+            Self::SemiCondensed => 87.5,
+            Self::Normal => 100.,
+            Self::SemiExpanded => 112.5,
+            Self::Expanded => 125.,
+            Self::ExtraExpanded => 150.,
+            Self::UltraExpanded => 200.,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct FontAttributes {
     /// The font family name
     pub family: String,
@@ -47,36 +142,100 @@ pub struct FontAttributes {
     /// Whether the font should be an italic variant
     #[serde(default)]
     pub italic: bool,
+    /// The numeric weight of the font.  When set, this takes
+    /// precedence over `bold`.
+    #[serde(default)]
+    pub weight: Option<FontWeight>,
+    /// The width of the font
+    #[serde(default)]
+    pub stretch: Option<FontStretch>,
     pub is_fallback: bool,
+    /// Scales the glyphs from this font relative to `font_size`.
+    /// The cell dimensions are not affected.
+    #[serde(default)]
+    pub scale: Option<f64>,
+    /// Overrides the global `harfbuzz_features` for this font
+    #[serde(default)]
+    pub harfbuzz_features: Option<Vec<String>>,
+    /// Overrides the global `font_hinting` for this font
+    #[serde(default)]
+    pub hinting: Option<FontHinting>,
+    /// Overrides the global `font_antialias` for this font
+    #[serde(default)]
+    pub antialias: Option<FontAntiAliasing>,
 }
 impl_lua_conversion!(FontAttributes);
 
+// `scale` is a float, so these can't be derived
+impl Eq for FontAttributes {}
+
+impl std::hash::Hash for FontAttributes {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.family.hash(state);
+        self.bold.hash(state);
+        self.italic.hash(state);
+        self.weight.hash(state);
+        self.stretch.hash(state);
+        self.is_fallback.hash(state);
+        self.scale.map(f64::to_bits).hash(state);
+        self.harfbuzz_features.hash(state);
+        self.hinting.hash(state);
+        self.antialias.hash(state);
+    }
+}
+
 impl std::fmt::Display for FontAttributes {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         write!(
             fmt,
-            "wezterm.font('{}', {{bold={}, italic={}}})",
+            "wezterm.font('{}', {{bold={}, italic={}",
             self.family, self.bold, self.italic
-        )
+        )?;
+        if let Some(weight) = self.weight {
+            write!(fmt, ", weight={}", weight.0)?;
+        }
+        if let Some(stretch) = self.stretch {
+            write!(fmt, ", stretch='{:?}'", stretch)?;
+        }
+        write!(fmt, "}})")
     }
 }
 
 impl FontAttributes {
+    /// Returns the weight of the font, taking `bold` into account
+    /// if no explicit `weight` was specified
+    pub fn effective_weight(&self) -> FontWeight {
+        match self.weight {
+            Some(weight) => weight,
+            None if self.bold => FontWeight::BOLD,
+            None => FontWeight::REGULAR,
+        }
+    }
+
+    pub fn effective_stretch(&self) -> FontStretch {
+        self.stretch.unwrap_or_default()
+    }
+
+    /// Returns true if a particular style was requested, rather
+    /// than the regular, normal width, upright face
+    pub fn is_styled(&self) -> bool {
+        self.italic
+            || self.effective_weight() != FontWeight::REGULAR
+            || self.effective_stretch() != FontStretch::Normal
+    }
+
     pub fn new(family: &str) -> Self {
         Self {
             family: family.into(),
-            bold: false,
-            italic: false,
-            is_fallback: false,
+            ..Default::default()
         }
     }
 
     pub fn new_fallback(family: &str) -> Self {
         Self {
             family: family.into(),
-            bold: false,
-            italic: false,
             is_fallback: true,
+            ..Default::default()
         }
     }
 }
@@ -87,7 +246,13 @@ impl Default for FontAttributes {
             family: "JetBrains Mono".into(),
             bold: false,
             italic: false,
+            weight: None,
+            stretch: None,
             is_fallback: false,
+            scale: None,
+            harfbuzz_features: None,
+            hinting: None,
+            antialias: None,
         }
     }
 }
@@ -173,6 +338,7 @@ impl TextStyle {
                 .map(|attr| {
                     let mut attr = attr.clone();
                     attr.bold = true;
+                    attr.weight = attr.weight.map(FontWeight::bolder);
                     attr
                 })
                 .collect(),
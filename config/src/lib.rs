@@ -22,6 +22,7 @@ use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use termwiz::cell::UnicodeVersion;
 use termwiz::hyperlink;
 use termwiz::surface::CursorShape;
 use toml;
@@ -34,6 +35,7 @@ mod frontend;
 pub mod keyassignment;
 mod keys;
 pub mod lua;
+mod portforward;
 mod ssh;
 mod terminal;
 mod tls;
@@ -45,6 +47,7 @@ pub use daemon::*;
 pub use font::*;
 pub use frontend::*;
 pub use keys::*;
+pub use portforward::*;
 pub use ssh::*;
 pub use terminal::*;
 pub use tls::*;
@@ -474,6 +477,28 @@ pub struct Config {
     #[serde(default)]
     pub allow_square_glyphs_to_overflow_width: AllowSquareGlyphOverflow,
 
+    /// When true (the default), box drawing, block element and
+    /// powerline separator glyphs are drawn by wezterm to exactly
+    /// fill the cell rather than using the glyphs from the font.
+    #[serde(default = "default_true")]
+    pub custom_block_glyphs: bool,
+
+    /// When true, the text under the cursor is shaped separately from
+    /// the text around it, so that ligatures don't form or change
+    /// shape as the cursor moves through them.
+    #[serde(default)]
+    pub disable_ligatures_under_cursor: bool,
+
+    /// When true, selected text is shaped separately from the text
+    /// around it and with ligatures disabled.
+    #[serde(default)]
+    pub disable_ligatures_in_selection: bool,
+
+    /// The number of shaped runs of text that are cached by the
+    /// renderer.
+    #[serde(default = "default_shape_cache_size")]
+    pub shape_cache_size: usize,
+
     /// When using FontKitXXX font systems, a set of directories to
     /// search ahead of the standard font locations for fonts.
     /// Relative paths are taken to be relative to the directory
@@ -640,6 +665,50 @@ pub struct Config {
     #[serde(default = "default_ratelimit_line_prefetches_per_second")]
     pub ratelimit_mux_line_prefetches_per_second: u32,
 
+    /// When multiple clients are attached to the multiplexer server,
+    /// controls how the size of a pane is chosen from the sizes
+    /// requested by each of those clients.
+    #[serde(default)]
+    pub mux_client_size_policy: MuxClientSizePolicy,
+
+    /// When the round trip time for keyboard input to a remote pane
+    /// is at least this many milliseconds, the client will predict
+    /// the effect of typing and echo it locally until the server
+    /// confirms it.
+    #[serde(default = "default_local_echo_threshold_ms")]
+    pub local_echo_threshold_ms: u64,
+
+    /// Controls how PDUs exchanged with the multiplexer server are
+    /// compressed.  The client proposes these settings when it
+    /// attaches, and the server may restrict them using its own.
+    #[serde(default)]
+    pub mux_compression: MuxCompression,
+
+    /// How often the multiplexer client pings the server to measure
+    /// the latency of the connection.  Set to 0 to disable.
+    #[serde(default = "default_mux_ping_interval_ms")]
+    pub mux_ping_interval_ms: u64,
+
+    /// If the server doesn't answer a ping within this many
+    /// milliseconds, the client assumes that the connection has
+    /// failed and tries to reconnect.
+    #[serde(default = "default_mux_ping_timeout_ms")]
+    pub mux_ping_timeout_ms: u64,
+
+    /// Controls what happens to input that was typed into a remote
+    /// pane while the client was reconnecting to the server
+    #[serde(default)]
+    pub mux_buffered_input: MuxBufferedInput,
+
+    /// Where the multiplexer server keeps the tokens that clients
+    /// may authenticate with.  Defaults to `mux-auth-tokens.toml`
+    /// in the config directory.
+    pub mux_auth_tokens_file: Option<PathBuf>,
+
+    /// If set, the multiplexer server appends a line to this file
+    /// each time a client connects, authenticates or disconnects
+    pub mux_audit_log: Option<PathBuf>,
+
     #[serde(default)]
     pub keys: Vec<Key>,
     #[serde(default)]
@@ -687,6 +756,12 @@ pub struct Config {
     #[serde(default = "default_true")]
     pub show_tab_index_in_tab_bar: bool,
 
+    /// If true, the tab bar shows the latency of the connection to
+    /// the multiplexer server when the active pane is in a
+    /// multiplexer domain, or that it is reconnecting
+    #[serde(default = "default_true")]
+    pub show_connection_status_in_tab_bar: bool,
+
     /// If true, show_tab_index_in_tab_bar uses a zero-based index.
     /// The default is false and the tab shows a one-based index.
     #[serde(default)]
@@ -792,6 +867,14 @@ pub struct Config {
     #[serde(default = "default_cursor_blink_rate")]
     pub cursor_blink_rate: u64,
 
+    /// Limits the rate at which animated images are re-painted.
+    /// Frames that fall between re-paints are skipped so that the
+    /// animation still runs at its intended speed.
+    /// A value of 0 disables animation; only the first frame of
+    /// an animated image is shown.
+    #[serde(default = "default_animation_fps")]
+    pub animation_fps: u8,
+
     /// Specifies the default cursor style.  various escape sequences
     /// can override the default style in different situations (eg:
     /// an editor can change it depending on the mode), but this value
@@ -862,12 +945,41 @@ pub struct Config {
     #[serde(default = "default_true")]
     pub adjust_window_size_when_changing_font_size: bool,
 
-    #[serde(default = "default_alternate_buffer_wheel_scroll_speed")]
-    pub alternate_buffer_wheel_scroll_speed: u8,
+    /// When set to true, the terminal starts in implicit bidi mode
+    /// and applies the Unicode Bidirectional Algorithm to each line
+    /// so that RTL scripts such as Arabic and Hebrew are displayed
+    /// in the correct order.  Applications may switch to explicit
+    /// mode, where they perform the reordering themselves, using
+    /// `CSI 8 l`.
+    #[serde(default)]
+    pub bidi_enabled: bool,
+
+    /// When true, characters whose East Asian Width property is
+    /// Ambiguous are treated as occupying two cells
+    #[serde(default)]
+    pub treat_east_asian_ambiguous_width_as_wide: bool,
+
+    /// Selects the version of the unicode width rules that are
+    /// used to compute the number of cells occupied by a grapheme
+    #[serde(default = "default_unicode_version")]
+    pub unicode_version: u8,
+
+    /// Controls the visual indication of the bell
+    #[serde(default)]
+    pub visual_bell: VisualBell,
+
+    /// Controls whether the bell makes a sound
+    #[serde(default)]
+    pub audible_bell: AudibleBell,
+
+    /// Controls whether programs running in a pane are permitted
+    /// to read the clipboard using OSC 52
+    #[serde(default)]
+    pub clipboard_read_access: ClipboardReadAccess,
 }
 
-fn default_alternate_buffer_wheel_scroll_speed() -> u8 {
-    3
+fn default_unicode_version() -> u8 {
+    9
 }
 
 fn default_word_boundary() -> String {
@@ -959,6 +1071,150 @@ impl Default for WindowCloseConfirmation {
     }
 }
 
+#[derive(Default, Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct VisualBell {
+    /// How long the bell takes to fade in to the `visual_bell` color.
+    #[serde(default)]
+    pub fade_in_duration_ms: u64,
+    /// How long the bell takes to fade back out again.
+    /// The visual bell is disabled when both durations are 0.
+    #[serde(default)]
+    pub fade_out_duration_ms: u64,
+    #[serde(default)]
+    pub target: VisualBellTarget,
+}
+impl_lua_conversion!(VisualBell);
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualBellTarget {
+    BackgroundColor,
+    CursorColor,
+}
+impl_lua_conversion!(VisualBellTarget);
+
+impl Default for VisualBellTarget {
+    fn default() -> Self {
+        VisualBellTarget::BackgroundColor
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudibleBell {
+    SystemBeep,
+    Disabled,
+}
+impl_lua_conversion!(AudibleBell);
+
+impl Default for AudibleBell {
+    fn default() -> Self {
+        AudibleBell::Disabled
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardReadAccess {
+    /// Ignore requests to read the clipboard
+    Deny,
+    /// Ask the user before answering each request
+    Prompt,
+    /// Answer requests without asking
+    Allow,
+}
+impl_lua_conversion!(ClipboardReadAccess);
+
+impl Default for ClipboardReadAccess {
+    fn default() -> Self {
+        ClipboardReadAccess::Prompt
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MuxBufferedInput {
+    /// Ask the user whether to send the input once reconnected
+    Prompt,
+    /// Send the input once reconnected
+    Replay,
+    /// Throw the input away
+    Discard,
+}
+impl_lua_conversion!(MuxBufferedInput);
+
+impl Default for MuxBufferedInput {
+    fn default() -> Self {
+        MuxBufferedInput::Prompt
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MuxClientSizePolicy {
+    /// Use the smallest size requested by any attached client;
+    /// larger clients letterbox the pane
+    Smallest,
+    /// Use the size requested by the client that most recently
+    /// sent input
+    LatestActive,
+}
+impl_lua_conversion!(MuxClientSizePolicy);
+
+impl Default for MuxClientSizePolicy {
+    fn default() -> Self {
+        MuxClientSizePolicy::Smallest
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MuxCompressionAlgorithm {
+    /// Send PDUs uncompressed
+    None,
+    /// Compress PDUs using zstd
+    Zstd,
+}
+impl_lua_conversion!(MuxCompressionAlgorithm);
+
+impl Default for MuxCompressionAlgorithm {
+    fn default() -> Self {
+        MuxCompressionAlgorithm::Zstd
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MuxCompression {
+    #[serde(default)]
+    pub algorithm: MuxCompressionAlgorithm,
+    /// The zstd compression level
+    #[serde(default = "default_mux_compression_level")]
+    pub level: i32,
+    /// PDUs that serialize to no more than this many bytes
+    /// are sent uncompressed
+    #[serde(default = "default_mux_compression_threshold")]
+    pub threshold: usize,
+    /// Whether the server should train a zstd dictionary on the
+    /// terminal output that it sends, and use it to compress
+    /// subsequent output
+    #[serde(default = "default_true")]
+    pub dictionary: bool,
+}
+impl_lua_conversion!(MuxCompression);
+
+impl Default for MuxCompression {
+    fn default() -> Self {
+        Self {
+            algorithm: MuxCompressionAlgorithm::default(),
+            level: default_mux_compression_level(),
+            threshold: default_mux_compression_threshold(),
+            dictionary: true,
+        }
+    }
+}
+
+fn default_mux_compression_level() -> i32 {
+    3
+}
+
+fn default_mux_compression_threshold() -> usize {
+    256
+}
+
 impl Default for Config {
     fn default() -> Self {
         // Ask serde to provide the defaults based on the attributes
@@ -1268,6 +1524,22 @@ impl Config {
         }
     }
 
+    /// Returns the options for computing the width of graphemes
+    pub fn unicode_version(&self) -> UnicodeVersion {
+        UnicodeVersion {
+            version: self.unicode_version,
+            ambiguous_are_wide: self.treat_east_asian_ambiguous_width_as_wide,
+        }
+    }
+
+    /// Returns the path to the file that holds the mux server's
+    /// authentication tokens
+    pub fn mux_auth_tokens_file(&self) -> PathBuf {
+        self.mux_auth_tokens_file
+            .clone()
+            .unwrap_or_else(|| CONFIG_DIR.join("mux-auth-tokens.toml"))
+    }
+
     pub fn build_prog(&self, prog: Option<Vec<&OsStr>>) -> Result<CommandBuilder, Error> {
         let mut cmd = match prog {
             Some(args) => {
@@ -1318,6 +1590,18 @@ fn default_ratelimit_line_prefetches_per_second() -> u32 {
     10
 }
 
+fn default_local_echo_threshold_ms() -> u64 {
+    100
+}
+
+fn default_mux_ping_interval_ms() -> u64 {
+    5_000
+}
+
+fn default_mux_ping_timeout_ms() -> u64 {
+    15_000
+}
+
 fn default_true() -> bool {
     true
 }
@@ -1326,6 +1610,14 @@ fn default_cursor_blink_rate() -> u64 {
     800
 }
 
+fn default_animation_fps() -> u8 {
+    30
+}
+
+fn default_shape_cache_size() -> usize {
+    65536
+}
+
 fn default_swap_backspace_and_delete() -> bool {
     // cfg!(target_os = "macos")
     // See: https://github.com/wez/wezterm/issues/88
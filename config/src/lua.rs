@@ -1,4 +1,4 @@
-use crate::{FontAttributes, TextStyle};
+use crate::{FontAntiAliasing, FontAttributes, FontHinting, FontStretch, FontWeight, TextStyle};
 use anyhow::anyhow;
 use bstr::BString;
 pub use luahelper::*;
@@ -142,7 +142,7 @@ fn hostname<'lua>(_: &'lua Lua, _: ()) -> mlua::Result<String> {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 struct TextStyleAttributes {
     /// Whether the font should be a bold variant
     #[serde(default)]
@@ -150,14 +150,96 @@ struct TextStyleAttributes {
     /// Whether the font should be an italic variant
     #[serde(default)]
     pub italic: bool,
+    /// The numeric weight of the font, from 100 to 900.
+    /// Takes precedence over `bold`.
+    #[serde(default)]
+    pub weight: Option<FontWeight>,
+    /// The width of the font, eg: "Condensed"
+    #[serde(default)]
+    pub stretch: Option<FontStretch>,
     /// If set, when rendering text that is set to the default
     /// foreground color, use this color instead.  This is most
     /// useful in a `[[font_rules]]` section to implement changing
     /// the text color for eg: bold text.
     pub foreground: Option<termwiz::color::RgbColor>,
+    /// Scales the glyphs from the font relative to `font_size`
+    #[serde(default)]
+    pub scale: Option<f64>,
+    /// Overrides the global `harfbuzz_features` for the font
+    #[serde(default)]
+    pub harfbuzz_features: Option<Vec<String>>,
+    /// Overrides the global `font_hinting` for the font
+    #[serde(default)]
+    pub hinting: Option<FontHinting>,
+    /// Overrides the global `font_antialias` for the font
+    #[serde(default)]
+    pub antialias: Option<FontAntiAliasing>,
 }
 impl_lua_conversion!(TextStyleAttributes);
 
+impl TextStyleAttributes {
+    fn font_attributes(&self, family: String, is_fallback: bool) -> FontAttributes {
+        FontAttributes {
+            family,
+            bold: self.bold,
+            italic: self.italic,
+            weight: self.weight,
+            stretch: self.stretch,
+            is_fallback,
+            scale: self.scale,
+            harfbuzz_features: self.harfbuzz_features.clone(),
+            hinting: self.hinting,
+            antialias: self.antialias,
+        }
+    }
+}
+
+/// Returns an error if `scale` is specified but is not a positive
+/// number; anything else would yield a nonsensical font size
+fn validate_scale(scale: Option<f64>) -> mlua::Result<Option<f64>> {
+    match scale {
+        Some(scale) if !(scale.is_finite() && scale > 0.0) => Err(mlua::Error::external(anyhow!(
+            "font scale must be a positive number, but got {}",
+            scale
+        ))),
+        scale => Ok(scale),
+    }
+}
+
+/// An entry in the list passed to `wezterm.font_with_fallback`.
+/// This is either just the family name, or a table holding the
+/// family name along with overrides that apply to only that font:
+///
+/// `{family="Symbols Nerd Font", scale=0.9, harfbuzz_features={"liga=0"}}`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum FallbackFont {
+    Family(String),
+    Attributes(FallbackFontAttributes),
+}
+impl_lua_conversion!(FallbackFont);
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct FallbackFontAttributes {
+    family: String,
+    #[serde(default)]
+    bold: Option<bool>,
+    #[serde(default)]
+    italic: Option<bool>,
+    #[serde(default)]
+    weight: Option<FontWeight>,
+    #[serde(default)]
+    stretch: Option<FontStretch>,
+    #[serde(default)]
+    scale: Option<f64>,
+    #[serde(default)]
+    harfbuzz_features: Option<Vec<String>>,
+    #[serde(default)]
+    hinting: Option<FontHinting>,
+    #[serde(default)]
+    antialias: Option<FontAntiAliasing>,
+}
+
 /// Given a simple font family name, returns a text style instance.
 /// The second optional argument is a list of the other TextStyle
 /// fields, which at the time of writing includes only the
@@ -172,15 +254,11 @@ fn font<'lua>(
     (family, map_defaults): (String, Option<TextStyleAttributes>),
 ) -> mlua::Result<TextStyle> {
     let attrs = map_defaults.unwrap_or_else(TextStyleAttributes::default);
+    validate_scale(attrs.scale)?;
     let mut text_style = TextStyle::default();
 
     text_style.font.clear();
-    text_style.font.push(FontAttributes {
-        family,
-        bold: attrs.bold,
-        italic: attrs.italic,
-        is_fallback: false,
-    });
+    text_style.font.push(attrs.font_attributes(family, false));
     text_style.foreground = attrs.foreground;
 
     Ok(text_style)
@@ -191,23 +269,56 @@ fn font<'lua>(
 ///
 /// `wezterm.font_with_fallback({"Operator Mono", "DengXian"})`
 ///
+/// Each entry may instead be a table holding the `family` along with
+/// settings that apply only to that font:
+///
+/// `wezterm.font_with_fallback({"Operator Mono", {family="DengXian", scale=0.9}})`
+///
 /// The second optional argument is a list of other TextStyle fields,
 /// as described by the `wezterm.font` documentation.
 fn font_with_fallback<'lua>(
     _lua: &'lua Lua,
-    (fallback, map_defaults): (Vec<String>, Option<TextStyleAttributes>),
+    (fallback, map_defaults): (Vec<FallbackFont>, Option<TextStyleAttributes>),
 ) -> mlua::Result<TextStyle> {
     let attrs = map_defaults.unwrap_or_else(TextStyleAttributes::default);
+    validate_scale(attrs.scale)?;
     let mut text_style = TextStyle::default();
 
     text_style.font.clear();
-    for (idx, family) in fallback.into_iter().enumerate() {
-        text_style.font.push(FontAttributes {
-            family,
-            bold: attrs.bold,
-            italic: attrs.italic,
-            is_fallback: idx != 0,
-        });
+    for (idx, entry) in fallback.into_iter().enumerate() {
+        let is_fallback = idx != 0;
+        let font = match entry {
+            FallbackFont::Family(family) => attrs.font_attributes(family, is_fallback),
+            FallbackFont::Attributes(entry) => {
+                let mut font = attrs.font_attributes(entry.family, is_fallback);
+                if let Some(bold) = entry.bold {
+                    font.bold = bold;
+                }
+                if let Some(italic) = entry.italic {
+                    font.italic = italic;
+                }
+                if entry.weight.is_some() {
+                    font.weight = entry.weight;
+                }
+                if entry.stretch.is_some() {
+                    font.stretch = entry.stretch;
+                }
+                if entry.scale.is_some() {
+                    font.scale = validate_scale(entry.scale)?;
+                }
+                if entry.harfbuzz_features.is_some() {
+                    font.harfbuzz_features = entry.harfbuzz_features;
+                }
+                if entry.hinting.is_some() {
+                    font.hinting = entry.hinting;
+                }
+                if entry.antialias.is_some() {
+                    font.antialias = entry.antialias;
+                }
+                font
+            }
+        };
+        text_style.font.push(font);
     }
     text_style.foreground = attrs.foreground;
 
@@ -547,4 +658,68 @@ assert(wezterm.emit('bar', 42, 'woot') == true)
 
         Ok(())
     }
+
+    fn eval_text_style(code: &str) -> anyhow::Result<TextStyle> {
+        let lua = make_lua_context(&std::env::current_dir()?)?;
+        Ok(lua.load(code).eval()?)
+    }
+
+    #[test]
+    fn font_with_fallback_entries() -> anyhow::Result<()> {
+        let style = eval_text_style(
+            r#"
+local wezterm = require 'wezterm';
+return wezterm.font_with_fallback({
+  "Operator Mono",
+  {family="Symbols Nerd Font", scale=0.9, weight=300, harfbuzz_features={"liga=0"}},
+}, {italic=true})
+"#,
+        )?;
+
+        assert_eq!(style.font.len(), 2);
+
+        let first = &style.font[0];
+        assert_eq!(first.family, "Operator Mono");
+        assert!(!first.is_fallback);
+        assert!(first.italic);
+        assert_eq!(first.scale, None);
+        assert_eq!(first.harfbuzz_features, None);
+
+        let second = &style.font[1];
+        assert_eq!(second.family, "Symbols Nerd Font");
+        assert!(second.is_fallback);
+        assert!(second.italic);
+        assert_eq!(second.scale, Some(0.9));
+        assert_eq!(second.weight, Some(FontWeight(300)));
+        assert_eq!(second.harfbuzz_features, Some(vec!["liga=0".to_string()]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn font_with_fallback_rejects_bad_scale() {
+        for scale in &["0", "-1.5", "0/0", "1/0"] {
+            let code = format!(
+                r#"
+local wezterm = require 'wezterm';
+return wezterm.font_with_fallback({{"foo", {{family="bar", scale={}}}}})
+"#,
+                scale
+            );
+            assert!(
+                eval_text_style(&code).is_err(),
+                "scale={} should be rejected",
+                scale
+            );
+        }
+    }
+
+    #[test]
+    fn font_rejects_bad_scale() -> anyhow::Result<()> {
+        assert!(eval_text_style(r#"return require('wezterm').font("foo", {scale=0})"#).is_err());
+
+        let style = eval_text_style(r#"return require('wezterm').font("foo", {scale=1.25})"#)?;
+        assert_eq!(style.font[0].scale, Some(1.25));
+        Ok(())
+    }
 }
@@ -0,0 +1,50 @@
+use crate::*;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortForwardDirection {
+    /// Listen on the local port and forward connections to the
+    /// remote address, as seen from the mux server host
+    LocalToRemote,
+    /// Listen on the remote address on the mux server host and
+    /// forward connections to the local port
+    RemoteToLocal,
+}
+impl_lua_conversion!(PortForwardDirection);
+
+impl Default for PortForwardDirection {
+    fn default() -> Self {
+        PortForwardDirection::LocalToRemote
+    }
+}
+
+/// A TCP port forward that is tunnelled over the connection to
+/// a multiplexer domain
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PortForward {
+    /// The port on the local machine.  Local listeners only
+    /// accept connections from the loopback interface.
+    pub local: u16,
+
+    /// The host:port on the mux server side.  For LocalToRemote
+    /// forwards this is the address that the server connects to;
+    /// for RemoteToLocal forwards it is the address that the server
+    /// listens on.
+    pub remote: String,
+
+    #[serde(default)]
+    pub direction: PortForwardDirection,
+}
+impl_lua_conversion!(PortForward);
+
+impl std::fmt::Display for PortForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.direction {
+            PortForwardDirection::LocalToRemote => {
+                write!(f, "localhost:{} -> {}", self.local, self.remote)
+            }
+            PortForwardDirection::RemoteToLocal => {
+                write!(f, "{} -> localhost:{}", self.remote, self.local)
+            }
+        }
+    }
+}
@@ -27,6 +27,14 @@ pub struct SshDomain {
 
     /// The path to the wezterm binary on the remote host
     pub remote_wezterm_path: Option<String>,
+
+    /// TCP ports to forward over the connection to this domain
+    #[serde(default)]
+    pub forwards: Vec<PortForward>,
+
+    /// The token that the client presents to the mux server on
+    /// the remote host
+    pub auth_token: Option<String>,
 }
 impl_lua_conversion!(SshDomain);
 
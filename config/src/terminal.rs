@@ -1,6 +1,7 @@
 //! Bridge our gui config into the terminal crate configuration
 
 use crate::configuration;
+use termwiz::cell::UnicodeVersion;
 use termwiz::hyperlink::Rule as HyperlinkRule;
 use wezterm_term::color::ColorPalette;
 
@@ -31,7 +32,11 @@ impl wezterm_term::TerminalConfiguration for TermConfig {
         config.resolved_palette.clone().into()
     }
 
-    fn alternate_buffer_wheel_scroll_speed(&self) -> u8 {
-        configuration().alternate_buffer_wheel_scroll_speed
+    fn bidi_enabled(&self) -> bool {
+        configuration().bidi_enabled
+    }
+
+    fn unicode_version(&self) -> UnicodeVersion {
+        configuration().unicode_version()
     }
 }
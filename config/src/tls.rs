@@ -22,6 +22,12 @@ pub struct TlsDomainServer {
     /// to the trust store.
     #[serde(default)]
     pub pem_root_certs: Vec<PathBuf>,
+
+    /// If true, clients must authenticate with one of the tokens
+    /// from `mux_auth_tokens_file` before making any other request,
+    /// in addition to presenting a trusted certificate
+    #[serde(default)]
+    pub require_auth_token: bool,
 }
 impl_lua_conversion!(TlsDomainServer);
 
@@ -82,6 +88,14 @@ pub struct TlsDomainClient {
 
     /// The path to the wezterm binary on the remote host
     pub remote_wezterm_path: Option<String>,
+
+    /// TCP ports to forward over the connection to this domain
+    #[serde(default)]
+    pub forwards: Vec<PortForward>,
+
+    /// The token that the client presents when connecting to
+    /// this domain
+    pub auth_token: Option<String>,
 }
 impl_lua_conversion!(TlsDomainClient);
 
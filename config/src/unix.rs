@@ -43,6 +43,16 @@ pub struct UnixDomain {
 
     #[serde(default = "default_write_timeout")]
     pub write_timeout: Duration,
+
+    /// If true, the server requires clients that connect to this
+    /// socket to authenticate with one of the tokens from
+    /// `mux_auth_tokens_file` before making any other request
+    #[serde(default)]
+    pub require_auth_token: bool,
+
+    /// The token that the client presents when connecting to
+    /// this domain
+    pub auth_token: Option<String>,
 }
 impl_lua_conversion!(UnixDomain);
 
@@ -57,6 +67,8 @@ impl Default for UnixDomain {
             skip_permissions_check: false,
             read_timeout: default_read_timeout(),
             write_timeout: default_write_timeout(),
+            require_auth_token: false,
+            auth_token: None,
         }
     }
 }
@@ -1384,3 +1384,84 @@ pub use self::FT_Orientation_ as FT_Orientation;
 extern "C" {
     pub fn FT_Outline_Get_Orientation(outline: *mut FT_Outline) -> FT_Orientation;
 }
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FT_Var_Axis_ {
+    pub name: *mut FT_String,
+    pub minimum: FT_Fixed,
+    pub def: FT_Fixed,
+    pub maximum: FT_Fixed,
+    pub tag: FT_ULong,
+    pub strid: FT_UInt,
+}
+pub type FT_Var_Axis = FT_Var_Axis_;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FT_Var_Named_Style_ {
+    pub coords: *mut FT_Fixed,
+    pub strid: FT_UInt,
+    pub psid: FT_UInt,
+}
+pub type FT_Var_Named_Style = FT_Var_Named_Style_;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FT_MM_Var_ {
+    pub num_axis: FT_UInt,
+    pub num_designs: FT_UInt,
+    pub num_namedstyles: FT_UInt,
+    pub axis: *mut FT_Var_Axis,
+    pub namedstyle: *mut FT_Var_Named_Style,
+}
+pub type FT_MM_Var = FT_MM_Var_;
+extern "C" {
+    pub fn FT_Get_MM_Var(face: FT_Face, amaster: *mut *mut FT_MM_Var) -> FT_Error;
+}
+extern "C" {
+    pub fn FT_Done_MM_Var(library: FT_Library, amaster: *mut FT_MM_Var) -> FT_Error;
+}
+extern "C" {
+    pub fn FT_Set_Var_Design_Coordinates(
+        face: FT_Face,
+        num_coords: FT_UInt,
+        coords: *mut FT_Fixed,
+    ) -> FT_Error;
+}
+extern "C" {
+    pub fn FT_Get_Var_Design_Coordinates(
+        face: FT_Face,
+        num_coords: FT_UInt,
+        coords: *mut FT_Fixed,
+    ) -> FT_Error;
+}
+extern "C" {
+    pub fn FT_Set_Named_Instance(face: FT_Face, instance_index: FT_UInt) -> FT_Error;
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FT_Color_ {
+    pub blue: FT_Byte,
+    pub green: FT_Byte,
+    pub red: FT_Byte,
+    pub alpha: FT_Byte,
+}
+pub type FT_Color = FT_Color_;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FT_Palette_Data_ {
+    pub num_palettes: FT_UShort,
+    pub palette_name_ids: *const FT_UShort,
+    pub palette_flags: *const FT_UShort,
+    pub num_palette_entries: FT_UShort,
+    pub palette_entry_name_ids: *const FT_UShort,
+}
+pub type FT_Palette_Data = FT_Palette_Data_;
+extern "C" {
+    pub fn FT_Palette_Data_Get(face: FT_Face, apalette: *mut FT_Palette_Data) -> FT_Error;
+}
+extern "C" {
+    pub fn FT_Palette_Select(
+        face: FT_Face,
+        palette_index: FT_UShort,
+        apalette: *mut *mut FT_Color,
+    ) -> FT_Error;
+}
@@ -0,0 +1,282 @@
+use crate::pane::PaneId;
+use config::{MuxClientSizePolicy, PortForward};
+use portable_pty::PtySize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+static CLIENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies a client that is attached to the mux server.
+/// This is generated by the client and sent to the server
+/// when it attaches.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct ClientId {
+    pub hostname: String,
+    pub username: String,
+    pub pid: u32,
+    /// Distinguishes multiple connections made by the same process
+    pub id: usize,
+}
+
+impl ClientId {
+    pub fn new() -> Self {
+        let id = CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        Self {
+            hostname: hostname::get()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "localhost".to_string()),
+            username: config::username_from_env().unwrap_or_else(|_| "somebody".to_string()),
+            pid: std::process::id(),
+            id,
+        }
+    }
+}
+
+impl Default for ClientId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts the bytes exchanged over a connection, both before
+/// and after compression
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CompressionStats {
+    pub raw_bytes_sent: u64,
+    pub compressed_bytes_sent: u64,
+    pub raw_bytes_received: u64,
+    pub compressed_bytes_received: u64,
+}
+
+impl CompressionStats {
+    /// Returns the ratio of raw to compressed bytes sent
+    pub fn send_ratio(&self) -> f64 {
+        if self.compressed_bytes_sent == 0 {
+            1.0
+        } else {
+            self.raw_bytes_sent as f64 / self.compressed_bytes_sent as f64
+        }
+    }
+}
+
+/// What a client that authenticated using a token is permitted to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AuthPermission {
+    /// Anything, including managing the tokens
+    Full,
+    /// Watch the panes, but not send input, resize or spawn them
+    ReadOnly,
+    /// Interact with the existing panes, but not spawn new ones
+    NoSpawn,
+}
+
+impl std::fmt::Display for AuthPermission {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Full => "full",
+            Self::ReadOnly => "read-only",
+            Self::NoSpawn => "no-spawn",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+impl std::str::FromStr for AuthPermission {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "full" => Ok(Self::Full),
+            "read-only" => Ok(Self::ReadOnly),
+            "no-spawn" => Ok(Self::NoSpawn),
+            _ => anyhow::bail!(
+                "invalid permission {:?}; expected full, read-only or no-spawn",
+                s
+            ),
+        }
+    }
+}
+
+/// Tracks the state of a client that is attached to the mux server
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ClientInfo {
+    pub client_id: ClientId,
+    /// Observers may watch, but not send input or resize panes
+    pub read_only: bool,
+    pub connected_at: SystemTime,
+    pub last_input: SystemTime,
+    /// The traffic that the server has exchanged with this client
+    pub compression: CompressionStats,
+    /// The TCP ports that this client is forwarding, keyed by forward id
+    pub port_forwards: BTreeMap<u64, PortForward>,
+    /// The name of the token that the client authenticated with, if any
+    pub auth_token: Option<String>,
+    /// The size that this client would like each pane to have.
+    /// This is only meaningful to the server, so it isn't sent
+    /// to clients that ask for the client list.
+    #[serde(skip)]
+    pub pane_sizes: HashMap<PaneId, PtySize>,
+    /// When this client last sent input to each pane
+    #[serde(skip)]
+    pub pane_input: HashMap<PaneId, SystemTime>,
+}
+
+impl ClientInfo {
+    pub fn new(client_id: ClientId, read_only: bool) -> Self {
+        let now = SystemTime::now();
+        Self {
+            client_id,
+            read_only,
+            connected_at: now,
+            last_input: now,
+            compression: CompressionStats::default(),
+            port_forwards: BTreeMap::new(),
+            auth_token: None,
+            pane_sizes: HashMap::new(),
+            pane_input: HashMap::new(),
+        }
+    }
+}
+
+/// Decide which client should be asked for the contents of its
+/// clipboard when the program in `pane_id` queries it: the client
+/// that most recently sent input to that pane, on the basis that it
+/// belongs to the person operating the program.  Read-only clients
+/// are never asked.  Returns None if no client has sent input to
+/// the pane, in which case the query goes unanswered.
+pub fn clipboard_query_target<'a>(
+    clients: impl Iterator<Item = &'a ClientInfo>,
+    pane_id: PaneId,
+) -> Option<&'a ClientId> {
+    clients
+        .filter(|info| !info.read_only)
+        .filter_map(|info| info.pane_input.get(&pane_id).map(|when| (info, *when)))
+        .max_by_key(|(_, when)| *when)
+        .map(|(info, _)| &info.client_id)
+}
+
+/// Decide on the size of a pane given the sizes requested by each of
+/// the attached clients.  Returns None if no client has expressed a
+/// preference for that pane.
+pub fn arbitrate_pane_size<'a>(
+    clients: impl Iterator<Item = &'a ClientInfo>,
+    pane_id: PaneId,
+    policy: MuxClientSizePolicy,
+) -> Option<PtySize> {
+    let candidates = clients
+        .filter(|info| !info.read_only)
+        .filter_map(|info| info.pane_sizes.get(&pane_id).map(|size| (info, *size)));
+
+    match policy {
+        MuxClientSizePolicy::LatestActive => candidates
+            .max_by_key(|(info, _)| info.last_input)
+            .map(|(_, size)| size),
+        MuxClientSizePolicy::Smallest => {
+            candidates.fold(None, |result: Option<PtySize>, (_, size)| match result {
+                None => Some(size),
+                Some(smallest) => Some(PtySize {
+                    rows: smallest.rows.min(size.rows),
+                    cols: smallest.cols.min(size.cols),
+                    pixel_width: if size.cols < smallest.cols {
+                        size.pixel_width
+                    } else {
+                        smallest.pixel_width
+                    },
+                    pixel_height: if size.rows < smallest.rows {
+                        size.pixel_height
+                    } else {
+                        smallest.pixel_height
+                    },
+                }),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn size(cols: u16, rows: u16) -> PtySize {
+        PtySize {
+            rows,
+            cols,
+            pixel_width: cols * 8,
+            pixel_height: rows * 16,
+        }
+    }
+
+    fn client(id: usize, read_only: bool, pane_size: PtySize, idle: u64) -> ClientInfo {
+        let mut info = ClientInfo::new(
+            ClientId {
+                hostname: "host".to_string(),
+                username: "user".to_string(),
+                pid: 1,
+                id,
+            },
+            read_only,
+        );
+        info.last_input = SystemTime::UNIX_EPOCH + Duration::from_secs(1000 - idle);
+        info.pane_sizes.insert(1, pane_size);
+        info
+    }
+
+    #[test]
+    fn size_arbitration() {
+        let clients = vec![
+            client(0, false, size(80, 40), 10),
+            client(1, false, size(120, 24), 5),
+            client(2, true, size(20, 10), 0),
+        ];
+
+        assert_eq!(
+            arbitrate_pane_size(clients.iter(), 1, MuxClientSizePolicy::Smallest),
+            Some(PtySize {
+                rows: 24,
+                cols: 80,
+                pixel_width: 640,
+                pixel_height: 384,
+            })
+        );
+        assert_eq!(
+            arbitrate_pane_size(clients.iter(), 1, MuxClientSizePolicy::LatestActive),
+            Some(size(120, 24))
+        );
+        assert_eq!(
+            arbitrate_pane_size(clients.iter(), 2, MuxClientSizePolicy::Smallest),
+            None
+        );
+    }
+
+    #[test]
+    fn clipboard_query() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut clients = vec![
+            client(0, false, size(80, 24), 0),
+            client(1, false, size(80, 24), 0),
+            client(2, true, size(80, 24), 0),
+        ];
+        assert_eq!(clipboard_query_target(clients.iter(), 1), None);
+
+        clients[0].pane_input.insert(1, at(10));
+        clients[1].pane_input.insert(1, at(20));
+        clients[1].pane_input.insert(2, at(5));
+        clients[0].pane_input.insert(2, at(30));
+        // A read-only client can't send input, but even if it were
+        // recorded, it must never be asked
+        clients[2].pane_input.insert(1, at(40));
+        clients[2].pane_input.insert(3, at(40));
+
+        assert_eq!(
+            clipboard_query_target(clients.iter(), 1),
+            Some(&clients[1].client_id)
+        );
+        assert_eq!(
+            clipboard_query_target(clients.iter(), 2),
+            Some(&clients[0].client_id)
+        );
+        assert_eq!(clipboard_query_target(clients.iter(), 3), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::client::{ClientId, ClientInfo, CompressionStats};
 use crate::pane::{Pane, PaneId};
 use crate::tab::{Tab, TabId};
 use crate::window::{Window, WindowId};
@@ -16,10 +17,12 @@ use std::thread;
 use thiserror::*;
 
 pub mod activity;
+pub mod client;
 pub mod connui;
 pub mod domain;
 pub mod localpane;
 pub mod pane;
+pub mod portforward;
 pub mod renderable;
 pub mod ssh;
 pub mod tab;
@@ -32,7 +35,45 @@ use crate::activity::Activity;
 #[derive(Clone, Debug)]
 pub enum MuxNotification {
     PaneOutput(PaneId),
+    PaneAdded(PaneId),
+    PaneRemoved(PaneId),
+    /// The application changed the title or the working directory of
+    /// the pane.  A working directory that is inferred from the
+    /// foreground process isn't tracked by this notification.
+    PaneMetadataChanged(PaneId),
     WindowCreated(WindowId),
+    WindowRemoved(WindowId),
+    TabAddedToWindow {
+        tab_id: TabId,
+        window_id: WindowId,
+    },
+    TabRemoved(TabId),
+    /// The split tree of the tab changed: a pane was split or removed,
+    /// or a split was resized
+    TabLayoutChanged(TabId),
+    ActivePaneChanged {
+        tab_id: TabId,
+        pane_id: PaneId,
+    },
+    TabZoomChanged {
+        tab_id: TabId,
+        pane_id: PaneId,
+        zoomed: bool,
+    },
+    Alert {
+        pane_id: PaneId,
+        alert: wezterm_term::Alert,
+    },
+    /// A port forward was cancelled, perhaps by some client
+    /// other than the one that owns it
+    PortForwardRemoved(u64),
+    /// Input was typed into a remote pane while its connection was
+    /// being re-established, and the user should decide whether
+    /// to send it now that the connection is back
+    BufferedInputPending(PaneId),
+    /// The named authentication token was revoked; clients that
+    /// authenticated using it are disconnected
+    AuthTokenRevoked(String),
 }
 
 static SUB_ID: AtomicUsize = AtomicUsize::new(0);
@@ -46,6 +87,7 @@ pub struct Mux {
     domains_by_name: RefCell<HashMap<String, Arc<dyn Domain>>>,
     subscribers: RefCell<HashMap<usize, Box<dyn Fn(MuxNotification) -> bool>>>,
     banner: RefCell<Option<String>>,
+    clients: RefCell<HashMap<ClientId, ClientInfo>>,
 }
 
 /// This function bounces the data over to the main thread to feed to
@@ -57,8 +99,12 @@ fn send_to_mux(pane_id: PaneId, dead: &Arc<AtomicBool>, data: Vec<u8>) {
         async move {
             let mux = Mux::get().unwrap();
             if let Some(pane) = mux.get_pane(pane_id) {
+                let metadata_seqno = pane.get_metadata_seqno();
                 pane.advance_bytes(&data);
                 mux.notify(MuxNotification::PaneOutput(pane_id));
+                if pane.get_metadata_seqno() != metadata_seqno {
+                    mux.notify(MuxNotification::PaneMetadataChanged(pane_id));
+                }
             } else {
                 // Something else removed the pane from
                 // the mux, so we should stop trying to
@@ -238,6 +284,7 @@ impl Mux {
             domains: RefCell::new(domains),
             subscribers: RefCell::new(HashMap::new()),
             banner: RefCell::new(None),
+            clients: RefCell::new(HashMap::new()),
         }
     }
 
@@ -256,6 +303,20 @@ impl Mux {
         subscribers.retain(|_, notify| notify(notification.clone()));
     }
 
+    /// Like `notify`, but the notification is delivered from the main
+    /// thread executor once the current call stack has unwound.
+    /// This is used for changes that are detected while parts of the
+    /// mux are borrowed, so that subscribers are free to inspect the
+    /// mux when they are called.
+    pub fn notify_later(&self, notification: MuxNotification) {
+        promise::spawn::spawn_into_main_thread(async move {
+            if let Some(mux) = Mux::get() {
+                mux.notify(notification);
+            }
+        })
+        .detach();
+    }
+
     pub fn default_domain(&self) -> Arc<dyn Domain> {
         self.default_domain
             .borrow()
@@ -324,6 +385,7 @@ impl Mux {
         let pane_id = pane.pane_id();
         let banner = self.banner.borrow().clone();
         thread::spawn(move || read_from_pane_pty(pane_id, banner, reader));
+        self.notify_later(MuxNotification::PaneAdded(pane_id));
         Ok(())
     }
 
@@ -344,6 +406,10 @@ impl Mux {
         if let Some(pane) = self.panes.borrow_mut().remove(&pane_id) {
             log::debug!("killing pane {}", pane_id);
             pane.kill();
+            for info in self.clients.borrow_mut().values_mut() {
+                info.pane_sizes.remove(&pane_id);
+            }
+            self.notify_later(MuxNotification::PaneRemoved(pane_id));
         }
     }
 
@@ -359,6 +425,7 @@ impl Mux {
         for pane_id in pane_ids {
             self.remove_pane_internal(pane_id);
         }
+        self.notify_later(MuxNotification::TabRemoved(tab_id));
 
         Some(tab)
     }
@@ -370,6 +437,7 @@ impl Mux {
             for tab in window.iter() {
                 self.remove_tab_internal(tab.tab_id());
             }
+            self.notify_later(MuxNotification::WindowRemoved(window_id));
         }
     }
 
@@ -460,6 +528,10 @@ impl Mux {
             .get_window_mut(window_id)
             .ok_or_else(|| anyhow!("add_tab_to_window: no such window_id {}", window_id))?;
         window.push(tab);
+        self.notify_later(MuxNotification::TabAddedToWindow {
+            tab_id: tab.tab_id(),
+            window_id,
+        });
         Ok(())
     }
 
@@ -537,6 +609,118 @@ impl Mux {
     pub fn set_banner(&self, banner: Option<String>) {
         *self.banner.borrow_mut() = banner;
     }
+
+    /// Record that a client has attached.  `auth_token` is the name
+    /// of the token that it authenticated with, if any.
+    pub fn register_client(
+        &self,
+        client_id: ClientId,
+        read_only: bool,
+        auth_token: Option<String>,
+    ) {
+        let mut info = ClientInfo::new(client_id.clone(), read_only);
+        info.auth_token = auth_token;
+        self.clients.borrow_mut().insert(client_id, info);
+    }
+
+    /// Forget about a client that has detached.
+    /// Returns the panes for which that client had requested a size,
+    /// as their arbitrated size may now be different.
+    pub fn unregister_client(&self, client_id: &ClientId) -> Vec<PaneId> {
+        self.clients
+            .borrow_mut()
+            .remove(client_id)
+            .map(|info| info.pane_sizes.keys().cloned().collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    pub fn iter_clients(&self) -> Vec<ClientInfo> {
+        self.clients.borrow().values().cloned().collect()
+    }
+
+    /// Record that the client sent some input to `pane_id`.
+    /// Returns true if that made it the most recently active client.
+    pub fn client_had_input(&self, client_id: &ClientId, pane_id: PaneId) -> bool {
+        let mut clients = self.clients.borrow_mut();
+        let latest = clients.values().map(|info| info.last_input).max();
+        match clients.get_mut(client_id) {
+            Some(info) => {
+                let was_latest = Some(info.last_input) == latest;
+                let now = std::time::SystemTime::now();
+                info.last_input = now;
+                info.pane_input.insert(pane_id, now);
+                !was_latest
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the client that should answer a clipboard query made
+    /// by the program in `pane_id`; see `client::clipboard_query_target`
+    pub fn clipboard_query_target(&self, pane_id: PaneId) -> Option<ClientId> {
+        client::clipboard_query_target(self.clients.borrow().values(), pane_id).cloned()
+    }
+
+    /// Record the size that the client would like the pane to have
+    pub fn record_client_pane_size(
+        &self,
+        client_id: &ClientId,
+        pane_id: PaneId,
+        size: portable_pty::PtySize,
+    ) {
+        if let Some(info) = self.clients.borrow_mut().get_mut(client_id) {
+            info.pane_sizes.insert(pane_id, size);
+        }
+    }
+
+    pub fn record_client_compression_stats(&self, client_id: &ClientId, stats: CompressionStats) {
+        if let Some(info) = self.clients.borrow_mut().get_mut(client_id) {
+            info.compression = stats;
+        }
+    }
+
+    pub fn record_client_port_forward(
+        &self,
+        client_id: &ClientId,
+        forward_id: u64,
+        forward: config::PortForward,
+    ) {
+        if let Some(info) = self.clients.borrow_mut().get_mut(client_id) {
+            info.port_forwards.insert(forward_id, forward);
+        }
+    }
+
+    pub fn forget_client_port_forward(&self, client_id: &ClientId, forward_id: u64) {
+        if let Some(info) = self.clients.borrow_mut().get_mut(client_id) {
+            info.port_forwards.remove(&forward_id);
+        }
+    }
+
+    /// Returns true if some attached client owns the specified forward
+    pub fn has_port_forward(&self, forward_id: u64) -> bool {
+        self.clients
+            .borrow()
+            .values()
+            .any(|info| info.port_forwards.contains_key(&forward_id))
+    }
+
+    pub fn client_pane_ids(&self, client_id: &ClientId) -> Vec<PaneId> {
+        self.clients
+            .borrow()
+            .get(client_id)
+            .map(|info| info.pane_sizes.keys().cloned().collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Compute the size for the pane from the sizes requested
+    /// by the attached clients
+    pub fn arbitrate_pane_size(
+        &self,
+        pane_id: PaneId,
+        policy: config::MuxClientSizePolicy,
+    ) -> Option<portable_pty::PtySize> {
+        client::arbitrate_pane_size(self.clients.borrow().values(), pane_id, policy)
+    }
 }
 
 #[derive(Debug, Error)]
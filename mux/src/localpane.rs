@@ -2,7 +2,7 @@ use crate::domain::DomainId;
 use crate::pane::{Pane, PaneId, Pattern, SearchResult};
 use crate::renderable::*;
 use crate::tmux::{TmuxDomain, TmuxDomainState};
-use crate::{Domain, Mux};
+use crate::{Domain, Mux, MuxNotification};
 use anyhow::Error;
 use async_trait::async_trait;
 use config::keyassignment::ScrollbackEraseMode;
@@ -12,7 +12,7 @@ use std::cell::{RefCell, RefMut};
 use std::ops::Range;
 use std::sync::Arc;
 use termwiz::escape::DeviceControlMode;
-use termwiz::surface::Line;
+use termwiz::surface::{Line, SequenceNo};
 use url::Url;
 use wezterm_term::color::ColorPalette;
 use wezterm_term::{
@@ -187,6 +187,10 @@ impl Pane for LocalPane {
             .or_else(|| self.divine_current_working_dir())
     }
 
+    fn get_metadata_seqno(&self) -> SequenceNo {
+        self.terminal.borrow().get_metadata_seqno()
+    }
+
     fn get_semantic_zones(&self) -> anyhow::Result<Vec<SemanticZone>> {
         let term = self.terminal.borrow();
         term.get_semantic_zones()
@@ -299,6 +303,24 @@ impl Pane for LocalPane {
     }
 }
 
+struct LocalPaneNotifHandler {
+    pane_id: PaneId,
+}
+
+impl wezterm_term::AlertHandler for LocalPaneNotifHandler {
+    fn alert(&mut self, alert: wezterm_term::Alert) {
+        let pane_id = self.pane_id;
+        // We're called while the terminal is borrowed by advance_bytes,
+        // so defer the notification to avoid re-entrancy in subscribers
+        promise::spawn::spawn_into_main_thread(async move {
+            if let Some(mux) = Mux::get() {
+                mux.notify(MuxNotification::Alert { pane_id, alert });
+            }
+        })
+        .detach();
+    }
+}
+
 struct LocalPaneDCSHandler {
     pane_id: PaneId,
     tmux_domain: Option<Arc<TmuxDomainState>>,
@@ -379,6 +401,7 @@ impl LocalPane {
             pane_id,
             tmux_domain: None,
         }));
+        terminal.set_alert_handler(Box::new(LocalPaneNotifHandler { pane_id }));
         Self {
             pane_id,
             terminal: RefCell::new(terminal),
@@ -10,7 +10,7 @@ use serde::{Deserialize, Serialize};
 use std::cell::RefMut;
 use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use termwiz::surface::Line;
+use termwiz::surface::{Line, SequenceNo};
 use url::Url;
 use wezterm_term::color::ColorPalette;
 use wezterm_term::{Clipboard, KeyCode, KeyModifiers, MouseEvent, SemanticZone, StableRowIndex};
@@ -146,6 +146,12 @@ pub trait Pane: Downcast {
 
     fn get_current_working_dir(&self) -> Option<Url>;
 
+    /// Returns a sequence number that changes whenever the application
+    /// changes the title or working directory of the pane
+    fn get_metadata_seqno(&self) -> SequenceNo {
+        0
+    }
+
     fn trickle_paste(&self, text: String) -> anyhow::Result<()> {
         if text.len() <= PASTE_CHUNK_SIZE {
             // Send it all now
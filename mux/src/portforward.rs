@@ -0,0 +1,516 @@
+//! Helpers for relaying TCP connections over a mux connection.
+//! Both the client and the server side of a port forward use these
+//! to shuttle data between a local socket and the peer.
+use anyhow::Context;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+
+const RELAY_BUFFER_SIZE: usize = 32 * 1024;
+/// The most data that may be sent to the peer for a stream before
+/// the peer acknowledges that it has written it to its socket.
+/// This bounds the data queued on either side of the connection
+/// when one end reads more slowly than the other writes.
+pub const RELAY_WINDOW_SIZE: usize = 256 * 1024;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Allocate an identifier for a port forward
+pub fn alloc_port_forward_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Allocate an identifier for a forwarded connection.  The side that
+/// accepted the connection picks its id; the client uses odd numbers
+/// and the server even numbers so that they never collide.
+pub fn alloc_stream_id(is_client: bool) -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed) * 2 + if is_client { 1 } else { 0 }
+}
+
+#[derive(Default)]
+struct WindowState {
+    /// Bytes sent to the peer that it has yet to acknowledge
+    in_flight: usize,
+    closed: bool,
+}
+
+/// Limits the data that has been sent to the peer but not yet
+/// written to the socket at the other end
+#[derive(Default)]
+struct FlowWindow {
+    state: Mutex<WindowState>,
+    cond: Condvar,
+}
+
+impl FlowWindow {
+    /// Wait until there is room in the window, then account for
+    /// sending `len` more bytes.  Returns false if the stream was
+    /// closed while waiting.
+    fn acquire(&self, len: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.in_flight >= RELAY_WINDOW_SIZE && !state.closed {
+            state = self.cond.wait(state).unwrap();
+        }
+        if state.closed {
+            return false;
+        }
+        state.in_flight += len;
+        true
+    }
+
+    fn release(&self, len: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(len);
+        self.cond.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cond.notify_all();
+    }
+}
+
+struct RelayEntry {
+    stream: Option<TcpStream>,
+    tx: Option<Sender<Vec<u8>>>,
+    rx: Option<Receiver<Vec<u8>>>,
+    window: Arc<FlowWindow>,
+    read_done: bool,
+}
+
+impl RelayEntry {
+    fn new() -> Self {
+        let (tx, rx) = channel();
+        Self {
+            stream: None,
+            tx: Some(tx),
+            rx: Some(rx),
+            window: Arc::new(FlowWindow::default()),
+            read_done: false,
+        }
+    }
+
+    fn close(self) {
+        self.window.close();
+        if let Some(stream) = self.stream {
+            stream.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+/// Tracks the TCP connections that are being relayed to the peer.
+/// Each connection has a reader thread, which passes the data that
+/// it reads to a callback, and a writer thread which writes the data
+/// that the peer sent for it.  The two directions are closed
+/// independently; a stream is forgotten once both are done.
+///
+/// Each side acknowledges the data that it has written to its socket
+/// (see `add` and `ack`), and the reader thread stops reading while
+/// `RELAY_WINDOW_SIZE` bytes are unacknowledged, so that a slow
+/// consumer pushes back on the producer rather than causing the
+/// data to be queued without limit.
+#[derive(Clone, Default)]
+pub struct TcpRelay {
+    streams: Arc<Mutex<HashMap<u64, RelayEntry>>>,
+}
+
+impl TcpRelay {
+    /// Prepare to relay `stream_id` before its socket is connected.
+    /// Data that the peer sends in the meantime is queued until
+    /// the socket is passed to `add`.
+    pub fn reserve(&self, stream_id: u64) {
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(stream_id, RelayEntry::new());
+    }
+
+    /// Start relaying `stream` as `stream_id`.
+    /// `on_data` is called from the reader thread with each chunk of
+    /// data read from the socket; if it fails, reading stops.
+    /// `on_eof` is called once there is no more data to read.
+    /// `on_written` is called from the writer thread with the size of
+    /// each chunk that the peer sent once it has been written to the
+    /// socket; it should pass that on to the peer's `ack`.
+    pub fn add<D, E, W>(
+        &self,
+        stream_id: u64,
+        stream: TcpStream,
+        mut on_data: D,
+        on_eof: E,
+        mut on_written: W,
+    ) -> anyhow::Result<()>
+    where
+        D: FnMut(Vec<u8>) -> anyhow::Result<()> + Send + 'static,
+        E: FnOnce() + Send + 'static,
+        W: FnMut(usize) + Send + 'static,
+    {
+        stream.set_nodelay(true).ok();
+        let mut reader = stream.try_clone()?;
+        let mut writer = stream.try_clone()?;
+
+        let (rx, window) = {
+            let mut streams = self.streams.lock().unwrap();
+            let entry = streams.entry(stream_id).or_insert_with(RelayEntry::new);
+            entry.stream.replace(stream);
+            let rx = entry
+                .rx
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("stream {} is already relayed", stream_id))?;
+            (rx, Arc::clone(&entry.window))
+        };
+
+        std::thread::spawn(move || {
+            let mut failed = false;
+            for data in rx {
+                if !failed {
+                    if let Err(err) = writer.write_all(&data) {
+                        log::debug!("forwarded stream {}: write failed: {}", stream_id, err);
+                        writer.shutdown(Shutdown::Both).ok();
+                        failed = true;
+                    }
+                }
+                // Keep acknowledging after a failure, discarding the
+                // data, so that the peer isn't left waiting for room
+                // in the window
+                on_written(data.len());
+            }
+            if !failed {
+                writer.shutdown(Shutdown::Write).ok();
+            }
+        });
+
+        let streams = Arc::clone(&self.streams);
+        std::thread::spawn(move || {
+            let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        if !window.acquire(len) {
+                            break;
+                        }
+                        if let Err(err) = on_data(buf[..len].to_vec()) {
+                            log::debug!("forwarded stream {}: {:#}", stream_id, err);
+                            reader.shutdown(Shutdown::Both).ok();
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        log::debug!("forwarded stream {}: read failed: {}", stream_id, err);
+                        break;
+                    }
+                }
+            }
+            on_eof();
+
+            let mut streams = streams.lock().unwrap();
+            let finished = match streams.get_mut(&stream_id) {
+                Some(entry) => {
+                    entry.read_done = true;
+                    entry.tx.is_none()
+                }
+                None => false,
+            };
+            if finished {
+                streams.remove(&stream_id);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Queue data that the peer sent for `stream_id`
+    pub fn write(&self, stream_id: u64, data: Vec<u8>) {
+        match self
+            .streams
+            .lock()
+            .unwrap()
+            .get(&stream_id)
+            .and_then(|entry| entry.tx.as_ref())
+        {
+            Some(tx) => {
+                tx.send(data).ok();
+            }
+            None => log::debug!("ignoring data for closed stream {}", stream_id),
+        }
+    }
+
+    /// The peer has written `len` bytes of the data that we sent for
+    /// `stream_id` to its socket, making room for more to be sent
+    pub fn ack(&self, stream_id: u64, len: usize) {
+        if let Some(entry) = self.streams.lock().unwrap().get(&stream_id) {
+            entry.window.release(len);
+        }
+    }
+
+    /// The peer has no more data to send for `stream_id`; once the
+    /// queued data has been written, the socket is shut down for writing.
+    pub fn finish_writing(&self, stream_id: u64) {
+        let mut streams = self.streams.lock().unwrap();
+        let finished = match streams.get_mut(&stream_id) {
+            Some(entry) => {
+                entry.tx.take();
+                entry.read_done
+            }
+            None => false,
+        };
+        if finished {
+            streams.remove(&stream_id);
+        }
+    }
+
+    /// Abruptly close and forget `stream_id`
+    pub fn abort(&self, stream_id: u64) {
+        if let Some(entry) = self.streams.lock().unwrap().remove(&stream_id) {
+            entry.close();
+        }
+    }
+
+    /// Returns the number of connections being relayed
+    pub fn num_streams(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    /// Abruptly close all of the connections
+    pub fn close_all(&self) {
+        for (_, entry) in self.streams.lock().unwrap().drain() {
+            entry.close();
+        }
+    }
+}
+
+/// Accepts connections on a listening socket from a background thread
+/// until it is dropped
+pub struct TcpForwardListener {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TcpForwardListener {
+    pub fn spawn<F>(address: &str, mut on_accept: F) -> anyhow::Result<Self>
+    where
+        F: FnMut(TcpStream) + Send + 'static,
+    {
+        let listener =
+            TcpListener::bind(address).with_context(|| format!("listening on {}", address))?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        std::thread::spawn({
+            let stopped = Arc::clone(&stopped);
+            move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    match stream {
+                        Ok(stream) => on_accept(stream),
+                        Err(err) => log::error!("accepting on {}: {}", addr, err),
+                    }
+                }
+            }
+        });
+
+        Ok(Self { addr, stopped })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for TcpForwardListener {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Wake up the accept loop so that it notices that it is done
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => std::net::Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        TcpStream::connect(addr).ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let a = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (b, _) = listener.accept().unwrap();
+        (a, b)
+    }
+
+    fn recv(rx: &Receiver<Option<Vec<u8>>>) -> Option<Vec<u8>> {
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn relay_half_close() {
+        let relay = TcpRelay::default();
+        let (mut app, relayed) = socket_pair();
+        let (tx, rx) = channel();
+        let eof_tx = tx.clone();
+        relay
+            .add(
+                1,
+                relayed,
+                move |data| Ok(tx.send(Some(data))?),
+                move || {
+                    eof_tx.send(None).ok();
+                },
+                |_| {},
+            )
+            .unwrap();
+
+        app.write_all(b"hello").unwrap();
+        assert_eq!(recv(&rx), Some(b"hello".to_vec()));
+
+        // The application has finished sending, but can still
+        // receive what the peer sends
+        app.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(recv(&rx), None);
+        assert_eq!(relay.num_streams(), 1);
+
+        relay.write(1, b"world".to_vec());
+        relay.finish_writing(1);
+        let mut response = vec![];
+        app.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"world");
+    }
+
+    #[test]
+    fn relay_reserved() {
+        let relay = TcpRelay::default();
+        relay.reserve(2);
+        relay.write(2, b"queued".to_vec());
+        relay.finish_writing(2);
+
+        let (mut app, relayed) = socket_pair();
+        let (tx, rx) = channel();
+        relay
+            .add(
+                2,
+                relayed,
+                |_| Ok(()),
+                || {},
+                move |len| {
+                    tx.send(len).ok();
+                },
+            )
+            .unwrap();
+        let mut data = vec![];
+        app.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"queued");
+        // The data is acknowledged once it has been written
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 6);
+    }
+
+    #[test]
+    fn relay_window() {
+        let relay = TcpRelay::default();
+        let (mut app, relayed) = socket_pair();
+        let (tx, rx) = channel();
+        relay
+            .add(
+                3,
+                relayed,
+                move |data| Ok(tx.send(data.len())?),
+                || {},
+                |_| {},
+            )
+            .unwrap();
+
+        let total = RELAY_WINDOW_SIZE * 2;
+        std::thread::spawn(move || {
+            app.write_all(&vec![b'x'; total]).ok();
+            // Keep the socket open until the data has been relayed
+            std::thread::sleep(Duration::from_secs(10));
+        });
+
+        // Without any acknowledgement from the peer, reading stops
+        // once the window is full
+        let mut received = 0;
+        while let Ok(len) = rx.recv_timeout(Duration::from_millis(500)) {
+            received += len;
+        }
+        assert!(received >= RELAY_WINDOW_SIZE, "received {}", received);
+        assert!(
+            received < RELAY_WINDOW_SIZE + RELAY_BUFFER_SIZE,
+            "received {}",
+            received
+        );
+
+        // Acknowledging the data lets the rest through
+        relay.ack(3, received);
+        while received < total {
+            let len = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            received += len;
+            relay.ack(3, len);
+        }
+        assert_eq!(received, total);
+        relay.abort(3);
+    }
+
+    #[test]
+    fn abort_wakes_blocked_reader() {
+        let relay = TcpRelay::default();
+        let (mut app, relayed) = socket_pair();
+        let (tx, rx) = channel();
+        let eof_tx = tx.clone();
+        relay
+            .add(
+                4,
+                relayed,
+                move |data| Ok(tx.send(Some(data.len()))?),
+                move || {
+                    eof_tx.send(None).ok();
+                },
+                |_| {},
+            )
+            .unwrap();
+
+        std::thread::spawn(move || {
+            app.write_all(&vec![b'x'; RELAY_WINDOW_SIZE * 2]).ok();
+        });
+        let mut received = 0;
+        while received < RELAY_WINDOW_SIZE {
+            received += rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        }
+
+        relay.abort(4);
+        // Any data that was already read is passed on, then reading stops
+        while rx.recv_timeout(Duration::from_secs(5)).unwrap().is_some() {}
+        assert_eq!(relay.num_streams(), 0);
+    }
+
+    #[test]
+    fn listener() {
+        let (tx, rx) = channel();
+        let listener = TcpForwardListener::spawn("127.0.0.1:0", move |stream| {
+            tx.send(stream.peer_addr().unwrap()).ok();
+        })
+        .unwrap();
+        let stream = TcpStream::connect(listener.local_addr()).unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            stream.local_addr().unwrap()
+        );
+
+        let addr = listener.local_addr();
+        drop(listener);
+        // Give the accept loop a moment to notice and close the socket
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
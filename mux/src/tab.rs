@@ -1,6 +1,6 @@
 use crate::domain::DomainId;
 use crate::pane::*;
-use crate::{Mux, WindowId};
+use crate::{Mux, MuxNotification, WindowId};
 use bintree::PathBranch;
 use config::keyassignment::PaneDirection;
 use portable_pty::PtySize;
@@ -513,15 +513,37 @@ impl Tab {
     }
 
     pub fn set_zoomed(&self, zoomed: bool) {
+        if self.apply_zoom(zoomed) {
+            self.notify_zoom_changed();
+        }
+    }
+
+    pub fn toggle_zoom(&self) {
+        let zoomed = self.zoomed.borrow().is_none();
+        if self.apply_zoom(zoomed) {
+            self.notify_zoom_changed();
+        }
+    }
+
+    fn notify_zoom_changed(&self) {
+        if let Some(pane) = self.get_active_pane() {
+            self.notify(MuxNotification::TabZoomChanged {
+                tab_id: self.id,
+                pane_id: pane.pane_id(),
+                zoomed: self.zoomed.borrow().is_some(),
+            });
+        }
+    }
+
+    /// Adjusts the zoom state without notifying the mux.
+    /// Returns true if the zoom state changed.
+    fn apply_zoom(&self, zoomed: bool) -> bool {
         if self.zoomed.borrow().is_some() == zoomed {
             // Current zoom state matches intended zoom state,
             // so we have nothing to do.
-            return;
+            return false;
         }
-        self.toggle_zoom();
-    }
 
-    pub fn toggle_zoom(&self) {
         let size = *self.size.borrow();
         if self.zoomed.borrow_mut().take().is_some() {
             // We were zoomed, but now we are not.
@@ -541,6 +563,17 @@ impl Tab {
                 self.zoomed.borrow_mut().replace(pane);
             }
         }
+        true
+    }
+
+    /// Tell the mux about a change to this tab.
+    /// The notification is deferred so that it is safe to call this
+    /// while the tree is borrowed.  When there is no mux (such as in
+    /// the unit tests) this does nothing.
+    fn notify(&self, notification: MuxNotification) {
+        if let Some(mux) = Mux::get() {
+            mux.notify_later(notification);
+        }
     }
 
     /// Walks the pane tree to produce the topologically ordered flattened
@@ -698,7 +731,7 @@ impl Tab {
         // Un-zoom first, so that the layout can be reasoned about
         // more easily.
         let was_zoomed = self.zoomed.borrow().is_some();
-        self.set_zoomed(false);
+        self.apply_zoom(false);
 
         {
             let mut root = self.pane.borrow_mut();
@@ -737,7 +770,7 @@ impl Tab {
         }
 
         // And finally restore the zoom, if appropriate
-        self.set_zoomed(was_zoomed);
+        self.apply_zoom(was_zoomed);
     }
 
     fn apply_pane_size(&self, pane_size: PtySize, cursor: &mut Cursor) {
@@ -859,6 +892,7 @@ impl Tab {
         // Now cursor is looking at the split
         self.adjust_node_at_cursor(&mut cursor, delta);
         self.cascade_size_from_cursor(root, cursor);
+        self.notify(MuxNotification::TabLayoutChanged(self.id));
     }
 
     fn adjust_node_at_cursor(&self, cursor: &mut Cursor, delta: isize) {
@@ -991,6 +1025,7 @@ impl Tab {
                         if node.direction == split_direction {
                             self.adjust_node_at_cursor(&mut c, delta);
                             self.cascade_size_from_cursor(root, c);
+                            self.notify(MuxNotification::TabLayoutChanged(self.id));
                             return;
                         }
                     }
@@ -1187,6 +1222,7 @@ impl Tab {
         }
 
         if !dead_panes.is_empty() {
+            self.notify(MuxNotification::TabLayoutChanged(self.id));
             promise::spawn::spawn_into_main_thread(async move {
                 let mux = Mux::get().unwrap();
                 for pane_id in dead_panes.into_iter() {
@@ -1233,12 +1269,20 @@ impl Tab {
             .iter()
             .find(|p| p.pane.pane_id() == pane.pane_id())
         {
-            *self.active.borrow_mut() = item.index;
+            self.set_active_idx(item.index);
         }
     }
 
     pub fn set_active_idx(&self, pane_index: usize) {
-        *self.active.borrow_mut() = pane_index;
+        let prior = self.active.replace(pane_index);
+        if prior != pane_index {
+            if let Some(pane) = self.get_active_pane() {
+                self.notify(MuxNotification::ActivePaneChanged {
+                    tab_id: self.id,
+                    pane_id: pane.pane_id(),
+                });
+            }
+        }
     }
 
     /// Assigns the root pane.
@@ -1377,6 +1421,7 @@ impl Tab {
 
             *self.active.borrow_mut() = pane_index + 1;
         }
+        self.notify(MuxNotification::TabLayoutChanged(self.id));
 
         log::debug!("split info after split: {:#?}", self.iter_splits());
         log::debug!("pane info after split: {:#?}", self.iter_panes());
@@ -28,7 +28,7 @@ use termwiz::caps::{Capabilities, ColorLevel, ProbeHints};
 use termwiz::input::{InputEvent, KeyEvent, MouseEvent as TermWizMouseEvent};
 use termwiz::render::terminfo::TerminfoRenderer;
 use termwiz::surface::Change;
-use termwiz::surface::Line;
+use termwiz::surface::{Line, SequenceNo};
 use termwiz::terminal::{ScreenSize, TerminalWaker};
 use termwiz::Context;
 use url::Url;
@@ -249,6 +249,10 @@ impl Pane for TermWizTerminalPane {
         self.terminal.borrow().get_current_dir().cloned()
     }
 
+    fn get_metadata_seqno(&self) -> SequenceNo {
+        self.terminal.borrow().get_metadata_seqno()
+    }
+
     fn erase_scrollback(&self, erase_mode: ScrollbackEraseMode) {
         match erase_mode {
             ScrollbackEraseMode::ScrollbackOnly => {
@@ -29,6 +29,7 @@ pub struct ColorPalette {
     pub selection_bg: RgbColor,
     pub scrollbar_thumb: RgbColor,
     pub split: RgbColor,
+    pub visual_bell: RgbColor,
 }
 
 /// Adjust the color to make it appear disabled.
@@ -90,6 +91,7 @@ impl ColorPalette {
             selection_bg: grey_out(self.selection_bg),
             scrollbar_thumb: grey_out(self.scrollbar_thumb),
             split: grey_out(self.split),
+            visual_bell: grey_out(self.visual_bell),
         }
     }
 }
@@ -247,6 +249,7 @@ impl ColorPalette {
 
         let scrollbar_thumb = RgbColor::new(0x22, 0x22, 0x22);
         let split = RgbColor::new(0x44, 0x44, 0x44);
+        let visual_bell = foreground;
 
         ColorPalette {
             colors: Palette256(colors),
@@ -259,6 +262,7 @@ impl ColorPalette {
             selection_bg,
             scrollbar_thumb,
             split,
+            visual_bell,
         }
     }
 }
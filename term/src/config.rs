@@ -1,4 +1,5 @@
 use crate::color::ColorPalette;
+use termwiz::cell::UnicodeVersion;
 use termwiz::hyperlink::Rule as HyperlinkRule;
 
 /// TerminalConfiguration allows for the embedding application to pass configuration
@@ -69,7 +70,20 @@ pub trait TerminalConfiguration: std::fmt::Debug {
         cfg!(windows)
     }
 
-    fn alternate_buffer_wheel_scroll_speed(&self) -> u8 {
-        3
+    /// Return true if the terminal should start in implicit bidi mode,
+    /// applying the Unicode Bidirectional Algorithm to the lines that
+    /// it presents.  Applications can switch between implicit and
+    /// explicit mode at runtime using BDSM (`CSI 8 h` and `CSI 8 l`).
+    fn bidi_enabled(&self) -> bool {
+        false
+    }
+
+    /// Returns the options that control the width of graphemes
+    /// printed to the terminal.  These need to agree with the
+    /// `wcwidth` implementation used by the applications running
+    /// in the terminal, otherwise the cursor position will get
+    /// out of sync when they print wide or ambiguous characters.
+    fn unicode_version(&self) -> UnicodeVersion {
+        UnicodeVersion::default()
     }
 }
@@ -37,6 +37,12 @@ pub struct MouseEvent {
     pub kind: MouseEventKind,
     pub x: usize,
     pub y: VisibleRowIndex,
+    /// The position of the mouse within the cell at `x`, in pixels.
+    /// This may be negative if the gui rounded `x` up to the next cell.
+    /// Used to compute the position reported by the SGR-Pixels encoding.
+    pub x_pixel_offset: isize,
+    /// The position of the mouse within the cell at `y`, in pixels
+    pub y_pixel_offset: isize,
     pub button: MouseButton,
     pub modifiers: KeyModifiers,
 }
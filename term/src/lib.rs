@@ -44,6 +44,8 @@ pub use crate::terminal::*;
 pub mod terminalstate;
 pub use crate::terminalstate::*;
 
+mod webp;
+
 /// Represents the index into screen.lines.  Index 0 is the top of
 /// the scrollback (if any).  The index of the top of the visible screen
 /// depends on the terminal dimensions and the scrollback size.
@@ -35,6 +35,35 @@ pub trait DeviceControlHandler {
     fn handle_device_control(&mut self, _control: termwiz::escape::DeviceControlMode);
 }
 
+/// Represents an event that the application running in the terminal
+/// would like to bring to the attention of the user
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    /// The application emitted BEL
+    Bell,
+    /// The application requested a system notification,
+    /// via OSC 9 or OSC 777
+    ToastNotification {
+        /// The title text for the notification
+        title: Option<String>,
+        /// The message body
+        body: String,
+    },
+    /// The window title was changed via OSC 0 or OSC 2
+    WindowTitleChanged(String),
+    /// The icon title was changed via OSC 0 or OSC 1
+    IconTitleChanged(Option<String>),
+    /// The application wants to read the clipboard via OSC 52.
+    /// The handler is responsible for sending the response to
+    /// the application, if it decides to allow it.
+    QueryClipboard(ClipboardSelection),
+}
+
+pub trait AlertHandler {
+    fn alert(&mut self, alert: Alert);
+}
+
 /// Represents an instance of a terminal emulator.
 pub struct Terminal {
     /// The terminal model/state
@@ -24,10 +24,102 @@ use termwiz::escape::{
     Action, ControlCode, DeviceControlMode, Esc, EscCode, OneBased, OperatingSystemCommand, Sixel,
     SixelData, CSI,
 };
-use termwiz::image::{ImageCell, ImageData, TextureCoordinate};
-use termwiz::surface::{CursorShape, CursorVisibility};
+use termwiz::image::{ImageCell, ImageData, ImageDataType, TextureCoordinate};
+use termwiz::surface::{CursorShape, CursorVisibility, SequenceNo};
 use url::Url;
 
+/// Animations are decoded into a buffer per frame, which can take a
+/// great deal more memory than the compressed image; animations that
+/// would exceed either of these limits are shown as a still image.
+const MAX_ANIMATION_FRAMES: usize = 2048;
+const MAX_ANIMATION_BYTES: usize = 128 * 1024 * 1024;
+
+/// Decodes the frames of an animated GIF, APNG or WebP image.
+/// Returns None if the data isn't an animation with more than
+/// a single frame, or if it is too large to decode, in which case
+/// the encoded data is used as-is.
+fn decode_animation(data: &[u8]) -> Option<ImageDataType> {
+    use image::{AnimationDecoder, ImageDecoder};
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    fn frame_bytes(dimensions: (u32, u32)) -> usize {
+        dimensions.0 as usize * dimensions.1 as usize * 4
+    }
+
+    let frames = match image::guess_format(data).ok()? {
+        image::ImageFormat::Gif => {
+            let decoder = image::gif::GifDecoder::new(Cursor::new(data)).ok()?;
+            if frame_bytes(decoder.dimensions()) > MAX_ANIMATION_BYTES {
+                return None;
+            }
+            decoder.into_frames()
+        }
+        image::ImageFormat::Png => {
+            let decoder = image::png::PngDecoder::new(Cursor::new(data)).ok()?;
+            if !decoder.is_apng() || frame_bytes(decoder.dimensions()) > MAX_ANIMATION_BYTES {
+                return None;
+            }
+            decoder.apng().into_frames()
+        }
+        image::ImageFormat::WebP => {
+            let decoder = crate::webp::AnimatedWebPDecoder::new(data).ok()?;
+            if frame_bytes(decoder.dimensions()) > MAX_ANIMATION_BYTES {
+                return None;
+            }
+            decoder.into_frames()
+        }
+        _ => return None,
+    };
+
+    let mut dimensions = None;
+    let mut durations = vec![];
+    let mut decoded = vec![];
+    let mut total_bytes = 0;
+    for frame in frames {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => {
+                error!("Unable to decode animated image frames: {}", err);
+                return None;
+            }
+        };
+        total_bytes += frame.buffer().len();
+        if decoded.len() >= MAX_ANIMATION_FRAMES || total_bytes > MAX_ANIMATION_BYTES {
+            error!(
+                "Animated image has more than {} frames or {} bytes of \
+                 pixels; showing it as a still image",
+                MAX_ANIMATION_FRAMES, MAX_ANIMATION_BYTES
+            );
+            return None;
+        }
+        dimensions.get_or_insert(frame.buffer().dimensions());
+
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let duration = Duration::from_secs_f64(numer as f64 / denom.max(1) as f64 / 1000.);
+        // Browsers show frames with very short delays for 100ms,
+        // and animations are authored with that expectation
+        durations.push(if duration <= Duration::from_millis(10) {
+            Duration::from_millis(100)
+        } else {
+            duration
+        });
+        decoded.push(frame.into_buffer().into_raw());
+    }
+    if decoded.len() < 2 {
+        return None;
+    }
+    let (width, height) = dimensions?;
+
+    Some(ImageDataType::AnimRgba8 {
+        width,
+        height,
+        durations,
+        frames: decoded,
+        data: data.to_vec(),
+    })
+}
+
 struct TabStop {
     tabs: Vec<bool>,
     tab_width: usize,
@@ -95,6 +187,21 @@ impl TabStop {
     }
 }
 
+/// Selects how mouse reports are encoded when sent to the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MouseEncoding {
+    /// The original encoding, limited to 223 rows and columns
+    X10,
+    /// Mode 1005
+    Utf8,
+    /// Mode 1006
+    SGR,
+    /// Mode 1015
+    Urxvt,
+    /// Mode 1016; the same as SGR, but in pixel coordinates
+    SgrPixels,
+}
+
 #[derive(Debug, Clone)]
 struct SavedCursor {
     position: CursorPosition,
@@ -223,6 +330,10 @@ pub struct TerminalState {
     /// If true, writing a character inserts a new cell
     insert: bool,
 
+    /// BDSM: if true, the terminal is in implicit bidi mode and
+    /// lines are marked for reordering by the renderer.
+    bidi_enabled: bool,
+
     /// https://vt100.net/docs/vt510-rm/DECAWM.html
     dec_auto_wrap: bool,
 
@@ -245,6 +356,7 @@ pub struct TerminalState {
     /// keys.  The code in key_down() is responsible for interpreting this.
     application_cursor_keys: bool,
 
+    /// DECANM; when reset, the terminal is in VT52 compatibility mode
     dec_ansi_mode: bool,
 
     /// https://vt100.net/docs/vt3xx-gp/chapter14.html has a discussion
@@ -266,8 +378,11 @@ pub struct TerminalState {
     /// Movement events enabled
     any_event_mouse: bool,
     focus_tracking: bool,
-    /// SGR style mouse tracking and reporting is enabled
-    sgr_mouse: bool,
+    /// How mouse reports are to be encoded
+    mouse_encoding: MouseEncoding,
+    /// When set, wheel events in the alternate screen are sent as
+    /// cursor keys if mouse reporting is not enabled
+    alternate_scroll: bool,
     mouse_tracking: bool,
     /// Button events enabled
     button_event_mouse: bool,
@@ -289,8 +404,12 @@ pub struct TerminalState {
 
     clipboard: Option<Arc<dyn Clipboard>>,
     device_control_handler: Option<Box<dyn DeviceControlHandler>>,
+    alert_handler: Option<Box<dyn AlertHandler>>,
 
     current_dir: Option<Url>,
+    /// Incremented whenever the application changes the title,
+    /// icon title or working directory
+    metadata_seqno: SequenceNo,
 
     term_program: String,
     term_version: String,
@@ -394,6 +513,7 @@ impl TerminalState {
         let screen = ScreenOrAlt::new(size.physical_rows, size.physical_cols, &config);
 
         let color_map = default_color_map();
+        let bidi_enabled = config.bidi_enabled();
 
         TerminalState {
             config,
@@ -410,15 +530,17 @@ impl TerminalState {
             reverse_wraparound_mode: false,
             dec_origin_mode: false,
             insert: false,
+            bidi_enabled,
             application_cursor_keys: false,
-            dec_ansi_mode: false,
+            dec_ansi_mode: true,
             sixel_scrolling: true,
             use_private_color_registers_for_each_graphic: false,
             color_map,
             application_keypad: false,
             bracketed_paste: false,
             focus_tracking: false,
-            sgr_mouse: false,
+            mouse_encoding: MouseEncoding::X10,
+            alternate_scroll: true,
             any_event_mouse: false,
             button_event_mouse: false,
             mouse_tracking: false,
@@ -433,7 +555,9 @@ impl TerminalState {
             pixel_width: size.pixel_width,
             clipboard: None,
             device_control_handler: None,
+            alert_handler: None,
             current_dir: None,
+            metadata_seqno: 0,
             term_program: term_program.to_string(),
             term_version: term_version.to_string(),
             writer: Box::new(std::io::BufWriter::new(writer)),
@@ -449,6 +573,16 @@ impl TerminalState {
         self.device_control_handler.replace(handler);
     }
 
+    pub fn set_alert_handler(&mut self, handler: Box<dyn AlertHandler>) {
+        self.alert_handler.replace(handler);
+    }
+
+    fn alert(&mut self, alert: Alert) {
+        if let Some(handler) = self.alert_handler.as_mut() {
+            handler.alert(alert);
+        }
+    }
+
     /// Returns the title text associated with the terminal session.
     /// The title can be changed by the application using a number
     /// of escape sequences:
@@ -477,6 +611,13 @@ impl TerminalState {
         self.current_dir.as_ref()
     }
 
+    /// Returns a sequence number that changes whenever the title
+    /// or working directory is changed by the application, which
+    /// allows detecting such changes without comparing the values.
+    pub fn get_metadata_seqno(&self) -> SequenceNo {
+        self.metadata_seqno
+    }
+
     /// Returns a copy of the palette.
     /// By default we don't keep a copy in the terminal state,
     /// preferring to take the config values from the users
@@ -534,6 +675,25 @@ impl TerminalState {
         (pos + 1 + 32) as char
     }
 
+    /// The UTF-8 mouse encoding (mode 1005) extends the legacy encoding
+    /// by encoding the coordinate as a character, allowing positions
+    /// up to 2015 to be reported.
+    fn utf8_mouse_coord(position: i64) -> char {
+        let pos = if position < 0 || position > 2047 - 32 - 1 {
+            0
+        } else {
+            position as u32
+        };
+
+        std::char::from_u32(pos + 1 + 32).unwrap_or(' ')
+    }
+
+    fn reset_mouse_encoding(&mut self, encoding: MouseEncoding) {
+        if self.mouse_encoding == encoding {
+            self.mouse_encoding = MouseEncoding::X10;
+        }
+    }
+
     fn mouse_report_button_number(&self, event: &MouseEvent) -> i8 {
         let button = match event.button {
             MouseButton::None => self.current_mouse_button,
@@ -561,40 +721,95 @@ impl TerminalState {
         code
     }
 
+    /// Encode and send a mouse report.
+    /// `button` is the button number computed by `mouse_report_button_number`,
+    /// adjusted for motion as appropriate.
+    /// The encodings other than SGR cannot convey which button was
+    /// released, so they report button 3 for a release.
+    fn write_mouse_report(
+        &mut self,
+        button: i8,
+        event: &MouseEvent,
+        release: bool,
+    ) -> Result<(), Error> {
+        let final_byte = if release { 'm' } else { 'M' };
+        let legacy_button = if release { 3 } else { button };
+        match self.mouse_encoding {
+            MouseEncoding::SGR => {
+                write!(
+                    self.writer,
+                    "\x1b[<{};{};{}{}",
+                    button,
+                    event.x + 1,
+                    event.y + 1,
+                    final_byte
+                )?;
+            }
+            MouseEncoding::SgrPixels => {
+                let cell_pixel_width = (self.pixel_width / self.screen().physical_cols) as isize;
+                let cell_pixel_height = (self.pixel_height / self.screen().physical_rows) as isize;
+                let x = (event.x as isize * cell_pixel_width + event.x_pixel_offset).max(0);
+                let y = (event.y as isize * cell_pixel_height + event.y_pixel_offset).max(0);
+                write!(
+                    self.writer,
+                    "\x1b[<{};{};{}{}",
+                    button,
+                    x + 1,
+                    y + 1,
+                    final_byte
+                )?;
+            }
+            MouseEncoding::Urxvt => {
+                write!(
+                    self.writer,
+                    "\x1b[{};{};{}M",
+                    32 + legacy_button,
+                    event.x + 1,
+                    event.y + 1
+                )?;
+            }
+            MouseEncoding::Utf8 => {
+                write!(
+                    self.writer,
+                    "\x1b[M{}{}{}",
+                    (32 + legacy_button) as u8 as char,
+                    Self::utf8_mouse_coord(event.x as i64),
+                    Self::utf8_mouse_coord(event.y),
+                )?;
+            }
+            MouseEncoding::X10 => {
+                // The legacy encoding sends raw bytes rather than
+                // characters, so we cannot use write! with chars > 127
+                let mut buf = vec![b'\x1b', b'[', b'M'];
+                buf.push((32 + legacy_button) as u8);
+                buf.push(Self::legacy_mouse_coord(event.x as i64) as u8);
+                buf.push(Self::legacy_mouse_coord(event.y) as u8);
+                self.writer.write_all(&buf)?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn mouse_reporting_enabled(&self) -> bool {
+        self.mouse_tracking || self.button_event_mouse || self.any_event_mouse
+    }
+
     fn mouse_wheel(&mut self, event: MouseEvent) -> Result<(), Error> {
         let button = self.mouse_report_button_number(&event);
 
-        if self.sgr_mouse
-            && (self.mouse_tracking || self.button_event_mouse || self.any_event_mouse)
-        {
-            write!(
-                self.writer,
-                "\x1b[<{};{};{}M",
-                button,
-                event.x + 1,
-                event.y + 1
-            )?;
-            self.writer.flush()?;
-        } else if self.mouse_tracking || self.button_event_mouse || self.any_event_mouse {
-            write!(
-                self.writer,
-                "\x1b[M{}{}{}",
-                (32 + button) as u8 as char,
-                Self::legacy_mouse_coord(event.x as i64),
-                Self::legacy_mouse_coord(event.y),
-            )?;
-            self.writer.flush()?;
-        } else if self.screen.is_alt_screen_active() {
-            // Send cursor keys instead (equivalent to xterm's alternateScroll mode)
-            for _ in 0..self.config.alternate_buffer_wheel_scroll_speed() {
-                self.key_down(
-                    match event.button {
-                        MouseButton::WheelDown(_) => KeyCode::DownArrow,
-                        MouseButton::WheelUp(_) => KeyCode::UpArrow,
-                        _ => bail!("unexpected mouse event"),
-                    },
-                    KeyModifiers::default(),
-                )?;
+        if self.mouse_reporting_enabled() {
+            self.write_mouse_report(button, &event, false)?;
+        } else if self.screen.is_alt_screen_active() && self.alternate_scroll {
+            // Send a cursor key for each notch of the wheel instead
+            // (xterm's alternateScroll mode)
+            let (key, notches) = match event.button {
+                MouseButton::WheelDown(notches) => (KeyCode::DownArrow, notches),
+                MouseButton::WheelUp(notches) => (KeyCode::UpArrow, notches),
+                _ => bail!("unexpected mouse event"),
+            };
+            for _ in 0..notches {
+                self.key_down(key, KeyModifiers::default())?;
             }
         }
         Ok(())
@@ -603,61 +818,19 @@ impl TerminalState {
     fn mouse_button_press(&mut self, event: MouseEvent) -> Result<(), Error> {
         self.current_mouse_button = event.button;
 
-        if !(self.mouse_tracking || self.button_event_mouse || self.any_event_mouse) {
+        if !self.mouse_reporting_enabled() {
             return Ok(());
         }
 
         let button = self.mouse_report_button_number(&event);
-        if self.sgr_mouse {
-            write!(
-                self.writer,
-                "\x1b[<{};{};{}M",
-                button,
-                event.x + 1,
-                event.y + 1
-            )?;
-            self.writer.flush()?;
-        } else {
-            write!(
-                self.writer,
-                "\x1b[M{}{}{}",
-                (32 + button) as u8 as char,
-                Self::legacy_mouse_coord(event.x as i64),
-                Self::legacy_mouse_coord(event.y),
-            )?;
-            self.writer.flush()?;
-        }
-
-        Ok(())
+        self.write_mouse_report(button, &event, false)
     }
 
     fn mouse_button_release(&mut self, event: MouseEvent) -> Result<(), Error> {
-        if self.current_mouse_button != MouseButton::None
-            && (self.mouse_tracking || self.button_event_mouse || self.any_event_mouse)
-        {
-            if self.sgr_mouse {
-                let release_button = self.mouse_report_button_number(&event);
-                self.current_mouse_button = MouseButton::None;
-                write!(
-                    self.writer,
-                    "\x1b[<{};{};{}m",
-                    release_button,
-                    event.x + 1,
-                    event.y + 1
-                )?;
-                self.writer.flush()?;
-            } else {
-                let release_button = 3;
-                self.current_mouse_button = MouseButton::None;
-                write!(
-                    self.writer,
-                    "\x1b[M{}{}{}",
-                    (32 + release_button) as u8 as char,
-                    Self::legacy_mouse_coord(event.x as i64),
-                    Self::legacy_mouse_coord(event.y),
-                )?;
-                self.writer.flush()?;
-            }
+        if self.current_mouse_button != MouseButton::None && self.mouse_reporting_enabled() {
+            let release_button = self.mouse_report_button_number(&event);
+            self.current_mouse_button = MouseButton::None;
+            self.write_mouse_report(release_button, &event, true)?;
         }
 
         Ok(())
@@ -668,26 +841,7 @@ impl TerminalState {
         // Note: self.mouse_tracking on its own is for clicks, not drags!
         if reportable && (self.button_event_mouse || self.any_event_mouse) {
             let button = 32 + self.mouse_report_button_number(&event);
-
-            if self.sgr_mouse {
-                write!(
-                    self.writer,
-                    "\x1b[<{};{};{}M",
-                    button,
-                    event.x + 1,
-                    event.y + 1
-                )?;
-                self.writer.flush()?;
-            } else {
-                write!(
-                    self.writer,
-                    "\x1b[M{}{}{}",
-                    (32 + button) as u8 as char,
-                    Self::legacy_mouse_coord(event.x as i64),
-                    Self::legacy_mouse_coord(event.y),
-                )?;
-                self.writer.flush()?;
-            }
+            self.write_mouse_report(button, &event, false)?;
         }
         Ok(())
     }
@@ -996,14 +1150,18 @@ impl TerminalState {
                     _ => unreachable!(),
                 };
 
-                let csi_or_ss3 = if force_app
+                let csi_or_ss3 = if !self.dec_ansi_mode {
+                    // VT52 cursor keys are ESC followed by the direction
+                    "\x1b"
+                } else if force_app
                     || (
                         self.application_cursor_keys
                         // Strict reading of DECCKM suggests that application_cursor_keys
                         // only applies when DECANM and DECKPAM are active, but that seems
                         // to break unmodified cursor keys in vim
                         /* && self.dec_ansi_mode && self.application_keypad */
-                    ) {
+                    )
+                {
                     // Use SS3 in application mode
                     SS3
                 } else {
@@ -1172,6 +1330,34 @@ impl TerminalState {
         screen.dirty_line(y);
     }
 
+    /// Returns the number of columns that can be addressed on the
+    /// line at the specified visible row.  Lines that have been set
+    /// to double width or double height have half as many columns.
+    fn logical_cols_for_row(&self, y: VisibleRowIndex) -> usize {
+        let screen = self.screen();
+        let cols = screen.physical_cols;
+        let line_idx = screen.phys_row(y);
+        match screen.lines.get(line_idx) {
+            Some(line) if line.is_double_width() => (cols / 2).max(1),
+            _ => cols,
+        }
+    }
+
+    /// Applies DECDWL, DECDHL and DECSWL to the line containing the cursor.
+    /// When switching to a double width size, the cursor is clamped
+    /// so that it remains within the reduced logical width of the line.
+    fn set_line_size(&mut self, size: LineSize) {
+        let y = self.cursor.y;
+        let screen = self.screen_mut();
+        let line_idx = screen.phys_row(y);
+        screen.line_mut(line_idx).set_line_size(size);
+
+        let limit = self.logical_cols_for_row(y);
+        if self.cursor.x >= limit {
+            self.set_cursor_position_absolute(limit - 1, y);
+        }
+    }
+
     /// Sets the cursor position. x and y are 0-based and relative to the
     /// top left of the visible screen.
     fn set_cursor_pos(&mut self, x: &Position, y: &Position) {
@@ -1227,7 +1413,10 @@ impl TerminalState {
             .max(0),
         };
 
-        self.set_cursor_position_absolute(x as usize, y);
+        // Double width lines have half the addressable columns
+        let x = (x as usize).min(self.logical_cols_for_row(y) - 1);
+
+        self.set_cursor_position_absolute(x, y);
     }
 
     fn scroll_up(&mut self, num_rows: usize) {
@@ -1333,8 +1522,10 @@ impl TerminalState {
             Some(x) => x,
             None => self.left_and_right_margins.end - 1,
         };
-        self.cursor.x = x.min(self.left_and_right_margins.end - 1);
         let y = self.cursor.y;
+        self.cursor.x = x
+            .min(self.left_and_right_margins.end - 1)
+            .min(self.logical_cols_for_row(y) - 1);
         self.screen_mut().dirty_line(y);
     }
 
@@ -1478,7 +1669,10 @@ impl TerminalState {
         if let Some(item) = self.image_cache.get(&key) {
             Arc::clone(item)
         } else {
-            let image_data = Arc::new(ImageData::with_raw_data(raw_data));
+            let image_data = Arc::new(match decode_animation(&raw_data) {
+                Some(anim) => ImageData::with_data(anim),
+                None => ImageData::with_raw_data(raw_data),
+            });
             self.image_cache.put(key, Arc::clone(&image_data));
             image_data
         }
@@ -1566,18 +1760,22 @@ impl TerminalState {
             return;
         }
 
-        // Decode the image data
-        let decoded_image = match image::load_from_memory(&image.data) {
-            Ok(im) => im,
-            Err(e) => {
-                error!(
-                    "Unable to decode image: {}: size={} {:?}",
-                    e,
-                    image.data.len(),
-                    image
-                );
-                return;
-            }
+        // Decode the image data.  The image crate can't decode animated
+        // WebP images, so for those we take the size of the canvas
+        let dimensions = match image::load_from_memory(&image.data) {
+            Ok(im) => im.dimensions(),
+            Err(e) => match crate::webp::AnimatedWebPDecoder::new(&image.data) {
+                Ok(decoder) => decoder.dimensions(),
+                Err(_) => {
+                    error!(
+                        "Unable to decode image: {}: size={} {:?}",
+                        e,
+                        image.data.len(),
+                        image
+                    );
+                    return;
+                }
+            },
         };
 
         // Figure out the dimensions.
@@ -1590,13 +1788,10 @@ impl TerminalState {
         let height = image.height.to_pixels(cell_pixel_height, physical_rows);
 
         // Compute any Automatic dimensions
-        let aspect = decoded_image.width() as f32 / decoded_image.height() as f32;
+        let aspect = dimensions.0 as f32 / dimensions.1 as f32;
 
         let (width, height) = match (width, height) {
-            (None, None) => (
-                decoded_image.width() as usize,
-                decoded_image.height() as usize,
-            ),
+            (None, None) => (dimensions.0 as usize, dimensions.1 as usize),
             (Some(w), None) => {
                 let h = w as f32 / aspect;
                 (w, h as usize)
@@ -1777,6 +1972,13 @@ impl TerminalState {
                 self.insert = false;
             }
 
+            Mode::SetMode(TerminalMode::Code(TerminalModeCode::BiDirectionalSupportMode)) => {
+                self.bidi_enabled = true;
+            }
+            Mode::ResetMode(TerminalMode::Code(TerminalModeCode::BiDirectionalSupportMode)) => {
+                self.bidi_enabled = false;
+            }
+
             Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::BracketedPaste)) => {
                 self.bracketed_paste = true;
             }
@@ -1889,11 +2091,41 @@ impl TerminalState {
                 self.focus_tracking = false;
             }
 
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::Utf8Mouse)) => {
+                self.mouse_encoding = MouseEncoding::Utf8;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::Utf8Mouse)) => {
+                self.reset_mouse_encoding(MouseEncoding::Utf8);
+            }
+
             Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse)) => {
-                self.sgr_mouse = true;
+                self.mouse_encoding = MouseEncoding::SGR;
             }
             Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRMouse)) => {
-                self.sgr_mouse = false;
+                self.reset_mouse_encoding(MouseEncoding::SGR);
+            }
+
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::UrxvtMouse)) => {
+                self.mouse_encoding = MouseEncoding::Urxvt;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::UrxvtMouse)) => {
+                self.reset_mouse_encoding(MouseEncoding::Urxvt);
+            }
+
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRPixelsMouse)) => {
+                self.mouse_encoding = MouseEncoding::SgrPixels;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::SGRPixelsMouse)) => {
+                self.reset_mouse_encoding(MouseEncoding::SgrPixels);
+            }
+
+            Mode::SetDecPrivateMode(DecPrivateMode::Code(DecPrivateModeCode::AlternateScroll)) => {
+                self.alternate_scroll = true;
+            }
+            Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::AlternateScroll,
+            )) => {
+                self.alternate_scroll = false;
             }
 
             Mode::SetDecPrivateMode(DecPrivateMode::Code(
@@ -2088,6 +2320,12 @@ impl TerminalState {
             let screen = self.screen_mut();
             for y in row_range.clone() {
                 screen.clear_line(y, col_range.clone(), &pen);
+                // Erasing a line in its entirety also resets its
+                // DEC line size attribute
+                let line_idx = screen.phys_row(y);
+                screen
+                    .line_mut(line_idx)
+                    .set_line_size(LineSize::SingleWidth);
             }
         }
     }
@@ -2346,13 +2584,15 @@ impl TerminalState {
             Cursor::Right(n) => {
                 // https://vt100.net/docs/vt510-rm/CUF.html
                 let y = self.cursor.y;
-                let cols = self.screen().physical_cols;
+                let cols = self.logical_cols_for_row(y);
                 let new_x = if self.cursor.x >= self.left_and_right_margins.end {
                     // outside the margin, so allow movement to screen edge
                     (self.cursor.x + n as usize).min(cols - 1)
                 } else {
                     // Else constrain to margin
-                    (self.cursor.x + n as usize).min(self.left_and_right_margins.end - 1)
+                    (self.cursor.x + n as usize)
+                        .min(self.left_and_right_margins.end - 1)
+                        .min(cols - 1)
                 };
 
                 self.cursor.x = new_x;
@@ -2762,6 +3002,8 @@ impl<'a> Performer<'a> {
             None => return,
         };
 
+        let unicode_version = self.config.unicode_version();
+
         for g in unicode_segmentation::UnicodeSegmentation::graphemes(p.as_str(), true) {
             let g = if self.dec_line_drawing_mode {
                 match g {
@@ -2788,7 +3030,10 @@ impl<'a> Performer<'a> {
 
             let x = self.cursor.x;
             let y = self.cursor.y;
-            let width = self.left_and_right_margins.end;
+            let width = self
+                .left_and_right_margins
+                .end
+                .min(self.logical_cols_for_row(y));
 
             let mut pen = self.pen.clone();
             // the max(1) here is to ensure that we advance to the next cell
@@ -2796,13 +3041,13 @@ impl<'a> Performer<'a> {
             // they occupy a cell so that we can re-emit them when we output them.
             // If we didn't do this, then we'd effectively filter them out from
             // the model, which seems like a lossy design choice.
-            let print_width = unicode_column_width(g).max(1);
+            let print_width = unicode_version.grapheme_column_width(g).max(1);
 
             if x + print_width >= width {
                 pen.set_wrapped(true);
             }
 
-            let cell = Cell::new_grapheme(g, pen);
+            let cell = Cell::new_grapheme_with_width(g, print_width, pen);
 
             if self.insert {
                 let margin = self.left_and_right_margins.end;
@@ -2814,7 +3059,11 @@ impl<'a> Performer<'a> {
 
             // Assign the cell
             log::trace!("print x={} y={} cell={:?}", x, y, cell);
-            self.screen_mut().set_cell(x, y, &cell);
+            let bidi_enabled = self.bidi_enabled;
+            let screen = self.screen_mut();
+            screen.set_cell(x, y, &cell);
+            let line_idx = screen.phys_row(y);
+            screen.line_mut(line_idx).set_bidi_enabled(bidi_enabled);
 
             if x + print_width < width {
                 self.cursor.x += print_width;
@@ -2976,7 +3225,13 @@ impl<'a> Performer<'a> {
             ControlCode::HTS => self.c1_hts(),
             ControlCode::IND => self.c1_index(),
             ControlCode::NEL => self.c1_nel(),
-            ControlCode::Bell => log::info!("Ding! (this is the bell)"),
+            ControlCode::Bell => {
+                if self.alert_handler.is_some() {
+                    self.alert(Alert::Bell);
+                } else {
+                    log::info!("Ding! (this is the bell)");
+                }
+            }
             ControlCode::RI => self.c1_reverse_index(),
             _ => error!("unhandled ControlCode {:?}", control),
         }
@@ -3025,6 +3280,25 @@ impl<'a> Performer<'a> {
             }
             Esc::Code(EscCode::DecSaveCursorPosition) => self.dec_save_cursor(),
             Esc::Code(EscCode::DecRestoreCursorPosition) => self.dec_restore_cursor(),
+            Esc::Code(EscCode::ReturnTerminalId) => {
+                if self.dec_ansi_mode {
+                    // DECID is an obsolete form of DA
+                    self.perform_device(Device::RequestPrimaryDeviceAttributes);
+                } else {
+                    // VT52 Identify; we claim to be a VT100 in VT52 mode
+                    self.writer.write(b"\x1b/Z").ok();
+                    self.writer.flush().ok();
+                }
+            }
+
+            Esc::Code(EscCode::DecDoubleHeightTopHalfLine) => {
+                self.set_line_size(LineSize::DoubleHeightTop)
+            }
+            Esc::Code(EscCode::DecDoubleHeightBottomHalfLine) => {
+                self.set_line_size(LineSize::DoubleHeightBottom)
+            }
+            Esc::Code(EscCode::DecSingleWidthLine) => self.set_line_size(LineSize::SingleWidth),
+            Esc::Code(EscCode::DecDoubleWidthLine) => self.set_line_size(LineSize::DoubleWidth),
 
             Esc::Code(EscCode::DecScreenAlignmentDisplay) => {
                 // This one is just to make vttest happy;
@@ -3037,6 +3311,7 @@ impl<'a> Performer<'a> {
                     let line_idx = screen.phys_row(y);
                     let line = screen.line_mut(line_idx);
                     line.resize(col_range.end);
+                    line.set_line_size(LineSize::SingleWidth);
                     line.fill_range(
                         col_range.clone(),
                         &Cell::new('E', CellAttributes::default()),
@@ -3057,6 +3332,7 @@ impl<'a> Performer<'a> {
                 self.cursor = Default::default();
                 self.wrap_next = false;
                 self.insert = false;
+                self.bidi_enabled = self.config.bidi_enabled();
                 self.dec_auto_wrap = true;
                 self.reverse_wraparound_mode = false;
                 self.dec_origin_mode = false;
@@ -3064,11 +3340,12 @@ impl<'a> Performer<'a> {
                 self.color_map = default_color_map();
                 self.application_cursor_keys = false;
                 self.sixel_scrolling = true;
-                self.dec_ansi_mode = false;
+                self.dec_ansi_mode = true;
                 self.application_keypad = false;
                 self.bracketed_paste = false;
                 self.focus_tracking = false;
-                self.sgr_mouse = false;
+                self.mouse_encoding = MouseEncoding::X10;
+                self.alternate_scroll = true;
                 self.any_event_mouse = false;
                 self.button_event_mouse = false;
                 self.current_mouse_button = MouseButton::None;
@@ -3097,15 +3374,23 @@ impl<'a> Performer<'a> {
                 } else {
                     self.icon_title = Some(title.clone());
                 }
+                self.metadata_seqno += 1;
+                let icon_title = self.icon_title.clone();
+                self.alert(Alert::IconTitleChanged(icon_title));
             }
             OperatingSystemCommand::SetIconNameAndWindowTitle(title) => {
                 self.icon_title.take();
                 self.title = title.clone();
+                self.metadata_seqno += 1;
+                self.alert(Alert::IconTitleChanged(None));
+                self.alert(Alert::WindowTitleChanged(title));
             }
 
             OperatingSystemCommand::SetWindowTitleSun(title)
             | OperatingSystemCommand::SetWindowTitle(title) => {
                 self.title = title.clone();
+                self.metadata_seqno += 1;
+                self.alert(Alert::WindowTitleChanged(title));
             }
             OperatingSystemCommand::SetHyperlink(link) => {
                 self.set_hyperlink(link);
@@ -3123,7 +3408,10 @@ impl<'a> Performer<'a> {
                 let selection = selection_to_selection(selection);
                 self.set_clipboard_contents(selection, None).ok();
             }
-            OperatingSystemCommand::QuerySelection(_) => {}
+            OperatingSystemCommand::QuerySelection(selection) => {
+                let selection = selection_to_selection(selection);
+                self.alert(Alert::QueryClipboard(selection));
+            }
             OperatingSystemCommand::SetSelection(selection, selection_data) => {
                 let selection = selection_to_selection(selection);
                 match self.set_clipboard_contents(selection, Some(selection_data)) {
@@ -3175,11 +3463,21 @@ impl<'a> Performer<'a> {
                 error!("unhandled: {:?}", ft);
             }
 
-            OperatingSystemCommand::SystemNotification(message) => {
-                error!("Application sends SystemNotification: {}", message);
+            OperatingSystemCommand::SystemNotification(body) => {
+                self.alert(Alert::ToastNotification { title: None, body });
+            }
+            OperatingSystemCommand::RxvtExtension(params) => {
+                if params[0] == "notify" {
+                    let title = params.get(1).cloned();
+                    let body = params.get(2..).map(|b| b.join(";")).unwrap_or_default();
+                    self.alert(Alert::ToastNotification { title, body });
+                } else {
+                    error!("unhandled OSC 777: {:?}", params);
+                }
             }
             OperatingSystemCommand::CurrentWorkingDirectory(url) => {
                 self.current_dir = Url::parse(&url).ok();
+                self.metadata_seqno += 1;
             }
             OperatingSystemCommand::ChangeColorNumber(specs) => {
                 log::trace!("ChangeColorNumber: {:?}", specs);
@@ -10,10 +10,10 @@ mod csi;
 use crate::color::ColorPalette;
 use pretty_assertions::assert_eq;
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use termwiz::escape::csi::{Edit, EraseInDisplay, EraseInLine};
 use termwiz::escape::{OneBased, OperatingSystemCommand, CSI};
-use termwiz::surface::{CursorShape, CursorVisibility};
+use termwiz::surface::{CursorShape, CursorVisibility, SequenceNo};
 
 #[derive(Debug)]
 struct LocalClip {
@@ -47,19 +47,42 @@ impl Clipboard for LocalClip {
     }
 }
 
+/// Captures the data that the terminal sends back to the application
+#[derive(Clone, Default)]
+struct LocalWriter {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl std::io::Write for LocalWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 struct TestTerm {
     term: Terminal,
+    output: LocalWriter,
 }
 
 #[derive(Debug)]
 struct TestTermConfig {
     scrollback: usize,
+    unicode_version: UnicodeVersion,
 }
 impl TerminalConfiguration for TestTermConfig {
     fn scrollback_size(&self) -> usize {
         self.scrollback
     }
 
+    fn unicode_version(&self) -> UnicodeVersion {
+        self.unicode_version
+    }
+
     fn color_palette(&self) -> ColorPalette {
         ColorPalette::default()
     }
@@ -67,11 +90,21 @@ impl TerminalConfiguration for TestTermConfig {
 
 impl TestTerm {
     fn new(height: usize, width: usize, scrollback: usize) -> Self {
+        Self::with_unicode_version(height, width, scrollback, UnicodeVersion::default())
+    }
+
+    fn with_unicode_version(
+        height: usize,
+        width: usize,
+        scrollback: usize,
+        unicode_version: UnicodeVersion,
+    ) -> Self {
         let _ = pretty_env_logger::formatted_builder()
             .is_test(true)
             .filter_level(log::LevelFilter::Trace)
             .try_init();
 
+        let output = LocalWriter::default();
         let mut term = Terminal::new(
             TerminalSize {
                 physical_rows: height,
@@ -79,15 +112,18 @@ impl TestTerm {
                 pixel_width: width * 8,
                 pixel_height: height * 16,
             },
-            Arc::new(TestTermConfig { scrollback }),
+            Arc::new(TestTermConfig {
+                scrollback,
+                unicode_version,
+            }),
             "WezTerm",
             "O_o",
-            Box::new(Vec::new()),
+            Box::new(output.clone()),
         );
         let clip: Arc<dyn Clipboard> = Arc::new(LocalClip::new());
         term.set_clipboard(&clip);
 
-        let mut term = Self { term };
+        let mut term = Self { term, output };
 
         term.set_auto_wrap(true);
 
@@ -98,6 +134,35 @@ impl TestTerm {
         self.term.advance_bytes(bytes);
     }
 
+    /// Asserts that the data written back to the application since
+    /// the last call to this method matches `expected`.
+    /// The terminal writes from a separate thread, so this waits
+    /// a little while for the data to arrive.
+    fn assert_output(&mut self, expected: &[u8]) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while self.output.buf.lock().unwrap().len() < expected.len()
+            && std::time::Instant::now() < deadline
+        {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let output = std::mem::take(&mut *self.output.buf.lock().unwrap());
+        assert_eq!(output, expected.to_vec());
+    }
+
+    fn mouse(&mut self, kind: MouseEventKind, button: MouseButton, x: usize, y: i64) {
+        self.term
+            .mouse_event(MouseEvent {
+                kind,
+                x,
+                y,
+                x_pixel_offset: 3,
+                y_pixel_offset: 5,
+                button,
+                modifiers: KeyModifiers::default(),
+            })
+            .unwrap();
+    }
+
     #[allow(dead_code)]
     fn set_mode(&mut self, mode: &str, enable: bool) {
         self.print(CSI);
@@ -800,6 +865,490 @@ fn test_scroll_margins() {
     );
 }
 
+#[test]
+fn test_double_width_lines() {
+    let mut term = TestTerm::new(3, 6, 0);
+    term.print("\x1b#6");
+    assert_eq!(
+        term.screen().visible_lines()[0].line_size(),
+        LineSize::DoubleWidth
+    );
+
+    // Only half of the physical columns are addressable, so
+    // we wrap at the logical width
+    term.print("abcdef");
+    assert_visible_contents(&term, file!(), line!(), &["abc   ", "def   ", "      "]);
+
+    term.cup(5, 1);
+    term.print("\x1b#3");
+    term.assert_cursor_pos(2, 1, Some("DECDHL clamps the cursor"));
+    term.cup(5, 0);
+    term.assert_cursor_pos(2, 0, Some("CUP is clamped on a double width line"));
+
+    term.print("\x1b#5");
+    assert_eq!(
+        term.screen().visible_lines()[0].line_size(),
+        LineSize::SingleWidth
+    );
+    term.cup(5, 0);
+    term.assert_cursor_pos(5, 0, Some("full width restored"));
+
+    term.erase_in_display(EraseInDisplay::EraseDisplay);
+    assert_eq!(
+        term.screen().visible_lines()[1].line_size(),
+        LineSize::SingleWidth
+    );
+}
+
+#[test]
+fn test_vt52() {
+    let mut term = TestTerm::new(4, 6, 0);
+    term.set_mode("?2", false);
+    term.print("\x1bY\x22\x23a\x1bA\x1bDb");
+    assert_visible_contents(
+        &term,
+        file!(),
+        line!(),
+        &["      ", "   b  ", "   a  ", "      "],
+    );
+    term.print("\x1bH\x1bJ");
+    term.assert_cursor_pos(0, 0, Some("ESC H homes the cursor"));
+    assert_visible_contents(
+        &term,
+        file!(),
+        line!(),
+        &["      ", "      ", "      ", "      "],
+    );
+
+    // ESC < returns to ANSI mode, where CSI works again
+    term.print("\x1b<");
+    term.cup(2, 1);
+    term.assert_cursor_pos(2, 1, None);
+}
+
+#[test]
+fn test_ambiguous_width() {
+    // CIRCLED DIGIT ONE has an ambiguous East Asian Width
+    let mut term = TestTerm::new(2, 4, 0);
+    term.print("\u{2460}");
+    term.assert_cursor_pos(1, 0, Some("ambiguous is narrow by default"));
+
+    let mut term = TestTerm::with_unicode_version(
+        2,
+        4,
+        0,
+        UnicodeVersion {
+            version: 9,
+            ambiguous_are_wide: true,
+        },
+    );
+    term.print("\u{2460}");
+    term.assert_cursor_pos(2, 0, Some("ambiguous is wide when configured"));
+    assert_eq!(term.screen().visible_lines()[0].cells()[0].width(), 2);
+}
+
+#[test]
+fn test_bidi_mode() {
+    let mut term = TestTerm::new(3, 6, 0);
+    term.print("abc");
+    assert!(!term.screen().visible_lines()[0].is_bidi_enabled());
+
+    // BDSM set selects implicit mode; lines written while it is
+    // in effect are reordered when rendered
+    term.set_mode("8", true);
+    term.print("\r\ndef");
+    assert!(term.screen().visible_lines()[1].is_bidi_enabled());
+
+    // Explicit mode leaves the presentation order to the application
+    term.set_mode("8", false);
+    term.print("\r\nghi");
+    assert!(!term.screen().visible_lines()[2].is_bidi_enabled());
+
+    // The text itself is always stored in logical order
+    assert_visible_contents(&term, file!(), line!(), &["abc   ", "def   ", "ghi   "]);
+}
+
+#[test]
+fn test_mouse_encodings() {
+    let mut term = TestTerm::new(3, 300, 0);
+    term.set_mode("?1000", true);
+
+    term.mouse(MouseEventKind::Press, MouseButton::Left, 1, 2);
+    term.mouse(MouseEventKind::Release, MouseButton::Left, 1, 2);
+    term.assert_output(b"\x1b[M \"#\x1b[M#\"#");
+
+    // The legacy encoding cannot represent large coordinates
+    term.mouse(MouseEventKind::Press, MouseButton::Left, 250, 0);
+    term.assert_output(b"\x1b[M !!");
+    term.mouse(MouseEventKind::Release, MouseButton::Left, 250, 0);
+    term.assert_output(b"\x1b[M#!!");
+
+    term.set_mode("?1005", true);
+    term.mouse(MouseEventKind::Press, MouseButton::Left, 250, 0);
+    term.assert_output("\x1b[M \u{11b}!".as_bytes());
+    term.mouse(MouseEventKind::Release, MouseButton::Left, 250, 0);
+    term.assert_output("\x1b[M#\u{11b}!".as_bytes());
+
+    term.set_mode("?1015", true);
+    term.mouse(MouseEventKind::Press, MouseButton::Right, 250, 1);
+    term.mouse(MouseEventKind::Release, MouseButton::Right, 250, 1);
+    term.assert_output(b"\x1b[34;251;2M\x1b[35;251;2M");
+
+    term.set_mode("?1006", true);
+    term.mouse(MouseEventKind::Press, MouseButton::Right, 250, 1);
+    term.mouse(MouseEventKind::Release, MouseButton::Right, 250, 1);
+    term.assert_output(b"\x1b[<2;251;2M\x1b[<2;251;2m");
+
+    // Cells are 8x16 pixels in the test terminal
+    term.set_mode("?1016", true);
+    term.mouse(MouseEventKind::Press, MouseButton::Left, 2, 1);
+    term.mouse(MouseEventKind::Release, MouseButton::Left, 2, 1);
+    term.assert_output(b"\x1b[<0;20;22M\x1b[<0;20;22m");
+
+    // Resetting a mode other than the current encoding has no effect
+    term.set_mode("?1006", false);
+    term.mouse(MouseEventKind::Press, MouseButton::Left, 2, 1);
+    term.assert_output(b"\x1b[<0;20;22M");
+    term.mouse(MouseEventKind::Release, MouseButton::Left, 2, 1);
+    term.assert_output(b"\x1b[<0;20;22m");
+
+    // Resetting the current encoding reverts to the legacy encoding
+    term.set_mode("?1016", false);
+    term.mouse(MouseEventKind::Press, MouseButton::Left, 2, 1);
+    term.assert_output(b"\x1b[M #\"");
+}
+
+#[test]
+fn test_alternate_scroll() {
+    let mut term = TestTerm::new(3, 10, 0);
+
+    // Wheel events are not reported in the primary screen
+    term.mouse(MouseEventKind::Press, MouseButton::WheelUp(1), 0, 0);
+    term.assert_output(b"");
+
+    // In the alternate screen they are sent as a cursor key per notch
+    term.set_mode("?1049", true);
+    term.mouse(MouseEventKind::Press, MouseButton::WheelUp(1), 0, 0);
+    term.mouse(MouseEventKind::Press, MouseButton::WheelDown(2), 0, 0);
+    term.assert_output(b"\x1b[A\x1b[B\x1b[B");
+
+    // unless alternate scroll mode has been disabled
+    term.set_mode("?1007", false);
+    term.mouse(MouseEventKind::Press, MouseButton::WheelUp(1), 0, 0);
+    term.assert_output(b"");
+
+    // Mouse reporting takes precedence over alternate scroll mode
+    term.set_mode("?1007", true);
+    term.set_mode("?1000", true);
+    term.set_mode("?1006", true);
+    term.mouse(MouseEventKind::Press, MouseButton::WheelUp(1), 0, 0);
+    term.assert_output(b"\x1b[<64;1;1M");
+}
+
+#[test]
+fn test_bell() {
+    struct Alerts(Arc<Mutex<Vec<Alert>>>);
+    impl AlertHandler for Alerts {
+        fn alert(&mut self, alert: Alert) {
+            self.0.lock().unwrap().push(alert);
+        }
+    }
+
+    let alerts = Arc::new(Mutex::new(vec![]));
+    let mut term = TestTerm::new(3, 10, 0);
+    term.set_alert_handler(Box::new(Alerts(Arc::clone(&alerts))));
+
+    term.print("a\x07b\x07");
+    assert_eq!(*alerts.lock().unwrap(), vec![Alert::Bell, Alert::Bell]);
+    assert_visible_contents(
+        &term,
+        file!(),
+        line!(),
+        &["ab        ", "          ", "          "],
+    );
+}
+
+#[test]
+fn test_alerts() {
+    struct Alerts(Arc<Mutex<Vec<Alert>>>);
+    impl AlertHandler for Alerts {
+        fn alert(&mut self, alert: Alert) {
+            self.0.lock().unwrap().push(alert);
+        }
+    }
+
+    let alerts = Arc::new(Mutex::new(vec![]));
+    let mut term = TestTerm::new(3, 10, 0);
+    term.set_alert_handler(Box::new(Alerts(Arc::clone(&alerts))));
+
+    term.print("\x1b]2;hello\x1b\\");
+    term.print("\x1b]9;done\x1b\\");
+    term.print("\x1b]777;notify;build;failed; see log\x1b\\");
+    term.print("\x1b]52;c;?\x1b\\");
+    assert_eq!(
+        *alerts.lock().unwrap(),
+        vec![
+            Alert::WindowTitleChanged("hello".to_string()),
+            Alert::ToastNotification {
+                title: None,
+                body: "done".to_string(),
+            },
+            Alert::ToastNotification {
+                title: Some("build".to_string()),
+                body: "failed; see log".to_string(),
+            },
+            Alert::QueryClipboard(ClipboardSelection::Clipboard),
+        ]
+    );
+    assert_eq!(term.get_title(), "hello");
+}
+
+#[test]
+fn test_metadata_seqno() {
+    let mut term = TestTerm::new(3, 10, 0);
+    let seqno = term.get_metadata_seqno();
+
+    term.print("hello\r\n\x1b[1mworld");
+    assert_eq!(term.get_metadata_seqno(), seqno);
+
+    term.print("\x1b]2;title\x1b\\");
+    let seqno = assert_metadata_changed(&term, seqno);
+    term.print("\x1b]1;icon\x1b\\");
+    let seqno = assert_metadata_changed(&term, seqno);
+    term.print("\x1b]0;both\x1b\\");
+    let seqno = assert_metadata_changed(&term, seqno);
+    assert_eq!(term.get_title(), "both");
+
+    term.print("\x1b]7;file://host/tmp\x1b\\");
+    assert_metadata_changed(&term, seqno);
+    assert_eq!(
+        term.get_current_dir(),
+        Some(&url::Url::parse("file://host/tmp").unwrap())
+    );
+}
+
+fn assert_metadata_changed(term: &TestTerm, seqno: SequenceNo) -> SequenceNo {
+    let new_seqno = term.get_metadata_seqno();
+    assert_ne!(new_seqno, seqno);
+    new_seqno
+}
+
+#[test]
+fn test_animated_image() {
+    use image::{Delay, Frame, RgbaImage};
+    use termwiz::escape::osc::{ITermDimension, ITermFileData, ITermProprietary};
+    use termwiz::image::ImageDataType;
+
+    let mut gif = vec![];
+    image::gif::GifEncoder::new(&mut gif)
+        .encode_frames(vec![
+            Frame::from_parts(
+                RgbaImage::from_pixel(2, 2, image::Rgba([0xff, 0, 0, 0xff])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(50, 1),
+            ),
+            Frame::from_parts(
+                RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0xff, 0xff])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(0, 1),
+            ),
+        ])
+        .unwrap();
+
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print(format!(
+        "{}",
+        OperatingSystemCommand::ITermProprietary(ITermProprietary::File(Box::new(ITermFileData {
+            name: None,
+            size: Some(gif.len()),
+            width: ITermDimension::Automatic,
+            height: ITermDimension::Automatic,
+            preserve_aspect_ratio: true,
+            inline: true,
+            data: gif.clone(),
+        })))
+    ));
+
+    let screen = term.screen();
+    let line = &screen.visible_lines()[0];
+    let image = line.cells()[0]
+        .attrs()
+        .image()
+        .expect("cell to hold an image");
+    assert!(image.image_data().is_animated());
+    match image.image_data().data() {
+        ImageDataType::AnimRgba8 {
+            width,
+            height,
+            durations,
+            frames,
+            data,
+        } => {
+            assert_eq!((*width, *height), (2, 2));
+            // The original bytes are retained so that the image
+            // can be passed on by termwiz
+            assert_eq!(*data, gif);
+            // The zero delay is treated as 100ms, as browsers do
+            assert_eq!(
+                *durations,
+                vec![
+                    std::time::Duration::from_millis(50),
+                    std::time::Duration::from_millis(100)
+                ]
+            );
+            assert_eq!(frames.len(), 2);
+            assert_eq!(&frames[0][0..4], &[0xff, 0, 0, 0xff]);
+            assert_eq!(&frames[1][0..4], &[0, 0, 0xff, 0xff]);
+        }
+        data => panic!("expected an animation, got {:?}", data),
+    }
+}
+
+#[test]
+fn test_animated_webp_image() {
+    use termwiz::escape::osc::{ITermDimension, ITermFileData, ITermProprietary};
+    use termwiz::image::ImageDataType;
+
+    fn chunk(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// Encodes a 2x2 lossless image of a single color, whose
+    /// prefix codes each have a single symbol
+    fn solid_vp8l([r, g, b, a]: [u8; 4]) -> Vec<u8> {
+        let mut bits: Vec<(u32, u32)> = vec![(0x2f, 8), (1, 14), (1, 14), (1, 1), (0, 3)];
+        // No transforms, color cache or meta prefix codes
+        bits.extend_from_slice(&[(0, 1), (0, 1), (0, 1)]);
+        for symbol in [g, r, b, a, 0].iter() {
+            bits.extend_from_slice(&[(1, 1), (0, 1), (1, 1), (*symbol as u32, 8)]);
+        }
+        let mut data = vec![];
+        let mut pos = 0;
+        for (value, len) in bits {
+            for i in 0..len {
+                if pos % 8 == 0 {
+                    data.push(0);
+                }
+                *data.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (pos % 8);
+                pos += 1;
+            }
+        }
+        data
+    }
+
+    fn frame(duration_ms: u8, rgba: [u8; 4]) -> Vec<u8> {
+        let mut anmf = vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, duration_ms, 0, 0, 0];
+        anmf.extend(chunk(b"VP8L", &solid_vp8l(rgba)));
+        chunk(b"ANMF", &anmf)
+    }
+
+    let mut body = b"WEBP".to_vec();
+    body.extend(chunk(b"VP8X", &[0x12, 0, 0, 0, 1, 0, 0, 1, 0, 0]));
+    body.extend(chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]));
+    body.extend(frame(50, [0xff, 0, 0, 0xff]));
+    body.extend(frame(0, [0, 0, 0xff, 0xff]));
+    let mut webp = b"RIFF".to_vec();
+    webp.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    webp.extend(body);
+
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print(format!(
+        "{}",
+        OperatingSystemCommand::ITermProprietary(ITermProprietary::File(Box::new(ITermFileData {
+            name: None,
+            size: Some(webp.len()),
+            width: ITermDimension::Automatic,
+            height: ITermDimension::Automatic,
+            preserve_aspect_ratio: true,
+            inline: true,
+            data: webp.clone(),
+        })))
+    ));
+
+    let screen = term.screen();
+    let line = &screen.visible_lines()[0];
+    let image = line.cells()[0]
+        .attrs()
+        .image()
+        .expect("cell to hold an image");
+    assert!(image.image_data().is_animated());
+    match image.image_data().data() {
+        ImageDataType::AnimRgba8 {
+            width,
+            height,
+            durations,
+            frames,
+            data,
+        } => {
+            assert_eq!((*width, *height), (2, 2));
+            assert_eq!(*data, webp);
+            assert_eq!(
+                *durations,
+                vec![
+                    std::time::Duration::from_millis(50),
+                    std::time::Duration::from_millis(100)
+                ]
+            );
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0], [0xff, 0, 0, 0xff].repeat(4));
+            assert_eq!(frames[1], [0, 0, 0xff, 0xff].repeat(4));
+        }
+        data => panic!("expected an animation, got {:?}", data),
+    }
+}
+
+#[test]
+fn test_animated_image_frame_limit() {
+    use image::{Delay, Frame, RgbaImage};
+    use termwiz::escape::osc::{ITermDimension, ITermFileData, ITermProprietary};
+    use termwiz::image::ImageDataType;
+
+    let mut gif = vec![];
+    image::gif::GifEncoder::new(&mut gif)
+        .encode_frames((0..2049u32).map(|i| {
+            Frame::from_parts(
+                RgbaImage::from_pixel(1, 1, image::Rgba([(i % 256) as u8, 0, 0, 0xff])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(50, 1),
+            )
+        }))
+        .unwrap();
+
+    let mut term = TestTerm::new(3, 10, 0);
+    term.print(format!(
+        "{}",
+        OperatingSystemCommand::ITermProprietary(ITermProprietary::File(Box::new(ITermFileData {
+            name: None,
+            size: Some(gif.len()),
+            width: ITermDimension::Automatic,
+            height: ITermDimension::Automatic,
+            preserve_aspect_ratio: true,
+            inline: true,
+            data: gif.clone(),
+        })))
+    ));
+
+    // Too many frames to decode; the image is kept in its
+    // encoded form and shown as a still image instead
+    let screen = term.screen();
+    let line = &screen.visible_lines()[0];
+    let image = line.cells()[0]
+        .attrs()
+        .image()
+        .expect("cell to hold an image");
+    assert!(!image.image_data().is_animated());
+    assert_eq!(*image.image_data().data(), ImageDataType::EncodedFile(gif));
+}
+
 #[test]
 fn test_emoji_with_modifier() {
     let waving_hand = "\u{1f44b}";
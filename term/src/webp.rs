@@ -0,0 +1,844 @@
+//! Decodes animated WebP images, which the image crate is unable to do.
+//! Only frames that use lossless (VP8L) compression are supported;
+//! a frame using lossy (VP8) compression fails to decode.
+//! <https://developers.google.com/speed/webp/docs/riff_container>
+//! <https://developers.google.com/speed/webp/docs/webp_lossless_bitstream_specification>
+use image::error::{DecodingError, ImageFormatHint};
+use image::{AnimationDecoder, Delay, Frame, Frames, ImageError, ImageFormat, ImageResult};
+use image::{Rgba, RgbaImage};
+
+fn error(message: &'static str) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::WebP),
+        message,
+    ))
+}
+
+fn le24(data: &[u8]) -> u32 {
+    data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16
+}
+
+fn le32(data: &[u8]) -> u32 {
+    le24(data) | (data[3] as u32) << 24
+}
+
+/// Splits the first RIFF chunk from `data`, returning its FourCC,
+/// its payload and the remaining chunks
+fn next_chunk(data: &[u8]) -> ImageResult<([u8; 4], &[u8], &[u8])> {
+    if data.len() < 8 {
+        return Err(error("truncated chunk header"));
+    }
+    let fourcc = [data[0], data[1], data[2], data[3]];
+    let size = le32(&data[4..8]) as usize;
+    let data = &data[8..];
+    if size > data.len() {
+        return Err(error("truncated chunk"));
+    }
+    // Chunks are padded to an even length
+    let padded = (size + (size & 1)).min(data.len());
+    Ok((fourcc, &data[..size], &data[padded..]))
+}
+
+struct AnimationFrame<'a> {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+    duration: u32,
+    blend: bool,
+    dispose: bool,
+    data: &'a [u8],
+}
+
+pub struct AnimatedWebPDecoder<'a> {
+    width: u32,
+    height: u32,
+    frames: Vec<AnimationFrame<'a>>,
+}
+
+impl<'a> AnimatedWebPDecoder<'a> {
+    /// Parses the container of an animated WebP image.
+    /// Returns an error if the data isn't an animated WebP image.
+    /// The frames themselves are decoded by `into_frames`.
+    pub fn new(data: &'a [u8]) -> ImageResult<Self> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+            return Err(error("not a WebP image"));
+        }
+        let riff_size = le32(&data[4..8]) as usize;
+        let mut chunks = &data[12..(8 + riff_size).min(data.len())];
+
+        let (fourcc, vp8x, rest) = next_chunk(chunks)?;
+        if &fourcc != b"VP8X" || vp8x.len() < 10 {
+            return Err(error("not an extended format WebP image"));
+        }
+        const ANIMATION_FLAG: u8 = 0x02;
+        if vp8x[0] & ANIMATION_FLAG == 0 {
+            return Err(error("not an animated WebP image"));
+        }
+        let width = le24(&vp8x[4..7]) + 1;
+        let height = le24(&vp8x[7..10]) + 1;
+        chunks = rest;
+
+        let mut frames = vec![];
+        while !chunks.is_empty() {
+            let (fourcc, payload, rest) = next_chunk(chunks)?;
+            chunks = rest;
+            if &fourcc != b"ANMF" {
+                continue;
+            }
+            if payload.len() < 16 {
+                return Err(error("truncated ANMF chunk"));
+            }
+            let frame = AnimationFrame {
+                left: le24(&payload[0..3]) * 2,
+                top: le24(&payload[3..6]) * 2,
+                width: le24(&payload[6..9]) + 1,
+                height: le24(&payload[9..12]) + 1,
+                duration: le24(&payload[12..15]),
+                blend: payload[15] & 0x02 == 0,
+                dispose: payload[15] & 0x01 != 0,
+                data: &payload[16..],
+            };
+            if frame.left + frame.width > width || frame.top + frame.height > height {
+                return Err(error("animation frame is outside of the canvas"));
+            }
+            frames.push(frame);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            frames,
+        })
+    }
+
+    /// Returns the dimensions of the animation canvas
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+impl<'a> AnimationDecoder<'a> for AnimatedWebPDecoder<'a> {
+    fn into_frames(self) -> Frames<'a> {
+        let mut canvas = RgbaImage::new(self.width, self.height);
+        let mut dispose: Option<(u32, u32, u32, u32)> = None;
+
+        Frames::new(Box::new(self.frames.into_iter().map(move |frame| {
+            // Browsers ignore the background color from the ANIM chunk
+            // and dispose to transparent, so we do too
+            if let Some((left, top, width, height)) = dispose.take() {
+                for y in top..top + height {
+                    for x in left..left + width {
+                        canvas.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+
+            let pixels = decode_frame(frame.data, frame.width, frame.height)?;
+            for (idx, argb) in pixels.into_iter().enumerate() {
+                let x = frame.left + idx as u32 % frame.width;
+                let y = frame.top + idx as u32 / frame.width;
+                let src = [
+                    (argb >> 16) as u8,
+                    (argb >> 8) as u8,
+                    argb as u8,
+                    (argb >> 24) as u8,
+                ];
+                let pixel = if frame.blend {
+                    blend(src, canvas.get_pixel(x, y).0)
+                } else {
+                    src
+                };
+                canvas.put_pixel(x, y, Rgba(pixel));
+            }
+
+            if frame.dispose {
+                dispose = Some((frame.left, frame.top, frame.width, frame.height));
+            }
+
+            Ok(Frame::from_parts(
+                canvas.clone(),
+                0,
+                0,
+                Delay::from_numer_denom_ms(frame.duration, 1),
+            ))
+        })))
+    }
+}
+
+/// Alpha-blends the non-premultiplied RGBA `src` over `dst`
+fn blend(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let src_a = src[3] as u32;
+    let dst_a = dst[3] as u32 * (255 - src_a) / 255;
+    let alpha = src_a + dst_a;
+    if alpha == 0 {
+        return [0, 0, 0, 0];
+    }
+    let channel = |i: usize| ((src[i] as u32 * src_a + dst[i] as u32 * dst_a) / alpha) as u8;
+    [channel(0), channel(1), channel(2), alpha as u8]
+}
+
+/// Decodes the image data of an ANMF chunk into ARGB pixels
+fn decode_frame(mut data: &[u8], width: u32, height: u32) -> ImageResult<Vec<u32>> {
+    while !data.is_empty() {
+        let (fourcc, payload, rest) = next_chunk(data)?;
+        data = rest;
+        match &fourcc {
+            b"VP8L" => {
+                let mut reader = BitReader::new(payload);
+                if reader.read(8)? != 0x2f {
+                    return Err(error("invalid VP8L signature"));
+                }
+                let vp8l_width = reader.read(14)? + 1;
+                let vp8l_height = reader.read(14)? + 1;
+                let _alpha_is_used = reader.read(1)?;
+                if reader.read(3)? != 0 {
+                    return Err(error("unsupported VP8L version"));
+                }
+                if (vp8l_width, vp8l_height) != (width, height) {
+                    return Err(error("VP8L image size doesn't match its frame"));
+                }
+                return decode_vp8l(&mut reader, width as usize, height as usize);
+            }
+            b"VP8 " | b"ALPH" => {
+                return Err(error("lossy WebP animation frames are not supported"));
+            }
+            _ => {}
+        }
+    }
+    Err(error("animation frame has no image data"))
+}
+
+/// Reads the bits of a VP8L bitstream, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    num_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bits: 0,
+            num_bits: 0,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.num_bits <= 56 && self.pos < self.data.len() {
+            self.bits |= (self.data[self.pos] as u64) << self.num_bits;
+            self.pos += 1;
+            self.num_bits += 8;
+        }
+    }
+
+    /// Returns the next `n` bits without consuming them; bits past
+    /// the end of the data read as zero
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.num_bits < n {
+            self.fill();
+        }
+        (self.bits & ((1 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u32) -> ImageResult<()> {
+        if self.num_bits < n {
+            return Err(error("truncated VP8L bitstream"));
+        }
+        self.bits >>= n;
+        self.num_bits -= n;
+        Ok(())
+    }
+
+    fn read(&mut self, n: u32) -> ImageResult<u32> {
+        let value = self.peek(n);
+        self.consume(n)?;
+        Ok(value)
+    }
+}
+
+/// Codes of up to this many bits are decoded by a single table lookup
+const TABLE_BITS: u32 = 8;
+
+/// A canonical prefix (Huffman) code
+struct PrefixCode {
+    /// The number of codes of each length
+    counts: [u16; 16],
+    /// The symbols, ordered by code
+    symbols: Vec<u16>,
+    /// Maps the next TABLE_BITS bits to the symbol and the length of
+    /// its code; a zero length means the code is longer than that
+    table: Vec<(u16, u8)>,
+    /// A code with a single symbol reads no bits at all
+    single: Option<u16>,
+}
+
+impl PrefixCode {
+    fn from_lengths(lengths: &[u8]) -> ImageResult<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let used: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] != 0).collect();
+        match used.len() {
+            0 => return Err(error("empty prefix code")),
+            1 => {
+                return Ok(Self {
+                    counts,
+                    symbols: vec![],
+                    table: vec![],
+                    single: Some(used[0] as u16),
+                })
+            }
+            _ => {}
+        }
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(error("over-subscribed prefix code"));
+            }
+        }
+
+        let mut offsets = [0usize; 16];
+        let mut next_code = [0u32; 16];
+        let mut code = 0;
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1] as usize;
+            code = (code + counts[len - 1] as u32) << 1;
+            next_code[len] = code;
+        }
+
+        let mut symbols = vec![0u16; used.len()];
+        let mut table = vec![(0u16, 0u8); 1 << TABLE_BITS];
+        for symbol in used {
+            let len = lengths[symbol] as usize;
+            symbols[offsets[len]] = symbol as u16;
+            offsets[len] += 1;
+
+            let code = next_code[len];
+            next_code[len] += 1;
+            if len as u32 <= TABLE_BITS {
+                // Codes are stored most significant bit first, so the
+                // table is indexed by the bit-reversed code
+                let reversed = (code.reverse_bits() >> (32 - len)) as usize;
+                for fill in (reversed..table.len()).step_by(1 << len) {
+                    table[fill] = (symbol as u16, len as u8);
+                }
+            }
+        }
+
+        Ok(Self {
+            counts,
+            symbols,
+            table,
+            single: None,
+        })
+    }
+
+    fn read(&self, reader: &mut BitReader) -> ImageResult<u16> {
+        if let Some(symbol) = self.single {
+            return Ok(symbol);
+        }
+        let (symbol, len) = self.table[reader.peek(TABLE_BITS) as usize];
+        if len > 0 {
+            reader.consume(len as u32)?;
+            return Ok(symbol);
+        }
+
+        // The code is longer than the table; decode it a bit at a time
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= reader.read(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(error("invalid prefix code"))
+    }
+
+    fn decode(reader: &mut BitReader, alphabet_size: usize) -> ImageResult<Self> {
+        let mut lengths = vec![0u8; alphabet_size];
+
+        if reader.read(1)? == 1 {
+            // A simple code of one or two symbols
+            let num_symbols = reader.read(1)? + 1;
+            let first_bits = if reader.read(1)? == 1 { 8 } else { 1 };
+            let mut symbols = vec![reader.read(first_bits)?];
+            if num_symbols == 2 {
+                symbols.push(reader.read(8)?);
+            }
+            for symbol in symbols {
+                *lengths
+                    .get_mut(symbol as usize)
+                    .ok_or_else(|| error("prefix code symbol out of range"))? = 1;
+            }
+            return Self::from_lengths(&lengths);
+        }
+
+        const CODE_LENGTH_ORDER: [usize; 19] = [
+            17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+        ];
+        let mut code_length_lengths = [0u8; 19];
+        let num_code_lengths = reader.read(4)? as usize + 4;
+        for &i in &CODE_LENGTH_ORDER[..num_code_lengths] {
+            code_length_lengths[i] = reader.read(3)? as u8;
+        }
+        let code_length_code = Self::from_lengths(&code_length_lengths)?;
+
+        let mut max_symbol = if reader.read(1)? == 1 {
+            let bits = 2 + 2 * reader.read(3)?;
+            2 + reader.read(bits)? as usize
+        } else {
+            alphabet_size
+        };
+        if max_symbol > alphabet_size {
+            return Err(error("prefix code has too many symbols"));
+        }
+
+        let mut prev_len = 8;
+        let mut symbol = 0;
+        while symbol < alphabet_size && max_symbol > 0 {
+            max_symbol -= 1;
+            let len = code_length_code.read(reader)? as u8;
+            if len < 16 {
+                lengths[symbol] = len;
+                symbol += 1;
+                if len != 0 {
+                    prev_len = len;
+                }
+                continue;
+            }
+            let (repeat, value) = match len {
+                16 => (3 + reader.read(2)?, prev_len),
+                17 => (3 + reader.read(3)?, 0),
+                _ => (11 + reader.read(7)?, 0),
+            };
+            let repeat = repeat as usize;
+            if symbol + repeat > alphabet_size {
+                return Err(error("prefix code lengths overflow the alphabet"));
+            }
+            for len in &mut lengths[symbol..symbol + repeat] {
+                *len = value;
+            }
+            symbol += repeat;
+        }
+
+        Self::from_lengths(&lengths)
+    }
+}
+
+/// The prefix codes for the green (plus length and color cache),
+/// red, blue, alpha and distance symbols
+type PrefixCodeGroup = [PrefixCode; 5];
+
+fn div_round_up(value: usize, bits: u32) -> usize {
+    (value + (1 << bits) - 1) >> bits
+}
+
+/// Returns the value of a length or distance prefix symbol,
+/// reading its extra bits
+fn prefix_value(reader: &mut BitReader, prefix: usize) -> ImageResult<usize> {
+    if prefix < 4 {
+        return Ok(prefix + 1);
+    }
+    let extra_bits = (prefix as u32 - 2) >> 1;
+    let offset = (2 + (prefix & 1)) << extra_bits;
+    Ok(offset + reader.read(extra_bits)? as usize + 1)
+}
+
+/// Maps the short distance codes to (x, y) offsets of nearby pixels
+#[rustfmt::skip]
+const DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1), (1, 0), (1, 1), (-1, 1), (0, 2), (2, 0), (1, 2),
+    (-1, 2), (2, 1), (-2, 1), (2, 2), (-2, 2), (0, 3), (3, 0),
+    (1, 3), (-1, 3), (3, 1), (-3, 1), (2, 3), (-2, 3), (3, 2),
+    (-3, 2), (0, 4), (4, 0), (1, 4), (-1, 4), (4, 1), (-4, 1),
+    (3, 3), (-3, 3), (2, 4), (-2, 4), (4, 2), (-4, 2), (0, 5),
+    (3, 4), (-3, 4), (4, 3), (-4, 3), (5, 0), (1, 5), (-1, 5),
+    (5, 1), (-5, 1), (2, 5), (-2, 5), (5, 2), (-5, 2), (4, 4),
+    (-4, 4), (3, 5), (-3, 5), (5, 3), (-5, 3), (0, 6), (6, 0),
+    (1, 6), (-1, 6), (6, 1), (-6, 1), (2, 6), (-2, 6), (6, 2),
+    (-6, 2), (4, 5), (-4, 5), (5, 4), (-5, 4), (3, 6), (-3, 6),
+    (6, 3), (-6, 3), (0, 7), (7, 0), (1, 7), (-1, 7), (5, 5),
+    (-5, 5), (7, 1), (-7, 1), (4, 6), (-4, 6), (6, 4), (-6, 4),
+    (2, 7), (-2, 7), (7, 2), (-7, 2), (3, 7), (-3, 7), (7, 3),
+    (-7, 3), (5, 6), (-5, 6), (6, 5), (-6, 5), (8, 0), (4, 7),
+    (-4, 7), (7, 4), (-7, 4), (8, 1), (8, 2), (6, 6), (-6, 6),
+    (8, 3), (5, 7), (-5, 7), (7, 5), (-7, 5), (8, 4), (6, 7),
+    (-6, 7), (7, 6), (-7, 6), (8, 5), (7, 7), (-7, 7), (8, 6),
+    (8, 7),
+];
+
+fn distance(width: usize, code: usize) -> usize {
+    if code > 120 {
+        return code - 120;
+    }
+    let (x, y) = DISTANCE_MAP[code - 1];
+    (x as isize + y as isize * width as isize).max(1) as usize
+}
+
+/// Decodes an entropy coded image of ARGB pixels.
+/// Only the main image of a VP8L bitstream may use more than
+/// one group of prefix codes.
+fn decode_image_stream(
+    reader: &mut BitReader,
+    width: usize,
+    height: usize,
+    is_main_image: bool,
+) -> ImageResult<Vec<u32>> {
+    let cache_bits = if reader.read(1)? == 1 {
+        let bits = reader.read(4)?;
+        if !(1..=11).contains(&bits) {
+            return Err(error("invalid VP8L color cache size"));
+        }
+        bits
+    } else {
+        0
+    };
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+
+    let meta_codes = if is_main_image && reader.read(1)? == 1 {
+        let bits = reader.read(3)? + 2;
+        let image = decode_image_stream(
+            reader,
+            div_round_up(width, bits),
+            div_round_up(height, bits),
+            false,
+        )?;
+        Some((bits, image))
+    } else {
+        None
+    };
+    let num_groups = match &meta_codes {
+        Some((_, image)) => image.iter().map(|p| (p >> 8) & 0xffff).max().unwrap_or(0) + 1,
+        None => 1,
+    };
+
+    let mut groups: Vec<PrefixCodeGroup> = vec![];
+    for _ in 0..num_groups {
+        groups.push([
+            PrefixCode::decode(reader, 256 + 24 + cache_size)?,
+            PrefixCode::decode(reader, 256)?,
+            PrefixCode::decode(reader, 256)?,
+            PrefixCode::decode(reader, 256)?,
+            PrefixCode::decode(reader, 40)?,
+        ]);
+    }
+
+    let total = width * height;
+    let mut pixels = vec![0u32; total];
+    let mut cache = vec![0u32; cache_size];
+    let mut cached = 0;
+    let mut pos = 0;
+    while pos < total {
+        let group = match &meta_codes {
+            Some((bits, image)) => {
+                let (x, y) = (pos % width, pos / width);
+                let meta = image[(y >> bits) * div_round_up(width, *bits) + (x >> bits)];
+                &groups[((meta >> 8) & 0xffff) as usize]
+            }
+            None => &groups[0],
+        };
+
+        let green = group[0].read(reader)? as usize;
+        if green < 256 {
+            let red = group[1].read(reader)? as u32;
+            let blue = group[2].read(reader)? as u32;
+            let alpha = group[3].read(reader)? as u32;
+            pixels[pos] = alpha << 24 | red << 16 | (green as u32) << 8 | blue;
+            pos += 1;
+        } else if green < 256 + 24 {
+            let length = prefix_value(reader, green - 256)?;
+            let dist_symbol = group[4].read(reader)? as usize;
+            let dist = distance(width, prefix_value(reader, dist_symbol)?);
+            if dist > pos || length > total - pos {
+                return Err(error("invalid VP8L backward reference"));
+            }
+            for i in pos..pos + length {
+                pixels[i] = pixels[i - dist];
+            }
+            pos += length;
+        } else {
+            pixels[pos] = cache[green - 256 - 24];
+            pos += 1;
+        }
+
+        if cache_bits > 0 {
+            for &argb in &pixels[cached..pos] {
+                cache[(0x1e35a7bd_u32.wrapping_mul(argb) >> (32 - cache_bits)) as usize] = argb;
+            }
+            cached = pos;
+        }
+    }
+
+    Ok(pixels)
+}
+
+enum Transform {
+    Predictor {
+        width: usize,
+        bits: u32,
+        modes: Vec<u32>,
+    },
+    Color {
+        width: usize,
+        bits: u32,
+        elements: Vec<u32>,
+    },
+    SubtractGreen,
+    ColorIndexing {
+        width: usize,
+        bits: u32,
+        palette: Vec<u32>,
+    },
+}
+
+/// Adds each of the 8-bit channels of two ARGB pixels, modulo 256
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xff00ff00).wrapping_add(b & 0xff00ff00);
+    let red_blue = (a & 0x00ff00ff).wrapping_add(b & 0x00ff00ff);
+    (alpha_green & 0xff00ff00) | (red_blue & 0x00ff00ff)
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xfefefefe) >> 1) + (a & b)
+}
+
+fn channels(argb: u32) -> [i32; 4] {
+    [
+        (argb >> 24) as i32,
+        (argb >> 16 & 0xff) as i32,
+        (argb >> 8 & 0xff) as i32,
+        (argb & 0xff) as i32,
+    ]
+}
+
+fn from_channels(c: [i32; 4]) -> u32 {
+    let clamp = |v: i32| v.max(0).min(255) as u32;
+    clamp(c[0]) << 24 | clamp(c[1]) << 16 | clamp(c[2]) << 8 | clamp(c[3])
+}
+
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+    let dist_left: i32 = (0..4).map(|i| (t[i] - tl[i]).abs()).sum();
+    let dist_top: i32 = (0..4).map(|i| (l[i] - tl[i]).abs()).sum();
+    if dist_left < dist_top {
+        left
+    } else {
+        top
+    }
+}
+
+fn clamp_add_subtract_full(a: u32, b: u32, c: u32) -> u32 {
+    let (a, b, c) = (channels(a), channels(b), channels(c));
+    from_channels([
+        a[0] + b[0] - c[0],
+        a[1] + b[1] - c[1],
+        a[2] + b[2] - c[2],
+        a[3] + b[3] - c[3],
+    ])
+}
+
+fn clamp_add_subtract_half(a: u32, b: u32) -> u32 {
+    let (a, b) = (channels(a), channels(b));
+    from_channels([
+        a[0] + (a[0] - b[0]) / 2,
+        a[1] + (a[1] - b[1]) / 2,
+        a[2] + (a[2] - b[2]) / 2,
+        a[3] + (a[3] - b[3]) / 2,
+    ])
+}
+
+fn predict(mode: u32, left: u32, top: u32, top_left: u32, top_right: u32) -> u32 {
+    match mode {
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average2(average2(left, top_right), top),
+        6 => average2(left, top_left),
+        7 => average2(left, top),
+        8 => average2(top_left, top),
+        9 => average2(top, top_right),
+        10 => average2(average2(left, top_left), average2(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => clamp_add_subtract_full(left, top, top_left),
+        13 => clamp_add_subtract_half(average2(left, top), top_left),
+        _ => 0xff000000,
+    }
+}
+
+fn color_transform_delta(t: u32, c: u32) -> u32 {
+    ((t as u8 as i8 as i32 * c as u8 as i8 as i32) >> 5) as u32
+}
+
+impl Transform {
+    fn apply(&self, pixels: Vec<u32>, height: usize) -> Vec<u32> {
+        match self {
+            Self::Predictor { width, bits, modes } => {
+                let (width, mut pixels) = (*width, pixels);
+                let blocks_per_row = div_round_up(width, *bits);
+                for y in 0..height {
+                    for x in 0..width {
+                        let pos = y * width + x;
+                        let prediction = match (x, y) {
+                            (0, 0) => 0xff000000,
+                            (_, 0) => pixels[pos - 1],
+                            (0, _) => pixels[pos - width],
+                            _ => {
+                                let mode = modes[(y >> bits) * blocks_per_row + (x >> bits)];
+                                // The top right pixel of the last column is
+                                // the first pixel of the current row
+                                predict(
+                                    (mode >> 8) & 0xf,
+                                    pixels[pos - 1],
+                                    pixels[pos - width],
+                                    pixels[pos - width - 1],
+                                    pixels[pos - width + 1],
+                                )
+                            }
+                        };
+                        pixels[pos] = add_pixels(pixels[pos], prediction);
+                    }
+                }
+                pixels
+            }
+            Self::Color {
+                width,
+                bits,
+                elements,
+            } => {
+                let blocks_per_row = div_round_up(*width, *bits);
+                pixels
+                    .into_iter()
+                    .enumerate()
+                    .map(|(pos, argb)| {
+                        let (x, y) = (pos % width, pos / width);
+                        let element = elements[(y >> bits) * blocks_per_row + (x >> bits)];
+                        let green_to_red = element;
+                        let green_to_blue = element >> 8;
+                        let red_to_blue = element >> 16;
+
+                        let green = argb >> 8;
+                        let red = (argb >> 16)
+                            .wrapping_add(color_transform_delta(green_to_red, green))
+                            & 0xff;
+                        let blue = argb
+                            .wrapping_add(color_transform_delta(green_to_blue, green))
+                            .wrapping_add(color_transform_delta(red_to_blue, red))
+                            & 0xff;
+                        (argb & 0xff00ff00) | red << 16 | blue
+                    })
+                    .collect()
+            }
+            Self::SubtractGreen => pixels
+                .into_iter()
+                .map(|argb| {
+                    let green = (argb >> 8) & 0xff;
+                    add_pixels(argb, green << 16 | green)
+                })
+                .collect(),
+            Self::ColorIndexing {
+                width,
+                bits,
+                palette,
+            } => {
+                // Several small indices may be packed into the
+                // green channel of a single pixel
+                let packed_width = div_round_up(*width, *bits);
+                let bits_per_index = 8 >> bits;
+                let mask = (1 << bits_per_index) - 1;
+                let mut unpacked = Vec::with_capacity(width * height);
+                for y in 0..height {
+                    for x in 0..*width {
+                        let packed = pixels[y * packed_width + (x >> bits)] >> 8;
+                        let shift = (x & ((1 << bits) - 1)) as u32 * bits_per_index;
+                        let index = (packed >> shift) & mask;
+                        unpacked.push(palette.get(index as usize).copied().unwrap_or(0));
+                    }
+                }
+                unpacked
+            }
+        }
+    }
+}
+
+/// Decodes the transforms and image data of a VP8L bitstream
+/// into ARGB pixels
+fn decode_vp8l(reader: &mut BitReader, width: usize, height: usize) -> ImageResult<Vec<u32>> {
+    let mut transforms = vec![];
+    let mut seen = 0u8;
+    let mut coded_width = width;
+    while reader.read(1)? == 1 {
+        let kind = reader.read(2)?;
+        if seen & (1 << kind) != 0 {
+            return Err(error("repeated VP8L transform"));
+        }
+        seen |= 1 << kind;
+
+        transforms.push(match kind {
+            0 | 1 => {
+                let bits = reader.read(3)? + 2;
+                let image = decode_image_stream(
+                    reader,
+                    div_round_up(coded_width, bits),
+                    div_round_up(height, bits),
+                    false,
+                )?;
+                if kind == 0 {
+                    Transform::Predictor {
+                        width: coded_width,
+                        bits,
+                        modes: image,
+                    }
+                } else {
+                    Transform::Color {
+                        width: coded_width,
+                        bits,
+                        elements: image,
+                    }
+                }
+            }
+            2 => Transform::SubtractGreen,
+            _ => {
+                let size = reader.read(8)? as usize + 1;
+                let mut palette = decode_image_stream(reader, size, 1, false)?;
+                for i in 1..size {
+                    palette[i] = add_pixels(palette[i], palette[i - 1]);
+                }
+                let bits = match size {
+                    1..=2 => 3,
+                    3..=4 => 2,
+                    5..=16 => 1,
+                    _ => 0,
+                };
+                let transform = Transform::ColorIndexing {
+                    width: coded_width,
+                    bits,
+                    palette,
+                };
+                coded_width = div_round_up(coded_width, bits);
+                transform
+            }
+        });
+    }
+
+    let mut pixels = decode_image_stream(reader, coded_width, height, true)?;
+    for transform in transforms.iter().rev() {
+        pixels = transform.apply(pixels, height);
+    }
+    Ok(pixels)
+}
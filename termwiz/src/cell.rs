@@ -341,7 +341,7 @@ where
     D: Deserializer<'de>,
 {
     let text = String::deserialize(deserializer)?;
-    Ok(TeenyString::from_slice(text.as_bytes()))
+    Ok(TeenyString::from_str(&text, None, None))
 }
 
 #[cfg(feature = "use_serde")]
@@ -361,19 +361,28 @@ where
 /// be encoded directly into the usize bits stored in the struct.
 /// A marker bit (LSB for big endian, MSB for little endian) is
 /// set to indicate that the string is stored inline.
-/// If the string is longer than this then a `Vec<u8>` is allocated
+/// The column width of the string is also held alongside the
+/// marker bits, so that it doesn't need to be recomputed.
+/// If the string is longer than this then a `TeenyStringHeap` is allocated
 /// from the heap and the usize holds its raw pointer address.
 struct TeenyString(usize);
+
+/// Holds the storage for a `TeenyString` that doesn't fit inline
+struct TeenyStringHeap {
+    bytes: Vec<u8>,
+    width: usize,
+}
+
 impl TeenyString {
     fn marker_mask() -> usize {
         if cfg!(target_endian = "little") {
             cfg_if::cfg_if! {
                 if #[cfg(target_pointer_width = "64")] {
-                    0x7f000000_00000000
+                    0x1f000000_00000000
                 } else if #[cfg(target_pointer_width = "32")] {
-                    0x7f000000
+                    0x1f000000
                 } else if #[cfg(target_pointer_width = "16")] {
-                    0x7f00
+                    0x1f00
                 } else {
                     panic!("unsupported target");
                 }
@@ -384,30 +393,50 @@ impl TeenyString {
             0x1
         }
     }
+
+    /// The inline width is held in the 2 bits that sit just
+    /// above the marker bits
+    fn width_shift() -> usize {
+        if cfg!(target_endian = "little") {
+            (std::mem::size_of::<usize>() * 8) - 3
+        } else {
+            1
+        }
+    }
+
+    const MAX_INLINE_WIDTH: usize = 3;
+
     fn is_marker_bit_set(word: usize) -> bool {
         let mask = Self::marker_mask();
         word & mask == mask
     }
 
-    fn set_marker_bit(word: usize) -> usize {
-        word | Self::marker_mask()
+    fn set_marker_bit(word: usize, width: usize) -> usize {
+        word | Self::marker_mask() | (width << Self::width_shift())
     }
 
-    pub fn from_slice(bytes: &[u8]) -> Self {
+    /// Create storage for `s`, which is typically a single grapheme.
+    /// If `width` is None, then the column width is computed from
+    /// the text using the specified unicode version, or the default
+    /// version if that is None.
+    pub fn from_str(s: &str, width: Option<usize>, version: Option<UnicodeVersion>) -> Self {
         // De-fang the input text such that it has no special meaning
         // to a terminal.  All control and movement characters are rewritten
         // as a space.
-        let bytes = if bytes.is_empty() {
-            b" "
-        } else if bytes == b"\r\n" {
-            b" "
-        } else if bytes.len() == 1 && (bytes[0] < 0x20 || bytes[0] == 0x7f) {
-            b" "
+        let bytes = s.as_bytes();
+        let (bytes, width) = if bytes.is_empty()
+            || bytes == b"\r\n"
+            || (bytes.len() == 1 && (bytes[0] < 0x20 || bytes[0] == 0x7f))
+        {
+            (&b" "[..], 1)
         } else {
-            bytes
+            let width =
+                width.unwrap_or_else(|| version.unwrap_or_default().grapheme_column_width(s));
+            (bytes, width)
         };
+
         let len = bytes.len();
-        if len < std::mem::size_of::<usize>() {
+        if len < std::mem::size_of::<usize>() && width <= Self::MAX_INLINE_WIDTH {
             let mut word = 0usize;
             unsafe {
                 std::ptr::copy_nonoverlapping(
@@ -416,21 +445,30 @@ impl TeenyString {
                     len,
                 );
             }
-            let word = Self::set_marker_bit(word);
+            let word = Self::set_marker_bit(word, width);
             Self(word)
         } else {
-            let vec = Box::new(bytes.to_vec());
-            let ptr = Box::into_raw(vec);
+            let heap = Box::new(TeenyStringHeap {
+                bytes: bytes.to_vec(),
+                width,
+            });
+            let ptr = Box::into_raw(heap);
             Self(ptr as usize)
         }
     }
 
     pub fn from_char(c: char) -> Self {
         let mut bytes = [0u8; 8];
-        let len = c.len_utf8();
-        debug_assert!(len < std::mem::size_of_val(&bytes));
-        c.encode_utf8(&mut bytes);
-        Self::from_slice(&bytes[0..len])
+        Self::from_str(c.encode_utf8(&mut bytes), None, None)
+    }
+
+    pub fn width(&self) -> usize {
+        if Self::is_marker_bit_set(self.0) {
+            (self.0 >> Self::width_shift()) & Self::MAX_INLINE_WIDTH
+        } else {
+            let heap = self.0 as *const usize as *const TeenyStringHeap;
+            unsafe { (*heap).width }
+        }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -445,8 +483,8 @@ impl TeenyString {
 
             &bytes[0..len]
         } else {
-            let vec = self.0 as *const usize as *const Vec<u8>;
-            unsafe { (*vec).as_slice() }
+            let heap = self.0 as *const usize as *const TeenyStringHeap;
+            unsafe { (*heap).bytes.as_slice() }
         }
     }
 }
@@ -454,15 +492,20 @@ impl TeenyString {
 impl Drop for TeenyString {
     fn drop(&mut self) {
         if !Self::is_marker_bit_set(self.0) {
-            let vec = unsafe { Box::from_raw(self.0 as *mut usize as *mut Vec<u8>) };
-            drop(vec);
+            let heap = unsafe { Box::from_raw(self.0 as *mut usize as *mut TeenyStringHeap) };
+            drop(heap);
         }
     }
 }
 
 impl std::clone::Clone for TeenyString {
     fn clone(&self) -> Self {
-        Self::from_slice(self.as_bytes())
+        if Self::is_marker_bit_set(self.0) {
+            Self(self.0)
+        } else {
+            let s = unsafe { std::str::from_utf8_unchecked(self.as_bytes()) };
+            Self::from_str(s, Some(self.width()), None)
+        }
     }
 }
 
@@ -492,6 +535,7 @@ impl std::fmt::Debug for Cell {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         fmt.debug_struct("Cell")
             .field("text", &self.str())
+            .field("width", &self.width())
             .field("attrs", &self.attrs)
             .finish()
     }
@@ -522,8 +566,22 @@ impl Cell {
     /// over.  This function technically allows for an arbitrary string to
     /// be passed but it should not be used to hold strings other than
     /// graphemes.
+    /// The width of the cell is computed using the default `UnicodeVersion`.
     pub fn new_grapheme(text: &str, attrs: CellAttributes) -> Self {
-        let storage = TeenyString::from_slice(text.as_bytes());
+        let storage = TeenyString::from_str(text, None, None);
+
+        Self {
+            text: storage,
+            attrs,
+        }
+    }
+
+    /// Create a new cell holding the specified grapheme, whose width
+    /// has already been computed by the caller; this is typically
+    /// used together with `UnicodeVersion::grapheme_column_width`
+    /// so that the width respects the configured unicode options.
+    pub fn new_grapheme_with_width(text: &str, width: usize, attrs: CellAttributes) -> Self {
+        let storage = TeenyString::from_str(text, Some(width), None);
 
         Self {
             text: storage,
@@ -540,7 +598,7 @@ impl Cell {
 
     /// Returns the number of cells visually occupied by this grapheme
     pub fn width(&self) -> usize {
-        grapheme_column_width(self.str())
+        self.text.width()
     }
 
     /// Returns the attributes of the cell
@@ -553,43 +611,123 @@ impl Cell {
     }
 }
 
+/// Controls how the column width of graphemes is computed, so that
+/// the terminal can agree with the `wcwidth` implementation used
+/// by the applications that run inside it.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnicodeVersion {
+    /// The major version of the unicode width rules to follow.
+    /// Prior to version 9, emoji were considered to be a single
+    /// cell wide.  From version 14, the emoji variation selectors
+    /// select the width of the preceding emoji: VS15 (U+FE0E)
+    /// selects the single width text presentation and VS16 (U+FE0F)
+    /// selects the double width emoji presentation.
+    pub version: u8,
+    /// When true, characters whose East Asian Width property is
+    /// Ambiguous are treated as double width, as is the case in
+    /// legacy CJK contexts.
+    pub ambiguous_are_wide: bool,
+}
+
+impl Default for UnicodeVersion {
+    fn default() -> Self {
+        Self {
+            version: 9,
+            ambiguous_are_wide: false,
+        }
+    }
+}
+
+impl UnicodeVersion {
+    /// Returns the number of cells visually occupied by a sequence
+    /// of graphemes
+    pub fn column_width(&self, s: &str) -> usize {
+        use unicode_segmentation::UnicodeSegmentation;
+        s.graphemes(true)
+            .map(|g| self.grapheme_column_width(g))
+            .sum()
+    }
+
+    /// Returns the number of cells visually occupied by a grapheme.
+    /// The input string must be a single grapheme.
+    pub fn grapheme_column_width(&self, s: &str) -> usize {
+        // Due to this issue:
+        // https://github.com/unicode-rs/unicode-width/issues/4
+        // we cannot simply use the unicode-width crate to compute
+        // the desired value.
+        // Let's check for emoji-ness for ourselves first
+        use xi_unicode::EmojiExt;
+        let mut emoji = false;
+        let mut modifier = false;
+        let mut zwj = false;
+        let mut text_presentation = false;
+        let mut emoji_presentation = false;
+        for c in s.chars() {
+            match c {
+                '\u{200d}' => zwj = true,
+                '\u{fe0e}' => text_presentation = true,
+                '\u{fe0f}' => emoji_presentation = true,
+                _ => {}
+            }
+            if c.is_emoji_modifier_base() || c.is_emoji_modifier() {
+                modifier = true;
+            }
+            if c.is_emoji() {
+                emoji = true;
+            }
+        }
+
+        if emoji || modifier {
+            if self.version < 9 {
+                // Emoji were not double width prior to unicode 9
+                return 1;
+            }
+            if self.version >= 14 && !zwj {
+                if text_presentation {
+                    return 1;
+                }
+                if emoji_presentation {
+                    return 2;
+                }
+            }
+            if modifier || zwj {
+                // Treat modifier sequences as double wide.
+                // A ZWJ sequence is presented as a single double wide
+                // glyph, even if some of its components are narrow.
+                return 2;
+            }
+        }
+
+        let width = if self.ambiguous_are_wide {
+            UnicodeWidthStr::width_cjk(s)
+        } else {
+            UnicodeWidthStr::width(s)
+        };
+        if emoji {
+            // For sequences such as "deaf man", UnicodeWidthStr::width()
+            // returns 3 because of the widths of the component glyphs,
+            // rather than 2 for a single double width grapheme.
+            // If we saw any emoji within the characters then we assume
+            // that it can be a maximum of 2 cells in width.
+            width.min(2)
+        } else {
+            width
+        }
+    }
+}
+
 /// Returns the number of cells visually occupied by a sequence
-/// of graphemes
+/// of graphemes, using the default `UnicodeVersion`
 pub fn unicode_column_width(s: &str) -> usize {
-    use unicode_segmentation::UnicodeSegmentation;
-    s.graphemes(true).map(grapheme_column_width).sum()
+    UnicodeVersion::default().column_width(s)
 }
 
-/// Returns the number of cells visually occupied by a grapheme.
+/// Returns the number of cells visually occupied by a grapheme,
+/// using the default `UnicodeVersion`.
 /// The input string must be a single grapheme.
 pub fn grapheme_column_width(s: &str) -> usize {
-    // Due to this issue:
-    // https://github.com/unicode-rs/unicode-width/issues/4
-    // we cannot simply use the unicode-width crate to compute
-    // the desired value.
-    // Let's check for emoji-ness for ourselves first
-    use xi_unicode::EmojiExt;
-    let mut emoji = false;
-    for c in s.chars() {
-        if c.is_emoji_modifier_base() || c.is_emoji_modifier() {
-            // treat modifier sequences as double wide
-            return 2;
-        }
-        if c.is_emoji() {
-            emoji = true;
-        }
-    }
-    let width = UnicodeWidthStr::width(s);
-    if emoji {
-        // For sequences such as "deaf man", UnicodeWidthStr::width()
-        // returns 3 because of the widths of the component glyphs,
-        // rather than 2 for a single double width grapheme.
-        // If we saw any emoji within the characters then we assume
-        // that it can be a maximum of 2 cells in width.
-        width.min(2)
-    } else {
-        width
-    }
+    UnicodeVersion::default().grapheme_column_width(s)
 }
 
 /// Models a change in the attributes of a cell in a stream of changes.
@@ -619,8 +757,13 @@ mod test {
         let s = TeenyString::from_char('a');
         assert_eq!(s.as_bytes(), &[b'a']);
 
-        let longer = TeenyString::from_slice(b"hellothere");
+        let longer = TeenyString::from_str("hellothere", None, None);
         assert_eq!(longer.as_bytes(), b"hellothere");
+        assert_eq!(longer.width(), 10);
+
+        let wide = TeenyString::from_str("\u{1f9b6}", None, None);
+        assert_eq!(wide.width(), 2);
+        assert_eq!(wide.clone().width(), 2);
     }
 
     #[test]
@@ -697,4 +840,51 @@ mod test {
         eprintln!("font_awesome_star {}", font_awesome_star.escape_debug());
         assert_eq!(unicode_column_width(font_awesome_star), 1);
     }
+
+    #[test]
+    fn unicode_version_width() {
+        let unicode_8 = UnicodeVersion {
+            version: 8,
+            ambiguous_are_wide: false,
+        };
+        let unicode_14 = UnicodeVersion {
+            version: 14,
+            ambiguous_are_wide: false,
+        };
+        let cjk = UnicodeVersion {
+            version: 9,
+            ambiguous_are_wide: true,
+        };
+
+        let foot = "\u{1f9b6}";
+        assert_eq!(unicode_8.grapheme_column_width(foot), 1);
+        assert_eq!(grapheme_column_width(foot), 2);
+
+        // HEAVY BLACK HEART has a text presentation by default
+        let heart = "\u{2764}";
+        let heart_emoji = "\u{2764}\u{fe0f}";
+        let foot_text = "\u{1f9b6}\u{fe0e}";
+        assert_eq!(grapheme_column_width(heart), 1);
+        assert_eq!(unicode_14.grapheme_column_width(heart), 1);
+        assert_eq!(unicode_14.grapheme_column_width(heart_emoji), 2);
+        assert_eq!(unicode_14.grapheme_column_width(foot_text), 1);
+
+        // A ZWJ sequence whose first component is narrow
+        let eye_in_speech_bubble = "\u{1f441}\u{200d}\u{1f5e8}";
+        assert_eq!(grapheme_column_width(eye_in_speech_bubble), 2);
+
+        // CIRCLED DIGIT ONE is East Asian Ambiguous
+        let circled_one = "\u{2460}";
+        assert_eq!(grapheme_column_width(circled_one), 1);
+        assert_eq!(cjk.grapheme_column_width(circled_one), 2);
+        assert_eq!(cjk.column_width("a\u{2460}"), 3);
+
+        let cell = Cell::new_grapheme_with_width(circled_one, 2, CellAttributes::default());
+        assert_eq!(cell.width(), 2);
+        // The width is derived from the text, so it doesn't take
+        // part in comparisons
+        let default_width = Cell::new_grapheme(circled_one, CellAttributes::default());
+        assert_eq!(default_width.width(), 1);
+        assert_eq!(cell, default_width);
+    }
 }
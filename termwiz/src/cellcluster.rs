@@ -1,4 +1,28 @@
 use crate::cell::{Cell, CellAttributes};
+use unicode_bidi::{BidiInfo, Level};
+
+/// The direction in which the text of a `CellCluster` flows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Self::LeftToRight
+    }
+}
+
+impl Direction {
+    fn from_level(level: Level) -> Self {
+        if level.is_rtl() {
+            Self::RightToLeft
+        } else {
+            Self::LeftToRight
+        }
+    }
+}
 
 /// A `CellCluster` is another representation of a Line.
 /// A `Vec<CellCluster>` is produced by walking through the Cells in
@@ -10,6 +34,9 @@ pub struct CellCluster {
     pub attrs: CellAttributes,
     pub text: String,
     pub byte_to_cell_idx: Vec<usize>,
+    /// The text is always held in logical order; a RightToLeft
+    /// cluster needs to be shaped right to left by the renderer.
+    pub direction: Direction,
 }
 
 impl CellCluster {
@@ -26,13 +53,23 @@ impl CellCluster {
             last_cluster = match last_cluster.take() {
                 None => {
                     // Start new cluster
-                    Some(CellCluster::new(c.attrs().clone(), cell_str, cell_idx))
+                    Some(CellCluster::new(
+                        c.attrs().clone(),
+                        cell_str,
+                        cell_idx,
+                        Direction::LeftToRight,
+                    ))
                 }
                 Some(mut last) => {
                     if last.attrs != normalized_attr {
                         // Flush pending cluster and start a new one
                         clusters.push(last);
-                        Some(CellCluster::new(normalized_attr, cell_str, cell_idx))
+                        Some(CellCluster::new(
+                            normalized_attr,
+                            cell_str,
+                            cell_idx,
+                            Direction::LeftToRight,
+                        ))
                     } else {
                         // Add to current cluster
                         last.add(cell_str, cell_idx);
@@ -51,7 +88,12 @@ impl CellCluster {
     }
 
     /// Start off a new cluster with some initial data
-    fn new(attrs: CellAttributes, text: &str, cell_idx: usize) -> CellCluster {
+    fn new(
+        attrs: CellAttributes,
+        text: &str,
+        cell_idx: usize,
+        direction: Direction,
+    ) -> CellCluster {
         let mut idx = Vec::new();
         for _ in 0..text.len() {
             idx.push(cell_idx);
@@ -60,6 +102,7 @@ impl CellCluster {
             attrs,
             text: text.into(),
             byte_to_cell_idx: idx,
+            direction,
         }
     }
 
@@ -70,4 +113,316 @@ impl CellCluster {
         }
         self.text.push_str(text);
     }
+
+    /// Split this cluster into several clusters such that each of
+    /// the cell indices in `boundaries` starts a new cluster.
+    /// This is used by the renderer to shape the text on either side
+    /// of the cursor or a selection separately, so that ligatures are
+    /// not formed across them.
+    /// The pieces are returned in logical order.
+    pub fn split_at_cells(self, boundaries: &[usize]) -> Vec<CellCluster> {
+        if !self
+            .byte_to_cell_idx
+            .iter()
+            .skip(1)
+            .any(|idx| boundaries.contains(idx))
+        {
+            return vec![self];
+        }
+
+        let mut clusters = vec![];
+        let mut start = 0;
+        let mut prior_cell = None;
+        for (byte, &cell_idx) in self.byte_to_cell_idx.iter().enumerate() {
+            if prior_cell != Some(cell_idx) {
+                if byte > start && boundaries.contains(&cell_idx) {
+                    clusters.push(CellCluster {
+                        attrs: self.attrs.clone(),
+                        text: self.text[start..byte].to_string(),
+                        byte_to_cell_idx: self.byte_to_cell_idx[start..byte].to_vec(),
+                        direction: self.direction,
+                    });
+                    start = byte;
+                }
+                prior_cell = Some(cell_idx);
+            }
+        }
+        clusters.push(CellCluster {
+            attrs: self.attrs,
+            text: self.text[start..].to_string(),
+            byte_to_cell_idx: self.byte_to_cell_idx[start..].to_vec(),
+            direction: self.direction,
+        });
+        clusters
+    }
+
+    /// Returns the range of logical cell indices covered by this cluster
+    pub fn cell_range(&self) -> std::ops::Range<usize> {
+        let first = self.byte_to_cell_idx.first().copied().unwrap_or(0);
+        let last = self.byte_to_cell_idx.last().copied().unwrap_or(first);
+        first..last + 1
+    }
+}
+
+/// Holds the clusters of a line in visual order together with
+/// the mapping between the logical cell positions held by the
+/// terminal model and the visual positions at which they are
+/// displayed.
+/// The `byte_to_cell_idx` of each cluster continues to hold logical
+/// cell indices; use `logical_to_visual` to find out where a given
+/// cell is to be displayed.
+#[derive(Debug, Clone)]
+pub struct BidiClusters {
+    pub clusters: Vec<CellCluster>,
+    logical_to_visual: Vec<usize>,
+    visual_to_logical: Vec<usize>,
+}
+
+impl BidiClusters {
+    /// Wrap up clusters that are already in visual order, such as
+    /// those produced by `CellCluster::make_cluster`.
+    pub fn identity(clusters: Vec<CellCluster>) -> Self {
+        Self {
+            clusters,
+            logical_to_visual: vec![],
+            visual_to_logical: vec![],
+        }
+    }
+
+    /// Apply the Unicode Bidirectional Algorithm (UAX #9) to a set of
+    /// visible cells and compute the clusters in visual order.
+    /// The paragraph embedding level is always left-to-right, which is
+    /// the behavior expected of a terminal in implicit mode.
+    /// `num_cells` is the total number of cells in the line, including
+    /// those that are obscured by wide characters.
+    pub fn make_clusters<'a>(
+        num_cells: usize,
+        iter: impl Iterator<Item = (usize, &'a Cell)>,
+    ) -> Self {
+        let cells: Vec<(usize, &Cell)> = iter.collect();
+
+        let mut text = String::new();
+        let mut byte_to_unit = vec![];
+        for (unit, (_, cell)) in cells.iter().enumerate() {
+            let s = cell.str();
+            for _ in 0..s.len() {
+                byte_to_unit.push(unit);
+            }
+            text.push_str(s);
+        }
+
+        let info = BidiInfo::new(&text, Some(Level::ltr()));
+        if !info.has_rtl() {
+            return Self::identity(CellCluster::make_cluster(cells.into_iter()));
+        }
+
+        // Resolve the level of each visible cell; the level of the
+        // first byte of the cell applies to the whole grapheme.
+        let mut levels = vec![Level::ltr(); cells.len()];
+        let mut resolved = vec![false; cells.len()];
+        for para in &info.paragraphs {
+            let para_levels = info.reordered_levels(para, para.range.clone());
+            for byte in para.range.clone() {
+                let unit = byte_to_unit[byte];
+                if !resolved[unit] {
+                    resolved[unit] = true;
+                    levels[unit] = para_levels[byte];
+                }
+            }
+        }
+
+        // L2: reverse any contiguous sequence of cells that are at
+        // the highest level or above, from the highest level down to
+        // the lowest odd level on the line.
+        let mut order: Vec<usize> = (0..cells.len()).collect();
+        let max_level = levels.iter().map(Level::number).max().unwrap_or(0);
+        let min_level = levels.iter().map(Level::number).min().unwrap_or(0);
+        let lowest_odd = min_level | 1;
+        let mut level = max_level;
+        while level >= lowest_odd {
+            let mut idx = 0;
+            while idx < order.len() {
+                if levels[order[idx]].number() < level {
+                    idx += 1;
+                    continue;
+                }
+                let start = idx;
+                while idx < order.len() && levels[order[idx]].number() >= level {
+                    idx += 1;
+                }
+                order[start..idx].reverse();
+            }
+            level -= 1;
+        }
+
+        // Wide cells are kept intact when they are moved about, so
+        // their trailing spacer cells travel along with them.
+        let mut logical_to_visual: Vec<usize> = (0..num_cells).collect();
+        let mut visual_to_logical: Vec<usize> = (0..num_cells).collect();
+        let mut visual_idx = 0;
+        for &unit in &order {
+            let (cell_idx, cell) = cells[unit];
+            for i in 0..cell.width().max(1) {
+                if cell_idx + i < num_cells && visual_idx < num_cells {
+                    logical_to_visual[cell_idx + i] = visual_idx;
+                    visual_to_logical[visual_idx] = cell_idx + i;
+                }
+                visual_idx += 1;
+            }
+        }
+
+        // Group runs of visually adjacent cells that share the same
+        // attributes and direction, and that are also logically adjacent.
+        let mut clusters = vec![];
+        let mut run: Vec<usize> = vec![];
+        let mut run_attrs: Option<CellAttributes> = None;
+        let mut run_direction = Direction::LeftToRight;
+
+        let flush = |run: &mut Vec<usize>,
+                     attrs: CellAttributes,
+                     direction: Direction,
+                     clusters: &mut Vec<CellCluster>| {
+            if direction == Direction::RightToLeft {
+                run.reverse();
+            }
+            let mut iter = run.drain(..);
+            if let Some(first) = iter.next() {
+                let (cell_idx, cell) = cells[first];
+                let mut cluster = CellCluster::new(attrs, cell.str(), cell_idx, direction);
+                for unit in iter {
+                    let (cell_idx, cell) = cells[unit];
+                    cluster.add(cell.str(), cell_idx);
+                }
+                clusters.push(cluster);
+            }
+        };
+
+        for &unit in &order {
+            let (_, cell) = cells[unit];
+            let attrs = cell.attrs().clone().set_wrapped(false).clone();
+            let direction = Direction::from_level(levels[unit]);
+
+            let contiguous = match (run.last(), direction) {
+                (Some(&prior), Direction::LeftToRight) => prior + 1 == unit,
+                (Some(&prior), Direction::RightToLeft) => unit + 1 == prior,
+                (None, _) => false,
+            };
+
+            if !contiguous
+                || run_direction != direction
+                || run_attrs.as_ref().map(|a| *a != attrs).unwrap_or(true)
+            {
+                if let Some(prior_attrs) = run_attrs.take() {
+                    flush(&mut run, prior_attrs, run_direction, &mut clusters);
+                }
+                run_attrs = Some(attrs);
+                run_direction = direction;
+            }
+            run.push(unit);
+        }
+        if let Some(prior_attrs) = run_attrs.take() {
+            flush(&mut run, prior_attrs, run_direction, &mut clusters);
+        }
+
+        Self {
+            clusters,
+            logical_to_visual,
+            visual_to_logical,
+        }
+    }
+
+    /// Returns the visual column at which the logical cell `cell_idx`
+    /// is displayed
+    pub fn logical_to_visual(&self, cell_idx: usize) -> usize {
+        self.logical_to_visual
+            .get(cell_idx)
+            .copied()
+            .unwrap_or(cell_idx)
+    }
+
+    /// Returns the logical cell that is displayed at the visual
+    /// column `visual_idx`
+    pub fn visual_to_logical(&self, visual_idx: usize) -> usize {
+        self.visual_to_logical
+            .get(visual_idx)
+            .copied()
+            .unwrap_or(visual_idx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::surface::line::Line;
+
+    fn visual_text(bidi: &BidiClusters) -> String {
+        bidi.clusters
+            .iter()
+            .map(|c| match c.direction {
+                Direction::LeftToRight => c.text.clone(),
+                Direction::RightToLeft => c.text.chars().rev().collect(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ltr_is_identity() {
+        let line = Line::from_text("hello", &CellAttributes::default());
+        let bidi = BidiClusters::make_clusters(5, line.visible_cells());
+        assert_eq!(bidi.clusters.len(), 1);
+        assert_eq!(bidi.clusters[0].direction, Direction::LeftToRight);
+        for i in 0..5 {
+            assert_eq!(bidi.logical_to_visual(i), i);
+            assert_eq!(bidi.visual_to_logical(i), i);
+        }
+    }
+
+    #[test]
+    fn rtl_run_is_reversed() {
+        // "ab" followed by the hebrew letters alef, bet, gimel and then "cd"
+        let line = Line::from_text("ab \u{5d0}\u{5d1}\u{5d2} cd", &CellAttributes::default());
+        let bidi = BidiClusters::make_clusters(9, line.visible_cells());
+        assert_eq!(visual_text(&bidi), "ab \u{5d2}\u{5d1}\u{5d0} cd");
+
+        let rtl = bidi
+            .clusters
+            .iter()
+            .find(|c| c.direction == Direction::RightToLeft)
+            .unwrap();
+        // The text of the cluster remains in logical order
+        assert_eq!(rtl.text, "\u{5d0}\u{5d1}\u{5d2}");
+        assert_eq!(rtl.byte_to_cell_idx[0], 3);
+
+        assert_eq!(bidi.logical_to_visual(3), 5);
+        assert_eq!(bidi.logical_to_visual(5), 3);
+        assert_eq!(bidi.visual_to_logical(3), 5);
+        assert_eq!(bidi.visual_to_logical(5), 3);
+        assert_eq!(bidi.logical_to_visual(7), 7);
+    }
+
+    #[test]
+    fn split_cluster_at_cells() {
+        let line = Line::from_text("a->b", &CellAttributes::default());
+        let cluster = line.cluster().remove(0);
+        assert_eq!(cluster.cell_range(), 0..4);
+
+        let pieces = cluster.clone().split_at_cells(&[2, 3]);
+        let text: Vec<&str> = pieces.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(text, vec!["a-", ">", "b"]);
+        assert_eq!(pieces[1].byte_to_cell_idx, vec![2]);
+        assert_eq!(pieces[2].cell_range(), 3..4);
+
+        // Boundaries at the start of, or outside, the cluster have no effect
+        let pieces = cluster.split_at_cells(&[0, 7]);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].text, "a->b");
+    }
+
+    #[test]
+    fn numbers_within_rtl() {
+        // Digits embedded in RTL text retain their left to right order
+        let line = Line::from_text("\u{5d0} 12 \u{5d1}", &CellAttributes::default());
+        let bidi = BidiClusters::make_clusters(6, line.visible_cells());
+        assert_eq!(visual_text(&bidi), "\u{5d1} 12 \u{5d0}");
+    }
 }
@@ -562,10 +562,21 @@ pub enum DecPrivateModeCode {
     AnyEventMouse = 1003,
     /// Enable FocusIn/FocusOut events
     FocusTracking = 1004,
+    /// Encode mouse coordinates as UTF-8 characters, extending the
+    /// range of the legacy encoding to 2015 cells.
+    Utf8Mouse = 1005,
     /// Use extended coordinate system in mouse reporting.  Does not
     /// enable mouse reporting itself, it just controls how reports
     /// will be encoded.
     SGRMouse = 1006,
+    /// When the alternate screen is active and mouse reporting is
+    /// not enabled, send mouse wheel events as cursor up/down keys
+    AlternateScroll = 1007,
+    /// Use the decimal encoding of mouse reports used by urxvt
+    UrxvtMouse = 1015,
+    /// Like SGRMouse, but reports coordinates in pixels rather
+    /// than cells
+    SGRPixelsMouse = 1016,
     /// Save cursor as in DECSC
     SaveCursor = 1048,
     ClearAndEnableAlternateScreen = 1049,
@@ -588,6 +599,12 @@ pub enum TerminalModeCode {
     KeyboardAction = 2,
     /// https://vt100.net/docs/vt510-rm/IRM.html
     Insert = 4,
+    /// BDSM - ECMA-48 Bi-directional support mode.
+    /// When set, the terminal is in implicit mode and applies the
+    /// bidirectional algorithm when presenting text.  When reset,
+    /// the terminal is in explicit mode and presents text in the
+    /// order that it was written.
+    BiDirectionalSupportMode = 8,
     /// https://vt100.net/docs/vt510-rm/SRM.html
     /// But in the MS terminal this is cursor blinking.
     SendReceive = 12,
@@ -2394,7 +2411,9 @@ mod test {
                 CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
                     DecPrivateModeCode::AnyEventMouse,
                 ))),
-                CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Unspecified(1005))),
+                CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::Utf8Mouse,
+                ))),
                 CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
                     DecPrivateModeCode::SGRMouse,
                 ))),
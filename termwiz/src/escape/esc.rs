@@ -73,6 +73,15 @@ pub enum EscCode {
     /// Designate Character Set – US ASCII
     AsciiCharacterSet = esc!('(', 'B'),
 
+    /// DECDHL - DEC double-height line, top half
+    DecDoubleHeightTopHalfLine = esc!('#', '3'),
+    /// DECDHL - DEC double-height line, bottom half
+    DecDoubleHeightBottomHalfLine = esc!('#', '4'),
+    /// DECSWL - DEC single-width line
+    DecSingleWidthLine = esc!('#', '5'),
+    /// DECDWL - DEC double-width line
+    DecDoubleWidthLine = esc!('#', '6'),
+
     /// https://vt100.net/docs/vt510-rm/DECALN.html
     DecScreenAlignmentDisplay = esc!('#', '8'),
 
@@ -172,5 +181,12 @@ mod test {
     fn test() {
         assert_eq!(parse("(0"), Esc::Code(EscCode::DecLineDrawing));
         assert_eq!(parse("(B"), Esc::Code(EscCode::AsciiCharacterSet));
+        assert_eq!(parse("#3"), Esc::Code(EscCode::DecDoubleHeightTopHalfLine));
+        assert_eq!(
+            parse("#4"),
+            Esc::Code(EscCode::DecDoubleHeightBottomHalfLine)
+        );
+        assert_eq!(parse("#5"), Esc::Code(EscCode::DecSingleWidthLine));
+        assert_eq!(parse("#6"), Esc::Code(EscCode::DecDoubleWidthLine));
     }
 }
@@ -44,6 +44,9 @@ pub enum OperatingSystemCommand {
     ResetDynamicColor(DynamicColorNumber),
     CurrentWorkingDirectory(String),
     ResetColors(Vec<u8>),
+    /// OSC 777; the rxvt extension mechanism, whose first parameter
+    /// names the extension, eg: `notify`.
+    RxvtExtension(Vec<String>),
 
     Unspecified(Vec<Vec<u8>>),
 }
@@ -296,6 +299,14 @@ impl OperatingSystemCommand {
             SetHyperlink => Ok(OperatingSystemCommand::SetHyperlink(Hyperlink::parse(osc)?)),
             ManipulateSelectionData => Self::parse_selection(osc),
             SystemNotification => single_string!(SystemNotification),
+            RxvtProprietary => {
+                ensure!(osc.len() > 1, "no extension name");
+                let params = osc[1..]
+                    .iter()
+                    .map(|p| String::from_utf8(p.to_vec()))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                Ok(OperatingSystemCommand::RxvtExtension(params))
+            }
             SetCurrentWorkingDirectory => single_string!(CurrentWorkingDirectory),
             ITermProprietary => {
                 self::ITermProprietary::parse(osc).map(OperatingSystemCommand::ITermProprietary)
@@ -485,6 +496,7 @@ impl Display for OperatingSystemCommand {
             QuerySelection(s) => write!(f, "52;{};?", s)?,
             SetSelection(s, val) => write!(f, "52;{};{}", s, base64::encode(val))?,
             SystemNotification(s) => write!(f, "9;{}", s)?,
+            RxvtExtension(params) => write!(f, "777;{}", params.join(";"))?,
             ITermProprietary(i) => i.fmt(f)?,
             FinalTermSemanticPrompt(i) => i.fmt(f)?,
             ResetColors(colors) => {
@@ -1235,6 +1247,32 @@ mod test {
         );
     }
 
+    #[test]
+    fn notifications() {
+        assert_eq!(
+            parse(&["9", "hello"], "\x1b]9;hello\x1b\\"),
+            OperatingSystemCommand::SystemNotification("hello".into())
+        );
+
+        assert_eq!(
+            parse(
+                &["777", "notify", "title", "body"],
+                "\x1b]777;notify;title;body\x1b\\"
+            ),
+            OperatingSystemCommand::RxvtExtension(vec![
+                "notify".into(),
+                "title".into(),
+                "body".into()
+            ])
+        );
+
+        // no extension name
+        assert_eq!(
+            parse(&["777"], "\x1b]777\x1b\\"),
+            OperatingSystemCommand::Unspecified(vec![b"777".to_vec()])
+        );
+    }
+
     #[test]
     fn hyperlink() {
         assert_eq!(
@@ -1,7 +1,10 @@
 use crate::color::RgbColor;
+use crate::escape::csi::{
+    Cursor, DecPrivateMode, DecPrivateModeCode, Edit, EraseInDisplay, EraseInLine, Mode,
+};
 use crate::escape::{
-    Action, DeviceControlMode, EnterDeviceControlMode, Esc, OperatingSystemCommand,
-    ShortDeviceControl, Sixel, SixelData, CSI,
+    Action, DeviceControlMode, EnterDeviceControlMode, Esc, EscCode, OneBased,
+    OperatingSystemCommand, ShortDeviceControl, Sixel, SixelData, CSI,
 };
 use log::error;
 use num_traits::FromPrimitive;
@@ -22,6 +25,9 @@ struct SixelBuilder {
 struct ParseState {
     sixel: Option<SixelBuilder>,
     dcs: Option<ShortDeviceControl>,
+    /// Set when DECANM is reset to select VT52 compatibility mode,
+    /// and cleared when the VT52 `ESC <` sequence returns to ANSI mode.
+    vt52: bool,
 }
 
 /// The `Parser` struct holds the state machine that is used to decode
@@ -30,6 +36,11 @@ struct ParseState {
 /// You can either have the parser trigger a callback as `Action`s are
 /// decoded, or have it return a `Vec<Action>` holding zero-or-more
 /// decoded actions.
+///
+/// The parser follows the DECANM mode: resetting it via `CSI ? 2 l`
+/// switches the parser into VT52 compatibility mode, where VT52
+/// escape sequences are translated into their ANSI equivalent `Action`s.
+/// The VT52 `ESC <` sequence returns to ANSI mode.
 pub struct Parser {
     state_machine: VTParser,
     state: RefCell<ParseState>,
@@ -50,11 +61,32 @@ impl Parser {
     }
 
     pub fn parse<F: FnMut(Action)>(&mut self, bytes: &[u8], mut callback: F) {
-        let mut perform = Performer {
-            callback: &mut callback,
-            state: &mut self.state.borrow_mut(),
-        };
-        self.state_machine.parse(bytes, &mut perform);
+        let mut state = self.state.borrow_mut();
+        for b in bytes {
+            self.state_machine.parse_byte(
+                *b,
+                &mut Performer {
+                    callback: &mut callback,
+                    state: &mut state,
+                },
+            );
+            Self::sync_vt52_mode(&mut self.state_machine, &state);
+        }
+    }
+
+    /// Returns true if the parser is currently in VT52 compatibility mode
+    pub fn is_vt52_mode(&self) -> bool {
+        self.state.borrow().vt52
+    }
+
+    /// Propagate a change in the DECANM mode observed by the Performer
+    /// to the underlying state machine, so that subsequent bytes are
+    /// parsed according to the newly selected mode.
+    #[inline]
+    fn sync_vt52_mode(state_machine: &mut VTParser, state: &ParseState) {
+        if state.vt52 != state_machine.is_vt52_mode() {
+            state_machine.set_vt52_mode(state.vt52);
+        }
     }
 
     /// A specialized version of the parser that halts after recognizing the
@@ -81,6 +113,7 @@ impl Parser {
             };
             for (idx, b) in bytes.iter().enumerate() {
                 self.state_machine.parse_byte(*b, &mut perform);
+                Self::sync_vt52_mode(&mut self.state_machine, perform.state);
                 if first.borrow().is_some() {
                     // if we recognized an action, record the iterator index
                     first_idx = Some(idx);
@@ -108,13 +141,15 @@ impl Parser {
         let mut actions = Vec::new();
         let mut first_idx = None;
         for (idx, b) in bytes.iter().enumerate() {
+            let mut state = self.state.borrow_mut();
             self.state_machine.parse_byte(
                 *b,
                 &mut Performer {
                     callback: &mut |action| actions.push(action),
-                    state: &mut self.state.borrow_mut(),
+                    state: &mut state,
                 },
             );
+            Self::sync_vt52_mode(&mut self.state_machine, &state);
             if !actions.is_empty() {
                 // if we recognized any actions, record the iterator index
                 first_idx = Some(idx);
@@ -224,17 +259,27 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
             ignored_extra_intermediates,
             control as char,
         ) {
+            if let CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::DecAnsiMode,
+            ))) = &action
+            {
+                self.state.vt52 = true;
+            }
             (self.callback)(Action::CSI(action));
         }
     }
 
     fn esc_dispatch(
         &mut self,
-        _params: &[i64],
+        params: &[i64],
         intermediates: &[u8],
         _ignored_extra_intermediates: bool,
         control: u8,
     ) {
+        if self.state.vt52 {
+            return self.vt52_dispatch(params, control);
+        }
+
         // It doesn't appear to be possible for params.len() > 1 due to the way
         // that the state machine in vte functions.  As such, it also seems to
         // be impossible for ignored_extra_intermediates to be true too.
@@ -249,6 +294,54 @@ impl<'a, F: FnMut(Action)> VTActor for Performer<'a, F> {
     }
 }
 
+impl<'a, F: FnMut(Action)> Performer<'a, F> {
+    /// Translate a VT52 escape sequence into the equivalent ANSI action.
+    /// <https://vt100.net/docs/vt100-ug/chapter3.html#S3.3.5>
+    fn vt52_dispatch(&mut self, params: &[i64], control: u8) {
+        let action = match control {
+            b'A' => Action::CSI(CSI::Cursor(Cursor::Up(1))),
+            b'B' => Action::CSI(CSI::Cursor(Cursor::Down(1))),
+            b'C' => Action::CSI(CSI::Cursor(Cursor::Right(1))),
+            b'D' => Action::CSI(CSI::Cursor(Cursor::Left(1))),
+            b'F' => Action::Esc(Esc::Code(EscCode::DecLineDrawing)),
+            b'G' => Action::Esc(Esc::Code(EscCode::AsciiCharacterSet)),
+            b'H' => Action::CSI(CSI::Cursor(Cursor::Position {
+                line: OneBased::new(1),
+                col: OneBased::new(1),
+            })),
+            b'I' => Action::Esc(Esc::Code(EscCode::ReverseIndex)),
+            b'J' => Action::CSI(CSI::Edit(Edit::EraseInDisplay(
+                EraseInDisplay::EraseToEndOfDisplay,
+            ))),
+            b'K' => Action::CSI(CSI::Edit(Edit::EraseInLine(EraseInLine::EraseToEndOfLine))),
+            b'Y' => {
+                let line = params.get(0).copied().unwrap_or(0).max(0) as u32;
+                let col = params.get(1).copied().unwrap_or(0).max(0) as u32;
+                Action::CSI(CSI::Cursor(Cursor::Position {
+                    line: OneBased::from_zero_based(line),
+                    col: OneBased::from_zero_based(col),
+                }))
+            }
+            // The host is responsible for answering with the VT52
+            // identity when DECID arrives in VT52 mode
+            b'Z' => Action::Esc(Esc::Code(EscCode::ReturnTerminalId)),
+            b'=' => Action::Esc(Esc::Code(EscCode::DecApplicationKeyPad)),
+            b'>' => Action::Esc(Esc::Code(EscCode::DecNormalKeyPad)),
+            b'<' => {
+                self.state.vt52 = false;
+                Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::DecAnsiMode,
+                ))))
+            }
+            _ => Action::Esc(Esc::Unspecified {
+                intermediate: None,
+                control,
+            }),
+        };
+        (self.callback)(action);
+    }
+}
+
 impl SixelBuilder {
     fn new(params: &[i64]) -> Self {
         let pan = match params.get(0).unwrap_or(&0) {
@@ -421,7 +514,6 @@ mod test {
     use crate::cell::{Intensity, Underline};
     use crate::color::ColorSpec;
     use crate::escape::csi::Sgr;
-    use crate::escape::EscCode;
     use std::io::Write;
 
     fn encode(seq: &Vec<Action>) -> String {
@@ -578,6 +670,36 @@ mod test {
         assert_eq!(encode(&actions), "\x1b%H");
     }
 
+    #[test]
+    fn vt52() {
+        let mut p = Parser::new();
+        let actions = p.parse_as_vec(b"\x1b[?2l\x1bA\x1bY\x21\x22x\x1bZ\x1b<\x1bA");
+        assert_eq!(
+            vec![
+                Action::CSI(CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::DecAnsiMode,
+                )))),
+                Action::CSI(CSI::Cursor(Cursor::Up(1))),
+                Action::CSI(CSI::Cursor(Cursor::Position {
+                    line: OneBased::new(2),
+                    col: OneBased::new(3),
+                })),
+                Action::Print('x'),
+                Action::Esc(Esc::Code(EscCode::ReturnTerminalId)),
+                Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                    DecPrivateModeCode::DecAnsiMode,
+                )))),
+                // Back in ANSI mode, this is no longer cursor up
+                Action::Esc(Esc::Unspecified {
+                    intermediate: None,
+                    control: b'A',
+                }),
+            ],
+            actions
+        );
+        assert!(!p.is_vt52_mode());
+    }
+
     #[test]
     fn sixel() {
         let mut p = Parser::new();
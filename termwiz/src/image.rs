@@ -15,6 +15,7 @@ use ordered_float::NotNan;
 #[cfg(feature = "use_serde")]
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[cfg(feature = "use_serde")]
 fn deserialize_notnan<'de, D>(deserializer: D) -> Result<NotNan<f32>, D::Error>
@@ -113,26 +114,84 @@ impl ImageCell {
 
 static IMAGE_ID: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
 
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Clone, PartialEq, Eq)]
+pub enum ImageDataType {
+    /// Data is in the native image file format
+    EncodedFile(Vec<u8>),
+    /// Data is a sequence of decoded frames of RGBA pixels.
+    /// Each frame covers the full width and height of the image
+    /// and is displayed for its corresponding duration before
+    /// advancing to the next frame; the sequence loops.
+    /// `data` holds the image in its native file format, so that
+    /// it can be passed on to another terminal.
+    AnimRgba8 {
+        width: u32,
+        height: u32,
+        durations: Vec<Duration>,
+        frames: Vec<Vec<u8>>,
+        data: Vec<u8>,
+    },
+}
+
+impl std::fmt::Debug for ImageDataType {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::EncodedFile(data) => fmt
+                .debug_struct("EncodedFile")
+                .field("data_of_len", &data.len())
+                .finish(),
+            Self::AnimRgba8 {
+                width,
+                height,
+                durations,
+                frames,
+                data,
+            } => fmt
+                .debug_struct("AnimRgba8")
+                .field("width", width)
+                .field("height", height)
+                .field("durations", durations)
+                .field("num_frames", &frames.len())
+                .field("data_of_len", &data.len())
+                .finish(),
+        }
+    }
+}
+
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ImageData {
     id: usize,
-    /// The image data bytes.  Data is the native image file format
-    data: Vec<u8>,
+    data: ImageDataType,
 }
 
 impl ImageData {
     /// Create a new ImageData struct with the provided raw data.
+    /// The data is the bytes of an image in its native file format.
     pub fn with_raw_data(data: Vec<u8>) -> Self {
+        Self::with_data(ImageDataType::EncodedFile(data))
+    }
+
+    /// Create a new ImageData struct with the provided data
+    pub fn with_data(data: ImageDataType) -> Self {
         let id = IMAGE_ID.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed);
         Self { id, data }
     }
 
     #[inline]
-    pub fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &ImageDataType {
         &self.data
     }
 
+    /// Returns true if the image has more than one frame
+    pub fn is_animated(&self) -> bool {
+        match &self.data {
+            ImageDataType::EncodedFile(_) => false,
+            ImageDataType::AnimRgba8 { frames, .. } => frames.len() > 1,
+        }
+    }
+
     #[inline]
     pub fn id(&self) -> usize {
         self.id
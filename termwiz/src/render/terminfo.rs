@@ -5,7 +5,7 @@ use crate::color::{ColorAttribute, ColorSpec};
 use crate::escape::csi::{Cursor, Edit, EraseInDisplay, EraseInLine, Sgr, CSI};
 use crate::escape::osc::{ITermDimension, ITermFileData, ITermProprietary, OperatingSystemCommand};
 use crate::escape::OneBased;
-use crate::image::TextureCoordinate;
+use crate::image::{ImageDataType, TextureCoordinate};
 use crate::render::RenderTty;
 use crate::surface::{Change, CursorShape, CursorVisibility, Position};
 use crate::Result;
@@ -556,9 +556,12 @@ impl TerminfoRenderer {
                         let data = if image.top_left == TextureCoordinate::new_f32(0.0, 0.0)
                             && image.bottom_right == TextureCoordinate::new_f32(1.0, 1.0)
                         {
-                            // The whole image is requested, so we can send the
-                            // original image bytes over
-                            image.image.data().to_vec()
+                            match image.image.data() {
+                                // The whole image is requested, so we can send the
+                                // original image bytes over
+                                ImageDataType::EncodedFile(data)
+                                | ImageDataType::AnimRgba8 { data, .. } => data.to_vec(),
+                            }
                         } else {
                             // TODO: slice out the requested region of the image,
                             // and encode as a PNG.
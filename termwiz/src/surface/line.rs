@@ -1,11 +1,12 @@
 use crate::cell::{Cell, CellAttributes};
-use crate::cellcluster::CellCluster;
+use crate::cellcluster::{BidiClusters, CellCluster};
 use crate::hyperlink::Rule;
-use crate::surface::Change;
+use crate::surface::{Change, SequenceNo};
 use bitflags::bitflags;
 #[cfg(feature = "use_serde")]
 use serde::{Deserialize, Serialize};
 use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -22,14 +23,92 @@ bitflags! {
         const SCANNED_IMPLICIT_HYPERLINKS = 1<<2;
         /// true if we found implicit hyperlinks in the last scan
         const HAS_IMPLICIT_HYPERLINKS = 1<<3;
+
+        /// The line should be rendered with each cell twice as wide
+        /// (DECDWL).
+        const DOUBLE_WIDTH = 1<<4;
+        /// The line should be rendered as the top half of a double
+        /// width, double height line (DECDHL).
+        const DOUBLE_HEIGHT_TOP = 1<<5;
+        /// The line should be rendered as the bottom half of a double
+        /// width, double height line (DECDHL).
+        const DOUBLE_HEIGHT_BOTTOM = 1<<6;
+        /// The line was written while the terminal was in implicit
+        /// bidi mode, so the renderer should apply the bidirectional
+        /// algorithm to it.
+        const BIDI_ENABLED = 1<<7;
+
+        const DOUBLE_WIDTH_HEIGHT_MASK =
+            Self::DOUBLE_WIDTH.bits |
+            Self::DOUBLE_HEIGHT_TOP.bits |
+            Self::DOUBLE_HEIGHT_BOTTOM.bits;
     }
 }
 
+/// Describes how the cells of a line are scaled when rendered,
+/// as selected by the DEC line size escape sequences
+/// (`ESC # 3`, `ESC # 4`, `ESC # 5` and `ESC # 6`).
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineSize {
+    /// DECSWL - the default, single width and single height
+    SingleWidth,
+    /// DECDWL - each cell is twice as wide as normal
+    DoubleWidth,
+    /// DECDHL - each cell is twice as wide and twice as tall as normal,
+    /// and this line holds the top half of the glyphs
+    DoubleHeightTop,
+    /// DECDHL - each cell is twice as wide and twice as tall as normal,
+    /// and this line holds the bottom half of the glyphs
+    DoubleHeightBottom,
+}
+
+impl Default for LineSize {
+    fn default() -> Self {
+        Self::SingleWidth
+    }
+}
+
+impl LineSize {
+    /// Returns true if the cells of the line occupy two columns
+    /// of the display when rendered.  This is true for both the
+    /// double width and the double height sizes.
+    pub fn is_double_width(self) -> bool {
+        self != Self::SingleWidth
+    }
+
+    /// Returns true for either half of a double height line
+    pub fn is_double_height(self) -> bool {
+        match self {
+            Self::DoubleHeightTop | Self::DoubleHeightBottom => true,
+            _ => false,
+        }
+    }
+}
+
+/// Returns a sequence number that has not been used by any other
+/// line in this process
+fn next_seqno() -> SequenceNo {
+    static SEQNO: AtomicUsize = AtomicUsize::new(1);
+    SEQNO.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
 pub struct Line {
     bits: LineBits,
     cells: Vec<Cell>,
+    /// Changes whenever the content of the line is changed.
+    /// Sequence numbers are not meaningful outside of this process,
+    /// so a deserialized line is assigned a fresh one.
+    #[cfg_attr(feature = "use_serde", serde(skip, default = "next_seqno"))]
+    seqno: SequenceNo,
+}
+
+impl PartialEq for Line {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits && self.cells == other.cells
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -43,7 +122,11 @@ impl Line {
         let mut cells = Vec::with_capacity(width);
         cells.resize(width, Cell::default());
         let bits = LineBits::DIRTY;
-        Self { bits, cells }
+        Self {
+            bits,
+            cells,
+            seqno: next_seqno(),
+        }
     }
 
     pub fn from_text(s: &str, attrs: &CellAttributes) -> Line {
@@ -61,14 +144,13 @@ impl Line {
         Line {
             cells,
             bits: LineBits::DIRTY,
+            seqno: next_seqno(),
         }
     }
 
     pub fn from_text_with_wrapped_last_col(s: &str, attrs: &CellAttributes) -> Line {
         let mut line = Self::from_text(s, attrs);
-        line.cells
-            .last_mut()
-            .map(|cell| cell.attrs_mut().set_wrapped(true));
+        line.set_last_cell_was_wrapped(true);
         line
     }
 
@@ -77,11 +159,13 @@ impl Line {
         self.cells.clear();
         self.cells.resize(width, blank);
         self.bits = LineBits::DIRTY;
+        self.touch();
     }
 
     pub fn resize(&mut self, width: usize) {
         self.cells.resize(width, Cell::default());
         self.bits |= LineBits::DIRTY;
+        self.touch();
     }
 
     /// Wrap the line so that it fits within the provided width.
@@ -97,6 +181,7 @@ impl Line {
                     let mut line = Line {
                         cells: chunk.to_vec(),
                         bits: LineBits::DIRTY,
+                        seqno: next_seqno(),
                     };
                     if line.cells.len() == width {
                         // Ensure that we don't forget that we wrapped
@@ -123,6 +208,76 @@ impl Line {
         (self.bits & LineBits::DIRTY) == LineBits::DIRTY
     }
 
+    /// Returns the line size attribute that was set via the DEC
+    /// line size escape sequences
+    pub fn line_size(&self) -> LineSize {
+        let bits = self.bits & LineBits::DOUBLE_WIDTH_HEIGHT_MASK;
+        if bits == LineBits::DOUBLE_HEIGHT_TOP {
+            LineSize::DoubleHeightTop
+        } else if bits == LineBits::DOUBLE_HEIGHT_BOTTOM {
+            LineSize::DoubleHeightBottom
+        } else if bits == LineBits::DOUBLE_WIDTH {
+            LineSize::DoubleWidth
+        } else {
+            LineSize::SingleWidth
+        }
+    }
+
+    /// Change the line size attribute of the line.
+    /// The cell content is retained, but the caller is responsible
+    /// for ensuring that the cursor doesn't extend beyond the
+    /// reduced logical width of a double width line.
+    pub fn set_line_size(&mut self, size: LineSize) {
+        self.bits &= !LineBits::DOUBLE_WIDTH_HEIGHT_MASK;
+        self.bits |= match size {
+            LineSize::SingleWidth => LineBits::NONE,
+            LineSize::DoubleWidth => LineBits::DOUBLE_WIDTH,
+            LineSize::DoubleHeightTop => LineBits::DOUBLE_HEIGHT_TOP,
+            LineSize::DoubleHeightBottom => LineBits::DOUBLE_HEIGHT_BOTTOM,
+        };
+        self.bits |= LineBits::DIRTY;
+        self.touch();
+    }
+
+    /// Returns true if the cells of this line occupy two columns of
+    /// the display
+    #[inline]
+    pub fn is_double_width(&self) -> bool {
+        (self.bits & LineBits::DOUBLE_WIDTH_HEIGHT_MASK) != LineBits::NONE
+    }
+
+    /// Returns true if the bidirectional algorithm should be applied
+    /// when rendering this line
+    #[inline]
+    pub fn is_bidi_enabled(&self) -> bool {
+        (self.bits & LineBits::BIDI_ENABLED) == LineBits::BIDI_ENABLED
+    }
+
+    /// Set whether the bidirectional algorithm should be applied
+    /// when rendering this line
+    pub fn set_bidi_enabled(&mut self, enabled: bool) {
+        if enabled != self.is_bidi_enabled() {
+            self.bits.set(LineBits::BIDI_ENABLED, enabled);
+            self.bits |= LineBits::DIRTY;
+            self.touch();
+        }
+    }
+
+    /// Returns the sequence number of the line.  It changes whenever
+    /// the content of the line is changed, and is shared by clones of
+    /// the line, so it can be used as the key for caching information
+    /// that is derived from the line.
+    #[inline]
+    pub fn current_seqno(&self) -> SequenceNo {
+        self.seqno
+    }
+
+    /// Assign a new sequence number after changing the content
+    #[inline]
+    fn touch(&mut self) {
+        self.seqno = next_seqno();
+    }
+
     /// Force the dirty bit set.
     /// FIXME: this is abused by term::Screen, want to remove or rethink it.
     #[inline]
@@ -165,6 +320,7 @@ impl Line {
 
         self.bits &= !LineBits::HAS_IMPLICIT_HYPERLINKS;
         self.bits |= LineBits::DIRTY;
+        self.touch();
     }
 
     /// Scan through the line and look for sequences that match the provided
@@ -195,6 +351,7 @@ impl Line {
         if matches.is_empty() {
             return;
         }
+        self.touch();
 
         // The capture range is measured in bytes but we need to translate
         // that to the index of the column.  This is complicated a bit further
@@ -298,6 +455,7 @@ impl Line {
 
         self.invalidate_implicit_hyperlinks();
         self.bits |= LineBits::DIRTY;
+        self.touch();
         if cell.attrs().hyperlink().is_some() {
             self.bits |= LineBits::HAS_HYPERLINK;
         }
@@ -349,6 +507,7 @@ impl Line {
 
     pub fn insert_cell(&mut self, x: usize, cell: Cell, right_margin: usize) {
         self.invalidate_implicit_hyperlinks();
+        self.touch();
 
         if right_margin <= self.cells.len() {
             self.cells.remove(right_margin - 1);
@@ -370,6 +529,7 @@ impl Line {
 
     pub fn erase_cell(&mut self, x: usize) {
         self.invalidate_implicit_hyperlinks();
+        self.touch();
         self.invalidate_grapheme_at_or_before(x);
         self.cells.remove(x);
         self.cells.push(Cell::default());
@@ -377,6 +537,7 @@ impl Line {
 
     pub fn erase_cell_with_margin(&mut self, x: usize, right_margin: usize) {
         self.invalidate_implicit_hyperlinks();
+        self.touch();
         self.invalidate_grapheme_at_or_before(x);
         self.cells.remove(x);
         self.cells.insert(right_margin - 1, Cell::default());
@@ -418,11 +579,24 @@ impl Line {
         CellCluster::make_cluster(self.visible_cells())
     }
 
+    /// Compute the clusters of this line in visual order.
+    /// If bidi is enabled for the line then the bidirectional
+    /// algorithm is applied, otherwise the clusters are the same
+    /// as those returned by `cluster`.
+    pub fn cluster_bidi(&self) -> BidiClusters {
+        if self.is_bidi_enabled() {
+            BidiClusters::make_clusters(self.cells.len(), self.visible_cells())
+        } else {
+            BidiClusters::identity(self.cluster())
+        }
+    }
+
     pub fn cells(&self) -> &[Cell] {
         &self.cells
     }
 
     pub fn cells_mut(&mut self) -> &mut [Cell] {
+        self.touch();
         &mut self.cells
     }
 
@@ -445,6 +619,7 @@ impl Line {
     pub fn set_last_cell_was_wrapped(&mut self, wrapped: bool) {
         if let Some(cell) = self.cells.last_mut() {
             cell.attrs_mut().set_wrapped(wrapped);
+            self.touch();
         }
     }
 
@@ -455,6 +630,7 @@ impl Line {
     pub fn append_line(&mut self, mut other: Line) {
         self.cells.append(&mut other.cells);
         self.set_dirty();
+        self.touch();
     }
 
     /// mutable access the cell data, but the caller must take care
@@ -462,6 +638,7 @@ impl Line {
     /// Use set_cell if you need to modify the textual content of the
     /// cell, so that important invariants are upheld.
     pub fn cells_mut_for_attr_changes_only(&mut self) -> &mut [Cell] {
+        self.touch();
         &mut self.cells
     }
 
@@ -627,4 +804,31 @@ mod test {
         let r = line.compute_double_click_range(200, |_| true);
         assert_eq!(r, DoubleClickRange::Range(0..200));
     }
+
+    #[test]
+    fn seqno() {
+        let mut line: Line = "hello".into();
+        let other: Line = "hello".into();
+        assert_ne!(line.current_seqno(), other.current_seqno());
+        assert_eq!(line, other);
+
+        let seqno = line.current_seqno();
+        let copy = line.clone();
+        assert_eq!(copy.current_seqno(), seqno);
+
+        line.clear_dirty();
+        assert_eq!(line.current_seqno(), seqno);
+
+        line.set_cell(0, Cell::new('j', CellAttributes::default()));
+        assert_ne!(line.current_seqno(), seqno);
+        assert_eq!(copy.current_seqno(), seqno);
+
+        let seqno = line.current_seqno();
+        line.set_bidi_enabled(true);
+        assert_ne!(line.current_seqno(), seqno);
+
+        let seqno = line.current_seqno();
+        line.set_bidi_enabled(true);
+        assert_eq!(line.current_seqno(), seqno);
+    }
 }
@@ -323,9 +323,22 @@ impl OscState {
     }
 }
 
+/// The states used while parsing in VT52 compatibility mode.
+/// VT52 escape sequences are much simpler than their ANSI counterparts;
+/// they consist of ESC followed by a single character, with the exception
+/// of direct cursor addressing which is followed by two position bytes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Vt52State {
+    Ground,
+    Escape,
+    CursorRow,
+    CursorCol(u8),
+}
+
 /// The virtual terminal parser.  It works together with an implementation of `VTActor`.
 pub struct VTParser {
     state: State,
+    vt52: Option<Vt52State>,
 
     intermediates: [u8; MAX_INTERMEDIATES],
     num_intermediates: usize,
@@ -426,6 +439,7 @@ impl VTParser {
 
         Self {
             state: State::Ground,
+            vt52: None,
             utf8_return_state: State::Ground,
 
             intermediates: [0, 0],
@@ -448,6 +462,31 @@ impl VTParser {
         }
     }
 
+    /// Switch between ANSI and VT52 compatibility parsing modes.
+    ///
+    /// In VT52 mode, escape sequences are recognized as ESC followed
+    /// by a single final character, and are passed to
+    /// `VTActor::esc_dispatch` with no intermediates.  The direct cursor
+    /// address sequence `ESC Y line column` is passed through with `byte`
+    /// set to `Y` and the zero-based line and column as the two `params`.
+    /// CSI, OSC and DCS sequences are not recognized in VT52 mode.
+    ///
+    /// Switching modes discards any partially parsed sequence.
+    pub fn set_vt52_mode(&mut self, enable: bool) {
+        self.state = State::Ground;
+        self.utf8_return_state = State::Ground;
+        self.vt52 = if enable {
+            Some(Vt52State::Ground)
+        } else {
+            None
+        };
+    }
+
+    /// Returns true if the parser is in VT52 compatibility mode.
+    pub fn is_vt52_mode(&self) -> bool {
+        self.vt52.is_some()
+    }
+
     fn as_integer_params(&self) -> [i64; MAX_PARAMS] {
         let mut res = [0i64; MAX_PARAMS];
         for (src, dest) in self.params[0..self.num_params]
@@ -653,6 +692,11 @@ impl VTParser {
             return;
         }
 
+        if let Some(vt52) = self.vt52 {
+            self.parse_vt52_byte(vt52, byte, actor);
+            return;
+        }
+
         let (action, state) = lookup(self.state, byte);
 
         if state != self.state {
@@ -668,6 +712,60 @@ impl VTParser {
         }
     }
 
+    fn parse_vt52_byte(&mut self, state: Vt52State, byte: u8, actor: &mut dyn VTActor) {
+        const CAN: u8 = 0x18;
+        const SUB: u8 = 0x1a;
+        const ESC: u8 = 0x1b;
+        const DEL: u8 = 0x7f;
+
+        match (state, byte) {
+            // CAN and SUB abort any sequence in progress
+            (_, CAN) | (_, SUB) => {
+                self.vt52.replace(Vt52State::Ground);
+                actor.execute_c0_or_c1(byte);
+            }
+            // ESC always starts a new sequence
+            (_, ESC) => {
+                self.vt52.replace(Vt52State::Escape);
+            }
+            // Other C0 controls are executed without disturbing
+            // any sequence in progress
+            (_, 0x00..=0x1f) => actor.execute_c0_or_c1(byte),
+            (_, DEL) => {}
+
+            (Vt52State::Ground, 0x20..=0x7e) => actor.print(byte as char),
+            (Vt52State::Ground, _) => {
+                // Decode multi-byte sequences via the utf-8 machinery
+                // used by the ANSI ground state.
+                self.utf8_return_state = State::Ground;
+                self.state = State::Utf8Sequence;
+                self.next_utf8(actor, byte);
+            }
+
+            (Vt52State::Escape, b'Y') => {
+                self.vt52.replace(Vt52State::CursorRow);
+            }
+            (Vt52State::Escape, _) => {
+                self.vt52.replace(Vt52State::Ground);
+                actor.esc_dispatch(&[], &[], false, byte);
+            }
+
+            (Vt52State::CursorRow, _) => {
+                self.vt52.replace(Vt52State::CursorCol(byte));
+            }
+            (Vt52State::CursorCol(row), _) => {
+                self.vt52.replace(Vt52State::Ground);
+                // Positions are encoded as the value plus 32 so that
+                // they are sent as printable characters
+                let params = [
+                    i64::from(row.saturating_sub(0x20)),
+                    i64::from(byte.saturating_sub(0x20)),
+                ];
+                actor.esc_dispatch(&params, &[], false, b'Y');
+            }
+        }
+    }
+
     /// Parse a sequence of bytes.  The sequence need not be complete.
     /// This may result in some number of calls to the methods on the
     /// provided `actor`.
@@ -1027,4 +1125,54 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn vt52() {
+        let mut parser = VTParser::new();
+        parser.set_vt52_mode(true);
+        let mut actor = CollectingVTActor::default();
+        parser.parse(b"a\x1bA\x1bY\x25\x2a\x1b[b\x1bY!\x18c", &mut actor);
+        assert_eq!(
+            actor.into_vec(),
+            vec![
+                VTAction::Print('a'),
+                VTAction::EscDispatch {
+                    params: vec![],
+                    intermediates: vec![],
+                    ignored_excess_intermediates: false,
+                    byte: b'A',
+                },
+                VTAction::EscDispatch {
+                    params: vec![5, 10],
+                    intermediates: vec![],
+                    ignored_excess_intermediates: false,
+                    byte: b'Y',
+                },
+                // CSI is not recognized in VT52 mode
+                VTAction::EscDispatch {
+                    params: vec![],
+                    intermediates: vec![],
+                    ignored_excess_intermediates: false,
+                    byte: b'[',
+                },
+                VTAction::Print('b'),
+                // CAN aborts the partial cursor address
+                VTAction::ExecuteC0orC1(0x18),
+                VTAction::Print('c'),
+            ]
+        );
+
+        parser.set_vt52_mode(false);
+        let mut actor = CollectingVTActor::default();
+        parser.parse(b"\x1b[A", &mut actor);
+        assert_eq!(
+            actor.into_vec(),
+            vec![VTAction::CsiDispatch {
+                params: vec![],
+                intermediates: vec![],
+                ignored_excess_intermediates: false,
+                byte: b'A',
+            }]
+        );
+    }
 }
@@ -1,11 +1,13 @@
 use crate::domain::{ClientDomain, ClientDomainConfig};
+use crate::health::{ConnectionHealth, ConnectionState, HealthTracker};
 use crate::pane::ClientPane;
+use crate::portforward::PortForwards;
 use crate::UnixStream;
 use anyhow::{anyhow, bail, Context};
 use async_ossl::AsyncSslStream;
 use async_trait::async_trait;
 use codec::*;
-use config::{configuration, SshDomain, TlsDomainClient, UnixDomain};
+use config::{configuration, PortForward, SshDomain, TlsDomainClient, UnixDomain};
 use futures::FutureExt;
 use mux::connui::ConnectionUI;
 use mux::domain::{alloc_domain_id, DomainId};
@@ -24,6 +26,7 @@ use std::marker::Unpin;
 use std::net::TcpStream;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::Duration;
 use thiserror::Error;
@@ -33,14 +36,25 @@ enum ReaderMessage {
         pdu: Pdu,
         promise: Sender<anyhow::Result<Pdu>>,
     },
+    /// Send a PDU to which the server doesn't respond
+    SendUnilateral {
+        pdu: Pdu,
+    },
     Readable,
+    /// Drop the connection, perhaps because it has stopped responding
+    Disconnect {
+        reason: String,
+    },
 }
 
 #[derive(Clone)]
 pub struct Client {
-    sender: Sender<ReaderMessage>,
+    sender: Arc<Sender<ReaderMessage>>,
     local_domain_id: DomainId,
     pub is_reconnectable: bool,
+    port_forwards: Arc<PortForwards>,
+    health: HealthTracker,
+    auth_token: Option<String>,
 }
 
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
@@ -48,10 +62,11 @@ pub struct Client {
     "Please install the same version of wezterm on both the client and server!\n\
      The server version is {} (codec version {}),\n\
      which is not compatible with our version \n\
-     {} (codec version {}).",
+     {} (codec versions {} through {}).",
     version,
     codec_vers,
     config::wezterm_version(),
+    MIN_CODEC_VERSION,
     CODEC_VERSION
 )]
 pub struct IncompatibleVersionError {
@@ -156,8 +171,40 @@ async fn process_unilateral_inner_async(
     client_pane.process_unilateral(decoded.pdu)
 }
 
-fn process_unilateral(local_domain_id: DomainId, decoded: DecodedPdu) -> anyhow::Result<()> {
-    if let Some(pane_id) = decoded.pdu.pane_id() {
+fn process_topology_change(local_domain_id: DomainId, pdu: Pdu) -> anyhow::Result<()> {
+    let mux = match Mux::get() {
+        Some(mux) => mux,
+        None => {
+            // This can happen for some client scenarios; it is ok to ignore it.
+            return Ok(());
+        }
+    };
+
+    let client_domain = mux
+        .get_domain(local_domain_id)
+        .ok_or_else(|| anyhow!("no such domain {}", local_domain_id))?;
+    let client_domain = client_domain
+        .downcast_ref::<ClientDomain>()
+        .ok_or_else(|| anyhow!("domain {} is not a ClientDomain instance", local_domain_id))?;
+
+    client_domain.apply_topology_change(pdu)
+}
+
+fn process_unilateral(
+    local_domain_id: DomainId,
+    port_forwards: &PortForwards,
+    decoded: DecodedPdu,
+) -> anyhow::Result<()> {
+    if decoded.pdu.is_port_forward() {
+        port_forwards.process_unilateral(decoded.pdu);
+    } else if decoded.pdu.is_topology_change() {
+        promise::spawn::spawn_into_main_thread(async move {
+            if let Err(err) = process_topology_change(local_domain_id, decoded.pdu) {
+                log::error!("process_topology_change: {:#}", err);
+            }
+        })
+        .detach();
+    } else if let Some(pane_id) = decoded.pdu.pane_id() {
         promise::spawn::spawn_into_main_thread(async move {
             process_unilateral_inner(pane_id, local_domain_id, decoded)
         })
@@ -168,6 +215,44 @@ fn process_unilateral(local_domain_id: DomainId, decoded: DecodedPdu) -> anyhow:
     Ok(())
 }
 
+async fn send_pdu(sender: &Sender<ReaderMessage>, pdu: Pdu) -> anyhow::Result<Pdu> {
+    let (promise, rx) = bounded(1);
+    sender.send(ReaderMessage::SendPdu { pdu, promise }).await?;
+    rx.recv().await?
+}
+
+/// A handle to a Client that doesn't prevent the connection from
+/// being closed once the Client and its clones have been dropped.
+/// This is used by the threads that relay forwarded ports.
+#[derive(Clone)]
+pub struct WeakClient {
+    sender: Weak<Sender<ReaderMessage>>,
+}
+
+impl WeakClient {
+    fn sender(&self) -> anyhow::Result<Arc<Sender<ReaderMessage>>> {
+        self.sender
+            .upgrade()
+            .ok_or_else(|| NotReconnectableError::ClientWasDestroyed.into())
+    }
+
+    pub async fn open_tcp_stream(&self, pdu: OpenTcpStream) -> anyhow::Result<()> {
+        match send_pdu(&*self.sender()?, Pdu::OpenTcpStream(pdu)).await? {
+            Pdu::UnitResponse(_) => Ok(()),
+            response => bail!("unexpected response {:?}", response),
+        }
+    }
+
+    /// Send a PDU that the server doesn't respond to, such as the
+    /// data for a forwarded connection
+    pub async fn send_unilateral(&self, pdu: Pdu) -> anyhow::Result<()> {
+        self.sender()?
+            .send(ReaderMessage::SendUnilateral { pdu })
+            .await?;
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
 enum NotReconnectableError {
     #[error("Client was destroyed")]
@@ -177,14 +262,21 @@ enum NotReconnectableError {
 fn client_thread(
     reconnectable: &mut Reconnectable,
     local_domain_id: DomainId,
+    port_forwards: &PortForwards,
     rx: &mut Receiver<ReaderMessage>,
 ) -> anyhow::Result<()> {
-    block_on(client_thread_async(reconnectable, local_domain_id, rx))
+    block_on(client_thread_async(
+        reconnectable,
+        local_domain_id,
+        port_forwards,
+        rx,
+    ))
 }
 
 async fn client_thread_async(
     reconnectable: &mut Reconnectable,
     local_domain_id: DomainId,
+    port_forwards: &PortForwards,
     rx: &mut Receiver<ReaderMessage>,
 ) -> anyhow::Result<()> {
     let mut next_serial = 1u64;
@@ -212,6 +304,7 @@ async fn client_thread_async(
     };
 
     let mut stream = reconnectable.take_stream().unwrap();
+    let mut codec = CodecState::new();
 
     loop {
         let rx_msg = rx.recv();
@@ -225,37 +318,52 @@ async fn client_thread_async(
                 next_serial += 1;
                 promises.map.insert(serial, promise);
 
-                pdu.encode_async(&mut stream, serial)
+                pdu.encode_async_with(&mut stream, serial, &mut codec)
                     .await
                     .context("encoding a PDU to send to the server")?;
                 stream.flush().await.context("flushing PDU to server")?;
             }
-            Ok(ReaderMessage::Readable) => match Pdu::decode_async(&mut stream).await {
-                Ok(decoded) => {
-                    log::trace!("decoded serial {}", decoded.serial);
-                    if decoded.serial == 0 {
-                        process_unilateral(local_domain_id, decoded)
-                            .context("processing unilateral PDU from server")
-                            .map_err(|e| {
-                                log::error!("process_unilateral: {:?}", e);
-                                e
-                            })?;
-                    } else if let Some(promise) = promises.map.remove(&decoded.serial) {
-                        promise.try_send(Ok(decoded.pdu)).unwrap();
-                    } else {
-                        log::error!(
-                            "got serial {} without a corresponding promise",
-                            decoded.serial
-                        );
+            Ok(ReaderMessage::SendUnilateral { pdu }) => {
+                pdu.encode_async_with(&mut stream, 0, &mut codec)
+                    .await
+                    .context("encoding a PDU to send to the server")?;
+                stream.flush().await.context("flushing PDU to server")?;
+            }
+            Ok(ReaderMessage::Readable) => {
+                match Pdu::decode_async_with(&mut stream, &mut codec).await {
+                    Ok(decoded) => {
+                        log::trace!("decoded serial {}", decoded.serial);
+                        if let Pdu::SetCompressionDictionary(_) = decoded.pdu {
+                            // The codec has already applied this; there
+                            // is nothing more to do
+                        } else if decoded.serial == 0 {
+                            process_unilateral(local_domain_id, port_forwards, decoded)
+                                .context("processing unilateral PDU from server")
+                                .map_err(|e| {
+                                    log::error!("process_unilateral: {:?}", e);
+                                    e
+                                })?;
+                        } else if let Some(promise) = promises.map.remove(&decoded.serial) {
+                            promise.try_send(Ok(decoded.pdu)).unwrap();
+                        } else {
+                            log::error!(
+                                "got serial {} without a corresponding promise",
+                                decoded.serial
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        let reason = format!("Error while decoding response pdu: {:#}", err);
+                        log::error!("{}", reason);
+                        promises.fail_all(&reason);
+                        return Err(err).context("Error while decoding response pdu");
                     }
                 }
-                Err(err) => {
-                    let reason = format!("Error while decoding response pdu: {:#}", err);
-                    log::error!("{}", reason);
-                    promises.fail_all(&reason);
-                    return Err(err).context("Error while decoding response pdu");
-                }
-            },
+            }
+            Ok(ReaderMessage::Disconnect { reason }) => {
+                promises.fail_all(&reason);
+                bail!("{}", reason);
+            }
             Err(_) => {
                 return Err(NotReconnectableError::ClientWasDestroyed.into());
             }
@@ -755,15 +863,25 @@ impl Reconnectable {
 impl Client {
     fn new(local_domain_id: DomainId, mut reconnectable: Reconnectable) -> Self {
         let is_reconnectable = reconnectable.reconnectable();
+        let auth_token = reconnectable.config.auth_token().map(str::to_string);
         let (sender, mut receiver) = unbounded();
+        let port_forwards = Arc::new(PortForwards::default());
+        let health = HealthTracker::default();
 
+        let thread_port_forwards = Arc::clone(&port_forwards);
+        let thread_health = health.clone();
         thread::spawn(move || {
             const BASE_INTERVAL: Duration = Duration::from_secs(1);
             const MAX_INTERVAL: Duration = Duration::from_secs(10);
 
             let mut backoff = BASE_INTERVAL;
             loop {
-                if let Err(e) = client_thread(&mut reconnectable, local_domain_id, &mut receiver) {
+                if let Err(e) = client_thread(
+                    &mut reconnectable,
+                    local_domain_id,
+                    &thread_port_forwards,
+                    &mut receiver,
+                ) {
                     if !reconnectable.reconnectable() {
                         log::debug!("client thread ended: {}", e);
                         break;
@@ -785,7 +903,13 @@ impl Client {
                     let mut ui = ConnectionUI::new();
                     ui.title("wezterm: Reconnecting...");
 
+                    let mut attempt = 0;
                     loop {
+                        attempt += 1;
+                        thread_health.set_state(ConnectionState::Reconnecting {
+                            attempt,
+                            reason: format!("{:#}", e),
+                        });
                         ui.sleep_with_reason(
                             &format!("client disconnected {}; will reconnect", e),
                             backoff,
@@ -795,8 +919,22 @@ impl Client {
                             Ok(_) => {
                                 backoff = BASE_INTERVAL;
                                 log::error!("Reconnected!");
+                                // Input remains buffered until the server knows
+                                // who we are again and our view of its panes has
+                                // been refreshed; only then is it safe to send it
+                                let health = thread_health.clone();
                                 promise::spawn::spawn_into_main_thread(async move {
-                                    ClientDomain::reattach(local_domain_id, ui).await.ok();
+                                    if let Err(err) =
+                                        ClientDomain::reattach(local_domain_id, ui).await
+                                    {
+                                        log::error!(
+                                            "reattaching domain {}: {:#}",
+                                            local_domain_id,
+                                            err
+                                        );
+                                    }
+                                    health.set_state(ConnectionState::Connected);
+                                    ClientDomain::resolve_buffered_input(local_domain_id);
                                 })
                                 .detach();
                                 break;
@@ -815,6 +953,7 @@ impl Client {
                     break;
                 }
             }
+            thread_health.set_state(ConnectionState::Disconnected);
 
             async fn detach(local_domain_id: DomainId) -> anyhow::Result<()> {
                 if let Some(mux) = Mux::get() {
@@ -838,15 +977,242 @@ impl Client {
         });
 
         Self {
-            sender,
+            sender: Arc::new(sender),
             local_domain_id,
             is_reconnectable,
+            port_forwards,
+            health,
+            auth_token,
         }
     }
 
+    /// If a token is configured for the domain, present it to the
+    /// server.  This must be done before making any other request
+    /// to a server that requires authentication.
+    pub async fn authenticate(&self) -> anyhow::Result<()> {
+        let token = match &self.auth_token {
+            Some(token) => token.clone(),
+            None => return Ok(()),
+        };
+        let response = self
+            .authenticate_with_token(Authenticate { token })
+            .await
+            .context("authenticating with the mux server")?;
+        log::trace!(
+            "authenticated as {} ({})",
+            response.name,
+            response.permission
+        );
+        Ok(())
+    }
+
+    /// Returns the current health of the connection to the server
+    pub fn health(&self) -> ConnectionHealth {
+        self.health.get()
+    }
+
+    /// Ping the server and record the round trip time.
+    /// If there is no response within `timeout`, the connection is
+    /// assumed to have silently failed (eg: because the network went
+    /// away) and is dropped so that we can try to reconnect.
+    pub async fn check_health(&self, timeout: Duration) -> anyhow::Result<Duration> {
+        let start = std::time::Instant::now();
+        let ping = self.ping().map(Some);
+        let expired = smol::Timer::after(timeout).map(|_| None);
+        match smol::future::or(ping, expired).await {
+            Some(Ok(_)) => {
+                let latency = start.elapsed();
+                self.health.record_latency(latency);
+                Ok(latency)
+            }
+            Some(Err(err)) => Err(err),
+            None => {
+                let reason = format!("server did not respond to ping within {:?}", timeout);
+                if self.is_reconnectable {
+                    self.sender
+                        .send(ReaderMessage::Disconnect {
+                            reason: reason.clone(),
+                        })
+                        .await
+                        .ok();
+                }
+                bail!("{}", reason);
+            }
+        }
+    }
+
+    /// Propose our configured compression settings to the server.
+    /// If the server is too old to negotiate compression, or doesn't
+    /// understand the request, the connection remains uncompressed.
+    pub async fn negotiate_compression(&self) -> anyhow::Result<()> {
+        let info = self.get_codec_version(GetCodecVersion {}).await?;
+        if info.codec_vers < NEGOTIATE_COMPRESSION_CODEC_VERSION {
+            log::debug!(
+                "server codec version {} predates compression negotiation; \
+                 not compressing",
+                info.codec_vers
+            );
+            return Ok(());
+        }
+
+        let compression = configuration().mux_compression;
+        match self
+            .negotiate_compression_settings(NegotiateCompression { compression })
+            .await
+        {
+            Ok(resp) => {
+                log::debug!("negotiated compression {:?}", resp.compression);
+            }
+            Err(err) => {
+                log::warn!(
+                    "server declined compression negotiation, not compressing: {:#}",
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask the server to take part in forwarding a TCP port, and
+    /// start the local side of the forward.  Returns the id that
+    /// the server assigned to the forward.
+    pub async fn add_port_forward(&self, forward: PortForward) -> anyhow::Result<u64> {
+        let forward_id = self
+            .request_port_forward(AddPortForward {
+                forward: forward.clone(),
+            })
+            .await?
+            .forward_id;
+        if let Err(err) = self.port_forwards.add(self, forward_id, forward) {
+            self.remove_port_forward(RemovePortForward { forward_id })
+                .await
+                .ok();
+            return Err(err);
+        }
+        Ok(forward_id)
+    }
+
+    /// Register our port forwards with the server again after
+    /// reconnecting to it
+    pub async fn reestablish_port_forwards(&self) {
+        self.port_forwards.reestablish(self).await
+    }
+
+    /// Wait until all of the port forwards made over this connection
+    /// have been removed, checking periodically that the server is
+    /// still reachable
+    pub async fn wait_for_port_forwards(&self) -> anyhow::Result<()> {
+        while !self.port_forwards.is_empty() {
+            smol::Timer::after(Duration::from_secs(5)).await;
+            self.ping().await?;
+        }
+        Ok(())
+    }
+
+    /// Upload the file at `local` to the `remote` path on the server.
+    /// `progress` is called with the number of bytes sent so far and
+    /// the size of the file.  The remote file is only replaced once
+    /// all of the data has been sent.
+    pub async fn upload_file<P: FnMut(u64, u64)>(
+        &self,
+        local: &Path,
+        remote: &str,
+        mut progress: P,
+    ) -> anyhow::Result<()> {
+        let mut file =
+            std::fs::File::open(local).with_context(|| format!("opening {}", local.display()))?;
+        let size = file.metadata()?.len();
+        let transfer_id = self
+            .open_file_transfer(OpenFileTransfer {
+                path: remote.to_string(),
+                mode: FileTransferMode::Upload { size },
+            })
+            .await?
+            .transfer_id;
+
+        let result = async {
+            let mut buf = vec![0u8; FILE_TRANSFER_CHUNK_SIZE];
+            let mut sent = 0;
+            progress(sent, size);
+            while sent < size {
+                let want = (size - sent).min(buf.len() as u64) as usize;
+                let len = file.read(&mut buf[..want])?;
+                if len == 0 {
+                    bail!("{} was truncated while uploading", local.display());
+                }
+                self.write_file_chunk(WriteFileChunk {
+                    transfer_id,
+                    data: buf[..len].to_vec(),
+                })
+                .await?;
+                sent += len as u64;
+                progress(sent, size);
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+
+        self.close_file_transfer(CloseFileTransfer {
+            transfer_id,
+            complete: result.is_ok(),
+        })
+        .await?;
+        result
+    }
+
+    /// Download the `remote` file from the server and save it as `local`.
+    /// `progress` is called with the number of bytes received so far and
+    /// the size of the file.
+    pub async fn download_file<P: FnMut(u64, u64)>(
+        &self,
+        remote: &str,
+        local: &Path,
+        mut progress: P,
+    ) -> anyhow::Result<()> {
+        let response = self
+            .open_file_transfer(OpenFileTransfer {
+                path: remote.to_string(),
+                mode: FileTransferMode::Download,
+            })
+            .await?;
+        let transfer_id = response.transfer_id;
+        let size = response.size;
+
+        let result = async {
+            let mut file = std::fs::File::create(local)
+                .with_context(|| format!("creating {}", local.display()))?;
+            let mut received = 0;
+            progress(received, size);
+            loop {
+                let data = self
+                    .read_file_chunk(ReadFileChunk {
+                        transfer_id,
+                        max_len: FILE_TRANSFER_CHUNK_SIZE,
+                    })
+                    .await?
+                    .data;
+                if data.is_empty() {
+                    break;
+                }
+                file.write_all(&data)?;
+                received += data.len() as u64;
+                progress(received, size);
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+
+        self.close_file_transfer(CloseFileTransfer {
+            transfer_id,
+            complete: result.is_ok(),
+        })
+        .await?;
+        result
+    }
+
     pub async fn verify_version_compat(&self, ui: &ConnectionUI) -> anyhow::Result<()> {
         match self.get_codec_version(GetCodecVersion {}).await {
-            Ok(info) if info.codec_vers == CODEC_VERSION => {
+            Ok(info) if is_compatible_codec_version(info.codec_vers) => {
                 log::trace!(
                     "Server version is {} (codec version {})",
                     info.version_string,
@@ -887,10 +1253,20 @@ impl Client {
         let config = configuration();
 
         let unix_dom = match std::env::var_os("WEZTERM_UNIX_SOCKET") {
-            Some(path) => config::UnixDomain {
-                socket_path: Some(path.into()),
-                ..Default::default()
-            },
+            Some(path) => {
+                let socket_path = PathBuf::from(path);
+                // Use the token of the configured domain for that socket, if any
+                let auth_token = config
+                    .unix_domains
+                    .iter()
+                    .find(|dom| dom.socket_path() == socket_path)
+                    .and_then(|dom| dom.auth_token.clone());
+                config::UnixDomain {
+                    socket_path: Some(socket_path),
+                    auth_token,
+                    ..Default::default()
+                }
+            }
             None => config
                 .unix_domains
                 .first()
@@ -906,6 +1282,22 @@ impl Client {
         Self::new_unix_domain(alloc_domain_id(), &unix_dom, initial, ui)
     }
 
+    /// Connect to the unix, tls or ssh domain with the specified name
+    pub fn new_for_domain_name(name: &str, ui: &mut ConnectionUI) -> anyhow::Result<Self> {
+        let config = configuration();
+        let local_domain_id = alloc_domain_id();
+
+        if let Some(unix_dom) = config.unix_domains.iter().find(|d| d.name == name) {
+            Self::new_unix_domain(local_domain_id, unix_dom, true, ui)
+        } else if let Some(tls_client) = config.tls_clients.iter().find(|d| d.name == name) {
+            Self::new_tls(local_domain_id, tls_client, ui)
+        } else if let Some(ssh_dom) = config.ssh_domains.iter().find(|d| d.name == name) {
+            Self::new_ssh(local_domain_id, ssh_dom, ui)
+        } else {
+            bail!("no unix, tls or ssh domain named {} is configured", name)
+        }
+    }
+
     pub fn new_unix_domain(
         local_domain_id: DomainId,
         unix_dom: &UnixDomain,
@@ -940,11 +1332,30 @@ impl Client {
     }
 
     pub async fn send_pdu(&self, pdu: Pdu) -> anyhow::Result<Pdu> {
-        let (promise, rx) = bounded(1);
-        self.sender
-            .send(ReaderMessage::SendPdu { pdu, promise })
-            .await?;
-        rx.recv().await?
+        send_pdu(&self.sender, pdu).await
+    }
+
+    /// Returns a client that isn't connected to anything; its
+    /// requests fail without being sent
+    #[cfg(test)]
+    pub(crate) fn new_detached(local_domain_id: DomainId) -> Self {
+        let (sender, _receiver) = unbounded();
+        Self {
+            sender: Arc::new(sender),
+            local_domain_id,
+            is_reconnectable: false,
+            port_forwards: Arc::new(PortForwards::default()),
+            health: HealthTracker::default(),
+            auth_token: None,
+        }
+    }
+
+    /// Returns a handle that can send PDUs over this connection
+    /// without keeping it alive
+    pub fn downgrade(&self) -> WeakClient {
+        WeakClient {
+            sender: Arc::downgrade(&self.sender),
+        }
     }
 
     rpc!(ping, Ping = (), Pong);
@@ -964,7 +1375,34 @@ impl Client {
     );
     rpc!(get_lines, GetLines, GetLinesResponse);
     rpc!(get_codec_version, GetCodecVersion, GetCodecVersionResponse);
+    rpc!(
+        negotiate_compression_settings,
+        NegotiateCompression,
+        NegotiateCompressionResponse
+    );
     rpc!(get_tls_creds, GetTlsCreds = (), GetTlsCredsResponse);
+    rpc!(set_client_id, SetClientId, UnitResponse);
+    rpc!(list_clients, GetClientList = (), GetClientListResponse);
+    rpc!(
+        open_file_transfer,
+        OpenFileTransfer,
+        OpenFileTransferResponse
+    );
+    rpc!(write_file_chunk, WriteFileChunk, UnitResponse);
+    rpc!(read_file_chunk, ReadFileChunk, ReadFileChunkResponse);
+    rpc!(close_file_transfer, CloseFileTransfer, UnitResponse);
+    rpc!(request_port_forward, AddPortForward, AddPortForwardResponse);
+    rpc!(remove_port_forward, RemovePortForward, UnitResponse);
+    rpc!(open_tcp_stream, OpenTcpStream, UnitResponse);
+    rpc!(authenticate_with_token, Authenticate, AuthenticateResponse);
+    rpc!(create_auth_token, CreateAuthToken, AuthTokenResponse);
+    rpc!(rotate_auth_token, RotateAuthToken, AuthTokenResponse);
+    rpc!(revoke_auth_token, RevokeAuthToken, UnitResponse);
+    rpc!(
+        list_auth_tokens,
+        ListAuthTokens = (),
+        ListAuthTokensResponse
+    );
     rpc!(
         search_scrollback,
         SearchScrollbackRequest,
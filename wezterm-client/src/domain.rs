@@ -2,26 +2,37 @@ use crate::client::Client;
 use crate::pane::ClientPane;
 use anyhow::{anyhow, bail};
 use async_trait::async_trait;
-use codec::{ListPanesResponse, Spawn, SplitPane};
+use codec::{
+    ActivePaneChanged, ListPanesResponse, PaneRemoved, PaneZoomChanged, Pdu, SetClientId, Spawn,
+    SplitPane, TabRemoved, TabTreeChanged,
+};
 use config::keyassignment::SpawnTabDomain;
-use config::{SshDomain, TlsDomainClient, UnixDomain};
+use config::{
+    configuration, MuxBufferedInput, PortForward, SshDomain, TlsDomainClient, UnixDomain,
+};
+use mux::client::ClientId;
 use mux::connui::ConnectionUI;
 use mux::domain::{alloc_domain_id, Domain, DomainId, DomainState};
 use mux::pane::{Pane, PaneId};
-use mux::tab::{SplitDirection, Tab, TabId};
+use mux::tab::{PaneNode, SplitDirection, Tab, TabId};
 use mux::window::WindowId;
-use mux::Mux;
+use mux::{Mux, MuxNotification};
 use portable_pty::{CommandBuilder, PtySize};
 use promise::spawn::spawn_into_new_thread;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub struct ClientInner {
     pub client: Client,
     pub local_domain_id: DomainId,
     pub remote_domain_id: DomainId,
+    pub client_id: ClientId,
+    /// If true, we are attached as an observer and the server
+    /// will reject input from us
+    pub read_only: bool,
     remote_to_local_window: Mutex<HashMap<WindowId, WindowId>>,
     remote_to_local_tab: Mutex<HashMap<TabId, TabId>>,
     remote_to_local_pane: Mutex<HashMap<PaneId, PaneId>>,
@@ -97,6 +108,17 @@ impl ClientInner {
         );
     }
 
+    fn resolve_tab_and_pane(
+        &self,
+        remote_tab_id: TabId,
+        remote_pane_id: PaneId,
+    ) -> Option<(Rc<Tab>, Rc<dyn Pane>)> {
+        let mux = Mux::get().unwrap();
+        let tab = mux.get_tab(self.remote_to_local_tab_id(remote_tab_id)?)?;
+        let pane = mux.get_pane(self.remote_to_local_pane_id(remote_pane_id)?)?;
+        Some((tab, pane))
+    }
+
     pub fn remote_to_local_tab_id(&self, remote_tab_id: TabId) -> Option<TabId> {
         let map = self.remote_to_local_tab.lock().unwrap();
         for (remote, local) in map.iter() {
@@ -134,6 +156,24 @@ impl ClientDomainConfig {
         }
     }
 
+    /// The TCP ports to forward over the connection to this domain
+    pub fn port_forwards(&self) -> &[PortForward] {
+        match self {
+            ClientDomainConfig::Unix(_) => &[],
+            ClientDomainConfig::Tls(tls) => &tls.forwards,
+            ClientDomainConfig::Ssh(ssh) => &ssh.forwards,
+        }
+    }
+
+    /// The token to authenticate with when connecting to this domain
+    pub fn auth_token(&self) -> Option<&str> {
+        match self {
+            ClientDomainConfig::Unix(unix) => unix.auth_token.as_deref(),
+            ClientDomainConfig::Tls(tls) => tls.auth_token.as_deref(),
+            ClientDomainConfig::Ssh(ssh) => ssh.auth_token.as_deref(),
+        }
+    }
+
     pub fn connect_automatically(&self) -> bool {
         match self {
             ClientDomainConfig::Unix(unix) => unix.connect_automatically,
@@ -144,7 +184,12 @@ impl ClientDomainConfig {
 }
 
 impl ClientInner {
-    pub fn new(local_domain_id: DomainId, client: Client) -> Self {
+    pub fn new(
+        local_domain_id: DomainId,
+        client: Client,
+        client_id: ClientId,
+        read_only: bool,
+    ) -> Self {
         // Assumption: that the domain id on the other end is
         // always the first created default domain.  In the future
         // we'll add a way to discover/enumerate domains to populate
@@ -154,11 +199,20 @@ impl ClientInner {
             client,
             local_domain_id,
             remote_domain_id,
+            client_id,
+            read_only,
             remote_to_local_window: Mutex::new(HashMap::new()),
             remote_to_local_tab: Mutex::new(HashMap::new()),
             remote_to_local_pane: Mutex::new(HashMap::new()),
         }
     }
+
+    fn set_client_id_pdu(&self) -> SetClientId {
+        SetClientId {
+            client_id: self.client_id.clone(),
+            read_only: self.read_only,
+        }
+    }
 }
 
 pub struct ClientDomain {
@@ -166,10 +220,15 @@ pub struct ClientDomain {
     label: String,
     inner: RefCell<Option<Arc<ClientInner>>>,
     local_domain_id: DomainId,
+    client_id: ClientId,
+    read_only: bool,
 }
 
 impl ClientDomain {
-    pub fn new(config: ClientDomainConfig) -> Self {
+    /// Create a domain for the specified configuration.
+    /// If `read_only` is true, the domain attaches as an observer:
+    /// it can see the panes on the server, but not interact with them.
+    pub fn new(config: ClientDomainConfig, read_only: bool) -> Self {
         let local_domain_id = alloc_domain_id();
         let label = config.label();
         Self {
@@ -177,6 +236,8 @@ impl ClientDomain {
             label,
             inner: RefCell::new(None),
             local_domain_id,
+            client_id: ClientId::new(),
+            read_only,
         }
     }
 
@@ -219,6 +280,14 @@ impl ClientDomain {
     pub async fn reattach(domain_id: DomainId, ui: ConnectionUI) -> anyhow::Result<()> {
         let inner = Self::get_client_inner_for_domain(domain_id)?;
 
+        // This is a new session from the perspective of the server
+        inner.client.authenticate().await?;
+        inner.client.negotiate_compression().await?;
+        inner
+            .client
+            .set_client_id(inner.set_client_id_pdu())
+            .await?;
+        inner.client.reestablish_port_forwards().await;
         let panes = inner.client.list_panes().await?;
         Self::process_pane_list(inner, panes)?;
 
@@ -226,6 +295,68 @@ impl ClientDomain {
         Ok(())
     }
 
+    /// Deal with input that was typed into our panes while we were
+    /// reconnecting, according to the `mux_buffered_input` config.
+    /// This must only be called once the connection is marked as
+    /// connected, otherwise replaying the input would buffer it again.
+    pub fn resolve_buffered_input(domain_id: DomainId) {
+        let mux = match Mux::get() {
+            Some(mux) => mux,
+            None => return,
+        };
+        let policy = configuration().mux_buffered_input;
+        for pane in mux.iter_panes() {
+            if pane.domain_id() != domain_id {
+                continue;
+            }
+            let client_pane = match pane.downcast_ref::<ClientPane>() {
+                Some(client_pane) if client_pane.num_buffered_inputs() > 0 => client_pane,
+                _ => continue,
+            };
+            match policy {
+                MuxBufferedInput::Replay => {
+                    if let Err(err) = client_pane.replay_buffered_input() {
+                        log::error!("replaying input to pane {}: {:#}", pane.pane_id(), err);
+                    }
+                }
+                MuxBufferedInput::Discard => client_pane.discard_buffered_input(),
+                MuxBufferedInput::Prompt => {
+                    mux.notify(MuxNotification::BufferedInputPending(pane.pane_id()))
+                }
+            }
+        }
+    }
+
+    /// Periodically ping the server while `inner` remains attached,
+    /// so that the latency of the connection can be shown to the user,
+    /// and so that we notice if the connection silently fails
+    async fn monitor_health(domain_id: DomainId, inner: std::sync::Weak<ClientInner>) {
+        loop {
+            let config = configuration();
+            if config.mux_ping_interval_ms == 0 {
+                return;
+            }
+            smol::Timer::after(Duration::from_millis(config.mux_ping_interval_ms)).await;
+
+            let client = match (
+                Self::get_client_inner_for_domain(domain_id),
+                inner.upgrade(),
+            ) {
+                (Ok(current), Some(inner)) if Arc::ptr_eq(&current, &inner) => inner.client.clone(),
+                // We were detached
+                _ => return,
+            };
+            if !client.health().is_connected() {
+                continue;
+            }
+            let timeout = Duration::from_millis(config.mux_ping_timeout_ms);
+            match client.check_health(timeout).await {
+                Ok(latency) => log::trace!("domain {} latency {:?}", domain_id, latency),
+                Err(err) => log::warn!("domain {}: {:#}", domain_id, err),
+            }
+        }
+    }
+
     pub async fn resync(&self) -> anyhow::Result<()> {
         if let Some(inner) = self.inner.borrow().as_ref() {
             let panes = inner.client.list_panes().await?;
@@ -235,97 +366,165 @@ impl ClientDomain {
     }
 
     fn process_pane_list(inner: Arc<ClientInner>, panes: ListPanesResponse) -> anyhow::Result<()> {
-        let mux = Mux::get().expect("to be called on main thread");
         log::debug!("ListPanes result {:#?}", panes);
 
         for tabroot in panes.tabs {
-            let root_size = match tabroot.root_size() {
-                Some(size) => size,
-                None => continue,
-            };
+            Self::process_tab_tree(&inner, tabroot)?;
+        }
 
-            if let Some((remote_window_id, remote_tab_id)) = tabroot.window_and_tab_ids() {
-                let tab;
+        Ok(())
+    }
+
+    /// Create or update the local tab that mirrors the remote tab
+    /// described by `tabroot`.
+    fn process_tab_tree(inner: &Arc<ClientInner>, tabroot: PaneNode) -> anyhow::Result<()> {
+        let mux = Mux::get().expect("to be called on main thread");
+        let root_size = match tabroot.root_size() {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+
+        if let Some((remote_window_id, remote_tab_id)) = tabroot.window_and_tab_ids() {
+            let tab;
+
+            if let Some(tab_id) = inner.remote_to_local_tab_id(remote_tab_id) {
+                match mux.get_tab(tab_id) {
+                    Some(t) => tab = t,
+                    None => {
+                        // We likely decided that we hit EOF on the tab and
+                        // removed it from the mux.  Let's add it back, but
+                        // with a new id.
+                        inner.remove_old_tab_mapping(remote_tab_id);
+                        tab = Rc::new(Tab::new(&root_size));
+                        inner.record_remote_to_local_tab_mapping(remote_tab_id, tab.tab_id());
+                        mux.add_tab_no_panes(&tab);
+                    }
+                };
+            } else {
+                tab = Rc::new(Tab::new(&root_size));
+                mux.add_tab_no_panes(&tab);
+                inner.record_remote_to_local_tab_mapping(remote_tab_id, tab.tab_id());
+            }
 
-                if let Some(tab_id) = inner.remote_to_local_tab_id(remote_tab_id) {
-                    match mux.get_tab(tab_id) {
-                        Some(t) => tab = t,
+            log::debug!("tree: {:#?}", tabroot);
+            tab.sync_with_pane_tree(root_size, tabroot, |entry| {
+                if let Some(pane_id) = inner.remote_to_local_pane_id(entry.pane_id) {
+                    match mux.get_pane(pane_id) {
+                        Some(pane) => pane,
                         None => {
                             // We likely decided that we hit EOF on the tab and
                             // removed it from the mux.  Let's add it back, but
                             // with a new id.
-                            inner.remove_old_tab_mapping(remote_tab_id);
-                            tab = Rc::new(Tab::new(&root_size));
-                            inner.record_remote_to_local_tab_mapping(remote_tab_id, tab.tab_id());
-                            mux.add_tab_no_panes(&tab);
+                            inner.remove_old_pane_mapping(entry.pane_id);
+                            let pane: Rc<dyn Pane> = Rc::new(ClientPane::new(
+                                inner,
+                                entry.tab_id,
+                                entry.pane_id,
+                                entry.size,
+                                &entry.title,
+                            ));
+                            mux.add_pane(&pane).expect("failed to add pane to mux");
+                            pane
                         }
-                    };
+                    }
                 } else {
-                    tab = Rc::new(Tab::new(&root_size));
-                    mux.add_tab_no_panes(&tab);
-                    inner.record_remote_to_local_tab_mapping(remote_tab_id, tab.tab_id());
+                    let pane: Rc<dyn Pane> = Rc::new(ClientPane::new(
+                        inner,
+                        entry.tab_id,
+                        entry.pane_id,
+                        entry.size,
+                        &entry.title,
+                    ));
+                    log::debug!(
+                        "attaching to remote pane {:?} -> local pane_id {}",
+                        entry,
+                        pane.pane_id()
+                    );
+                    mux.add_pane(&pane).expect("failed to add pane to mux");
+                    pane
                 }
+            });
+
+            if let Some(local_window_id) = inner.remote_to_local_window(remote_window_id) {
+                let mut window = mux
+                    .get_window_mut(local_window_id)
+                    .expect("no such window!?");
+                if window.idx_by_id(tab.tab_id()).is_none() {
+                    window.push(&tab);
+                }
+            } else {
+                let local_window_id = mux.new_empty_window();
+                inner.record_remote_to_local_window_mapping(remote_window_id, *local_window_id);
+                mux.add_tab_to_window(&tab, *local_window_id)?;
+            }
+        }
 
-                log::debug!("tree: {:#?}", tabroot);
-                tab.sync_with_pane_tree(root_size, tabroot, |entry| {
-                    if let Some(pane_id) = inner.remote_to_local_pane_id(entry.pane_id) {
-                        match mux.get_pane(pane_id) {
-                            Some(pane) => pane,
-                            None => {
-                                // We likely decided that we hit EOF on the tab and
-                                // removed it from the mux.  Let's add it back, but
-                                // with a new id.
-                                inner.remove_old_pane_mapping(entry.pane_id);
-                                let pane: Rc<dyn Pane> = Rc::new(ClientPane::new(
-                                    &inner,
-                                    entry.tab_id,
-                                    entry.pane_id,
-                                    entry.size,
-                                    &entry.title,
-                                ));
-                                mux.add_pane(&pane).expect("failed to add pane to mux");
-                                pane
-                            }
+        Ok(())
+    }
+
+    /// Apply a topology change that was pushed to us by the server.
+    /// This allows tabs and splits made by other clients to show up
+    /// without waiting for us to resync the full pane list.
+    pub fn apply_topology_change(&self, pdu: Pdu) -> anyhow::Result<()> {
+        let inner = match self.inner() {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+        let mux = Mux::get().expect("to be called on main thread");
+
+        match pdu {
+            Pdu::TabTreeChanged(TabTreeChanged { root, .. }) => {
+                Self::process_tab_tree(&inner, root)?;
+            }
+            Pdu::TabRemoved(TabRemoved { tab_id }) => {
+                if let Some(local_tab_id) = inner.remote_to_local_tab_id(tab_id) {
+                    inner.remove_old_tab_mapping(tab_id);
+                    mux.remove_tab(local_tab_id);
+                }
+            }
+            Pdu::PaneRemoved(PaneRemoved { pane_id }) => {
+                if let Some(local_pane_id) = inner.remote_to_local_pane_id(pane_id) {
+                    inner.remove_old_pane_mapping(pane_id);
+                    if let Some(pane) = mux.get_pane(local_pane_id) {
+                        if let Some(pane) = pane.downcast_ref::<ClientPane>() {
+                            pane.renderable.borrow().inner.borrow_mut().dead = true;
                         }
-                    } else {
-                        let pane: Rc<dyn Pane> = Rc::new(ClientPane::new(
-                            &inner,
-                            entry.tab_id,
-                            entry.pane_id,
-                            entry.size,
-                            &entry.title,
-                        ));
-                        log::debug!(
-                            "attaching to remote pane {:?} -> local pane_id {}",
-                            entry,
-                            pane.pane_id()
-                        );
-                        mux.add_pane(&pane).expect("failed to add pane to mux");
-                        pane
                     }
-                });
-
-                if let Some(local_window_id) = inner.remote_to_local_window(remote_window_id) {
-                    let mut window = mux
-                        .get_window_mut(local_window_id)
-                        .expect("no such window!?");
-                    if window.idx_by_id(tab.tab_id()).is_none() {
-                        window.push(&tab);
+                    match mux.resolve_pane_id(local_pane_id) {
+                        Some((_domain_id, _window_id, tab_id)) => {
+                            if let Some(tab) = mux.get_tab(tab_id) {
+                                tab.kill_pane(local_pane_id);
+                            }
+                        }
+                        None => mux.remove_pane(local_pane_id),
                     }
-                } else {
-                    let local_window_id = mux.new_empty_window();
-                    inner.record_remote_to_local_window_mapping(remote_window_id, *local_window_id);
-                    mux.add_tab_to_window(&tab, *local_window_id)?;
                 }
             }
+            Pdu::ActivePaneChanged(ActivePaneChanged { tab_id, pane_id }) => {
+                if let Some((tab, pane)) = inner.resolve_tab_and_pane(tab_id, pane_id) {
+                    tab.set_active_pane(&pane);
+                }
+            }
+            Pdu::PaneZoomChanged(PaneZoomChanged {
+                tab_id,
+                pane_id,
+                zoomed,
+            }) => {
+                if let Some((tab, pane)) = inner.resolve_tab_and_pane(tab_id, pane_id) {
+                    tab.set_active_pane(&pane);
+                    tab.set_zoomed(zoomed);
+                }
+            }
+            _ => bail!("unexpected topology pdu {:?}", pdu),
         }
-
         Ok(())
     }
 
     fn finish_attach(
         domain_id: DomainId,
         client: Client,
+        client_id: ClientId,
+        read_only: bool,
         panes: ListPanesResponse,
     ) -> anyhow::Result<()> {
         let mux = Mux::get().unwrap();
@@ -336,8 +535,9 @@ impl ClientDomain {
             .downcast_ref::<Self>()
             .ok_or_else(|| anyhow!("domain {} is not a ClientDomain", domain_id))?;
 
-        let inner = Arc::new(ClientInner::new(domain_id, client));
+        let inner = Arc::new(ClientInner::new(domain_id, client, client_id, read_only));
         *domain.inner.borrow_mut() = Some(Arc::clone(&inner));
+        promise::spawn::spawn(Self::monitor_health(domain_id, Arc::downgrade(&inner))).detach();
 
         Self::process_pane_list(inner, panes)?;
 
@@ -369,6 +569,9 @@ impl Domain for ClientDomain {
         let inner = self
             .inner()
             .ok_or_else(|| anyhow!("domain is not attached"))?;
+        if inner.read_only {
+            bail!("cannot spawn in a domain that is attached read-only");
+        }
         let result = inner
             .client
             .spawn(Spawn {
@@ -382,6 +585,17 @@ impl Domain for ClientDomain {
 
         inner.record_remote_to_local_window_mapping(result.window_id, window);
 
+        let mux = Mux::get().unwrap();
+
+        // The server may have already told us about this tab via a
+        // topology push that raced with the response.
+        if let Some(tab) = inner
+            .remote_to_local_tab_id(result.tab_id)
+            .and_then(|tab_id| mux.get_tab(tab_id))
+        {
+            return Ok(tab);
+        }
+
         let pane: Rc<dyn Pane> = Rc::new(ClientPane::new(
             &inner,
             result.tab_id,
@@ -392,7 +606,7 @@ impl Domain for ClientDomain {
         let tab = Rc::new(Tab::new(&size));
         tab.assign_pane(&pane);
 
-        let mux = Mux::get().unwrap();
+        inner.record_remote_to_local_tab_mapping(result.tab_id, tab.tab_id());
         mux.add_tab_and_active_pane(&tab)?;
         mux.add_tab_to_window(&tab, window)?;
 
@@ -410,6 +624,9 @@ impl Domain for ClientDomain {
         let inner = self
             .inner()
             .ok_or_else(|| anyhow!("domain is not attached"))?;
+        if inner.read_only {
+            bail!("cannot split a pane in a domain that is attached read-only");
+        }
 
         let mux = Mux::get().unwrap();
 
@@ -434,6 +651,15 @@ impl Domain for ClientDomain {
             })
             .await?;
 
+        // The server may have already told us about this pane via a
+        // topology push that raced with the response.
+        if let Some(pane) = inner
+            .remote_to_local_pane_id(result.pane_id)
+            .and_then(|pane_id| mux.get_pane(pane_id))
+        {
+            return Ok(pane);
+        }
+
         let pane: Rc<dyn Pane> = Rc::new(ClientPane::new(
             &inner,
             result.tab_id,
@@ -462,6 +688,9 @@ impl Domain for ClientDomain {
     async fn attach(&self) -> anyhow::Result<()> {
         let domain_id = self.local_domain_id;
         let config = self.config.clone();
+        let forwards = config.port_forwards().to_vec();
+        let client_id = self.client_id.clone();
+        let read_only = self.read_only;
 
         let activity = mux::activity::Activity::new();
         let ui = ConnectionUI::new();
@@ -483,14 +712,33 @@ impl Domain for ClientDomain {
 
                 ui.output_str("Checking server version\n");
                 client.verify_version_compat(&ui).await?;
+                client.authenticate().await.map_err(|err| {
+                    ui.output_str(&format!("{:#}\n", err));
+                    err
+                })?;
+                client.negotiate_compression().await?;
 
                 ui.output_str("Version check OK!  Requesting pane list...\n");
+                client
+                    .set_client_id(SetClientId {
+                        client_id: client_id.clone(),
+                        read_only,
+                    })
+                    .await?;
+                for forward in forwards {
+                    ui.output_str(&format!("Forwarding {}\n", forward));
+                    if let Err(err) = client.add_port_forward(forward.clone()).await {
+                        // Don't fail the attach over a port that is in use
+                        log::error!("port forward {}: {:#}", forward, err);
+                        ui.output_str(&format!("Error forwarding {}: {:#}\n", forward, err));
+                    }
+                }
                 let panes = client.list_panes().await?;
                 ui.output_str(&format!(
                     "Server has {} tabs.  Attaching to local UI...\n",
                     panes.tabs.len()
                 ));
-                ClientDomain::finish_attach(domain_id, client, panes)
+                ClientDomain::finish_attach(domain_id, client, client_id, read_only, panes)
             }
         })
         .await
@@ -517,3 +765,121 @@ impl Domain for ClientDomain {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use codec::Pong;
+    use mux::tab::{PaneEntry, SplitDirectionAndSize};
+
+    fn size(rows: u16, cols: u16) -> PtySize {
+        PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+
+    fn leaf(pane_id: PaneId, size: PtySize, is_active_pane: bool) -> PaneNode {
+        PaneNode::Leaf(PaneEntry {
+            window_id: 1,
+            tab_id: 10,
+            pane_id,
+            title: format!("pane {}", pane_id),
+            size,
+            working_dir: None,
+            is_active_pane,
+            is_zoomed_pane: false,
+        })
+    }
+
+    /// Remote tab 10 in window 1, split into panes 20 and 21
+    fn tab_tree() -> Pdu {
+        Pdu::TabTreeChanged(TabTreeChanged {
+            tab_id: 10,
+            window_id: 1,
+            root: PaneNode::Split {
+                left: Box::new(leaf(20, size(24, 40), true)),
+                right: Box::new(leaf(21, size(24, 39), false)),
+                node: SplitDirectionAndSize {
+                    direction: SplitDirection::Horizontal,
+                    first: size(24, 40),
+                    second: size(24, 39),
+                },
+            },
+        })
+    }
+
+    #[test]
+    fn apply_topology_change() {
+        let _executor = promise::spawn::SimpleExecutor::new();
+        let domain: Arc<dyn Domain> = Arc::new(ClientDomain::new(
+            ClientDomainConfig::Unix(UnixDomain::default()),
+            false,
+        ));
+        let mux = Rc::new(Mux::new(Some(Arc::clone(&domain))));
+        Mux::set_mux(&mux);
+        let domain = domain.downcast_ref::<ClientDomain>().unwrap();
+
+        // Changes are ignored until we are attached
+        domain.apply_topology_change(tab_tree()).unwrap();
+        assert!(mux.iter_windows().is_empty());
+
+        let local_domain_id = domain.domain_id();
+        let inner = Arc::new(ClientInner::new(
+            local_domain_id,
+            Client::new_detached(local_domain_id),
+            ClientId::new(),
+            false,
+        ));
+        *domain.inner.borrow_mut() = Some(Arc::clone(&inner));
+
+        // A tab made by another client shows up in a new window,
+        // and hearing about it again doesn't duplicate it
+        domain.apply_topology_change(tab_tree()).unwrap();
+        domain.apply_topology_change(tab_tree()).unwrap();
+        assert_eq!(mux.iter_windows().len(), 1);
+        let local_tab_id = inner.remote_to_local_tab_id(10).unwrap();
+        let tab = mux.get_tab(local_tab_id).unwrap();
+        assert_eq!(tab.count_panes(), 2);
+        let left = inner.remote_to_local_pane_id(20).unwrap();
+        let right = inner.remote_to_local_pane_id(21).unwrap();
+        assert_eq!(tab.get_active_pane().unwrap().pane_id(), left);
+
+        domain
+            .apply_topology_change(Pdu::ActivePaneChanged(ActivePaneChanged {
+                tab_id: 10,
+                pane_id: 21,
+            }))
+            .unwrap();
+        assert_eq!(tab.get_active_pane().unwrap().pane_id(), right);
+
+        domain
+            .apply_topology_change(Pdu::PaneRemoved(PaneRemoved { pane_id: 21 }))
+            .unwrap();
+        assert!(inner.remote_to_local_pane_id(21).is_none());
+        assert_eq!(tab.count_panes(), 1);
+
+        // Changes to things that we don't know about are ignored
+        domain
+            .apply_topology_change(Pdu::PaneRemoved(PaneRemoved { pane_id: 99 }))
+            .unwrap();
+        domain
+            .apply_topology_change(Pdu::ActivePaneChanged(ActivePaneChanged {
+                tab_id: 99,
+                pane_id: 20,
+            }))
+            .unwrap();
+        assert_eq!(tab.get_active_pane().unwrap().pane_id(), left);
+
+        domain
+            .apply_topology_change(Pdu::TabRemoved(TabRemoved { tab_id: 10 }))
+            .unwrap();
+        assert!(inner.remote_to_local_tab_id(10).is_none());
+        assert!(mux.get_tab(local_tab_id).is_none());
+
+        assert!(domain.apply_topology_change(Pdu::Pong(Pong {})).is_err());
+        Mux::shutdown();
+    }
+}
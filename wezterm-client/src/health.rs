@@ -0,0 +1,141 @@
+//! Tracks the health of the connection to a mux server so that
+//! it can be shown to the user
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The connection is established
+    Connected,
+    /// The connection was lost and we are trying to establish it again
+    Reconnecting { attempt: usize, reason: String },
+    /// The connection was lost and we have given up on it
+    Disconnected,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionHealth {
+    pub state: ConnectionState,
+    /// When `state` last changed
+    pub since: Instant,
+    /// The round trip time of the most recent ping.
+    /// This is cleared when the connection is lost.
+    pub latency: Option<Duration>,
+}
+
+impl ConnectionHealth {
+    pub fn is_connected(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    /// Returns a short description of the state, suitable for
+    /// showing in the tab bar
+    pub fn summary(&self) -> String {
+        match &self.state {
+            ConnectionState::Connected => match self.latency {
+                Some(latency) => format!("{}ms", latency.as_millis()),
+                None => "connected".to_string(),
+            },
+            ConnectionState::Reconnecting { attempt, .. } => {
+                format!("reconnecting (attempt {})", attempt)
+            }
+            ConnectionState::Disconnected => "disconnected".to_string(),
+        }
+    }
+}
+
+/// Shared between a `Client` and the thread that manages its connection
+#[derive(Clone)]
+pub struct HealthTracker {
+    health: Arc<Mutex<ConnectionHealth>>,
+}
+
+impl Default for HealthTracker {
+    fn default() -> Self {
+        Self {
+            health: Arc::new(Mutex::new(ConnectionHealth {
+                state: ConnectionState::Connected,
+                since: Instant::now(),
+                latency: None,
+            })),
+        }
+    }
+}
+
+impl HealthTracker {
+    pub fn get(&self) -> ConnectionHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn set_state(&self, state: ConnectionState) {
+        let mut health = self.health.lock().unwrap();
+        if std::mem::discriminant(&health.state) != std::mem::discriminant(&state) {
+            health.since = Instant::now();
+        }
+        if state != ConnectionState::Connected {
+            health.latency = None;
+        }
+        health.state = state;
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        self.health.lock().unwrap().latency.replace(latency);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reconnecting(attempt: usize) -> ConnectionState {
+        ConnectionState::Reconnecting {
+            attempt,
+            reason: "broken pipe".to_string(),
+        }
+    }
+
+    #[test]
+    fn tracks_state() {
+        let tracker = HealthTracker::default();
+        let health = tracker.get();
+        assert!(health.is_connected());
+        assert_eq!(health.summary(), "connected");
+
+        tracker.record_latency(Duration::from_millis(42));
+        assert_eq!(tracker.get().summary(), "42ms");
+
+        std::thread::sleep(Duration::from_millis(5));
+        tracker.set_state(reconnecting(1));
+        let lost = tracker.get();
+        assert!(!lost.is_connected());
+        assert!(lost.since > health.since);
+        // The latency of a connection that has gone away is meaningless
+        assert_eq!(lost.latency, None);
+        assert_eq!(lost.summary(), "reconnecting (attempt 1)");
+
+        // Further attempts don't reset the time at which the
+        // connection was lost
+        std::thread::sleep(Duration::from_millis(5));
+        tracker.set_state(reconnecting(2));
+        let retrying = tracker.get();
+        assert_eq!(retrying.since, lost.since);
+        assert_eq!(retrying.summary(), "reconnecting (attempt 2)");
+
+        tracker.set_state(ConnectionState::Connected);
+        let restored = tracker.get();
+        assert!(restored.is_connected());
+        assert!(restored.since > lost.since);
+        assert_eq!(restored.summary(), "connected");
+
+        tracker.set_state(ConnectionState::Disconnected);
+        assert_eq!(tracker.get().summary(), "disconnected");
+    }
+
+    #[test]
+    fn clones_share_state() {
+        let tracker = HealthTracker::default();
+        let other = tracker.clone();
+        other.set_state(reconnecting(1));
+        assert!(!tracker.get().is_connected());
+    }
+}
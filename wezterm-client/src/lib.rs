@@ -5,4 +5,6 @@ use uds_windows::UnixStream;
 
 pub mod client;
 pub mod domain;
+pub mod health;
 pub mod pane;
+pub mod portforward;
@@ -1,7 +1,8 @@
 use crate::domain::ClientInner;
+use crate::health::{ConnectionHealth, ConnectionState};
 use crate::pane::mousestate::MouseState;
 use crate::pane::renderable::{RenderableInner, RenderableState};
-use anyhow::bail;
+use anyhow::{anyhow, bail, Context};
 use async_trait::async_trait;
 use codec::*;
 use config::configuration;
@@ -10,18 +11,25 @@ use mux::domain::DomainId;
 use mux::pane::{alloc_pane_id, Pane, PaneId, Pattern, SearchResult};
 use mux::renderable::{RenderableDimensions, StableCursorPosition};
 use mux::tab::TabId;
+use mux::{Mux, MuxNotification};
 use portable_pty::PtySize;
 use rangeset::RangeSet;
 use ratelim::RateLimiter;
 use std::cell::RefCell;
 use std::cell::RefMut;
+use std::future::Future;
+use std::io::Write;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use termwiz::cell::CellAttributes;
+use termwiz::color::AnsiColor;
 use termwiz::input::KeyEvent;
 use url::Url;
 use wezterm_term::color::ColorPalette;
-use wezterm_term::{Clipboard, KeyCode, KeyModifiers, Line, MouseEvent, StableRowIndex};
+use wezterm_term::{Alert, Clipboard, KeyCode, KeyModifiers, Line, MouseEvent, StableRowIndex};
 
 pub struct ClientPane {
     client: Arc<ClientInner>,
@@ -34,6 +42,18 @@ pub struct ClientPane {
     mouse: Rc<RefCell<MouseState>>,
     clipboard: RefCell<Option<Arc<dyn Clipboard>>>,
     mouse_grabbed: RefCell<bool>,
+    buffered_input: Rc<RefCell<Vec<BufferedInput>>>,
+    /// When we last invalidated the row that shows the connection
+    /// status banner; None if the banner isn't being shown
+    last_banner_dirty: RefCell<Option<Instant>>,
+}
+
+/// Input that was typed into the pane while the connection to the
+/// server was being re-established
+enum BufferedInput {
+    Key(KeyCode, KeyModifiers),
+    Paste(String),
+    Data(Vec<u8>),
 }
 
 impl ClientPane {
@@ -45,9 +65,11 @@ impl ClientPane {
         title: &str,
     ) -> Self {
         let local_pane_id = alloc_pane_id();
+        let buffered_input = Rc::new(RefCell::new(vec![]));
         let writer = PaneWriter {
             client: Arc::clone(client),
             remote_pane_id,
+            buffered_input: Rc::clone(&buffered_input),
         };
 
         let mouse = Rc::new(RefCell::new(MouseState::new(
@@ -88,6 +110,8 @@ impl ClientPane {
             reader,
             clipboard: RefCell::new(None),
             mouse_grabbed: RefCell::new(false),
+            buffered_input,
+            last_banner_dirty: RefCell::new(None),
         }
     }
 
@@ -113,6 +137,25 @@ impl ClientPane {
                     log::error!("ClientPane: Ignoring SetClipboard request {:?}", clipboard);
                 }
             },
+            Pdu::PaneMetadataChanged(PaneMetadataChanged {
+                title, working_dir, ..
+            }) => {
+                let renderable = self.renderable.borrow();
+                let mut inner = renderable.inner.borrow_mut();
+                inner.title = title;
+                inner.working_dir = working_dir.map(Into::into);
+            }
+            Pdu::NotifyAlert(NotifyAlert { alert, .. }) => {
+                if let Alert::WindowTitleChanged(title) = &alert {
+                    let renderable = self.renderable.borrow();
+                    renderable.inner.borrow_mut().title = title.clone();
+                }
+                let mux = Mux::get().unwrap();
+                mux.notify(MuxNotification::Alert {
+                    pane_id: self.local_pane_id,
+                    alert,
+                });
+            }
             _ => bail!("unhandled unilateral pdu: {:?}", pdu),
         };
         Ok(())
@@ -121,6 +164,129 @@ impl ClientPane {
     pub fn remote_pane_id(&self) -> TabId {
         self.remote_pane_id
     }
+
+    /// Returns the health of the connection to the server
+    pub fn connection_health(&self) -> ConnectionHealth {
+        self.client.client.health()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection_health().is_connected()
+    }
+
+    /// Input is held while we are disconnected, and also while there
+    /// is input from an earlier disconnection that hasn't yet been
+    /// replayed or discarded, so that it isn't sent out of order
+    fn should_buffer_input(&self) -> bool {
+        !self.is_connected() || !self.buffered_input.borrow().is_empty()
+    }
+
+    /// Returns the number of inputs that were typed while the
+    /// connection was being re-established and that haven't been sent
+    pub fn num_buffered_inputs(&self) -> usize {
+        self.buffered_input.borrow().len()
+    }
+
+    /// Send the input that was typed while the connection was
+    /// being re-established
+    pub fn replay_buffered_input(&self) -> anyhow::Result<()> {
+        let buffered = std::mem::take(&mut *self.buffered_input.borrow_mut());
+        log::info!(
+            "pane {}: sending {} buffered inputs",
+            self.local_pane_id,
+            buffered.len()
+        );
+        for input in buffered {
+            match input {
+                BufferedInput::Key(key, mods) => self.key_down(key, mods)?,
+                BufferedInput::Paste(text) => self.send_paste(&text)?,
+                BufferedInput::Data(data) => self.writer().write_all(&data)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Throw away the input that was typed while the connection was
+    /// being re-established
+    pub fn discard_buffered_input(&self) {
+        let discarded = std::mem::take(&mut *self.buffered_input.borrow_mut());
+        log::info!(
+            "pane {}: discarded {} buffered inputs",
+            self.local_pane_id,
+            discarded.len()
+        );
+    }
+
+    /// Returns the text of the banner that is shown over the pane
+    /// while it is disconnected from the server
+    fn connection_banner(&self) -> Option<String> {
+        let health = self.connection_health();
+        let status = match health.state {
+            ConnectionState::Connected => return None,
+            ConnectionState::Reconnecting { attempt, .. } => format!(
+                "wezterm: connection lost {:.0?} ago; reconnecting (attempt {})",
+                health.since.elapsed(),
+                attempt
+            ),
+            ConnectionState::Disconnected => "wezterm: disconnected".to_string(),
+        };
+        match self.num_buffered_inputs() {
+            0 => Some(status),
+            n => Some(format!("{}; holding {} inputs", status, n)),
+        }
+    }
+
+    /// Returns the remote path to which a local file with the specified
+    /// name should be uploaded: the working directory of the pane, if
+    /// known, otherwise the remote user's home directory.
+    fn remote_upload_path(&self, local: &Path) -> anyhow::Result<String> {
+        let name = local
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("{} has no usable file name", local.display()))?;
+        match self.get_current_working_dir() {
+            Some(url) if url.scheme() == "file" => {
+                let dir = percent_encoding::percent_decode_str(url.path()).decode_utf8()?;
+                Ok(format!("{}/{}", dir.trim_end_matches('/'), name))
+            }
+            _ => Ok(name.to_string()),
+        }
+    }
+
+    /// Upload the specified local files into the current working
+    /// directory of the remote pane.  `progress` is called with the
+    /// local path, bytes sent and total size as each file is transferred.
+    /// Resolves to the list of remote paths that were written.
+    pub fn upload_files<P: FnMut(&Path, u64, u64) + 'static>(
+        &self,
+        paths: Vec<PathBuf>,
+        mut progress: P,
+    ) -> impl Future<Output = anyhow::Result<Vec<String>>> {
+        let client = Arc::clone(&self.client);
+        let read_only = client.read_only;
+        let targets: anyhow::Result<Vec<(PathBuf, String)>> = paths
+            .into_iter()
+            .map(|local| {
+                let remote = self.remote_upload_path(&local)?;
+                Ok((local, remote))
+            })
+            .collect();
+        async move {
+            if read_only {
+                bail!("cannot upload files using a read-only attachment");
+            }
+            let mut uploaded = vec![];
+            for (local, remote) in targets? {
+                client
+                    .client
+                    .upload_file(&local, &remote, |done, size| progress(&local, done, size))
+                    .await
+                    .with_context(|| format!("uploading {} to {}", local.display(), remote))?;
+                uploaded.push(remote);
+            }
+            Ok(uploaded)
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -137,11 +303,43 @@ impl Pane for ClientPane {
         self.renderable.borrow().get_dimensions()
     }
     fn get_lines(&self, lines: Range<StableRowIndex>) -> (StableRowIndex, Vec<Line>) {
-        self.renderable.borrow().get_lines(lines)
+        let (first, mut result) = self.renderable.borrow().get_lines(lines);
+
+        if let Some(banner) = self.connection_banner() {
+            // Center the banner in the viewport
+            let dims = self.get_dimensions();
+            let row = dims.physical_top + dims.viewport_rows as StableRowIndex / 2;
+            if let Some(line) = row
+                .checked_sub(first)
+                .and_then(|idx| result.get_mut(idx as usize))
+            {
+                let col = dims
+                    .cols
+                    .saturating_sub(wezterm_term::unicode_column_width(&banner))
+                    / 2;
+                let mut attr = CellAttributes::default();
+                attr.foreground = AnsiColor::White.into();
+                attr.background = AnsiColor::Maroon.into();
+                line.overlay_text_with_attribute(col, &banner, attr);
+            }
+        }
+
+        (first, result)
     }
 
     fn get_dirty_lines(&self, lines: Range<StableRowIndex>) -> RangeSet<StableRowIndex> {
-        self.renderable.borrow().get_dirty_lines(lines)
+        let mut result = self.renderable.borrow().get_dirty_lines(lines);
+
+        if banner_needs_update(
+            &mut self.last_banner_dirty.borrow_mut(),
+            self.is_connected(),
+            Instant::now(),
+        ) {
+            let dims = self.get_dimensions();
+            result.add(dims.physical_top + dims.viewport_rows as StableRowIndex / 2);
+        }
+
+        result
     }
 
     fn set_clipboard(&self, clipboard: &Arc<dyn Clipboard>) {
@@ -155,6 +353,15 @@ impl Pane for ClientPane {
     }
 
     fn send_paste(&self, text: &str) -> anyhow::Result<()> {
+        if self.client.read_only {
+            bail!("cannot paste using a read-only attachment");
+        }
+        if self.should_buffer_input() {
+            self.buffered_input
+                .borrow_mut()
+                .push(BufferedInput::Paste(text.to_string()));
+            return Ok(());
+        }
         let client = Arc::clone(&self.client);
         let remote_pane_id = self.remote_pane_id;
         self.renderable
@@ -262,6 +469,15 @@ impl Pane for ClientPane {
     }
 
     fn key_down(&self, key: KeyCode, mods: KeyModifiers) -> anyhow::Result<()> {
+        if self.client.read_only {
+            bail!("cannot send keys using a read-only attachment");
+        }
+        if self.should_buffer_input() {
+            self.buffered_input
+                .borrow_mut()
+                .push(BufferedInput::Key(key, mods));
+            return Ok(());
+        }
         let input_serial;
         {
             let renderable = self.renderable.borrow();
@@ -295,6 +511,11 @@ impl Pane for ClientPane {
     }
 
     fn mouse_event(&self, event: MouseEvent) -> anyhow::Result<()> {
+        // Mouse events are meaningless once the pane has changed
+        // underneath them, so they are not buffered while disconnected
+        if self.client.read_only || !self.is_connected() {
+            return Ok(());
+        }
         self.mouse.borrow_mut().append(event);
         if MouseState::next(Rc::clone(&self.mouse)) {
             self.renderable
@@ -315,7 +536,7 @@ impl Pane for ClientPane {
     }
 
     fn palette(&self) -> ColorPalette {
-        let tardy = self.renderable.borrow().inner.borrow().is_tardy();
+        let tardy = self.renderable.borrow().inner.borrow().is_tardy() || !self.is_connected();
 
         let config = configuration();
         let palette: ColorPalette = config.resolved_palette.clone().into();
@@ -336,8 +557,7 @@ impl Pane for ClientPane {
     }
 
     fn is_alt_screen_active(&self) -> bool {
-        // FIXME: retrieve this from the remote
-        false
+        self.renderable.borrow().inner.borrow().alt_screen_active
     }
 
     fn get_current_working_dir(&self) -> Option<Url> {
@@ -345,13 +565,43 @@ impl Pane for ClientPane {
     }
 }
 
+/// Decides whether the row holding the connection banner should be
+/// reported as dirty.  The banner is kept up to date while disconnected,
+/// and is erased when we reconnect, but it isn't reported as dirty all
+/// the time, as that would busy loop.  `last_dirty` records when the
+/// banner was last refreshed, or None if it isn't being shown.
+fn banner_needs_update(last_dirty: &mut Option<Instant>, connected: bool, now: Instant) -> bool {
+    let needs_update = match *last_dirty {
+        Some(_) if connected => true,
+        Some(last) => now.saturating_duration_since(last) >= Duration::from_secs(1),
+        None => !connected,
+    };
+    if needs_update {
+        *last_dirty = if connected { None } else { Some(now) };
+    }
+    needs_update
+}
+
 struct PaneWriter {
     client: Arc<ClientInner>,
     remote_pane_id: TabId,
+    buffered_input: Rc<RefCell<Vec<BufferedInput>>>,
 }
 
 impl std::io::Write for PaneWriter {
     fn write(&mut self, data: &[u8]) -> Result<usize, std::io::Error> {
+        if self.client.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "cannot write to the pane using a read-only attachment",
+            ));
+        }
+        if !self.client.client.health().is_connected() || !self.buffered_input.borrow().is_empty() {
+            self.buffered_input
+                .borrow_mut()
+                .push(BufferedInput::Data(data.to_vec()));
+            return Ok(data.len());
+        }
         promise::spawn::block_on(self.client.client.write_to_pane(WriteToPane {
             pane_id: self.remote_pane_id,
             data: data.to_vec(),
@@ -364,3 +614,41 @@ impl std::io::Write for PaneWriter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn banner_dirty_rows() {
+        let start = Instant::now();
+        let mut last_dirty = None;
+
+        // Nothing to do while connected
+        assert!(!banner_needs_update(&mut last_dirty, true, start));
+        assert_eq!(last_dirty, None);
+
+        // The banner is drawn as soon as we are disconnected
+        assert!(banner_needs_update(&mut last_dirty, false, start));
+        assert_eq!(last_dirty, Some(start));
+
+        // but then only refreshed once per second
+        let soon = start + Duration::from_millis(500);
+        assert!(!banner_needs_update(&mut last_dirty, false, soon));
+        assert_eq!(last_dirty, Some(start));
+        let later = start + Duration::from_secs(1);
+        assert!(banner_needs_update(&mut last_dirty, false, later));
+        assert_eq!(last_dirty, Some(later));
+
+        // It is erased immediately on reconnecting, and then
+        // left alone
+        let reconnected = later + Duration::from_millis(10);
+        assert!(banner_needs_update(&mut last_dirty, true, reconnected));
+        assert_eq!(last_dirty, None);
+        assert!(!banner_needs_update(
+            &mut last_dirty,
+            true,
+            reconnected + Duration::from_secs(5)
+        ));
+    }
+}
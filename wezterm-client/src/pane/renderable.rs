@@ -23,6 +23,9 @@ use wezterm_term::{Line, StableRowIndex};
 
 const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
 const BASE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long, beyond twice the measured round trip time, we'll wait
+/// for the server to confirm a prediction before rolling it back
+const PREDICTION_GRACE: Duration = Duration::from_millis(250);
 
 #[derive(Debug)]
 enum LineEntry {
@@ -53,6 +56,403 @@ impl LineEntry {
     }
 }
 
+/// A cell that we have locally echoed ahead of the server.
+/// Predictions are grouped into epochs; a new epoch begins whenever
+/// the user does something whose effect we can't predict, such as
+/// pressing Enter, or when a prediction turns out to be wrong.
+#[derive(Debug)]
+struct Prediction {
+    epoch: usize,
+    row: StableRowIndex,
+    col: usize,
+    cell: Cell,
+    /// The text of the cell as last reported by the server at the time
+    /// that the prediction was made
+    original: String,
+    made: Instant,
+}
+
+/// Returns the column just beyond the last non-blank cell in the line
+fn content_end(line: &Line) -> usize {
+    line.cells()
+        .iter()
+        .rposition(|cell| cell.str() != " ")
+        .map(|idx| idx + 1)
+        .unwrap_or(0)
+}
+
+/// We only predict shifting cells that are a single column wide
+fn all_narrow(line: &Line, range: Range<usize>) -> bool {
+    line.cells()[range].iter().all(|cell| cell.width() == 1)
+}
+
+/// Returns the most recent version of the line that we received
+/// from the server, if we have it in the cache
+fn server_line(lines: &LruCache<StableRowIndex, LineEntry>, row: StableRowIndex) -> Option<&Line> {
+    match lines.peek(&row) {
+        Some(LineEntry::Line(line))
+        | Some(LineEntry::Dirty(line))
+        | Some(LineEntry::DirtyAndFetching(line, _))
+        | Some(LineEntry::Stale(line)) => Some(line),
+        Some(LineEntry::Fetching(_)) | None => None,
+    }
+}
+
+/// The state of the pane as most recently reported by the server,
+/// against which predictions are made
+struct EchoContext<'a> {
+    lines: &'a LruCache<StableRowIndex, LineEntry>,
+    cursor: StableCursorPosition,
+    cols: usize,
+    alt_screen_active: bool,
+    /// The round trip time of our most recent input, in milliseconds
+    input_rtt: u64,
+    /// The value of `local_echo_threshold_ms`
+    threshold_ms: u64,
+}
+
+/// Predictive local echo for a pane.
+/// The predictor doesn't touch the line cache itself; the rows whose
+/// display it has changed are collected in `dirty` for the caller to
+/// invalidate.
+#[derive(Debug, Default)]
+struct Predictor {
+    predictions: Vec<Prediction>,
+    cursor: Option<(StableCursorPosition, Instant)>,
+    epoch: usize,
+    confirmed_epoch: Option<usize>,
+    dirty: Vec<StableRowIndex>,
+}
+
+impl Predictor {
+    /// Predictive echo can be noisy when the link is working well,
+    /// so we only employ it when it looks like the latency is high.
+    /// The threshold is controlled by `local_echo_threshold_ms`.
+    /// Full screen applications tend to use keys for their own purposes
+    /// rather than echoing them, so we don't predict in the alternate
+    /// screen.
+    fn should_predict(ctx: &EchoContext) -> bool {
+        !ctx.alt_screen_active && ctx.input_rtt >= ctx.threshold_ms
+    }
+
+    /// Returns the line as we expect it to look once the server has
+    /// caught up with all of our outstanding predictions
+    fn predicted_line(&self, ctx: &EchoContext, row: StableRowIndex) -> Option<Line> {
+        let mut line = server_line(ctx.lines, row)?.clone();
+        for pred in &self.predictions {
+            if pred.row == row {
+                line.set_cell(pred.col, pred.cell.clone());
+            }
+        }
+        Some(line)
+    }
+
+    /// Predictions are only shown once the server has confirmed at
+    /// least one prediction from their epoch
+    fn is_epoch_displayed(&self, epoch: usize) -> bool {
+        self.confirmed_epoch.map(|e| e >= epoch).unwrap_or(false)
+    }
+
+    /// Draw the predictions that are ready to be displayed over the
+    /// line data from the server.  Unconfirmed text is underlined.
+    fn overlay(&self, row: StableRowIndex, line: &mut Line) {
+        for pred in &self.predictions {
+            if pred.row == row && self.is_epoch_displayed(pred.epoch) {
+                let mut cell = pred.cell.clone();
+                if cell.str() != " " {
+                    cell.attrs_mut().set_underline(Underline::Double);
+                }
+                line.set_cell(pred.col, cell);
+            }
+        }
+    }
+
+    /// The cursor position that we want to render; this is the predicted
+    /// position if we're displaying the current epoch, otherwise the
+    /// position most recently reported by the server.
+    fn displayed_cursor_position(&self, server: StableCursorPosition) -> StableCursorPosition {
+        match self.cursor {
+            Some((cursor, _)) if self.is_epoch_displayed(self.epoch) => cursor,
+            _ => server,
+        }
+    }
+
+    /// The position from which we make the next prediction
+    fn prediction_cursor(&self, ctx: &EchoContext) -> StableCursorPosition {
+        self.cursor.map(|(cursor, _)| cursor).unwrap_or(ctx.cursor)
+    }
+
+    fn move_cursor(&mut self, ctx: &EchoContext, x: usize) {
+        let mut cursor = self.prediction_cursor(ctx);
+        cursor.x = x;
+        self.cursor.replace((cursor, Instant::now()));
+    }
+
+    fn clear_cursor(&mut self, ctx: &EchoContext) {
+        if let Some((cursor, _)) = self.cursor.take() {
+            self.dirty.push(cursor.y);
+            self.dirty.push(ctx.cursor.y);
+        }
+    }
+
+    /// Called when the user does something whose effect we cannot
+    /// predict.  Subsequent predictions are tentative until the server
+    /// confirms one of them, which prevents us from echoing input
+    /// when the remote end has turned off echo, such as at a password
+    /// prompt.
+    fn new_epoch(&mut self, ctx: &EchoContext) {
+        self.epoch += 1;
+        self.clear_cursor(ctx);
+    }
+
+    /// Discard all outstanding predictions, restoring the display
+    /// to the state reported by the server
+    fn rollback(&mut self, ctx: &EchoContext) {
+        self.dirty
+            .extend(self.predictions.drain(..).map(|pred| pred.row));
+        self.new_epoch(ctx);
+    }
+
+    fn predict_cell(&mut self, ctx: &EchoContext, row: StableRowIndex, col: usize, cell: Cell) {
+        let original = server_line(ctx.lines, row)
+            .and_then(|line| line.cells().get(col))
+            .map(|cell| cell.str().to_string())
+            .unwrap_or_else(|| " ".to_string());
+        self.predictions.push(Prediction {
+            epoch: self.epoch,
+            row,
+            col,
+            cell,
+            original,
+            made: Instant::now(),
+        });
+        self.dirty.push(row);
+    }
+
+    /// Predict the effect of typing `c` at the cursor.
+    /// Shells insert rather than overwrite when typing into the middle
+    /// of the line, so we shift the remainder of the line to the right.
+    /// Returns false if the effect is not predictable.
+    fn predict_char(&mut self, ctx: &EchoContext, c: char) -> bool {
+        let cursor = self.prediction_cursor(ctx);
+        let line = match self.predicted_line(ctx, cursor.y) {
+            Some(line) => line,
+            None => return false,
+        };
+        let cell = Cell::new(c, CellAttributes::default());
+        let width = cell.width();
+        let col = cursor.x;
+        if width == 0 || col + width > ctx.cols {
+            // Wrapping onto the next line is up to the application
+            return false;
+        }
+
+        let end = content_end(&line);
+        if col < end {
+            if width != 1 || end >= ctx.cols || !all_narrow(&line, col..end) {
+                return false;
+            }
+            for idx in (col..end).rev() {
+                self.predict_cell(ctx, cursor.y, idx + 1, line.cells()[idx].clone());
+            }
+        }
+        self.predict_cell(ctx, cursor.y, col, cell);
+        self.move_cursor(ctx, col + width);
+        true
+    }
+
+    /// Predict the removal of the cell at `col`, shifting the remainder
+    /// of the line to the left.
+    /// Returns false if the effect is not predictable.
+    fn predict_erase(
+        &mut self,
+        ctx: &EchoContext,
+        row: StableRowIndex,
+        col: usize,
+        line: &Line,
+    ) -> bool {
+        let end = content_end(line);
+        if col >= end {
+            return true;
+        }
+        if !all_narrow(line, col..end) {
+            return false;
+        }
+        for idx in col..end - 1 {
+            self.predict_cell(ctx, row, idx, line.cells()[idx + 1].clone());
+        }
+        self.predict_cell(ctx, row, end - 1, Cell::default());
+        true
+    }
+
+    fn predict_key(&mut self, ctx: &EchoContext, key: KeyCode, mods: KeyModifiers) -> bool {
+        let cursor = self.prediction_cursor(ctx);
+        match key {
+            KeyCode::Char(c)
+                if (mods == KeyModifiers::NONE || mods == KeyModifiers::SHIFT)
+                    && !c.is_control() =>
+            {
+                self.predict_char(ctx, c)
+            }
+            _ if mods != KeyModifiers::NONE => false,
+            KeyCode::Backspace => {
+                if cursor.x == 0 {
+                    // Backspacing onto the prior line is up to the application
+                    return false;
+                }
+                match self.predicted_line(ctx, cursor.y) {
+                    Some(line) if self.predict_erase(ctx, cursor.y, cursor.x - 1, &line) => {
+                        self.move_cursor(ctx, cursor.x - 1);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            KeyCode::Delete => match self.predicted_line(ctx, cursor.y) {
+                Some(line) => self.predict_erase(ctx, cursor.y, cursor.x, &line),
+                None => false,
+            },
+            KeyCode::LeftArrow => {
+                if cursor.x == 0 {
+                    return false;
+                }
+                self.move_cursor(ctx, cursor.x - 1);
+                true
+            }
+            KeyCode::RightArrow => match self.predicted_line(ctx, cursor.y) {
+                Some(line) => {
+                    // Line editors won't move past the end of the input
+                    if cursor.x < content_end(&line) {
+                        self.move_cursor(ctx, cursor.x + 1);
+                    }
+                    true
+                }
+                None => false,
+            },
+            // At a shell prompt, Up and Down recall history, replacing
+            // the input with text that we don't know, rather than moving
+            // the cursor, so they start a new epoch like Enter does
+            KeyCode::UpArrow | KeyCode::DownArrow => false,
+            _ => false,
+        }
+    }
+
+    /// Based on a keypress, apply a "prediction" of what the terminal
+    /// content will look like once we receive the response from the
+    /// remote system.
+    fn predict_from_key_event(&mut self, ctx: &EchoContext, key: KeyCode, mods: KeyModifiers) {
+        if !Self::should_predict(ctx) || !self.predict_key(ctx, key, mods) {
+            self.new_epoch(ctx);
+        }
+    }
+
+    fn predict_from_paste(&mut self, ctx: &EchoContext, text: &str) {
+        if !Self::should_predict(ctx) {
+            self.new_epoch(ctx);
+            return;
+        }
+
+        // A multi-line paste is likely to be acted upon by the application
+        // rather than simply echoed, so only predict up to the first
+        // control character.
+        for c in text.chars() {
+            if c.is_control() || !self.predict_char(ctx, c) {
+                self.new_epoch(ctx);
+                return;
+            }
+        }
+    }
+
+    /// Compare our predictions against a line received from the server.
+    /// Predictions that match are retired, along with any earlier
+    /// predictions for the same cell.
+    fn verify(&mut self, row: StableRowIndex, line: &Line) {
+        let mut retired = vec![];
+        for (idx, pred) in self.predictions.iter().enumerate() {
+            if pred.row != row {
+                continue;
+            }
+            let actual = line.cells().get(pred.col).map(Cell::str).unwrap_or(" ");
+            if actual == pred.cell.str() {
+                // A cell that already had the predicted content doesn't
+                // tell us whether the server is echoing our input
+                if actual != pred.original {
+                    self.confirmed_epoch = Some(
+                        self.confirmed_epoch
+                            .map(|e| e.max(pred.epoch))
+                            .unwrap_or(pred.epoch),
+                    );
+                }
+                retired.push((idx, pred.col));
+            }
+        }
+
+        if retired.is_empty() {
+            return;
+        }
+        let mut idx = 0;
+        self.predictions.retain(|pred| {
+            let keep = pred.row != row
+                || !retired
+                    .iter()
+                    .any(|&(retired_idx, col)| col == pred.col && idx <= retired_idx);
+            idx += 1;
+            keep
+        });
+        self.dirty.push(row);
+    }
+
+    /// If the server hasn't confirmed a prediction within a reasonable
+    /// time of making it, we assume that it was a misprediction and roll
+    /// back.  Returns true if anything changed.
+    fn expire(&mut self, ctx: &EchoContext, now: Instant) -> bool {
+        let deadline = Duration::from_millis(ctx.input_rtt.saturating_mul(2)) + PREDICTION_GRACE;
+        let expired = |made: Instant| now.saturating_duration_since(made) > deadline;
+        if self.predictions.iter().any(|pred| expired(pred.made)) {
+            log::trace!(
+                "rolling back {} mispredicted cells in epoch {}",
+                self.predictions.len(),
+                self.epoch
+            );
+            self.rollback(ctx);
+            return true;
+        }
+        if self.predictions.is_empty() {
+            if let Some((_, made)) = self.cursor {
+                if expired(made) {
+                    self.clear_cursor(ctx);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Called when the server has sent us an update for the pane.
+    /// `input_acked` is true if the server has processed all of our input.
+    /// Returns true if anything changed.
+    fn server_updated(&mut self, ctx: &EchoContext, input_acked: bool, now: Instant) -> bool {
+        let mut changed = false;
+        if ctx.alt_screen_active && !self.predictions.is_empty() {
+            self.rollback(ctx);
+            changed = true;
+        }
+
+        // Once the server has processed all of our input and agrees with
+        // the predicted cursor position, we no longer need to predict it
+        if let Some((cursor, _)) = self.cursor {
+            if self.predictions.is_empty()
+                && input_acked
+                && cursor.x == ctx.cursor.x
+                && cursor.y == ctx.cursor.y
+            {
+                self.cursor.take();
+            }
+        }
+        changed | self.expire(ctx, now)
+    }
+}
+
 pub struct RenderableInner {
     client: Arc<ClientInner>,
     remote_pane_id: TabId,
@@ -66,6 +466,9 @@ pub struct RenderableInner {
     pub dimensions: RenderableDimensions,
 
     lines: LruCache<StableRowIndex, LineEntry>,
+    /// The server's version number for the lines that we hold,
+    /// which allows the server to send us deltas
+    line_versions: LruCache<StableRowIndex, u64>,
     pub title: String,
     pub working_dir: Option<Url>,
 
@@ -77,6 +480,10 @@ pub struct RenderableInner {
     last_input_rtt: u64,
 
     pub input_serial: InputSerial,
+    acked_serial: InputSerial,
+
+    pub alt_screen_active: bool,
+    predictor: Predictor,
 }
 
 pub struct RenderableState {
@@ -105,6 +512,7 @@ impl RenderableInner {
             cursor_position: StableCursorPosition::default(),
             dimensions,
             lines: LruCache::new(configuration().scrollback_lines),
+            line_versions: LruCache::new(configuration().scrollback_lines),
             title: title.to_string(),
             working_dir: None,
             fetch_limiter,
@@ -113,6 +521,9 @@ impl RenderableInner {
             last_late_dirty: now,
             last_input_rtt: 0,
             input_serial: InputSerial::empty(),
+            acked_serial: InputSerial::empty(),
+            alt_screen_active: false,
+            predictor: Predictor::default(),
         }
     }
 
@@ -129,73 +540,32 @@ impl RenderableInner {
         }
     }
 
-    /// Predictive echo can be noisy when the link is working well,
-    /// so we only employ it when it looks like the latency is high.
-    /// We pick 100ms as the threshold for this.
-    fn should_predict(&self) -> bool {
-        self.last_input_rtt >= 100
-    }
-
-    /// Compute a "prediction" and apply it to the line data that we
-    /// have available, marking it as dirty so that it gets rendered.
-    /// The prediction is basically just local echo.
-    /// Open questions:
-    /// how do we tell if the intent is to suppress local echo during eg:
-    ///  * password prompt?  One option is to look back and see if the line
-    ///                      looks like a password prompt.
-    ///  * normal mode in vim: letter presses are typically movement or
-    ///                        other editor commands
-    /// There are bound to be a number of other edge cases that we should
-    /// handle.
-    fn apply_prediction(&mut self, c: KeyCode, line: &mut Line) {
-        let text = line.as_str();
-        if text.contains("sword") {
-            // This line might be a password prompt.  Don't force
-            // on local echo here, as we don't want to reveal content
-            // from their password
-            return;
+    /// Returns the line that the server knows by the specified version,
+    /// if we have it; this is the base for a delta sent by the server
+    fn versioned_line(&self, row: StableRowIndex, version: u64) -> Option<Line> {
+        if self.line_versions.peek(&row) == Some(&version) {
+            server_line(&self.lines, row).cloned()
+        } else {
+            None
         }
+    }
 
-        match c {
-            KeyCode::Enter => {
-                self.cursor_position.x = 0;
-                self.cursor_position.y += 1;
-            }
-            KeyCode::UpArrow => {
-                self.cursor_position.y = self.cursor_position.y.saturating_sub(1);
-            }
-            KeyCode::DownArrow => {
-                self.cursor_position.y += 1;
-            }
-            KeyCode::RightArrow => {
-                self.cursor_position.x += 1;
-            }
-            KeyCode::LeftArrow => {
-                self.cursor_position.x = self.cursor_position.x.saturating_sub(1);
-            }
-            KeyCode::Delete => {
-                line.erase_cell(self.cursor_position.x);
-            }
-            KeyCode::Backspace => {
-                if self.cursor_position.x > 0 {
-                    line.erase_cell(self.cursor_position.x - 1);
-                    self.cursor_position.x -= 1;
-                }
-            }
-            KeyCode::Char(c) => {
-                let cell = Cell::new(
-                    c,
-                    CellAttributes::default()
-                        .set_underline(Underline::Double)
-                        .clone(),
-                );
-
-                let cell = line.set_cell(self.cursor_position.x, cell);
-                // Adjust the cursor to reflect the width of this new cell
-                self.cursor_position.x += cell.width();
-            }
-            _ => {}
+    /// Run `f` against the local echo state, then invalidate the rows
+    /// whose display it changed
+    fn with_predictor<R>(&mut self, f: impl FnOnce(&mut Predictor, &EchoContext) -> R) -> R {
+        let ctx = EchoContext {
+            lines: &self.lines,
+            cursor: self.cursor_position,
+            cols: self.dimensions.cols,
+            alt_screen_active: self.alt_screen_active,
+            input_rtt: self.last_input_rtt,
+            threshold_ms: configuration().local_echo_threshold_ms,
+        };
+        let result = f(&mut self.predictor, &ctx);
+        for row in std::mem::take(&mut self.predictor.dirty) {
+            self.make_dirty(row);
         }
+        result
     }
 
     /// Based on a keypress, apply a "prediction" of what the terminal
@@ -203,97 +573,25 @@ impl RenderableInner {
     /// remote system.  The prediction helps to reduce perceived latency
     /// when a user is typing at any reasonable velocity.
     pub fn predict_from_key_event(&mut self, key: KeyCode, mods: KeyModifiers) {
-        if !self.should_predict() {
-            return;
-        }
+        self.with_predictor(|predictor, ctx| predictor.predict_from_key_event(ctx, key, mods));
+    }
 
-        let c = match key {
-            KeyCode::LeftArrow
-            | KeyCode::RightArrow
-            | KeyCode::UpArrow
-            | KeyCode::DownArrow
-            | KeyCode::Delete
-            | KeyCode::Backspace
-            | KeyCode::Enter
-            | KeyCode::Char(_) => key,
-            _ => return,
-        };
-        if mods != KeyModifiers::NONE && mods != KeyModifiers::SHIFT {
-            return;
-        }
+    pub fn predict_from_paste(&mut self, text: &str) {
+        self.with_predictor(|predictor, ctx| predictor.predict_from_paste(ctx, text));
+    }
 
-        let row = self.cursor_position.y;
-        match self.lines.pop(&row) {
-            Some(LineEntry::Stale(mut line))
-            | Some(LineEntry::Line(mut line))
-            | Some(LineEntry::Dirty(mut line)) => {
-                self.apply_prediction(c, &mut line);
-                self.lines.put(row, LineEntry::Dirty(line));
-            }
-            Some(LineEntry::DirtyAndFetching(mut line, instant)) => {
-                self.apply_prediction(c, &mut line);
-                self.lines
-                    .put(row, LineEntry::DirtyAndFetching(line, instant));
+    fn make_dirty(&mut self, stable_row: StableRowIndex) {
+        match self.lines.pop(&stable_row) {
+            Some(LineEntry::Line(line)) => {
+                self.lines.put(stable_row, LineEntry::Dirty(line));
             }
             Some(entry) => {
-                self.lines.put(row, entry);
+                self.lines.put(stable_row, entry);
             }
             None => {}
         }
     }
 
-    fn apply_paste_prediction(&mut self, row: usize, text: &str, line: &mut Line) {
-        let attrs = CellAttributes::default()
-            .set_underline(Underline::Double)
-            .clone();
-
-        let text_line = Line::from_text(text, &attrs);
-
-        if row == 0 {
-            for cell in text_line.cells() {
-                line.set_cell(self.cursor_position.x, cell.clone());
-                self.cursor_position.x += cell.width();
-            }
-        } else {
-            // The pasted line replaces the data for the existing line
-            line.resize_and_clear(0);
-            line.append_line(text_line);
-            self.cursor_position.x = line.cells().len();
-        }
-    }
-
-    pub fn predict_from_paste(&mut self, text: &str) {
-        if !self.should_predict() {
-            return;
-        }
-
-        let text = textwrap::fill(text, self.dimensions.cols);
-        let lines: Vec<&str> = text.split("\n").collect();
-
-        for (idx, paste_line) in lines.iter().enumerate() {
-            let row = self.cursor_position.y + idx as StableRowIndex;
-
-            match self.lines.pop(&row) {
-                Some(LineEntry::Stale(mut line))
-                | Some(LineEntry::Line(mut line))
-                | Some(LineEntry::Dirty(mut line)) => {
-                    self.apply_paste_prediction(idx, paste_line, &mut line);
-                    self.lines.put(row, LineEntry::Dirty(line));
-                }
-                Some(LineEntry::DirtyAndFetching(mut line, instant)) => {
-                    self.apply_paste_prediction(idx, paste_line, &mut line);
-                    self.lines
-                        .put(row, LineEntry::DirtyAndFetching(line, instant));
-                }
-                Some(entry) => {
-                    self.lines.put(row, entry);
-                }
-                None => {}
-            }
-        }
-        self.cursor_position.y += lines.len().saturating_sub(1) as StableRowIndex;
-    }
-
     pub fn update_last_send(&mut self) {
         self.last_send_time = Instant::now();
         self.poll_interval = BASE_POLL_INTERVAL;
@@ -319,6 +617,7 @@ impl RenderableInner {
         // long it took for this response to come back
         if let Some(serial) = delta.input_serial {
             self.last_input_rtt = serial.elapsed_millis();
+            self.acked_serial = self.acked_serial.max(serial);
         }
 
         // When it comes to updating the cursor position, if the update was tagged
@@ -341,13 +640,27 @@ impl RenderableInner {
         self.title = delta.title;
         self.working_dir = delta.working_dir.map(Into::into);
 
+        self.alt_screen_active = delta.alt_screen_active;
+
         let config = configuration();
-        for (stable_row, line) in delta.bonus_lines.lines() {
-            self.put_line(stable_row, line, &config, None);
+        let decoded = delta
+            .bonus_lines
+            .decode_lines(|row, version| self.versioned_line(row, version));
+        for (stable_row, line, version) in decoded.lines {
+            self.put_line(stable_row, line, version, &config, None);
             dirty.remove(stable_row);
         }
+        // We don't have the line that the server based its changes upon,
+        // so we need to fetch the whole line
+        for stable_row in decoded.missing {
+            dirty.add(stable_row);
+        }
+
+        let input_acked = self.acked_serial >= self.input_serial;
+        let predictions_changed =
+            self.with_predictor(|predictor, ctx| predictor.server_updated(ctx, input_acked, now));
 
-        if !dirty.is_empty() {
+        if !dirty.is_empty() || predictions_changed {
             Mux::get()
                 .unwrap()
                 .notify(mux::MuxNotification::PaneOutput(self.local_pane_id));
@@ -428,10 +741,12 @@ impl RenderableInner {
         &mut self,
         stable_row: StableRowIndex,
         mut line: Line,
+        version: u64,
         config: &ConfigHandle,
         fetch_start: Option<Instant>,
     ) {
         line.scan_and_create_hyperlinks(&config.hyperlink_rules);
+        self.with_predictor(|predictor, _| predictor.verify(stable_row, &line));
 
         let entry = if let Some(fetch_start) = fetch_start {
             // If we're completing a fetch, only replace entries that were
@@ -471,6 +786,11 @@ impl RenderableInner {
             }
         };
         self.lines.put(stable_row, entry);
+        if version == 0 {
+            self.line_versions.pop(&stable_row);
+        } else {
+            self.line_versions.put(stable_row, version);
+        }
     }
 
     fn schedule_fetch_lines(&mut self, to_fetch: RangeSet<StableRowIndex>, now: Instant) {
@@ -519,11 +839,13 @@ impl RenderableInner {
             match result {
                 Ok(result) => {
                     let config = configuration();
-                    let lines = result.lines.lines();
+                    // We only request whole lines, so there is no need
+                    // to supply a base for deltas
+                    let lines = result.lines.decode_lines(|_, _| None).lines;
 
                     log::trace!("fetch complete for {:?} at {:?}", to_fetch, now);
-                    for (stable_row, line) in lines.into_iter() {
-                        inner.put_line(stable_row, line, &config, Some(now));
+                    for (stable_row, line, version) in lines.into_iter() {
+                        inner.put_line(stable_row, line, version, &config, Some(now));
                     }
                 }
                 Err(err) => {
@@ -606,7 +928,10 @@ impl RenderableInner {
 
 impl RenderableState {
     pub fn get_cursor_position(&self) -> StableCursorPosition {
-        self.inner.borrow().cursor_position
+        let inner = self.inner.borrow();
+        inner
+            .predictor
+            .displayed_cursor_position(inner.cursor_position)
     }
 
     pub fn get_lines(&self, lines: Range<StableRowIndex>) -> (StableRowIndex, Vec<Line>) {
@@ -646,6 +971,8 @@ impl RenderableState {
                 }
             };
 
+            inner.predictor.overlay(idx, result.last_mut().unwrap());
+
             if idx == inner.dimensions.physical_top {
                 if inner.is_tardy() {
                     let status = format!(
@@ -688,6 +1015,7 @@ impl RenderableState {
                 inner.dead = true;
             }
         }
+        inner.with_predictor(|predictor, ctx| predictor.expire(ctx, Instant::now()));
 
         let mut result = RangeSet::new();
         for r in lines {
@@ -721,3 +1049,213 @@ impl RenderableState {
         self.inner.borrow().dimensions
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The state of a single line pane, as reported by the server
+    struct Server {
+        lines: LruCache<StableRowIndex, LineEntry>,
+        cursor: StableCursorPosition,
+        alt_screen_active: bool,
+    }
+
+    impl Server {
+        fn new(text: &str) -> Self {
+            let mut server = Self {
+                lines: LruCache::unbounded(),
+                cursor: StableCursorPosition::default(),
+                alt_screen_active: false,
+            };
+            server.echo(text);
+            server
+        }
+
+        /// Update the line, placing the cursor at the end of the text
+        fn echo(&mut self, text: &str) -> Line {
+            let line = Line::from_text(text, &CellAttributes::default());
+            self.lines.put(0, LineEntry::Line(line.clone()));
+            self.cursor.x = text.len();
+            line
+        }
+
+        fn ctx(&self) -> EchoContext<'_> {
+            EchoContext {
+                lines: &self.lines,
+                cursor: self.cursor,
+                cols: 80,
+                alt_screen_active: self.alt_screen_active,
+                input_rtt: 100,
+                threshold_ms: 0,
+            }
+        }
+
+        /// Returns what we render for the line
+        fn displayed(&self, predictor: &Predictor) -> String {
+            let mut line = server_line(&self.lines, 0).unwrap().clone();
+            predictor.overlay(0, &mut line);
+            line.as_str().trim_end().to_string()
+        }
+
+        fn displayed_cursor(&self, predictor: &Predictor) -> usize {
+            predictor.displayed_cursor_position(self.cursor).x
+        }
+
+        fn type_text(&self, predictor: &mut Predictor, text: &str) {
+            for c in text.chars() {
+                self.key(predictor, KeyCode::Char(c));
+            }
+        }
+
+        fn key(&self, predictor: &mut Predictor, key: KeyCode) {
+            predictor.predict_from_key_event(&self.ctx(), key, KeyModifiers::NONE);
+        }
+    }
+
+    fn later() -> Instant {
+        Instant::now() + Duration::from_secs(1)
+    }
+
+    #[test]
+    fn confirm() {
+        let mut server = Server::new("$ ");
+        let mut predictor = Predictor::default();
+
+        server.type_text(&mut predictor, "ls");
+        // Nothing is shown until the server shows that it echoes
+        assert_eq!(server.displayed(&predictor), "$");
+        assert_eq!(server.displayed_cursor(&predictor), 2);
+
+        let line = server.echo("$ l");
+        predictor.verify(0, &line);
+        assert_eq!(predictor.predictions.len(), 1);
+        assert_eq!(server.displayed(&predictor), "$ ls");
+        assert_eq!(server.displayed_cursor(&predictor), 4);
+        let mut line = line;
+        predictor.overlay(0, &mut line);
+        assert_eq!(line.cells()[2].attrs().underline(), Underline::None);
+        assert_eq!(line.cells()[3].attrs().underline(), Underline::Double);
+
+        let line = server.echo("$ ls");
+        predictor.verify(0, &line);
+        assert!(predictor.predictions.is_empty());
+        assert!(!predictor.server_updated(&server.ctx(), true, Instant::now()));
+        assert!(predictor.cursor.is_none());
+        assert_eq!(server.displayed_cursor(&predictor), 4);
+    }
+
+    #[test]
+    fn insert_and_erase() {
+        let server = Server::new("$ ac");
+        let mut predictor = Predictor::default();
+
+        server.key(&mut predictor, KeyCode::LeftArrow);
+        server.type_text(&mut predictor, "b");
+        let line = predictor.predicted_line(&server.ctx(), 0).unwrap();
+        assert_eq!(line.as_str().trim_end(), "$ abc");
+        assert_eq!(predictor.prediction_cursor(&server.ctx()).x, 4);
+
+        server.key(&mut predictor, KeyCode::Backspace);
+        server.key(&mut predictor, KeyCode::Backspace);
+        let line = predictor.predicted_line(&server.ctx(), 0).unwrap();
+        assert_eq!(line.as_str().trim_end(), "$ c");
+        assert_eq!(predictor.prediction_cursor(&server.ctx()).x, 2);
+        assert_eq!(predictor.epoch, 0);
+    }
+
+    #[test]
+    fn mispredict_rolls_back() {
+        let mut server = Server::new("$ ");
+        let mut predictor = Predictor::default();
+
+        server.type_text(&mut predictor, "a");
+        let line = server.echo("$ a");
+        predictor.verify(0, &line);
+
+        // The application echoes something other than what we typed
+        server.type_text(&mut predictor, "b");
+        let line = server.echo("$ aB");
+        predictor.verify(0, &line);
+        assert_eq!(server.displayed(&predictor), "$ ab");
+
+        assert!(!predictor.expire(&server.ctx(), Instant::now()));
+        assert!(predictor.expire(&server.ctx(), later()));
+        assert!(predictor.predictions.is_empty());
+        assert!(predictor.cursor.is_none());
+        assert_eq!(predictor.epoch, 1);
+        assert_eq!(server.displayed(&predictor), "$ aB");
+        assert_eq!(server.displayed_cursor(&predictor), 4);
+    }
+
+    #[test]
+    fn cursor_expiry() {
+        let server = Server::new("$ abc");
+        let mut predictor = Predictor::default();
+
+        server.type_text(&mut predictor, "d");
+        let line = Line::from_text("$ abcd", &CellAttributes::default());
+        predictor.verify(0, &line);
+        server.key(&mut predictor, KeyCode::LeftArrow);
+        server.key(&mut predictor, KeyCode::LeftArrow);
+        assert_eq!(server.displayed_cursor(&predictor), 4);
+
+        // The server never moves the cursor, so our prediction lapses
+        assert!(!predictor.expire(&server.ctx(), Instant::now()));
+        assert!(predictor.expire(&server.ctx(), later()));
+        assert_eq!(server.displayed_cursor(&predictor), 5);
+        assert!(predictor.cursor.is_none());
+    }
+
+    #[test]
+    fn password_prompt() {
+        let mut server = Server::new("$ ");
+        let mut predictor = Predictor::default();
+
+        server.type_text(&mut predictor, "sudo");
+        let line = server.echo("$ sudo");
+        predictor.verify(0, &line);
+        assert!(predictor.predictions.is_empty());
+
+        server.key(&mut predictor, KeyCode::Enter);
+        assert_eq!(predictor.epoch, 1);
+        let line = server.echo("Password: ");
+        predictor.verify(0, &line);
+
+        server.type_text(&mut predictor, "hunter2");
+        assert_eq!(predictor.predictions.len(), 7);
+        assert_eq!(server.displayed(&predictor), "Password:");
+        assert_eq!(server.displayed_cursor(&predictor), 10);
+
+        // The server doesn't echo, so the input is never shown
+        predictor.verify(0, &line);
+        assert_eq!(server.displayed(&predictor), "Password:");
+        assert!(predictor.expire(&server.ctx(), later()));
+        assert!(predictor.predictions.is_empty());
+        assert_eq!(server.displayed_cursor(&predictor), 10);
+    }
+
+    #[test]
+    fn unpredictable_keys() {
+        let mut server = Server::new("$ ");
+        let mut predictor = Predictor::default();
+
+        for key in &[KeyCode::UpArrow, KeyCode::DownArrow, KeyCode::Enter] {
+            let epoch = predictor.epoch;
+            server.key(&mut predictor, *key);
+            assert_eq!(predictor.epoch, epoch + 1);
+            assert!(predictor.predictions.is_empty());
+        }
+
+        server.type_text(&mut predictor, "v");
+        assert_eq!(predictor.predictions.len(), 1);
+
+        // Entering the alternate screen discards the predictions
+        // and suppresses further predictions
+        server.alt_screen_active = true;
+        assert!(predictor.server_updated(&server.ctx(), false, Instant::now()));
+        assert!(predictor.predictions.is_empty());
+        server.type_text(&mut predictor, "j");
+        assert!(predictor.predictions.is_empty());
+    }
+}
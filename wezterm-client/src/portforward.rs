@@ -0,0 +1,230 @@
+use crate::client::{Client, WeakClient};
+use codec::*;
+use config::{PortForward, PortForwardDirection};
+use mux::portforward::{alloc_stream_id, TcpForwardListener, TcpRelay};
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct ClientForward {
+    forward: PortForward,
+    /// The id that the server assigned to this forward.  This is
+    /// shared with the listener, and changes when we reconnect.
+    forward_id: Arc<AtomicU64>,
+    /// Accepts connections for LocalToRemote forwards
+    _listener: Option<TcpForwardListener>,
+}
+
+/// The client side of the port forwards made over a connection
+/// to a mux server
+#[derive(Default)]
+pub struct PortForwards {
+    /// Used to talk to the server from the threads that relay the
+    /// forwarded connections.  This is only populated once a forward
+    /// has been added.  It is a weak handle because the Client owns
+    /// us, and the connection should close once the Client is dropped.
+    client: Mutex<Option<WeakClient>>,
+    forwards: Mutex<HashMap<u64, ClientForward>>,
+    relay: TcpRelay,
+}
+
+impl PortForwards {
+    fn client(&self) -> Option<WeakClient> {
+        self.client.lock().unwrap().clone()
+    }
+
+    /// Start the local side of a forward that the server has
+    /// accepted as `forward_id`
+    pub fn add(
+        self: &Arc<Self>,
+        client: &Client,
+        forward_id: u64,
+        forward: PortForward,
+    ) -> anyhow::Result<()> {
+        self.client.lock().unwrap().replace(client.downgrade());
+        let forward_id = Arc::new(AtomicU64::new(forward_id));
+        let listener = match forward.direction {
+            PortForwardDirection::RemoteToLocal => None,
+            PortForwardDirection::LocalToRemote => {
+                let forwards = Arc::downgrade(self);
+                let forward_id = Arc::clone(&forward_id);
+                Some(TcpForwardListener::spawn(
+                    &format!("127.0.0.1:{}", forward.local),
+                    move |stream| {
+                        if let Some(forwards) = forwards.upgrade() {
+                            forwards.accept(forward_id.load(Ordering::Relaxed), stream);
+                        }
+                    },
+                )?)
+            }
+        };
+        self.forwards.lock().unwrap().insert(
+            forward_id.load(Ordering::Relaxed),
+            ClientForward {
+                forward,
+                forward_id,
+                _listener: listener,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forwards.lock().unwrap().is_empty()
+    }
+
+    /// After reconnecting, the server has no knowledge of our
+    /// forwards, so register them again.  The local listeners remain
+    /// active throughout.  Connections that were being forwarded are
+    /// closed, as the server will have closed its end.
+    pub async fn reestablish(&self, client: &Client) {
+        self.relay.close_all();
+        let entries: Vec<ClientForward> = self
+            .forwards
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, entry)| entry)
+            .collect();
+        for entry in entries {
+            match client
+                .request_port_forward(AddPortForward {
+                    forward: entry.forward.clone(),
+                })
+                .await
+            {
+                Ok(AddPortForwardResponse { forward_id }) => {
+                    entry.forward_id.store(forward_id, Ordering::Relaxed);
+                    self.forwards.lock().unwrap().insert(forward_id, entry);
+                }
+                Err(err) => {
+                    log::error!("re-establishing port forward {}: {:#}", entry.forward, err);
+                }
+            }
+        }
+    }
+
+    /// Accept a connection for a LocalToRemote forward
+    fn accept(self: Arc<Self>, forward_id: u64, stream: TcpStream) {
+        let client = match self.client() {
+            Some(client) => client,
+            None => return,
+        };
+        let stream_id = alloc_stream_id(true);
+        // Data from the server may arrive before the open
+        // request completes
+        self.relay.reserve(stream_id);
+        std::thread::spawn(move || {
+            let result = smol::block_on(client.open_tcp_stream(OpenTcpStream {
+                forward_id,
+                stream_id,
+            }))
+            .and_then(|_| relay_stream(&self.relay, client, stream_id, stream));
+            if let Err(err) = result {
+                log::error!("port forward {}: {:#}", forward_id, err);
+                self.relay.abort(stream_id);
+            }
+        });
+    }
+
+    /// Handle a unilateral PDU for which `Pdu::is_port_forward` is true
+    pub fn process_unilateral(&self, pdu: Pdu) {
+        match pdu {
+            Pdu::TcpStreamData(TcpStreamData { stream_id, data }) => {
+                self.relay.write(stream_id, data);
+            }
+            Pdu::TcpStreamAck(TcpStreamAck { stream_id, len }) => {
+                self.relay.ack(stream_id, len as usize);
+            }
+            Pdu::CloseTcpStream(CloseTcpStream { stream_id }) => {
+                self.relay.finish_writing(stream_id);
+            }
+            Pdu::TcpStreamOpened(TcpStreamOpened {
+                forward_id,
+                stream_id,
+            }) => {
+                let local = self
+                    .forwards
+                    .lock()
+                    .unwrap()
+                    .get(&forward_id)
+                    .map(|f| f.forward.local);
+                let (local, client) = match (local, self.client()) {
+                    (Some(local), Some(client)) => (local, client),
+                    _ => {
+                        log::error!("connection for unknown port forward {}", forward_id);
+                        return;
+                    }
+                };
+                self.relay.reserve(stream_id);
+                let relay = self.relay.clone();
+                std::thread::spawn(move || {
+                    let result = TcpStream::connect(("127.0.0.1", local))
+                        .map_err(|err| anyhow::anyhow!("connecting to port {}: {}", local, err))
+                        .and_then(|stream| relay_stream(&relay, client.clone(), stream_id, stream));
+                    if let Err(err) = result {
+                        log::error!("port forward {}: {:#}", forward_id, err);
+                        relay.abort(stream_id);
+                        smol::block_on(
+                            client
+                                .send_unilateral(Pdu::CloseTcpStream(CloseTcpStream { stream_id })),
+                        )
+                        .ok();
+                    }
+                });
+            }
+            Pdu::PortForwardRemoved(PortForwardRemoved { forward_id }) => {
+                if let Some(forward) = self.forwards.lock().unwrap().remove(&forward_id) {
+                    log::info!(
+                        "port forward {} ({}) was removed",
+                        forward_id,
+                        forward.forward
+                    );
+                }
+            }
+            _ => log::error!("unexpected port forward pdu {:?}", pdu),
+        }
+    }
+}
+
+impl Drop for PortForwards {
+    fn drop(&mut self) {
+        self.relay.close_all();
+    }
+}
+
+/// Relay `stream` to the server.  The data and acknowledgements are
+/// sent unilaterally, rather than waiting for a response to each chunk;
+/// the relay limits how much may be in flight at once.
+fn relay_stream(
+    relay: &TcpRelay,
+    client: WeakClient,
+    stream_id: u64,
+    stream: TcpStream,
+) -> anyhow::Result<()> {
+    let eof_client = client.clone();
+    let ack_client = client.clone();
+    relay.add(
+        stream_id,
+        stream,
+        move |data| {
+            smol::block_on(
+                client.send_unilateral(Pdu::TcpStreamData(TcpStreamData { stream_id, data })),
+            )
+        },
+        move || {
+            smol::block_on(
+                eof_client.send_unilateral(Pdu::CloseTcpStream(CloseTcpStream { stream_id })),
+            )
+            .ok();
+        },
+        move |len| {
+            smol::block_on(ack_client.send_unilateral(Pdu::TcpStreamAck(TcpStreamAck {
+                stream_id,
+                len: len as u64,
+            })))
+            .ok();
+        },
+    )
+}
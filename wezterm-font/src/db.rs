@@ -1,6 +1,8 @@
 //! A font-database to keep track of fonts that we've located
 
-use crate::parser::{font_info_matches, load_built_in_fonts, parse_and_collect_font_info, Names};
+use crate::parser::{
+    font_info_matches, load_built_in_fonts, parse_and_collect_font_info, ParsedFont,
+};
 use crate::FontDataHandle;
 use anyhow::{anyhow, Context};
 use config::{Config, FontAttributes};
@@ -12,7 +14,7 @@ use std::path::PathBuf;
 use std::rc::Rc;
 
 struct Entry {
-    names: Names,
+    parsed: ParsedFont,
     handle: FontDataHandle,
     coverage: RefCell<Option<RangeSet<u32>>>,
 }
@@ -58,7 +60,7 @@ impl Entry {
             metrics::histogram!("font.compute.codepoint.coverage", elapsed);
             log::debug!(
                 "{} codepoint coverage computed in {:?}",
-                self.names.full_name,
+                self.parsed.names().full_name,
                 elapsed
             );
         }
@@ -80,15 +82,15 @@ impl FontDatabase {
         }
     }
 
-    fn load_font_info(&mut self, font_info: Vec<(Names, PathBuf, FontDataHandle)>) {
-        for (names, _path, handle) in font_info {
+    fn load_font_info(&mut self, font_info: Vec<(ParsedFont, PathBuf, FontDataHandle)>) {
+        for (parsed, _path, handle) in font_info {
             let entry = Rc::new(Entry {
-                names,
+                parsed,
                 handle,
                 coverage: RefCell::new(None),
             });
 
-            if let Some(family) = entry.names.family.as_ref() {
+            if let Some(family) = entry.parsed.names().family.as_ref() {
                 self.by_family
                     .entry(family.to_string())
                     .or_insert_with(Vec::new)
@@ -96,7 +98,7 @@ impl FontDatabase {
             }
 
             self.by_full_name
-                .entry(entry.names.full_name.clone())
+                .entry(entry.parsed.names().full_name.clone())
                 .or_insert(entry);
         }
     }
@@ -196,21 +198,29 @@ impl FontDatabase {
         Ok(matches.into_iter().map(|(_len, handle)| handle).collect())
     }
 
+    /// Returns true if the handle was loaded into this database
+    pub fn contains(&self, handle: &FontDataHandle) -> bool {
+        self.by_family
+            .values()
+            .flatten()
+            .chain(self.by_full_name.values())
+            .any(|entry| entry.handle == *handle)
+    }
+
     pub fn resolve(&self, font_attr: &FontAttributes) -> Option<&FontDataHandle> {
         if let Some(entry) = self.by_full_name.get(&font_attr.family) {
-            if font_info_matches(font_attr, &entry.names) {
+            if font_info_matches(font_attr, &entry.parsed) {
                 return Some(&entry.handle);
             }
         }
 
-        if let Some(family) = self.by_family.get(&font_attr.family) {
-            for entry in family {
-                if font_info_matches(font_attr, &entry.names) {
-                    return Some(&entry.handle);
-                }
-            }
-        }
-
-        None
+        // A family may have several faces that are close enough to
+        // match; pick the one nearest to the requested weight and stretch
+        self.by_family
+            .get(&font_attr.family)?
+            .iter()
+            .filter(|entry| font_info_matches(font_attr, &entry.parsed))
+            .min_by_key(|entry| entry.parsed.style_distance(font_attr))
+            .map(|entry| &entry.handle)
     }
 }
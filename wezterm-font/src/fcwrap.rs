@@ -13,6 +13,16 @@ use std::sync::Mutex;
 pub const FC_MONO: i32 = 100;
 pub const FC_DUAL: i32 = 90;
 
+pub const FC_WEIGHT_THIN: i32 = 0;
+pub const FC_WEIGHT_EXTRALIGHT: i32 = 40;
+pub const FC_WEIGHT_LIGHT: i32 = 50;
+pub const FC_WEIGHT_REGULAR: i32 = 80;
+pub const FC_WEIGHT_MEDIUM: i32 = 100;
+pub const FC_WEIGHT_DEMIBOLD: i32 = 180;
+pub const FC_WEIGHT_BOLD: i32 = 200;
+pub const FC_WEIGHT_EXTRABOLD: i32 = 205;
+pub const FC_WEIGHT_BLACK: i32 = 210;
+
 lazy_static::lazy_static! {
     /// This is hideous and gross, but we don't have a lot of choice.
     /// The issue here is that the fontconfig library maintains some
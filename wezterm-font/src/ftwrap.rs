@@ -2,7 +2,7 @@
 
 use crate::locator::FontDataHandle;
 use anyhow::{anyhow, Context};
-use config::{configuration, FontAntiAliasing, FontHinting};
+use config::{FontAntiAliasing, FontHinting, FontStretch, FontWeight};
 pub use freetype::*;
 use std::ptr;
 
@@ -41,16 +41,17 @@ fn render_mode_to_load_target(render_mode: FT_Render_Mode) -> u32 {
     (render_mode as u32) & 15 << 16
 }
 
-pub fn compute_load_flags_from_config() -> (i32, FT_Render_Mode) {
-    let config = configuration();
-
-    let render = match config.font_antialias {
+pub fn compute_load_flags(
+    hinting: FontHinting,
+    antialias: FontAntiAliasing,
+) -> (i32, FT_Render_Mode) {
+    let render = match antialias {
         FontAntiAliasing::None => FT_Render_Mode::FT_RENDER_MODE_MONO,
         FontAntiAliasing::Greyscale => FT_Render_Mode::FT_RENDER_MODE_NORMAL,
         FontAntiAliasing::Subpixel => FT_Render_Mode::FT_RENDER_MODE_LCD,
     };
 
-    let flags = match config.font_hinting {
+    let flags = match hinting {
         FontHinting::None => {
             render_mode_to_load_target(FT_Render_Mode::FT_RENDER_MODE_NORMAL) | FT_LOAD_NO_HINTING
         }
@@ -63,7 +64,7 @@ pub fn compute_load_flags_from_config() -> (i32, FT_Render_Mode) {
     // If the bitmaps are in color, we want those!
     let flags = flags | FT_LOAD_COLOR;
 
-    let flags = if config.font_antialias == FontAntiAliasing::None {
+    let flags = if antialias == FontAntiAliasing::None {
         // When AA is disabled, force outline rendering to monochrome
         flags | FT_LOAD_MONOCHROME
     } else {
@@ -92,6 +93,11 @@ struct FaceSize {
     dpi: u32,
     cell_width: f64,
     cell_height: f64,
+    /// The factor by which glyphs from the selected bitmap strike
+    /// need to be scaled to match the requested size
+    bitmap_scale: f64,
+    /// The height of the selected bitmap strike, in pixels
+    strike_height: Option<f64>,
 }
 
 impl Face {
@@ -109,6 +115,8 @@ impl Face {
         // the fallback code for set_pixel_sizes below.
         let size = (point_size * 64.0) as FT_F26Dot6;
 
+        let mut bitmap_scale = 1.0;
+        let mut strike_height = None;
         let (cell_width, cell_height) = match self.set_char_size(size, size, dpi, dpi) {
             Ok(_) => {
                 // Compute metrics for the nominal monospace cell
@@ -122,24 +130,35 @@ impl Face {
                 if sizes.is_empty() {
                     return Err(err);
                 }
-                // Find the best matching size.
-                // We just take the biggest.
-                let mut best = 0;
-                let mut best_size = 0;
-                let mut cell_width = 0;
-                let mut cell_height = 0;
-
-                for (idx, info) in sizes.iter().enumerate() {
-                    let size = best_size.max(info.height);
-                    if size > best_size {
-                        best = idx;
-                        best_size = size;
-                        cell_width = info.width;
-                        cell_height = info.height;
+                // This is a bitmap font, such as a CBDT or sbix color
+                // emoji font.  Select the smallest strike that is at
+                // least as large as the requested size, so that glyphs
+                // only ever need to be scaled down, or otherwise the
+                // largest strike.
+                let wanted_ppem = point_size * f64::from(dpi) / 72.0;
+                let ppem = |info: &FT_Bitmap_Size| {
+                    if info.y_ppem > 0 {
+                        info.y_ppem as f64 / 64.0
+                    } else {
+                        f64::from(info.height)
                     }
-                }
+                };
+                let by_ppem = |a: &(usize, &FT_Bitmap_Size), b: &(usize, &FT_Bitmap_Size)| {
+                    ppem(a.1)
+                        .partial_cmp(&ppem(b.1))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                };
+                let (best, info) = sizes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, info)| ppem(info) >= wanted_ppem)
+                    .min_by(by_ppem)
+                    .or_else(|| sizes.iter().enumerate().max_by(by_ppem))
+                    .unwrap();
                 self.select_size(best)?;
-                (f64::from(cell_width), f64::from(cell_height))
+                bitmap_scale = wanted_ppem / ppem(info);
+                strike_height = Some(f64::from(info.height));
+                (f64::from(info.width), f64::from(info.height))
             }
         };
 
@@ -148,11 +167,76 @@ impl Face {
             dpi,
             cell_width,
             cell_height,
+            bitmap_scale,
+            strike_height,
         });
 
         Ok((cell_width, cell_height))
     }
 
+    /// Returns the factor by which glyphs need to be scaled to match
+    /// the size passed to `set_font_size`.  This is 1.0 for scalable
+    /// fonts, but bitmap fonts only have a fixed set of sizes.
+    /// If `cell_height` is specified, glyphs from a bitmap strike are
+    /// instead scaled so that the height of the strike fills the cell.
+    pub fn bitmap_scale(&self, cell_height: Option<f64>) -> f64 {
+        match self.size.as_ref() {
+            Some(size) => match (size.strike_height, cell_height) {
+                (Some(strike_height), Some(cell_height)) if strike_height > 0. => {
+                    cell_height / strike_height
+                }
+                _ => size.bitmap_scale,
+            },
+            None => 1.0,
+        }
+    }
+
+    /// Returns the `(glyph, palette_index)` pairs for the layers of a
+    /// COLRv0 color glyph, from bottom to top.  The list is empty if
+    /// the glyph is not a layered color glyph.
+    pub fn color_glyph_layers(&self, glyph_index: FT_UInt) -> Vec<(FT_UInt, FT_UInt)> {
+        let mut layers = vec![];
+        let mut iterator = FT_LayerIterator {
+            num_layers: 0,
+            layer: 0,
+            p: ptr::null_mut(),
+        };
+        loop {
+            let mut layer_glyph = 0;
+            let mut color_index = 0;
+            let more = unsafe {
+                FT_Get_Color_Glyph_Layer(
+                    self.face,
+                    glyph_index,
+                    &mut layer_glyph,
+                    &mut color_index,
+                    &mut iterator,
+                )
+            };
+            if more == 0 {
+                break;
+            }
+            layers.push((layer_glyph, color_index));
+        }
+        layers
+    }
+
+    /// Returns the colors of the default CPAL palette
+    pub fn palette(&self) -> anyhow::Result<Vec<FT_Color>> {
+        unsafe {
+            let mut data: FT_Palette_Data = std::mem::zeroed();
+            ft_result(FT_Palette_Data_Get(self.face, &mut data), ())
+                .context("FT_Palette_Data_Get")?;
+            let mut colors = ptr::null_mut();
+            ft_result(FT_Palette_Select(self.face, 0, &mut colors), ())
+                .context("FT_Palette_Select")?;
+            if colors.is_null() {
+                return Ok(vec![]);
+            }
+            Ok(std::slice::from_raw_parts(colors, data.num_palette_entries as usize).to_vec())
+        }
+    }
+
     fn set_char_size(
         &mut self,
         char_width: FT_F26Dot6,
@@ -245,6 +329,68 @@ impl Face {
     }
 }
 
+fn tag(tag: &[u8; 4]) -> FT_ULong {
+    u32::from_be_bytes(*tag) as FT_ULong
+}
+
+fn to_fixed(value: f64) -> FT_Fixed {
+    (value * 65536.0) as FT_Fixed
+}
+
+/// Safety: `mm` must have been obtained from `face` via `FT_Get_MM_Var`
+unsafe fn select_variation_from_mm(
+    face: &mut Face,
+    mm: &FT_MM_Var,
+    weight: FontWeight,
+    stretch: FontStretch,
+    italic: bool,
+) -> anyhow::Result<()> {
+    let axes = std::slice::from_raw_parts(mm.axis, mm.num_axis as usize);
+    let mut coords: Vec<FT_Fixed> = axes
+        .iter()
+        .map(|axis| {
+            let wanted = if axis.tag == tag(b"wght") {
+                to_fixed(f64::from(weight.0))
+            } else if axis.tag == tag(b"wdth") {
+                to_fixed(stretch.to_percent())
+            } else if axis.tag == tag(b"ital") {
+                to_fixed(if italic { 1. } else { 0. })
+            } else if axis.tag == tag(b"slnt") {
+                // Slant is measured counter-clockwise, so the most
+                // italic value is the minimum
+                if italic {
+                    axis.minimum
+                } else {
+                    0
+                }
+            } else {
+                axis.def
+            };
+            wanted.clamp(axis.minimum, axis.maximum)
+        })
+        .collect();
+
+    // If a named instance has exactly these coordinates then prefer
+    // it; the font may name or tune its instances specially.
+    let styles = std::slice::from_raw_parts(mm.namedstyle, mm.num_namedstyles as usize);
+    for (idx, style) in styles.iter().enumerate() {
+        let style_coords = std::slice::from_raw_parts(style.coords, mm.num_axis as usize);
+        if style_coords == coords.as_slice() {
+            log::trace!("selecting named instance {}", idx + 1);
+            // Named instance indices are 1-based; 0 is the default instance
+            return ft_result(FT_Set_Named_Instance(face.face, idx as FT_UInt + 1), ())
+                .context("FT_Set_Named_Instance");
+        }
+    }
+
+    log::trace!("setting variation coordinates {:?}", coords);
+    ft_result(
+        FT_Set_Var_Design_Coordinates(face.face, coords.len() as FT_UInt, coords.as_mut_ptr()),
+        (),
+    )
+    .context("FT_Set_Var_Design_Coordinates")
+}
+
 pub struct Library {
     lib: FT_Library,
 }
@@ -287,6 +433,32 @@ impl Library {
         Ok(lib)
     }
 
+    /// For a variable font, selects the named instance whose axes match
+    /// the requested style, or otherwise sets the `wght`, `wdth`, `ital`
+    /// and `slnt` axes to approximate it as closely as the font allows.
+    /// Fonts without variation axes are left unchanged.
+    pub fn select_variation(
+        &self,
+        face: &mut Face,
+        weight: FontWeight,
+        stretch: FontStretch,
+        italic: bool,
+    ) -> anyhow::Result<()> {
+        let is_variable =
+            unsafe { ((*face.face).face_flags as u32) & FT_FACE_FLAG_MULTIPLE_MASTERS != 0 };
+        if !is_variable {
+            return Ok(());
+        }
+
+        let mut mm = ptr::null_mut();
+        ft_result(unsafe { FT_Get_MM_Var(face.face, &mut mm) }, ()).context("FT_Get_MM_Var")?;
+        let result = unsafe { select_variation_from_mm(face, &*mm, weight, stretch, italic) };
+        unsafe {
+            FT_Done_MM_Var(self.lib, mm);
+        }
+        result
+    }
+
     pub fn face_from_locator(&self, handle: &FontDataHandle) -> anyhow::Result<Face> {
         match handle {
             FontDataHandle::OnDisk { path, index } => {
@@ -188,6 +188,23 @@ impl Buffer {
         }
     }
 
+    /// Fill in any unset script, direction and language based
+    /// on the text held by the buffer
+    pub fn guess_segment_properties(&mut self) {
+        unsafe {
+            hb_buffer_guess_segment_properties(self.buf);
+        }
+    }
+
+    /// Reverse the order of the glyphs held by the buffer.
+    /// This is used to return the output of shaping right to left
+    /// text to logical order.
+    pub fn reverse(&mut self) {
+        unsafe {
+            hb_buffer_reverse(self.buf);
+        }
+    }
+
     pub fn set_script(&mut self, script: hb_script_t) {
         unsafe {
             hb_buffer_set_script(self.buf, script);
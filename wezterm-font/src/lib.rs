@@ -3,7 +3,10 @@ use crate::locator::{new_locator, FontDataHandle, FontLocator, FontLocatorSelect
 use crate::rasterizer::{new_rasterizer, FontRasterizer};
 use crate::shaper::{new_shaper, FontShaper, FontShaperSelection};
 use anyhow::{Context, Error};
-use config::{configuration, ConfigHandle, FontRasterizerSelection, TextStyle};
+use config::{
+    configuration, Config, ConfigHandle, FontAntiAliasing, FontAttributes, FontHinting,
+    FontRasterizerSelection, FontStretch, FontWeight, TextStyle,
+};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::rc::{Rc, Weak};
@@ -23,11 +26,69 @@ pub mod units;
 pub mod fcwrap;
 
 pub use crate::rasterizer::RasterizedGlyph;
-pub use crate::shaper::{FallbackIdx, FontMetrics, GlyphInfo};
+pub use crate::shaper::{Direction, FallbackIdx, FontMetrics, GlyphInfo};
+
+/// Describes where a font was loaded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontSource {
+    /// One of the directories listed in `font_dirs`
+    FontDirs,
+    /// One of the fonts compiled into wezterm
+    BuiltIn,
+    /// The system font locator
+    Locator,
+}
+
+impl std::fmt::Display for FontSource {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::FontDirs => write!(fmt, "font_dirs"),
+            Self::BuiltIn => write!(fmt, "built-in"),
+            Self::Locator => write!(fmt, "system locator"),
+        }
+    }
+}
+
+/// Settings that apply to an individual font in the fallback list.
+/// They are taken from the `FontAttributes` that selected the font,
+/// with anything left unspecified there taken from the global config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontOptions {
+    pub scale: f64,
+    pub harfbuzz_features: Vec<String>,
+    pub hinting: FontHinting,
+    pub antialias: FontAntiAliasing,
+    /// The style to select from a variable font
+    pub weight: FontWeight,
+    pub stretch: FontStretch,
+    pub italic: bool,
+}
+
+impl FontOptions {
+    pub fn new(config: &Config, attr: Option<&FontAttributes>) -> Self {
+        Self {
+            scale: attr.and_then(|a| a.scale).unwrap_or(1.0),
+            harfbuzz_features: attr
+                .and_then(|a| a.harfbuzz_features.clone())
+                .unwrap_or_else(|| config.harfbuzz_features.clone()),
+            hinting: attr.and_then(|a| a.hinting).unwrap_or(config.font_hinting),
+            antialias: attr
+                .and_then(|a| a.antialias)
+                .unwrap_or(config.font_antialias),
+            weight: attr.map(|a| a.effective_weight()).unwrap_or_default(),
+            stretch: attr.map(|a| a.effective_stretch()).unwrap_or_default(),
+            italic: attr.map(|a| a.italic).unwrap_or(false),
+        }
+    }
+}
 
 pub struct LoadedFont {
     rasterizers: RefCell<HashMap<FallbackIdx, Box<dyn FontRasterizer>>>,
     handles: RefCell<Vec<FontDataHandle>>,
+    /// The options for each of the handles
+    options: RefCell<Vec<FontOptions>>,
+    /// Codepoints for which we've already searched for fallback fonts
+    fallback_searched: RefCell<HashSet<char>>,
     shaper: RefCell<Box<dyn FontShaper>>,
     metrics: FontMetrics,
     font_size: f64,
@@ -40,16 +101,35 @@ impl LoadedFont {
         self.metrics
     }
 
-    fn insert_fallback_handles(&self, extra_handles: Vec<FontDataHandle>) -> anyhow::Result<bool> {
+    /// Returns the handles for the fonts in the fallback sequence,
+    /// in order; a `GlyphInfo::font_idx` is an index into this list.
+    pub fn clone_handles(&self) -> Vec<FontDataHandle> {
+        self.handles.borrow().clone()
+    }
+
+    fn insert_fallback_handles(
+        &self,
+        extra_handles: Vec<FontDataHandle>,
+        fallback_str: &str,
+    ) -> anyhow::Result<bool> {
         let mut loaded = false;
         {
             let mut handles = self.handles.borrow_mut();
+            let mut options = self.options.borrow_mut();
+            let config = configuration();
             for h in extra_handles {
                 if !handles.iter().any(|existing| *existing == h) {
                     match crate::parser::ParsedFont::from_locator(&h) {
-                        Ok(_parsed) => {
+                        Ok(parsed) => {
+                            log::info!(
+                                "Using {} ({:?}) as a fallback for {}",
+                                parsed.names().full_name,
+                                h,
+                                fallback_str.escape_debug()
+                            );
                             let idx = handles.len() - 1;
                             handles.insert(idx, h);
+                            options.insert(idx, FontOptions::new(&config, None));
                             loaded = true;
                         }
                         Err(err) => {
@@ -60,22 +140,50 @@ impl LoadedFont {
             }
         }
         if loaded {
-            *self.shaper.borrow_mut() =
-                new_shaper(FontShaperSelection::get_default(), &self.handles.borrow())?;
+            *self.shaper.borrow_mut() = new_shaper(
+                FontShaperSelection::get_default(),
+                &self.handles.borrow(),
+                &self.options.borrow(),
+            )?;
+            // Inserting the handles shifted the fallback indices
+            self.rasterizers.borrow_mut().clear();
         }
         Ok(loaded)
     }
 
-    pub fn shape(&self, text: &str) -> anyhow::Result<Vec<GlyphInfo>> {
+    pub fn shape(&self, text: &str, direction: Direction) -> anyhow::Result<Vec<GlyphInfo>> {
+        self.shape_with_features(text, direction, &[])
+    }
+
+    /// Shape text, applying `features` in addition to the harfbuzz
+    /// features that are configured for the font
+    pub fn shape_with_features(
+        &self,
+        text: &str,
+        direction: Direction,
+        features: &[&str],
+    ) -> anyhow::Result<Vec<GlyphInfo>> {
         let mut no_glyphs = vec![];
-        let result = self
-            .shaper
-            .borrow()
-            .shape(text, self.font_size, self.dpi, &mut no_glyphs);
+        let result = self.shaper.borrow().shape(
+            text,
+            direction,
+            self.font_size,
+            self.dpi,
+            features,
+            &mut no_glyphs,
+        );
 
         if !no_glyphs.is_empty() {
             no_glyphs.sort();
             no_glyphs.dedup();
+            // Only search for codepoints that we haven't already tried
+            // to resolve; searching is expensive and won't produce a
+            // different result for this style until the config changes.
+            let mut searched = self.fallback_searched.borrow_mut();
+            no_glyphs.retain(|&c| searched.insert(c));
+        }
+
+        if !no_glyphs.is_empty() {
             if let Some(font_config) = self.font_config.upgrade() {
                 let mut extra_handles = vec![];
                 let fallback_str = no_glyphs.iter().collect::<String>();
@@ -120,10 +228,10 @@ impl LoadedFont {
                 if extra_handles.is_empty() {
                     log::error!("No fonts have glyphs for {}", fallback_str.escape_debug());
                 } else {
-                    let loaded = self.insert_fallback_handles(extra_handles)?;
+                    let loaded = self.insert_fallback_handles(extra_handles, &fallback_str)?;
                     if loaded {
                         log::trace!("handles is now: {:#?}", self.handles);
-                        return self.shape(text);
+                        return self.shape_with_features(text, direction, features);
                     } else {
                         log::error!(
                             "No fonts have glyphs for {}, even though fallback suggested some.",
@@ -137,10 +245,25 @@ impl LoadedFont {
         result
     }
 
-    pub fn metrics_for_idx(&self, font_idx: usize) -> anyhow::Result<FontMetrics> {
-        self.shaper
+    /// The height that glyphs from a bitmap font in the fallback
+    /// sequence are scaled to fit
+    fn bitmap_cell_height(&self, font_idx: usize) -> f64 {
+        let scale = self
+            .options
             .borrow()
-            .metrics_for_idx(font_idx, self.font_size, self.dpi)
+            .get(font_idx)
+            .map(|options| options.scale)
+            .unwrap_or(1.0);
+        self.metrics.cell_height.get() * scale
+    }
+
+    pub fn metrics_for_idx(&self, font_idx: usize) -> anyhow::Result<FontMetrics> {
+        self.shaper.borrow().metrics_for_idx(
+            font_idx,
+            self.font_size,
+            self.dpi,
+            Some(self.bitmap_cell_height(font_idx)),
+        )
     }
 
     pub fn rasterize_glyph(
@@ -148,15 +271,20 @@ impl LoadedFont {
         glyph_pos: u32,
         fallback: FallbackIdx,
     ) -> anyhow::Result<RasterizedGlyph> {
+        let all_options = self.options.borrow();
+        let options = &all_options[fallback];
+        let font_size = self.font_size * options.scale;
+        let cell_height = self.metrics.cell_height.get() * options.scale;
         let mut rasterizers = self.rasterizers.borrow_mut();
         if let Some(raster) = rasterizers.get(&fallback) {
-            raster.rasterize_glyph(glyph_pos, self.font_size, self.dpi)
+            raster.rasterize_glyph(glyph_pos, font_size, self.dpi, cell_height)
         } else {
             let raster = new_rasterizer(
                 FontRasterizerSelection::get_default(),
                 &(self.handles.borrow())[fallback],
+                options,
             )?;
-            let result = raster.rasterize_glyph(glyph_pos, self.font_size, self.dpi);
+            let result = raster.rasterize_glyph(glyph_pos, font_size, self.dpi, cell_height);
             rasterizers.insert(fallback, raster);
             result
         }
@@ -229,23 +357,44 @@ impl FontConfigInner {
         let mut loaded = HashSet::new();
 
         let mut handles = vec![];
+        let mut options = vec![];
         for attrs in &[&preferred_attributes, &fallback_attributes] {
-            self.font_dirs
-                .borrow()
-                .resolve_multiple(attrs, &mut handles, &mut loaded);
-            handles.append(&mut self.locator.load_fonts(attrs, &mut loaded)?);
-            self.built_in
-                .borrow()
-                .resolve_multiple(attrs, &mut handles, &mut loaded);
+            // Resolve the attributes one at a time so that we know which
+            // of them selected each handle, and can apply its options
+            for attr in attrs.iter() {
+                self.font_dirs.borrow().resolve_multiple(
+                    std::slice::from_ref(attr),
+                    &mut handles,
+                    &mut loaded,
+                );
+                options.resize(handles.len(), FontOptions::new(&config, Some(attr)));
+            }
+            for attr in attrs.iter() {
+                handles.append(
+                    &mut self
+                        .locator
+                        .load_fonts(std::slice::from_ref(attr), &mut loaded)?,
+                );
+                options.resize(handles.len(), FontOptions::new(&config, Some(attr)));
+            }
+            for attr in attrs.iter() {
+                self.built_in.borrow().resolve_multiple(
+                    std::slice::from_ref(attr),
+                    &mut handles,
+                    &mut loaded,
+                );
+                options.resize(handles.len(), FontOptions::new(&config, Some(attr)));
+            }
         }
 
         for attr in &attributes {
             if !attr.is_fallback && !loaded.contains(attr) {
-                let styled_extra = if attr.bold || attr.italic {
-                    ". A bold or italic variant of the font was requested; \
-                    TrueType and OpenType fonts don't have an automatic way to \
-                    produce these font variants, so a separate font file containing \
-                    the bold or italic variant must be installed"
+                let styled_extra = if attr.is_styled() {
+                    ". A bold, italic or otherwise styled variant of the font was \
+                    requested; unless the font is a variable font, TrueType and \
+                    OpenType fonts don't have an automatic way to produce these font \
+                    variants, so a separate font file containing the variant must \
+                    be installed"
                 } else {
                     ""
                 };
@@ -284,7 +433,7 @@ impl FontConfigInner {
             }
         }
 
-        let shaper = new_shaper(FontShaperSelection::get_default(), &handles)?;
+        let shaper = new_shaper(FontShaperSelection::get_default(), &handles, &options)?;
 
         let config = configuration();
         let font_size = config.font_size * *self.font_scale.borrow();
@@ -300,6 +449,8 @@ impl FontConfigInner {
         let loaded = Rc::new(LoadedFont {
             rasterizers: RefCell::new(HashMap::new()),
             handles: RefCell::new(handles),
+            options: RefCell::new(options),
+            fallback_searched: RefCell::new(HashSet::new()),
             shaper: RefCell::new(shaper),
             metrics,
             font_size,
@@ -349,6 +500,16 @@ impl FontConfigInner {
         Ok(metrics)
     }
 
+    fn font_source(&self, handle: &FontDataHandle) -> FontSource {
+        if self.font_dirs.borrow().contains(handle) {
+            FontSource::FontDirs
+        } else if self.built_in.borrow().contains(handle) {
+            FontSource::BuiltIn
+        } else {
+            FontSource::Locator
+        }
+    }
+
     /// Apply the defined font_rules from the user configuration to
     /// produce the text style that best matches the supplied input
     /// cell attributes.
@@ -420,6 +581,17 @@ impl FontConfiguration {
         self.inner.default_font_metrics(&self.inner)
     }
 
+    /// Returns where the font referenced by the handle was loaded from
+    pub fn font_source(&self, handle: &FontDataHandle) -> FontSource {
+        self.inner.font_source(handle)
+    }
+
+    /// Returns the list of all fonts that the system font locator
+    /// is able to find
+    pub fn list_system_fonts(&self) -> anyhow::Result<Vec<FontDataHandle>> {
+        self.inner.locator.enumerate_all_fonts()
+    }
+
     /// Apply the defined font_rules from the user configuration to
     /// produce the text style that best matches the supplied input
     /// cell attributes.
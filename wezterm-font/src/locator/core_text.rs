@@ -22,7 +22,11 @@ fn descriptor_from_attr(attr: &FontAttributes) -> anyhow::Result<CTFontDescripto
         .map_err(|_| anyhow::anyhow!("failed to parse family name {} as CFString", attr.family))?;
 
     let symbolic_traits: CTFontSymbolicTraits = kCTFontMonoSpaceTrait
-        | if attr.bold { kCTFontBoldTrait } else { 0 }
+        | if attr.effective_weight() >= config::FontWeight::BOLD {
+            kCTFontBoldTrait
+        } else {
+            0
+        }
         | if attr.italic { kCTFontItalicTrait } else { 0 };
 
     let family_attr: CFString = unsafe { TCFType::wrap_under_get_rule(kCTFontFamilyNameAttribute) };
@@ -81,7 +85,7 @@ impl FontLocator for CoreTextFontLocator {
                     if let Ok(parsed) = crate::parser::ParsedFont::from_locator(&handle) {
                         // The system may have returned a fallback font rather than the
                         // font that we requested, so verify that the name matches.
-                        if crate::parser::font_info_matches(attr, parsed.names()) {
+                        if crate::parser::font_info_matches(attr, &parsed) {
                             fonts.push(handle);
                             loaded.insert(attr.clone());
                         }
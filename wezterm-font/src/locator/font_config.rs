@@ -1,7 +1,7 @@
 use crate::fcwrap;
 use crate::locator::{FontDataHandle, FontLocator};
 use anyhow::Context;
-use config::FontAttributes;
+use config::{FontAttributes, FontWeight};
 use fcwrap::{CharSet, Pattern as FontPattern};
 use std::collections::HashSet;
 use std::convert::TryInto;
@@ -10,6 +10,32 @@ use std::convert::TryInto;
 /// fixed width styles so are desirable for a terminal use case.
 const SPACING: [i32; 2] = [fcwrap::FC_MONO, fcwrap::FC_DUAL];
 
+/// Maps an OpenType weight to the fontconfig weight scale by
+/// interpolating between the named weights that both scales define.
+fn fc_weight(weight: FontWeight) -> i32 {
+    const MAP: [(u16, i32); 9] = [
+        (100, fcwrap::FC_WEIGHT_THIN),
+        (200, fcwrap::FC_WEIGHT_EXTRALIGHT),
+        (300, fcwrap::FC_WEIGHT_LIGHT),
+        (400, fcwrap::FC_WEIGHT_REGULAR),
+        (500, fcwrap::FC_WEIGHT_MEDIUM),
+        (600, fcwrap::FC_WEIGHT_DEMIBOLD),
+        (700, fcwrap::FC_WEIGHT_BOLD),
+        (800, fcwrap::FC_WEIGHT_EXTRABOLD),
+        (900, fcwrap::FC_WEIGHT_BLACK),
+    ];
+    let weight = weight.0.clamp(100, 900);
+    for pair in MAP.windows(2) {
+        let (lo, lo_fc) = pair[0];
+        let (hi, hi_fc) = pair[1];
+        if weight <= hi {
+            let frac = f64::from(weight - lo) / f64::from(hi - lo);
+            return lo_fc + (f64::from(hi_fc - lo_fc) * frac).round() as i32;
+        }
+    }
+    fcwrap::FC_WEIGHT_BLACK
+}
+
 /// A FontLocator implemented using the system font loading
 /// functions provided by font-config
 pub struct FontConfigFontLocator {}
@@ -27,7 +53,8 @@ impl FontLocator for FontConfigFontLocator {
                 let mut pattern = FontPattern::new()?;
                 let start = std::time::Instant::now();
                 pattern.family(&attr.family)?;
-                pattern.add_integer("weight", if attr.bold { 200 } else { 80 })?;
+                pattern.add_integer("weight", fc_weight(attr.effective_weight()))?;
+                pattern.add_integer("width", attr.effective_stretch().to_percent() as i32)?;
                 pattern.add_integer("slant", if attr.italic { 100 } else { 0 })?;
                 pattern.add_integer("spacing", spacing)?;
 
@@ -52,7 +79,7 @@ impl FontLocator for FontConfigFontLocator {
                 // so we need to parse the returned font
                 // here to see if we got what we asked for.
                 if let Ok(parsed) = crate::parser::ParsedFont::from_locator(&handle) {
-                    if crate::parser::font_info_matches(attr, parsed.names()) {
+                    if crate::parser::font_info_matches(attr, &parsed) {
                         fonts.push(handle);
                         loaded.insert(attr.clone());
                         log::trace!("found font-config match for {:?}", parsed.names());
@@ -68,33 +95,62 @@ impl FontLocator for FontConfigFontLocator {
         &self,
         codepoints: &[char],
     ) -> anyhow::Result<Vec<FontDataHandle>> {
-        let mut charset = CharSet::new()?;
+        let mut fonts = vec![];
+
+        // Query for each codepoint individually; a single font is
+        // unlikely to cover eg: both CJK and symbols, and asking for
+        // the full set at once would find nothing at all.
         for &c in codepoints {
-            charset.add(c)?;
+            if let Some(handle) = self.locate_fallback_for_codepoint(c)? {
+                if !fonts.contains(&handle) {
+                    fonts.push(handle);
+                }
+            }
         }
 
+        Ok(fonts)
+    }
+
+    fn enumerate_all_fonts(&self) -> anyhow::Result<Vec<FontDataHandle>> {
+        let pattern = FontPattern::new()?;
+        let mut fonts = vec![];
+
+        for pat in pattern.list()?.iter() {
+            let file = pat.get_file().context("pat.get_file")?;
+            let handle = FontDataHandle::OnDisk {
+                path: file.into(),
+                index: pat.get_integer("index")?.try_into()?,
+            };
+            fonts.push(handle);
+        }
+
+        fonts.sort();
+        fonts.dedup();
+        Ok(fonts)
+    }
+}
+
+impl FontConfigFontLocator {
+    /// Find a font that has a glyph for the codepoint, preferring
+    /// fixed width fonts over proportional fonts.
+    fn locate_fallback_for_codepoint(&self, c: char) -> anyhow::Result<Option<FontDataHandle>> {
+        let mut charset = CharSet::new()?;
+        charset.add(c)?;
+
         let mut pattern = FontPattern::new()?;
         pattern.add_charset(&charset)?;
-        pattern.add_integer("weight", 80)?;
+        pattern.add_integer("weight", fcwrap::FC_WEIGHT_REGULAR)?;
         pattern.add_integer("slant", 0)?;
 
-        let mut lists = vec![pattern
-            .list()
-            .context("pattern.list with no spacing constraint")?];
-
-        for &spacing in &SPACING {
+        for spacing in SPACING.iter().map(Some).chain(std::iter::once(None)) {
             pattern.delete_property("spacing")?;
-            pattern.add_integer("spacing", spacing)?;
-            lists.push(
-                pattern
-                    .list()
-                    .with_context(|| format!("pattern.list with spacing={}", spacing))?,
-            );
-        }
-
-        let mut fonts = vec![];
+            if let Some(&spacing) = spacing {
+                pattern.add_integer("spacing", spacing)?;
+            }
+            let list = pattern
+                .list()
+                .with_context(|| format!("pattern.list with spacing={:?}", spacing))?;
 
-        for list in lists {
             for pat in list.iter() {
                 let num = pat.charset_intersect_count(&charset)?;
                 if num == 0 {
@@ -112,10 +168,33 @@ impl FontLocator for FontConfigFontLocator {
                     index: pat.get_integer("index")?.try_into()?,
                 };
 
-                fonts.push(handle);
+                log::trace!(
+                    "font-config suggests {:?} for {}",
+                    handle,
+                    c.escape_unicode()
+                );
+                return Ok(Some(handle));
             }
         }
 
-        Ok(fonts)
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weight_mapping() {
+        assert_eq!(fc_weight(FontWeight::REGULAR), fcwrap::FC_WEIGHT_REGULAR);
+        assert_eq!(fc_weight(FontWeight::BOLD), fcwrap::FC_WEIGHT_BOLD);
+        assert_eq!(fc_weight(FontWeight(900)), fcwrap::FC_WEIGHT_BLACK);
+        // Out of range weights are clamped
+        assert_eq!(fc_weight(FontWeight(50)), fcwrap::FC_WEIGHT_THIN);
+        assert_eq!(fc_weight(FontWeight(1000)), fcwrap::FC_WEIGHT_BLACK);
+        // Weights between the named weights are interpolated
+        assert_eq!(fc_weight(FontWeight(450)), 90);
+        assert_eq!(fc_weight(FontWeight(650)), 190);
     }
 }
@@ -85,7 +85,10 @@ fn load_font(font_attr: &FontAttributes) -> anyhow::Result<FontDataHandle> {
         lfWidth: 0,
         lfEscapement: 0,
         lfOrientation: 0,
-        lfWeight: if font_attr.bold { 700 } else { 0 },
+        lfWeight: match font_attr.effective_weight() {
+            config::FontWeight::REGULAR => 0,
+            weight => weight.0 as _,
+        },
         lfItalic: if font_attr.italic { 1 } else { 0 },
         lfUnderline: 0,
         lfStrikeOut: 0,
@@ -126,7 +129,7 @@ impl FontLocator for GdiFontLocator {
         for font_attr in fonts_selection {
             if let Ok(handle) = load_font(font_attr) {
                 if let Ok(parsed) = crate::parser::ParsedFont::from_locator(&handle) {
-                    if crate::parser::font_info_matches(font_attr, parsed.names()) {
+                    if crate::parser::font_info_matches(font_attr, &parsed) {
                         fonts.push(handle);
                         loaded.insert(font_attr.clone());
                     }
@@ -211,6 +214,7 @@ impl FontLocator for GdiFontLocator {
                         italic: false,
                         family: font.family_name(),
                         is_fallback: true,
+                        ..Default::default()
                     };
 
                     if !resolved.contains(&attr) {
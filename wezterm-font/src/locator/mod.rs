@@ -58,6 +58,13 @@ pub trait FontLocator {
         &self,
         codepoints: &[char],
     ) -> anyhow::Result<Vec<FontDataHandle>>;
+
+    /// Returns the list of all fonts known to the system, for
+    /// diagnostic purposes.  Locators that cannot enumerate the
+    /// system fonts return an empty list.
+    fn enumerate_all_fonts(&self) -> anyhow::Result<Vec<FontDataHandle>> {
+        Ok(vec![])
+    }
 }
 
 pub fn new_locator(locator: FontLocatorSelection) -> Box<dyn FontLocator> {
@@ -1,7 +1,7 @@
 use crate::locator::FontDataHandle;
 use crate::shaper::GlyphInfo;
 use anyhow::anyhow;
-use config::FontAttributes;
+use config::{FontAttributes, FontStretch, FontWeight};
 use std::path::{Path, PathBuf};
 use ttf_parser::{fonts_in_collection, Face, Name, PlatformId};
 
@@ -14,6 +14,10 @@ pub enum MaybeShaped {
 /// Represents a parsed font
 pub struct ParsedFont {
     names: Names,
+    weight: u16,
+    stretch: u16,
+    italic: bool,
+    is_variable: bool,
 }
 
 #[derive(Debug)]
@@ -230,46 +234,104 @@ impl ParsedFont {
             FontDataHandle::OnDisk { path, index } => {
                 let data = std::fs::read(path)?;
                 let face = Face::from_slice(&data, *index)?;
-                Ok(Self {
-                    names: Names::from_face(&face)?,
-                })
+                Self::from_face(&face)
             }
 
             FontDataHandle::Memory { data, index, .. } => {
                 let face = Face::from_slice(data, *index)?;
-                Ok(Self {
-                    names: Names::from_face(&face)?,
-                })
+                Self::from_face(&face)
             }
         }
     }
 
+    fn from_face(face: &Face) -> anyhow::Result<Self> {
+        Ok(Self {
+            names: Names::from_face(face)?,
+            weight: face.weight().to_number(),
+            stretch: face.width().to_number(),
+            italic: face.is_italic(),
+            is_variable: face.is_variable(),
+        })
+    }
+
     pub fn names(&self) -> &Names {
         &self.names
     }
+
+    /// The weight of the font as recorded in its OS/2 table;
+    /// 400 is regular and 700 is bold
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+
+    pub fn italic(&self) -> bool {
+        self.italic
+    }
+
+    /// The width of the font as recorded in its OS/2 table
+    pub fn stretch(&self) -> FontStretch {
+        FontStretch::from_opentype_width(self.stretch)
+    }
+
+    /// Returns true if the font has variation axes, which
+    /// allows it to be rendered at any weight or width
+    pub fn is_variable(&self) -> bool {
+        self.is_variable
+    }
+
+    /// Computes how far this font is from the style requested by
+    /// `attr`; smaller is better.  Following the CSS font matching
+    /// algorithm, italic is considered first, then stretch and
+    /// then weight.
+    pub fn style_distance(&self, attr: &FontAttributes) -> u32 {
+        let italic = if self.italic == attr.italic {
+            0
+        } else {
+            100_000
+        };
+        if self.is_variable {
+            // The axes can be set to match the weight and stretch
+            return italic;
+        }
+        let stretch = (i32::from(self.stretch)
+            - i32::from(attr.effective_stretch().to_opentype_width()))
+        .abs() as u32;
+        let weight = (i32::from(self.weight) - i32::from(attr.effective_weight().0)).abs() as u32;
+        italic + stretch * 1000 + weight
+    }
 }
 
-pub fn font_info_matches(attr: &FontAttributes, names: &Names) -> bool {
+/// The largest difference in weight that we consider to be a match;
+/// this allows eg: a Medium font to be used when Regular was requested,
+/// but not a Bold font.
+const WEIGHT_TOLERANCE: u16 = FontWeight::MEDIUM.0 - FontWeight::REGULAR.0;
+
+pub fn font_info_matches(attr: &FontAttributes, parsed: &ParsedFont) -> bool {
+    let names = parsed.names();
     if let Some(fam) = names.family.as_ref() {
-        // TODO: correctly match using family and sub-family;
-        // this is a pretty rough approximation
         if attr.family == *fam {
-            match names.sub_family.as_ref().map(String::as_str) {
-                Some("Italic") if attr.italic && !attr.bold => return true,
-                Some("Bold") if attr.bold && !attr.italic => return true,
-                Some("Bold Italic") if attr.bold && attr.italic => return true,
-                Some("Medium") | Some("Regular") | None if !attr.italic && !attr.bold => {
-                    return true
-                }
-                _ => {}
+            // The variation axes can't turn an upright face into an
+            // italic one, so check this first in order to prefer a
+            // separate italic face
+            if attr.italic != parsed.italic {
+                return false;
+            }
+            if parsed.is_variable {
+                // We can select the weight and width via the variation axes
+                return true;
+            }
+            let weight = attr.effective_weight().0;
+            let weight_matches =
+                (parsed.weight.max(weight) - parsed.weight.min(weight)) <= WEIGHT_TOLERANCE;
+            let stretch_matches = parsed.stretch() == attr.effective_stretch();
+            if weight_matches && stretch_matches {
+                return true;
             }
         }
     }
-    if attr.family == names.full_name && !attr.bold && !attr.italic {
-        true
-    } else {
-        false
-    }
+    // Allow selecting a specific face by its full name, such as
+    // "Operator Mono SSm Lig Medium"
+    attr.family == names.full_name && !attr.is_styled()
 }
 
 /// Given a blob representing a True Type Collection (.ttc) file,
@@ -284,16 +346,16 @@ pub fn resolve_font_from_ttc_data(
     if let Some(size) = fonts_in_collection(data) {
         for index in 0..size {
             let face = Face::from_slice(data, index)?;
-            let names = Names::from_face(&face)?;
-            if font_info_matches(attr, &names) {
+            let parsed = ParsedFont::from_face(&face)?;
+            if font_info_matches(attr, &parsed) {
                 return Ok(Some(index as usize));
             }
         }
         Ok(None)
     } else {
         let face = Face::from_slice(data, 0)?;
-        let names = Names::from_face(&face)?;
-        if font_info_matches(attr, &names) {
+        let parsed = ParsedFont::from_face(&face)?;
+        if font_info_matches(attr, &parsed) {
             Ok(Some(0))
         } else {
             Ok(None)
@@ -306,7 +368,7 @@ pub fn resolve_font_from_ttc_data(
 /// sane fallback fonts.
 /// This function loads those.
 pub(crate) fn load_built_in_fonts(
-    font_info: &mut Vec<(Names, PathBuf, FontDataHandle)>,
+    font_info: &mut Vec<(ParsedFont, PathBuf, FontDataHandle)>,
 ) -> anyhow::Result<()> {
     macro_rules! font {
         ($font:literal) => {
@@ -332,9 +394,9 @@ pub(crate) fn load_built_in_fonts(
         font!("../../assets/fonts/LastResortHE-Regular.ttf"),
     ] {
         let face = Face::from_slice(data, 0)?;
-        let names = Names::from_face(&face)?;
+        let parsed = ParsedFont::from_face(&face)?;
         font_info.push((
-            names,
+            parsed,
             PathBuf::from(name),
             FontDataHandle::Memory {
                 data: data.to_vec(),
@@ -349,7 +411,7 @@ pub(crate) fn load_built_in_fonts(
 
 pub(crate) fn parse_and_collect_font_info(
     path: &Path,
-    font_info: &mut Vec<(Names, PathBuf, FontDataHandle)>,
+    font_info: &mut Vec<(ParsedFont, PathBuf, FontDataHandle)>,
 ) -> anyhow::Result<()> {
     let data = std::fs::read(path)?;
     let size = fonts_in_collection(&data).unwrap_or(0);
@@ -358,12 +420,12 @@ pub(crate) fn parse_and_collect_font_info(
         data: &[u8],
         path: &Path,
         index: u32,
-        font_info: &mut Vec<(Names, PathBuf, FontDataHandle)>,
+        font_info: &mut Vec<(ParsedFont, PathBuf, FontDataHandle)>,
     ) -> anyhow::Result<()> {
         let face = Face::from_slice(data, index)?;
-        let names = Names::from_face(&face)?;
+        let parsed = ParsedFont::from_face(&face)?;
         font_info.push((
-            names,
+            parsed,
             path.to_path_buf(),
             FontDataHandle::OnDisk {
                 path: path.to_path_buf(),
@@ -386,3 +448,81 @@ pub(crate) fn parse_and_collect_font_info(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn font(family: &str, weight: u16, stretch: u16, italic: bool, is_variable: bool) -> ParsedFont {
+        ParsedFont {
+            names: Names {
+                full_name: format!("{} Face", family),
+                family: Some(family.to_string()),
+                sub_family: None,
+                postscript_name: None,
+            },
+            weight,
+            stretch,
+            italic,
+            is_variable,
+        }
+    }
+
+    fn attr(family: &str, weight: u16, italic: bool) -> FontAttributes {
+        FontAttributes {
+            weight: Some(FontWeight(weight)),
+            italic,
+            ..FontAttributes::new(family)
+        }
+    }
+
+    #[test]
+    fn matches_weight_within_tolerance() {
+        let medium = font("Mono", 500, 5, false, false);
+        assert!(font_info_matches(&attr("Mono", 400, false), &medium));
+        assert!(!font_info_matches(&attr("Mono", 700, false), &medium));
+        assert!(!font_info_matches(&attr("Other", 500, false), &medium));
+    }
+
+    #[test]
+    fn matches_italic() {
+        let upright = font("Mono", 400, 5, false, false);
+        let italic = font("Mono", 400, 5, true, false);
+        assert!(!font_info_matches(&attr("Mono", 400, true), &upright));
+        assert!(font_info_matches(&attr("Mono", 400, true), &italic));
+        assert!(!font_info_matches(&attr("Mono", 400, false), &italic));
+    }
+
+    #[test]
+    fn matches_variable() {
+        let variable = font("Mono", 400, 5, false, true);
+        assert!(font_info_matches(&attr("Mono", 700, false), &variable));
+        assert!(font_info_matches(&attr("Mono", 100, false), &variable));
+        // An upright variable font must not satisfy an italic request
+        assert!(!font_info_matches(&attr("Mono", 400, true), &variable));
+
+        let variable_italic = font("Mono", 400, 5, true, true);
+        assert!(font_info_matches(&attr("Mono", 700, true), &variable_italic));
+    }
+
+    #[test]
+    fn matches_full_name() {
+        let face = font("Mono", 500, 5, false, false);
+        assert!(font_info_matches(&FontAttributes::new("Mono Face"), &face));
+        assert!(!font_info_matches(&attr("Mono Face", 700, false), &face));
+    }
+
+    #[test]
+    fn style_distance_ordering() {
+        let want = attr("Mono", 400, true);
+        let upright = font("Mono", 400, 5, false, false);
+        let italic_bold = font("Mono", 700, 5, true, false);
+        let italic_condensed = font("Mono", 400, 3, true, false);
+        let italic_variable = font("Mono", 100, 5, true, true);
+
+        assert_eq!(italic_variable.style_distance(&want), 0);
+        // Italic is considered before stretch, and stretch before weight
+        assert!(italic_bold.style_distance(&want) < italic_condensed.style_distance(&want));
+        assert!(italic_condensed.style_distance(&want) < upright.style_distance(&want));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::locator::FontDataHandle;
 use crate::rasterizer::FontRasterizer;
 use crate::units::*;
-use crate::{ftwrap, RasterizedGlyph};
+use crate::{ftwrap, FontOptions, RasterizedGlyph};
 use ::freetype::FT_GlyphSlotRec_;
 use anyhow::bail;
 use std::cell::RefCell;
@@ -10,6 +10,8 @@ use std::slice;
 
 pub struct FreeTypeRasterizer {
     has_color: bool,
+    load_flags: i32,
+    render_mode: ftwrap::FT_Render_Mode,
     face: RefCell<ftwrap::Face>,
     _lib: ftwrap::Library,
 }
@@ -20,14 +22,20 @@ impl FontRasterizer for FreeTypeRasterizer {
         glyph_pos: u32,
         size: f64,
         dpi: u32,
+        cell_height: f64,
     ) -> anyhow::Result<RasterizedGlyph> {
         self.face.borrow_mut().set_font_size(size, dpi)?;
 
-        let (load_flags, render_mode) = ftwrap::compute_load_flags_from_config();
-
         let mut face = self.face.borrow_mut();
+        if self.has_color {
+            if let Some(glyph) = self.rasterize_colr(&mut face, glyph_pos)? {
+                return Ok(glyph);
+            }
+        }
+
         let descender = unsafe { (*(*face.face).size).metrics.descender as f64 / 64.0 };
-        let ft_glyph = face.load_and_render_glyph(glyph_pos, load_flags, render_mode)?;
+        let bitmap_scale = face.bitmap_scale(Some(cell_height));
+        let ft_glyph = face.load_and_render_glyph(glyph_pos, self.load_flags, self.render_mode)?;
 
         let mode: ftwrap::FT_Pixel_Mode =
             unsafe { mem::transmute(u32::from(ft_glyph.bitmap.pixel_mode)) };
@@ -44,7 +52,7 @@ impl FontRasterizer for FreeTypeRasterizer {
         let glyph = match mode {
             ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_LCD => self.rasterize_lcd(pitch, ft_glyph, data),
             ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_BGRA => {
-                self.rasterize_bgra(pitch, descender, ft_glyph, data)
+                self.rasterize_bgra(pitch, descender, bitmap_scale, ft_glyph, data)
             }
             ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_GRAY => self.rasterize_gray(pitch, ft_glyph, data),
             ftwrap::FT_Pixel_Mode::FT_PIXEL_MODE_MONO => self.rasterize_mono(pitch, ft_glyph, data),
@@ -95,6 +103,7 @@ impl FreeTypeRasterizer {
             bearing_x: PixelLength::new(ft_glyph.bitmap_left as f64),
             bearing_y: PixelLength::new(ft_glyph.bitmap_top as f64),
             has_color: false,
+            foreground: None,
         }
     }
 
@@ -127,6 +136,7 @@ impl FreeTypeRasterizer {
             bearing_x: PixelLength::new(ft_glyph.bitmap_left as f64),
             bearing_y: PixelLength::new(ft_glyph.bitmap_top as f64),
             has_color: false,
+            foreground: None,
         }
     }
 
@@ -165,13 +175,148 @@ impl FreeTypeRasterizer {
             bearing_x: PixelLength::new(ft_glyph.bitmap_left as f64),
             bearing_y: PixelLength::new(ft_glyph.bitmap_top as f64),
             has_color: self.has_color,
+            foreground: None,
+        }
+    }
+
+    /// Renders a COLRv0 layered color glyph by rasterizing each layer
+    /// as a greyscale mask and compositing it using its color from the
+    /// CPAL palette.  Layers that use the text color are returned as
+    /// coverage for the renderer to tint with the foreground color.
+    /// Returns None if the glyph has no color layers.
+    fn rasterize_colr(
+        &self,
+        face: &mut ftwrap::Face,
+        glyph_pos: u32,
+    ) -> anyhow::Result<Option<RasterizedGlyph>> {
+        let layers = face.color_glyph_layers(glyph_pos);
+        if layers.is_empty() {
+            return Ok(None);
+        }
+        let palette = face.palette()?;
+
+        // Render the layers as plain greyscale outlines
+        let load_flags =
+            self.load_flags & !((ftwrap::FT_LOAD_COLOR | ftwrap::FT_LOAD_MONOCHROME) as i32);
+
+        struct Layer {
+            left: isize,
+            top: isize,
+            width: usize,
+            height: usize,
+            coverage: Vec<u8>,
+            /// None if the layer uses the text color
+            color: Option<(u8, u8, u8, u8)>,
+        }
+
+        let mut rendered = vec![];
+        for (layer_glyph, color_index) in layers {
+            let ft_glyph = face.load_and_render_glyph(
+                layer_glyph,
+                load_flags,
+                ftwrap::FT_Render_Mode::FT_RENDER_MODE_NORMAL,
+            )?;
+            let width = ft_glyph.bitmap.width as usize;
+            let height = ft_glyph.bitmap.rows as usize;
+            let pitch = ft_glyph.bitmap.pitch.abs() as usize;
+            let data = unsafe { slice::from_raw_parts(ft_glyph.bitmap.buffer, height * pitch) };
+            let mut coverage = Vec::with_capacity(width * height);
+            for y in 0..height {
+                coverage.extend_from_slice(&data[y * pitch..y * pitch + width]);
+            }
+            // Index 0xffff means that the layer uses the text color
+            let color = palette
+                .get(color_index as usize)
+                .map(|c| (c.red, c.green, c.blue, c.alpha));
+            rendered.push(Layer {
+                left: ft_glyph.bitmap_left as isize,
+                top: ft_glyph.bitmap_top as isize,
+                width,
+                height,
+                coverage,
+                color,
+            });
+        }
+
+        let left = rendered.iter().map(|l| l.left).min().unwrap_or(0);
+        let top = rendered.iter().map(|l| l.top).max().unwrap_or(0);
+        let right = rendered
+            .iter()
+            .map(|l| l.left + l.width as isize)
+            .max()
+            .unwrap_or(0);
+        let bottom = rendered
+            .iter()
+            .map(|l| l.top - l.height as isize)
+            .min()
+            .unwrap_or(0);
+        let width = (right - left).max(0) as usize;
+        let height = (top - bottom).max(0) as usize;
+
+        // Composite the layers, bottom to top, into premultiplied RGBA.
+        // The layers that use the text color contribute black to `rgba`
+        // and their visible coverage is tracked in `foreground`, so that
+        // the final color is `rgba + text_color * foreground`.
+        let mut rgba = vec![0u8; width * height * 4];
+        let mut foreground = vec![0u8; width * height];
+        for layer in &rendered {
+            let (red, green, blue, alpha) = layer.color.unwrap_or((0, 0, 0, 0xff));
+            let fg = if layer.color.is_some() { 0 } else { 0xff };
+            let x_off = (layer.left - left) as usize;
+            let y_off = (top - layer.top) as usize;
+            for y in 0..layer.height {
+                for x in 0..layer.width {
+                    let cov = u32::from(layer.coverage[y * layer.width + x]);
+                    let a = cov * u32::from(alpha) / 255;
+                    if a == 0 {
+                        continue;
+                    }
+                    let idx = ((y + y_off) * width + x + x_off) * 4;
+                    let blend = |src: u8, dest: u8| -> u8 {
+                        (u32::from(src) * a / 255 + u32::from(dest) * (255 - a) / 255) as u8
+                    };
+                    rgba[idx] = blend(red, rgba[idx]);
+                    rgba[idx + 1] = blend(green, rgba[idx + 1]);
+                    rgba[idx + 2] = blend(blue, rgba[idx + 2]);
+                    rgba[idx + 3] = blend(0xff, rgba[idx + 3]);
+                    let fg_idx = (y + y_off) * width + x + x_off;
+                    foreground[fg_idx] = blend(fg, foreground[fg_idx]);
+                }
+            }
         }
+
+        let gray_to_rgba = |gray: &[u8]| -> Vec<u8> {
+            gray.iter()
+                .flat_map(|&g| std::iter::repeat(g).take(4))
+                .collect()
+        };
+
+        let (data, has_color, foreground) = if rendered.iter().all(|l| l.color.is_none()) {
+            // Every layer uses the text color, so this is really a
+            // monochrome glyph
+            (gray_to_rgba(&foreground), false, None)
+        } else if rendered.iter().all(|l| l.color.is_some()) {
+            (rgba, true, None)
+        } else {
+            (rgba, true, Some(gray_to_rgba(&foreground)))
+        };
+
+        Ok(Some(RasterizedGlyph {
+            data,
+            height,
+            width,
+            bearing_x: PixelLength::new(left as f64),
+            bearing_y: PixelLength::new(top as f64),
+            has_color,
+            foreground,
+        }))
     }
 
     fn rasterize_bgra(
         &self,
         pitch: usize,
         descender: f64,
+        bitmap_scale: f64,
         ft_glyph: &FT_GlyphSlotRec_,
         data: &[u8],
     ) -> RasterizedGlyph {
@@ -250,13 +395,29 @@ impl FreeTypeRasterizer {
                 rgba[dest_offset + (dest_x * 4) + 3] = alpha;
             }
         }
+        // Cropping moved the top left corner of the bitmap
+        let bearing_x = f64::from(ft_glyph.bitmap_left) + first_col as f64;
+        let bearing_y = f64::from(ft_glyph.bitmap_top) - first_line as f64;
+
+        // Bitmap strikes are only available in a few sizes, so scale
+        // the glyph from the selected strike to fit the cell
+        let (rgba, dest_width, dest_height) = if bitmap_scale != 1.0 {
+            let scaled_width = ((dest_width as f64 * bitmap_scale).round() as usize).max(1);
+            let scaled_height = ((dest_height as f64 * bitmap_scale).round() as usize).max(1);
+            (
+                resize_rgba(&rgba, dest_width, dest_height, scaled_width, scaled_height),
+                scaled_width,
+                scaled_height,
+            )
+        } else {
+            (rgba, dest_width, dest_height)
+        };
+
         RasterizedGlyph {
             data: rgba,
             height: dest_height,
             width: dest_width,
-            bearing_x: PixelLength::new(
-                f64::from(ft_glyph.bitmap_left) * (dest_width as f64 / width as f64),
-            ),
+            bearing_x: PixelLength::new(bearing_x * bitmap_scale),
 
             // Fudge alert: this is font specific: I've found
             // that the emoji font on macOS doesn't account for the
@@ -267,24 +428,108 @@ impl FreeTypeRasterizer {
                     descender
                 } else {
                     0.
-                } + (f64::from(ft_glyph.bitmap_top) * (dest_height as f64 / height as f64)),
+                } + bearing_y * bitmap_scale,
             ),
 
             has_color: self.has_color,
+            foreground: None,
         }
     }
 
-    pub fn from_locator(handle: &FontDataHandle) -> anyhow::Result<Self> {
+    pub fn from_locator(handle: &FontDataHandle, options: &FontOptions) -> anyhow::Result<Self> {
         log::trace!("Rasterizier wants {:?}", handle);
         let lib = ftwrap::Library::new()?;
-        let face = lib.face_from_locator(handle)?;
+        let mut face = lib.face_from_locator(handle)?;
+        lib.select_variation(&mut face, options.weight, options.stretch, options.italic)?;
         let has_color = unsafe {
             (((*face.face).face_flags as u32) & (ftwrap::FT_FACE_FLAG_COLOR as u32)) != 0
         };
+        let (load_flags, render_mode) =
+            ftwrap::compute_load_flags(options.hinting, options.antialias);
         Ok(Self {
             _lib: lib,
             face: RefCell::new(face),
             has_color,
+            load_flags,
+            render_mode,
         })
     }
 }
+
+/// Resizes premultiplied RGBA data by averaging the source pixels
+/// that are covered by each destination pixel.  This is used to
+/// scale glyphs from bitmap strikes to the requested size.
+fn resize_rgba(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    new_width: usize,
+    new_height: usize,
+) -> Vec<u8> {
+    let mut result = vec![0u8; new_width * new_height * 4];
+    let x_ratio = width as f64 / new_width as f64;
+    let y_ratio = height as f64 / new_height as f64;
+
+    for y in 0..new_height {
+        let y_start = (y as f64 * y_ratio) as usize;
+        let y_end = (((y + 1) as f64 * y_ratio).ceil() as usize)
+            .max(y_start + 1)
+            .min(height);
+        for x in 0..new_width {
+            let x_start = (x as f64 * x_ratio) as usize;
+            let x_end = (((x + 1) as f64 * x_ratio).ceil() as usize)
+                .max(x_start + 1)
+                .min(width);
+
+            let mut sum = [0u32; 4];
+            for src_y in y_start..y_end {
+                for src_x in x_start..x_end {
+                    let idx = (src_y * width + src_x) * 4;
+                    for (total, &value) in sum.iter_mut().zip(&data[idx..idx + 4]) {
+                        *total += u32::from(value);
+                    }
+                }
+            }
+            let count = ((y_end - y_start) * (x_end - x_start)) as u32;
+            let idx = (y * new_width + x) * 4;
+            for (dest, total) in result[idx..idx + 4].iter_mut().zip(sum.iter()) {
+                *dest = (total / count) as u8;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resize_rgba_averages() {
+        // A 2x2 image with one opaque white pixel
+        let mut data = vec![0u8; 2 * 2 * 4];
+        data[0..4].copy_from_slice(&[255, 255, 255, 255]);
+
+        assert_eq!(resize_rgba(&data, 2, 2, 1, 1), vec![63, 63, 63, 63]);
+        assert_eq!(resize_rgba(&data, 2, 2, 2, 2), data);
+
+        // Scaling up repeats the source pixel
+        let scaled = resize_rgba(&data, 2, 2, 4, 4);
+        assert_eq!(scaled.len(), 4 * 4 * 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                let idx = (y * 4 + x) * 4;
+                let expected = if x < 2 && y < 2 { 255 } else { 0 };
+                assert_eq!(scaled[idx + 3], expected, "alpha at {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn resize_rgba_non_integral() {
+        let data = vec![200u8; 3 * 3 * 4];
+        let scaled = resize_rgba(&data, 3, 3, 2, 2);
+        assert_eq!(scaled, vec![200u8; 2 * 2 * 4]);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::locator::FontDataHandle;
 use crate::units::*;
+use crate::FontOptions;
 use config::FontRasterizerSelection;
 
 pub mod freetype;
@@ -13,26 +14,34 @@ pub struct RasterizedGlyph {
     pub bearing_x: PixelLength,
     pub bearing_y: PixelLength,
     pub has_color: bool,
+    /// For a color glyph with layers that are drawn using the text
+    /// color, the coverage of those layers, in the same format as the
+    /// data for a monochrome glyph.  The renderer tints this with the
+    /// foreground color and adds it to the color data.
+    pub foreground: Option<Vec<u8>>,
 }
 
 /// Rasterizes the specified glyph index in the associated font
-/// and returns the generated bitmap
+/// and returns the generated bitmap.
+/// Glyphs from bitmap fonts are scaled to fit `cell_height`.
 pub trait FontRasterizer {
     fn rasterize_glyph(
         &self,
         glyph_pos: u32,
         size: f64,
         dpi: u32,
+        cell_height: f64,
     ) -> anyhow::Result<RasterizedGlyph>;
 }
 
 pub fn new_rasterizer(
     rasterizer: FontRasterizerSelection,
     handle: &FontDataHandle,
+    options: &FontOptions,
 ) -> anyhow::Result<Box<dyn FontRasterizer>> {
     match rasterizer {
         FontRasterizerSelection::FreeType => Ok(Box::new(
-            freetype::FreeTypeRasterizer::from_locator(handle)?,
+            freetype::FreeTypeRasterizer::from_locator(handle, options)?,
         )),
     }
 }
@@ -1,7 +1,8 @@
 use crate::locator::FontDataHandle;
 use crate::parser::*;
-use crate::shaper::{FallbackIdx, FontMetrics, FontShaper, GlyphInfo};
+use crate::shaper::{Direction, FallbackIdx, FontMetrics, FontShaper, GlyphInfo};
 use crate::units::*;
+use crate::FontOptions;
 use allsorts::binary::read::{ReadScope, ReadScopeOwned};
 use allsorts::font_data_impl::read_cmap_subtable;
 use allsorts::gpos::{gpos_apply, Info, Placement};
@@ -14,7 +15,7 @@ use allsorts::tables::{
 };
 use allsorts::tag;
 use anyhow::{anyhow, bail, Context};
-use termwiz::cell::unicode_column_width;
+use termwiz::cell::UnicodeVersion;
 use tinyvec::*;
 use unicode_general_category::{get_general_category, GeneralCategory};
 
@@ -251,6 +252,7 @@ impl ParsedFont {
         lang: u32,
         point_size: f64,
         dpi: u32,
+        unicode_version: &UnicodeVersion,
     ) -> anyhow::Result<Vec<MaybeShaped>> {
         #[derive(Debug)]
         enum Run {
@@ -413,7 +415,7 @@ impl ParsedFont {
 
                         let text = reverse_engineer_glyph_text(&glyph_info.glyph);
                         let text_len = text.len();
-                        let num_cells = unicode_column_width(&text);
+                        let num_cells = unicode_version.column_width(&text);
 
                         let pixel_scale =
                             (dpi as f64 / 72.) * point_size / self.units_per_em as f64;
@@ -446,10 +448,13 @@ impl ParsedFont {
 
 pub struct AllsortsShaper {
     fonts: Vec<Option<ParsedFont>>,
+    /// The per-font `scale` option, by fallback index.
+    /// `harfbuzz_features` don't apply to this shaper.
+    scales: Vec<f64>,
 }
 
 impl AllsortsShaper {
-    pub fn new(handles: &[FontDataHandle]) -> anyhow::Result<Self> {
+    pub fn new(handles: &[FontDataHandle], options: &[FontOptions]) -> anyhow::Result<Self> {
         let mut fonts = vec![];
         let mut success = false;
         for handle in handles {
@@ -467,7 +472,8 @@ impl AllsortsShaper {
         if !success {
             bail!("failed to load any fonts in this fallback set!?");
         }
-        Ok(Self { fonts })
+        let scales = options.iter().map(|opts| opts.scale).collect();
+        Ok(Self { fonts, scales })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -480,6 +486,7 @@ impl AllsortsShaper {
         lang: u32,
         font_size: f64,
         dpi: u32,
+        unicode_version: &UnicodeVersion,
         results: &mut Vec<GlyphInfo>,
         no_glyphs: &mut Vec<char>,
     ) -> anyhow::Result<()> {
@@ -494,6 +501,7 @@ impl AllsortsShaper {
                     lang,
                     font_size,
                     dpi,
+                    unicode_version,
                     results,
                     no_glyphs,
                 );
@@ -520,6 +528,7 @@ impl AllsortsShaper {
                     lang,
                     font_size,
                     dpi,
+                    unicode_version,
                     results,
                     no_glyphs,
                 );
@@ -534,8 +543,17 @@ impl AllsortsShaper {
             }
         }
 
-        let first_pass =
-            font.shape_text(s, slice_index, font_index, script, lang, font_size, dpi)?;
+        let scaled_size = font_size * self.scales.get(font_index).copied().unwrap_or(1.0);
+        let first_pass = font.shape_text(
+            s,
+            slice_index,
+            font_index,
+            script,
+            lang,
+            scaled_size,
+            dpi,
+            unicode_version,
+        )?;
 
         let mut item_iter = first_pass.into_iter();
         while let Some(item) = item_iter.next() {
@@ -555,6 +573,7 @@ impl AllsortsShaper {
                         lang,
                         font_size,
                         dpi,
+                        unicode_version,
                         results,
                         no_glyphs,
                     )?;
@@ -570,19 +589,42 @@ impl FontShaper for AllsortsShaper {
     fn shape(
         &self,
         text: &str,
+        // We always produce glyphs in logical order, which is what
+        // the caller expects for right to left text too.
+        _direction: Direction,
         size: f64,
         dpi: u32,
+        // Features only apply to the harfbuzz shaper
+        _features: &[&str],
         no_glyphs: &mut Vec<char>,
     ) -> anyhow::Result<Vec<GlyphInfo>> {
         let mut results = vec![];
         let script = allsorts::tag::LATN;
         let lang = allsorts::tag::DFLT;
-        self.shape_into(0, text, 0, script, lang, size, dpi, &mut results, no_glyphs)?;
+        let unicode_version = config::configuration().unicode_version();
+        self.shape_into(
+            0,
+            text,
+            0,
+            script,
+            lang,
+            size,
+            dpi,
+            &unicode_version,
+            &mut results,
+            no_glyphs,
+        )?;
         // log::error!("shape {} into {:?}", text, results);
         Ok(results)
     }
 
-    fn metrics_for_idx(&self, font_idx: usize, size: f64, dpi: u32) -> anyhow::Result<FontMetrics> {
+    fn metrics_for_idx(
+        &self,
+        font_idx: usize,
+        size: f64,
+        dpi: u32,
+        _cell_height: Option<f64>,
+    ) -> anyhow::Result<FontMetrics> {
         let font = self
             .fonts
             .get(font_idx)
@@ -1,15 +1,16 @@
 use crate::ftwrap;
 use crate::hbwrap as harfbuzz;
 use crate::locator::FontDataHandle;
-use crate::shaper::{FallbackIdx, FontMetrics, FontShaper, GlyphInfo};
+use crate::shaper::{Direction, FallbackIdx, FontMetrics, FontShaper, GlyphInfo};
 use crate::units::*;
+use crate::FontOptions;
 use anyhow::anyhow;
 use config::configuration;
 use log::error;
 use ordered_float::NotNan;
 use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
-use termwiz::cell::unicode_column_width;
+use termwiz::cell::UnicodeVersion;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -31,8 +32,13 @@ impl<'a> std::fmt::Debug for Info<'a> {
     }
 }
 
-fn make_glyphinfo(text: &str, font_idx: usize, info: &Info) -> GlyphInfo {
-    let num_cells = unicode_column_width(text) as u8;
+fn make_glyphinfo(
+    text: &str,
+    font_idx: usize,
+    info: &Info,
+    unicode_version: &UnicodeVersion,
+) -> GlyphInfo {
+    let num_cells = unicode_version.column_width(text) as u8;
     GlyphInfo {
         #[cfg(debug_assertions)]
         text: text.into(),
@@ -57,10 +63,12 @@ struct MetricsKey {
     font_idx: usize,
     size: NotNan<f64>,
     dpi: u32,
+    cell_height: Option<NotNan<f64>>,
 }
 
 pub struct HarfbuzzShaper {
     handles: Vec<FontDataHandle>,
+    options: Vec<FontOptions>,
     fonts: Vec<RefCell<Option<FontPair>>>,
     lib: ftwrap::Library,
     metrics: RefCell<HashMap<MetricsKey, FontMetrics>>,
@@ -100,9 +108,10 @@ fn is_question_string(s: &str) -> bool {
 }
 
 impl HarfbuzzShaper {
-    pub fn new(handles: &[FontDataHandle]) -> anyhow::Result<Self> {
+    pub fn new(handles: &[FontDataHandle], options: &[FontOptions]) -> anyhow::Result<Self> {
         let lib = ftwrap::Library::new()?;
         let handles = handles.to_vec();
+        let options = options.to_vec();
         let mut fonts = vec![];
         for _ in 0..handles.len() {
             fonts.push(RefCell::new(None));
@@ -110,6 +119,7 @@ impl HarfbuzzShaper {
         Ok(Self {
             fonts,
             handles,
+            options,
             lib,
             metrics: RefCell::new(HashMap::new()),
         })
@@ -125,9 +135,17 @@ impl HarfbuzzShaper {
                 let mut opt_pair = opt_pair.borrow_mut();
                 if opt_pair.is_none() {
                     log::trace!("shaper wants {} {:?}", font_idx, &self.handles[font_idx]);
-                    let face = self.lib.face_from_locator(&self.handles[font_idx])?;
+                    let options = &self.options[font_idx];
+                    let mut face = self.lib.face_from_locator(&self.handles[font_idx])?;
+                    self.lib.select_variation(
+                        &mut face,
+                        options.weight,
+                        options.stretch,
+                        options.italic,
+                    )?;
                     let mut font = harfbuzz::Font::new(face.face);
-                    let (load_flags, _) = ftwrap::compute_load_flags_from_config();
+                    let (load_flags, _) =
+                        ftwrap::compute_load_flags(options.hinting, options.antialias);
                     font.set_load_flags(load_flags);
                     *opt_pair = Some(FontPair { face, font });
                 }
@@ -139,26 +157,46 @@ impl HarfbuzzShaper {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn do_shape(
         &self,
         font_idx: FallbackIdx,
         s: &str,
+        direction: Direction,
         font_size: f64,
         dpi: u32,
+        extra_features: &[&str],
         no_glyphs: &mut Vec<char>,
     ) -> anyhow::Result<Vec<GlyphInfo>> {
         let config = configuration();
-        let features: Vec<harfbuzz::hb_feature_t> = config
-            .harfbuzz_features
+        let unicode_version = config.unicode_version();
+        let (features, scale) = match self.options.get(font_idx) {
+            Some(options) => (options.harfbuzz_features.as_slice(), options.scale),
+            None => (config.harfbuzz_features.as_slice(), 1.0),
+        };
+        // The extra features come last so that they take precedence
+        let features: Vec<harfbuzz::hb_feature_t> = features
             .iter()
+            .map(String::as_str)
+            .chain(extra_features.iter().copied())
             .filter_map(|s| harfbuzz::feature_from_string(s).ok())
             .collect();
 
         let mut buf = harfbuzz::Buffer::new()?;
-        buf.set_script(harfbuzz::hb_script_t::HB_SCRIPT_LATIN);
-        buf.set_direction(harfbuzz::hb_direction_t::HB_DIRECTION_LTR);
+        match direction {
+            Direction::LeftToRight => {
+                buf.set_script(harfbuzz::hb_script_t::HB_SCRIPT_LATIN);
+                buf.set_direction(harfbuzz::hb_direction_t::HB_DIRECTION_LTR);
+            }
+            Direction::RightToLeft => {
+                // Let harfbuzz figure out the script from the text,
+                // as eg: Arabic needs it to select the joining forms
+                buf.set_direction(harfbuzz::hb_direction_t::HB_DIRECTION_RTL);
+            }
+        }
         buf.set_language(harfbuzz::language_from_string("en")?);
         buf.add_str(s);
+        buf.guess_segment_properties();
         buf.set_cluster_level(
             harfbuzz::hb_buffer_cluster_level_t::HB_BUFFER_CLUSTER_LEVEL_MONOTONE_GRAPHEMES,
         );
@@ -169,9 +207,15 @@ impl HarfbuzzShaper {
             match self.load_fallback(font_idx)? {
                 #[allow(clippy::float_cmp)]
                 Some(mut pair) => {
-                    let (width, _height) = pair.face.set_font_size(font_size, dpi)?;
+                    let (width, _height) = pair.face.set_font_size(font_size * scale, dpi)?;
                     cell_width = width;
                     pair.font.shape(&mut buf, Some(features.as_slice()));
+                    if direction == Direction::RightToLeft {
+                        // The glyphs are produced in visual order; put them
+                        // back into logical order so that the cluster
+                        // computation below works the same in both directions
+                        buf.reverse();
+                    }
                 }
                 None => {
                     // Note: since we added a last resort font, this case
@@ -267,12 +311,27 @@ impl HarfbuzzShaper {
                 }
                 */
 
-                let mut shape = match self.do_shape(font_idx + 1, substr, font_size, dpi, no_glyphs)
-                {
+                let mut shape = match self.do_shape(
+                    font_idx + 1,
+                    substr,
+                    direction,
+                    font_size,
+                    dpi,
+                    extra_features,
+                    no_glyphs,
+                ) {
                     Ok(shape) => Ok(shape),
                     Err(e) => {
                         error!("{:?} for {:?}", e, substr);
-                        self.do_shape(0, &make_question_string(substr), font_size, dpi, no_glyphs)
+                        self.do_shape(
+                            0,
+                            &make_question_string(substr),
+                            direction,
+                            font_size,
+                            dpi,
+                            extra_features,
+                            no_glyphs,
+                        )
                     }
                 }?;
 
@@ -307,9 +366,9 @@ impl HarfbuzzShaper {
 
                 let glyph = if len > 0 {
                     let text = &substr[next_idx..next_idx + len];
-                    make_glyphinfo(text, font_idx, info)
+                    make_glyphinfo(text, font_idx, info, &unicode_version)
                 } else {
-                    make_glyphinfo("__", font_idx, info)
+                    make_glyphinfo("__", font_idx, info, &unicode_version)
                 };
 
                 if glyph.x_advance != PixelLength::new(0.0) {
@@ -329,12 +388,14 @@ impl FontShaper for HarfbuzzShaper {
     fn shape(
         &self,
         text: &str,
+        direction: Direction,
         size: f64,
         dpi: u32,
+        features: &[&str],
         no_glyphs: &mut Vec<char>,
     ) -> anyhow::Result<Vec<GlyphInfo>> {
         let start = std::time::Instant::now();
-        let result = self.do_shape(0, text, size, dpi, no_glyphs);
+        let result = self.do_shape(0, text, direction, size, dpi, features, no_glyphs);
         metrics::histogram!("shape.harfbuzz", start.elapsed());
         /*
         if let Ok(glyphs) = &result {
@@ -349,7 +410,13 @@ impl FontShaper for HarfbuzzShaper {
         result
     }
 
-    fn metrics_for_idx(&self, font_idx: usize, size: f64, dpi: u32) -> anyhow::Result<FontMetrics> {
+    fn metrics_for_idx(
+        &self,
+        font_idx: usize,
+        size: f64,
+        dpi: u32,
+        cell_height: Option<f64>,
+    ) -> anyhow::Result<FontMetrics> {
         let mut pair = self
             .load_fallback(font_idx)?
             .ok_or_else(|| anyhow!("unable to load font idx {}!?", font_idx))?;
@@ -358,6 +425,7 @@ impl FontShaper for HarfbuzzShaper {
             font_idx,
             size: NotNan::new(size).unwrap(),
             dpi,
+            cell_height: cell_height.and_then(|h| NotNan::new(h).ok()),
         };
         if let Some(metrics) = self.metrics.borrow().get(&key) {
             return Ok(metrics.clone());
@@ -365,19 +433,24 @@ impl FontShaper for HarfbuzzShaper {
 
         let (cell_width, cell_height) = pair.face.set_font_size(size, dpi)?;
         let y_scale = unsafe { (*(*pair.face.face).size).metrics.y_scale as f64 / 65536.0 };
+        // Glyphs from bitmap strikes are scaled to the requested size
+        // by the rasterizer, so the metrics need to match
+        let bitmap_scale = pair.face.bitmap_scale(cell_height);
         let metrics = FontMetrics {
-            cell_height: PixelLength::new(cell_height),
-            cell_width: PixelLength::new(cell_width),
+            cell_height: PixelLength::new(cell_height * bitmap_scale),
+            cell_width: PixelLength::new(cell_width * bitmap_scale),
             // Note: face.face.descender is useless, we have to go through
             // face.face.size.metrics to get to the real descender!
             descender: PixelLength::new(
-                unsafe { (*(*pair.face.face).size).metrics.descender as f64 } / 64.0,
+                unsafe { (*(*pair.face.face).size).metrics.descender as f64 } / 64.0 * bitmap_scale,
             ),
             underline_thickness: PixelLength::new(
-                unsafe { (*pair.face.face).underline_thickness as f64 } * y_scale / 64.,
+                unsafe { (*pair.face.face).underline_thickness as f64 } * y_scale / 64.
+                    * bitmap_scale,
             ),
             underline_position: PixelLength::new(
-                unsafe { (*pair.face.face).underline_position as f64 } * y_scale / 64.,
+                unsafe { (*pair.face.face).underline_position as f64 } * y_scale / 64.
+                    * bitmap_scale,
             ),
         };
 
@@ -420,6 +493,6 @@ impl FontShaper for HarfbuzzShaper {
             metrics_idx += 1;
         }
 
-        self.metrics_for_idx(metrics_idx, size, dpi)
+        self.metrics_for_idx(metrics_idx, size, dpi, None)
     }
 }
@@ -1,5 +1,7 @@
 use crate::locator::FontDataHandle;
 use crate::units::PixelLength;
+use crate::FontOptions;
+pub use termwiz::cellcluster::Direction;
 
 pub mod allsorts;
 pub mod harfbuzz;
@@ -53,12 +55,19 @@ pub struct FontMetrics {
 }
 
 pub trait FontShaper {
-    /// Shape text and return a vector of GlyphInfo
+    /// Shape text and return a vector of GlyphInfo.
+    /// The text is in logical order; `direction` specifies whether
+    /// it flows left to right or right to left.  The returned glyphs
+    /// are always in logical order.
+    /// `features` are applied after those configured for the font,
+    /// which allows eg: ligatures to be disabled for a run of text.
     fn shape(
         &self,
         text: &str,
+        direction: Direction,
         size: f64,
         dpi: u32,
+        features: &[&str],
         no_glyphs: &mut Vec<char>,
     ) -> anyhow::Result<Vec<GlyphInfo>>;
 
@@ -66,18 +75,32 @@ pub trait FontShaper {
     /// at the specified size.
     fn metrics(&self, size: f64, dpi: u32) -> anyhow::Result<FontMetrics>;
 
-    /// Compute the metrics for a given fallback font at the specified size
-    fn metrics_for_idx(&self, font_idx: usize, size: f64, dpi: u32) -> anyhow::Result<FontMetrics>;
+    /// Compute the metrics for a given fallback font at the specified size.
+    /// `cell_height` is the height of the cell that bitmap fonts are
+    /// scaled to fit, if known.
+    fn metrics_for_idx(
+        &self,
+        font_idx: usize,
+        size: f64,
+        dpi: u32,
+        cell_height: Option<f64>,
+    ) -> anyhow::Result<FontMetrics>;
 }
 
 pub use config::FontShaperSelection;
 
+/// `options` holds the per-font options for each of the `handles`
 pub fn new_shaper(
     shaper: FontShaperSelection,
     handles: &[FontDataHandle],
+    options: &[FontOptions],
 ) -> anyhow::Result<Box<dyn FontShaper>> {
     match shaper {
-        FontShaperSelection::Harfbuzz => Ok(Box::new(harfbuzz::HarfbuzzShaper::new(handles)?)),
-        FontShaperSelection::Allsorts => Ok(Box::new(allsorts::AllsortsShaper::new(handles)?)),
+        FontShaperSelection::Harfbuzz => {
+            Ok(Box::new(harfbuzz::HarfbuzzShaper::new(handles, options)?))
+        }
+        FontShaperSelection::Allsorts => {
+            Ok(Box::new(allsorts::AllsortsShaper::new(handles, options)?))
+        }
     }
 }
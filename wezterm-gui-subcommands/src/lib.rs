@@ -3,6 +3,7 @@ use config::FontRasterizerSelection;
 use config::FontShaperSelection;
 use config::{FrontEndSelection, SshParameters};
 use std::ffi::OsString;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt, Default, Clone)]
@@ -115,6 +116,12 @@ pub struct ConnectCommand {
     )]
     pub front_end: Option<FrontEndSelection>,
 
+    /// Attach as an observer: you can see the panes on the
+    /// server, but input is not sent to them, and your window
+    /// size does not affect their size
+    #[structopt(long = "read-only")]
+    pub read_only: bool,
+
     /// Name of the multiplexer domain section from the configuration
     /// to which you'd like to connect
     pub domain_name: String,
@@ -125,3 +132,34 @@ pub struct ConnectCommand {
     #[structopt(parse(from_os_str))]
     pub prog: Vec<OsString>,
 }
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct LsFontsCommand {
+    /// Explain which fonts are used to render the supplied text string
+    #[structopt(long = "text")]
+    pub text: Option<String>,
+
+    /// Also list all of the fonts that the system font locator can find
+    #[structopt(long = "list-system")]
+    pub list_system: bool,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct RenderPngCommand {
+    /// The number of columns in the rendered terminal
+    #[structopt(long = "cols", default_value = "80")]
+    pub cols: usize,
+
+    /// The number of rows in the rendered terminal
+    #[structopt(long = "rows", default_value = "24")]
+    pub rows: usize,
+
+    /// Read the terminal output to render from this file, rather
+    /// than from stdin.  Escape sequences are interpreted.
+    #[structopt(long = "input", parse(from_os_str))]
+    pub input: Option<PathBuf>,
+
+    /// The PNG file to write
+    #[structopt(parse(from_os_str))]
+    pub output: PathBuf,
+}
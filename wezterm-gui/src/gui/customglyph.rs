@@ -0,0 +1,801 @@
+//! Procedurally rendered box drawing, block element and powerline glyphs.
+//! Fonts frequently leave small gaps between adjacent box drawing
+//! characters, or size them inconsistently with the terminal cell when
+//! line_height is adjusted, so we synthesize them here
+//! at exactly the current cell size instead.
+use super::glyphcache::GlyphCache;
+use ::window::bitmaps::atlas::Sprite;
+use ::window::bitmaps::{BitmapImage, Image, Texture2d};
+use ::window::color::Color;
+
+/// Identifies a character that we know how to draw ourselves
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockKey(char);
+
+impl BlockKey {
+    /// Returns a key if the cell text is a single character that
+    /// can be rendered procedurally
+    pub fn from_text(s: &str) -> Option<Self> {
+        let mut chars = s.chars();
+        let c = chars.next()?;
+        if chars.next().is_some() {
+            return None;
+        }
+        match c as u32 {
+            // Box Drawing and Block Elements
+            0x2500..=0x259f |
+            // Braille Patterns
+            0x2800..=0x28ff |
+            // Powerline separators
+            0xe0b0..=0xe0b3 => Some(Self(c)),
+            _ => None,
+        }
+    }
+}
+
+/// The weight of one of the four arms that radiate from the
+/// center of a box drawing character
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Arm {
+    None,
+    Light,
+    Heavy,
+    Double,
+}
+
+/// Returns the (up, down, left, right) arms for the line drawing
+/// portions of the Box Drawing block
+fn box_arms(c: char) -> Option<[Arm; 4]> {
+    let spec = match c as u32 {
+        0x2500 => "..ll",
+        0x2501 => "..hh",
+        0x2502 => "ll..",
+        0x2503 => "hh..",
+        0x250c => ".l.l",
+        0x250d => ".l.h",
+        0x250e => ".h.l",
+        0x250f => ".h.h",
+        0x2510 => ".ll.",
+        0x2511 => ".lh.",
+        0x2512 => ".hl.",
+        0x2513 => ".hh.",
+        0x2514 => "l..l",
+        0x2515 => "l..h",
+        0x2516 => "h..l",
+        0x2517 => "h..h",
+        0x2518 => "l.l.",
+        0x2519 => "l.h.",
+        0x251a => "h.l.",
+        0x251b => "h.h.",
+        0x251c => "ll.l",
+        0x251d => "ll.h",
+        0x251e => "hl.l",
+        0x251f => "lh.l",
+        0x2520 => "hh.l",
+        0x2521 => "hl.h",
+        0x2522 => "lh.h",
+        0x2523 => "hh.h",
+        0x2524 => "lll.",
+        0x2525 => "llh.",
+        0x2526 => "hll.",
+        0x2527 => "lhl.",
+        0x2528 => "hhl.",
+        0x2529 => "hlh.",
+        0x252a => "lhh.",
+        0x252b => "hhh.",
+        0x252c => ".lll",
+        0x252d => ".lhl",
+        0x252e => ".llh",
+        0x252f => ".lhh",
+        0x2530 => ".hll",
+        0x2531 => ".hhl",
+        0x2532 => ".hlh",
+        0x2533 => ".hhh",
+        0x2534 => "l.ll",
+        0x2535 => "l.hl",
+        0x2536 => "l.lh",
+        0x2537 => "l.hh",
+        0x2538 => "h.ll",
+        0x2539 => "h.hl",
+        0x253a => "h.lh",
+        0x253b => "h.hh",
+        0x253c => "llll",
+        0x253d => "llhl",
+        0x253e => "lllh",
+        0x253f => "llhh",
+        0x2540 => "hlll",
+        0x2541 => "lhll",
+        0x2542 => "hhll",
+        0x2543 => "hlhl",
+        0x2544 => "hllh",
+        0x2545 => "lhhl",
+        0x2546 => "lhlh",
+        0x2547 => "hlhh",
+        0x2548 => "lhhh",
+        0x2549 => "hhhl",
+        0x254a => "hhlh",
+        0x254b => "hhhh",
+        0x2550 => "..dd",
+        0x2551 => "dd..",
+        0x2552 => ".l.d",
+        0x2553 => ".d.l",
+        0x2554 => ".d.d",
+        0x2555 => ".ld.",
+        0x2556 => ".dl.",
+        0x2557 => ".dd.",
+        0x2558 => "l..d",
+        0x2559 => "d..l",
+        0x255a => "d..d",
+        0x255b => "l.d.",
+        0x255c => "d.l.",
+        0x255d => "d.d.",
+        0x255e => "ll.d",
+        0x255f => "dd.l",
+        0x2560 => "dd.d",
+        0x2561 => "lld.",
+        0x2562 => "ddl.",
+        0x2563 => "ddd.",
+        0x2564 => ".ldd",
+        0x2565 => ".dll",
+        0x2566 => ".ddd",
+        0x2567 => "l.dd",
+        0x2568 => "d.ll",
+        0x2569 => "d.dd",
+        0x256a => "lldd",
+        0x256b => "ddll",
+        0x256c => "dddd",
+        0x2574 => "..l.",
+        0x2575 => "l...",
+        0x2576 => "...l",
+        0x2577 => ".l..",
+        0x2578 => "..h.",
+        0x2579 => "h...",
+        0x257a => "...h",
+        0x257b => ".h..",
+        0x257c => "..lh",
+        0x257d => "lh..",
+        0x257e => "..hl",
+        0x257f => "hl..",
+        _ => return None,
+    };
+    let mut arms = [Arm::None; 4];
+    for (arm, c) in arms.iter_mut().zip(spec.chars()) {
+        *arm = match c {
+            'l' => Arm::Light,
+            'h' => Arm::Heavy,
+            'd' => Arm::Double,
+            _ => Arm::None,
+        };
+    }
+    Some(arms)
+}
+
+/// A grayscale coverage buffer that we draw into before transferring
+/// the result into an Image for the atlas
+struct Canvas {
+    width: usize,
+    height: usize,
+    coverage: Vec<f32>,
+}
+
+/// Number of samples per axis when anti-aliasing curves and diagonals
+const SUPERSAMPLE: usize = 4;
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            coverage: vec![0.; width * height],
+        }
+    }
+
+    /// Fill the half open pixel rectangle x0..x1, y0..y1 with the
+    /// specified coverage, clipping to the canvas bounds.
+    fn fill_rect(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, alpha: f32) {
+        let x0 = x0.max(0) as usize;
+        let y0 = y0.max(0) as usize;
+        let x1 = (x1.max(0) as usize).min(self.width);
+        let y1 = (y1.max(0) as usize).min(self.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let c = &mut self.coverage[y * self.width + x];
+                *c = c.max(alpha);
+            }
+        }
+    }
+
+    /// Fill the pixels for which `inside` returns true for the sample
+    /// point, anti-aliasing by supersampling each pixel.
+    fn fill_with<F: Fn(f32, f32) -> bool>(&mut self, inside: F) {
+        let step = 1.0 / SUPERSAMPLE as f32;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut hits = 0;
+                for sy in 0..SUPERSAMPLE {
+                    for sx in 0..SUPERSAMPLE {
+                        let px = x as f32 + (sx as f32 + 0.5) * step;
+                        let py = y as f32 + (sy as f32 + 0.5) * step;
+                        if inside(px, py) {
+                            hits += 1;
+                        }
+                    }
+                }
+                if hits > 0 {
+                    let alpha = hits as f32 / (SUPERSAMPLE * SUPERSAMPLE) as f32;
+                    let c = &mut self.coverage[y * self.width + x];
+                    *c = c.max(alpha);
+                }
+            }
+        }
+    }
+
+    fn to_image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            let row = image.horizontal_pixel_range_mut(0, self.width, y);
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let v = (self.coverage[y * self.width + x].min(1.0) * 255.0) as u8;
+                *pixel = Color::rgba(v, v, v, v).0;
+            }
+        }
+        image
+    }
+}
+
+/// Distance from the point (px, py) to the line segment (x0, y0) - (x1, y1)
+fn distance_to_segment(px: f32, py: f32, x0: f32, y0: f32, x1: f32, y1: f32) -> f32 {
+    let dx = x1 - x0;
+    let dy = y1 - y0;
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq == 0. {
+        0.
+    } else {
+        (((px - x0) * dx + (py - y0) * dy) / len_sq).clamp(0., 1.)
+    };
+    let cx = x0 + t * dx;
+    let cy = y0 + t * dy;
+    ((px - cx).powi(2) + (py - cy).powi(2)).sqrt()
+}
+
+/// Offset that centers a stroke of `size` pixels within `total` pixels
+fn center(total: isize, size: isize) -> isize {
+    (total - size) / 2
+}
+
+/// Geometry for the line drawing characters
+struct Strokes {
+    width: isize,
+    height: isize,
+    /// Thickness of a light line; heavy is twice this, and each
+    /// of the lines comprising a double line are this thick with
+    /// a gap of the same thickness between them.
+    light: isize,
+}
+
+impl Strokes {
+    fn thickness(&self, arm: Arm) -> isize {
+        match arm {
+            Arm::None => 0,
+            Arm::Light => self.light,
+            Arm::Heavy => self.light * 2,
+            Arm::Double => self.light * 3,
+        }
+    }
+
+    /// Draw a rectangle expressed in terms of the axis of an arm;
+    /// `along` runs the length of the arm, `across` spans its thickness.
+    fn rect(
+        &self,
+        canvas: &mut Canvas,
+        vertical: bool,
+        along: (isize, isize),
+        across: (isize, isize),
+    ) {
+        if vertical {
+            canvas.fill_rect(across.0, along.0, across.1, along.1, 1.0);
+        } else {
+            canvas.fill_rect(along.0, across.0, along.1, across.1, 1.0);
+        }
+    }
+
+    /// Computes where an arm should stop in order to join with the
+    /// perpendicular arms.  `leading` is true for the arms that start
+    /// at the left or top edge and extend toward the center, in which
+    /// case the returned value is the exclusive end of the arm.
+    /// Otherwise the arm extends from the center to the right or bottom
+    /// edge and the returned value is its start.
+    /// `near` applies to a double perpendicular and selects whether
+    /// to stop at the nearest of its lines rather than the furthest.
+    fn join(&self, len: isize, leading: bool, perp: Arm, near: bool) -> isize {
+        let t = self.light;
+        match perp {
+            Arm::None => unreachable!(),
+            Arm::Double => {
+                let outer = center(len, 3 * t);
+                match (leading, near) {
+                    (true, true) => outer + t,
+                    (true, false) => outer + 3 * t,
+                    (false, true) => outer + 2 * t,
+                    (false, false) => outer,
+                }
+            }
+            arm => {
+                let size = self.thickness(arm);
+                let start = center(len, size);
+                if leading {
+                    start + size
+                } else {
+                    start
+                }
+            }
+        }
+    }
+
+    /// Draw one arm.  `opposite` is the arm on the other side of the
+    /// center along the same axis.  `side_a` and `side_b` are the
+    /// perpendicular arms on either side of this arm; for a horizontal
+    /// arm those are the up and down arms, for a vertical arm, the left
+    /// and right arms.
+    #[allow(clippy::too_many_arguments)]
+    fn arm(
+        &self,
+        canvas: &mut Canvas,
+        arm: Arm,
+        opposite: Arm,
+        vertical: bool,
+        leading: bool,
+        side_a: Arm,
+        side_b: Arm,
+    ) {
+        if arm == Arm::None {
+            return;
+        }
+        let (len, cross) = if vertical {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        let t = self.light;
+        let extent = |stop: isize| if leading { (0, stop) } else { (stop, len) };
+
+        if arm != Arm::Double {
+            let size = self.thickness(arm);
+            let start = center(cross, size);
+            // A line that ends against a continuous double line only
+            // touches the nearest of its two lines; in all other cases
+            // we run through to the far side of the perpendicular.
+            let near = opposite == Arm::None && side_a != Arm::None && side_b != Arm::None;
+            let perps = [side_a, side_b];
+            let perps = perps.iter().filter(|&&p| p != Arm::None);
+            let stop = if leading {
+                perps.map(|&p| self.join(len, true, p, near)).max()
+            } else {
+                perps.map(|&p| self.join(len, false, p, near)).min()
+            };
+            let stop = stop.unwrap_or_else(|| self.join(len, leading, arm, near));
+            self.rect(canvas, vertical, extent(stop), (start, start + size));
+            return;
+        }
+
+        let outer = center(cross, 3 * t);
+        let outer_len = center(len, 3 * t);
+        for (same, other, across) in [
+            (side_a, side_b, (outer, outer + t)),
+            (side_b, side_a, (outer + 2 * t, outer + 3 * t)),
+        ] {
+            let single = [same, other]
+                .iter()
+                .filter(|&&p| p != Arm::None && p != Arm::Double)
+                .map(|&p| self.thickness(p))
+                .max();
+            let stop = if let Some(size) = single {
+                // Crossing a single line: run up to its far edge
+                let start = center(len, size);
+                if leading {
+                    start + size
+                } else {
+                    start
+                }
+            } else if same == Arm::Double {
+                // Turn the inner corner toward the perpendicular
+                if leading {
+                    outer_len + t
+                } else {
+                    outer_len + 2 * t
+                }
+            } else {
+                // Run to the outside of the perpendicular double line,
+                // or straight through if there isn't one
+                if leading {
+                    outer_len + 3 * t
+                } else {
+                    outer_len
+                }
+            };
+            self.rect(canvas, vertical, extent(stop), across);
+        }
+    }
+
+    fn draw_arms(&self, canvas: &mut Canvas, arms: [Arm; 4]) {
+        let [up, down, left, right] = arms;
+        self.arm(canvas, up, down, true, true, left, right);
+        self.arm(canvas, down, up, true, false, left, right);
+        self.arm(canvas, left, right, false, true, up, down);
+        self.arm(canvas, right, left, false, false, up, down);
+    }
+
+    /// Draw a line broken into `segments` dashes
+    fn dashed(&self, canvas: &mut Canvas, arm: Arm, vertical: bool, segments: isize) {
+        let (len, cross) = if vertical {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        let size = self.thickness(arm);
+        let start = center(cross, size);
+        let gap = (len / (segments * 4)).max(1);
+        for i in 0..segments {
+            let seg_start = i * len / segments;
+            let seg_end = (i + 1) * len / segments;
+            self.rect(
+                canvas,
+                vertical,
+                (seg_start + gap / 2, seg_end - (gap - gap / 2)),
+                (start, start + size),
+            );
+        }
+    }
+
+    /// Draw a rounded corner; `dx` and `dy` are the directions of the
+    /// horizontal and vertical arms respectively, expressed as +1 for
+    /// right/down and -1 for left/up.
+    fn arc(&self, canvas: &mut Canvas, dx: f32, dy: f32) {
+        let t = self.light;
+        let x_start = center(self.width, t);
+        let y_start = center(self.height, t);
+        let cx = x_start as f32 + t as f32 / 2.;
+        let cy = y_start as f32 + t as f32 / 2.;
+        let radius = (self.width.min(self.height) as f32 / 2.).max(1.);
+        let ox = cx + dx * radius;
+        let oy = cy + dy * radius;
+        let half = t as f32 / 2.;
+        canvas.fill_with(|x, y| {
+            if (x - ox) * dx > 0. || (y - oy) * dy > 0. {
+                return false;
+            }
+            let dist = ((x - ox).powi(2) + (y - oy).powi(2)).sqrt();
+            (dist - radius).abs() <= half
+        });
+
+        // Straight segments from the end of the arc to the edges
+        let ox = ox.round() as isize;
+        let oy = oy.round() as isize;
+        if dx > 0. {
+            canvas.fill_rect(ox, y_start, self.width, y_start + t, 1.0);
+        } else {
+            canvas.fill_rect(0, y_start, ox, y_start + t, 1.0);
+        }
+        if dy > 0. {
+            canvas.fill_rect(x_start, oy, x_start + t, self.height, 1.0);
+        } else {
+            canvas.fill_rect(x_start, 0, x_start + t, oy, 1.0);
+        }
+    }
+
+    fn diagonal(&self, canvas: &mut Canvas, rising: bool, falling: bool) {
+        let w = self.width as f32;
+        let h = self.height as f32;
+        let half = self.light as f32 / 2.;
+        canvas.fill_with(|x, y| {
+            (rising && distance_to_segment(x, y, 0., h, w, 0.) <= half)
+                || (falling && distance_to_segment(x, y, 0., 0., w, h) <= half)
+        });
+    }
+}
+
+impl<T: Texture2d> GlyphCache<T> {
+    /// Returns the sprite for a procedurally rendered glyph,
+    /// rendering it on demand if it isn't already cached.
+    pub fn cached_block(&mut self, key: BlockKey) -> anyhow::Result<Sprite<T>> {
+        if let Some(s) = self.block_glyphs.get(&key) {
+            return Ok(s.clone());
+        }
+        let image = self.block_sprite(key);
+        let sprite = self.atlas.allocate(&image)?;
+        self.block_glyphs.insert(key, sprite.clone());
+        Ok(sprite)
+    }
+
+    fn block_sprite(&self, key: BlockKey) -> Image {
+        draw_block(
+            key,
+            self.metrics.cell_size.width,
+            self.metrics.cell_size.height,
+            self.metrics.underline_height.max(1),
+        )
+        .to_image()
+    }
+}
+
+/// Draws the glyph for `key` to fill a cell of the specified size,
+/// using lines that are `light` pixels thick
+fn draw_block(key: BlockKey, width: isize, height: isize, light: isize) -> Canvas {
+    let mut canvas = Canvas::new(width as usize, height as usize);
+    let strokes = Strokes {
+        width,
+        height,
+        light,
+    };
+
+    // Splits the cell into eighths, as used by the block elements
+    let x_eighth = |n: isize| n * width / 8;
+    let y_eighth = |n: isize| n * height / 8;
+    let half_x = width / 2;
+    let half_y = height / 2;
+
+    let c = key.0;
+    if let Some(arms) = box_arms(c) {
+        strokes.draw_arms(&mut canvas, arms);
+        return canvas;
+    }
+
+    let (w, h) = (width as f32, height as f32);
+    match c as u32 {
+        0x2504 => strokes.dashed(&mut canvas, Arm::Light, false, 3),
+        0x2505 => strokes.dashed(&mut canvas, Arm::Heavy, false, 3),
+        0x2506 => strokes.dashed(&mut canvas, Arm::Light, true, 3),
+        0x2507 => strokes.dashed(&mut canvas, Arm::Heavy, true, 3),
+        0x2508 => strokes.dashed(&mut canvas, Arm::Light, false, 4),
+        0x2509 => strokes.dashed(&mut canvas, Arm::Heavy, false, 4),
+        0x250a => strokes.dashed(&mut canvas, Arm::Light, true, 4),
+        0x250b => strokes.dashed(&mut canvas, Arm::Heavy, true, 4),
+        0x254c => strokes.dashed(&mut canvas, Arm::Light, false, 2),
+        0x254d => strokes.dashed(&mut canvas, Arm::Heavy, false, 2),
+        0x254e => strokes.dashed(&mut canvas, Arm::Light, true, 2),
+        0x254f => strokes.dashed(&mut canvas, Arm::Heavy, true, 2),
+        0x256d => strokes.arc(&mut canvas, 1., 1.),
+        0x256e => strokes.arc(&mut canvas, -1., 1.),
+        0x256f => strokes.arc(&mut canvas, -1., -1.),
+        0x2570 => strokes.arc(&mut canvas, 1., -1.),
+        0x2571 => strokes.diagonal(&mut canvas, true, false),
+        0x2572 => strokes.diagonal(&mut canvas, false, true),
+        0x2573 => strokes.diagonal(&mut canvas, true, true),
+        // Upper half block
+        0x2580 => canvas.fill_rect(0, 0, width, half_y, 1.0),
+        // Lower one eighth through lower seven eighths
+        0x2581..=0x2587 => {
+            let n = (c as u32 - 0x2580) as isize;
+            canvas.fill_rect(0, height - y_eighth(n), width, height, 1.0);
+        }
+        0x2588 => canvas.fill_rect(0, 0, width, height, 1.0),
+        // Left seven eighths through left one eighth
+        0x2589..=0x258f => {
+            let n = (0x2590 - c as u32) as isize;
+            canvas.fill_rect(0, 0, x_eighth(n), height, 1.0);
+        }
+        0x2590 => canvas.fill_rect(half_x, 0, width, height, 1.0),
+        // Light, medium and dark shades
+        0x2591..=0x2593 => {
+            let alpha = (c as u32 - 0x2590) as f32 * 0.25;
+            canvas.fill_rect(0, 0, width, height, alpha);
+        }
+        0x2594 => canvas.fill_rect(0, 0, width, y_eighth(1), 1.0),
+        0x2595 => canvas.fill_rect(width - x_eighth(1), 0, width, height, 1.0),
+        // Quadrants
+        0x2596..=0x259f => {
+            // upper left, upper right, lower left, lower right
+            let quads: [bool; 4] = match c as u32 {
+                0x2596 => [false, false, true, false],
+                0x2597 => [false, false, false, true],
+                0x2598 => [true, false, false, false],
+                0x2599 => [true, false, true, true],
+                0x259a => [true, false, false, true],
+                0x259b => [true, true, true, false],
+                0x259c => [true, true, false, true],
+                0x259d => [false, true, false, false],
+                0x259e => [false, true, true, false],
+                _ => [false, true, true, true],
+            };
+            let rects = [
+                (0, 0, half_x, half_y),
+                (half_x, 0, width, half_y),
+                (0, half_y, half_x, height),
+                (half_x, half_y, width, height),
+            ];
+            for (&(x0, y0, x1, y1), &fill) in rects.iter().zip(quads.iter()) {
+                if fill {
+                    canvas.fill_rect(x0, y0, x1, y1, 1.0);
+                }
+            }
+        }
+        // Powerline solid right and left pointing triangles
+        0xe0b0 => canvas.fill_with(|x, y| x / w <= 1. - (2. * y / h - 1.).abs()),
+        0xe0b2 => canvas.fill_with(|x, y| x / w >= (2. * y / h - 1.).abs()),
+        // Powerline right and left pointing chevrons
+        0xe0b1 | 0xe0b3 => {
+            let half = strokes.light as f32 / 2.;
+            let (edge, point) = if c as u32 == 0xe0b1 { (0., w) } else { (w, 0.) };
+            canvas.fill_with(|x, y| {
+                distance_to_segment(x, y, edge, 0., point, h / 2.) <= half
+                    || distance_to_segment(x, y, point, h / 2., edge, h) <= half
+            });
+        }
+        // Braille patterns: the low 8 bits select the dots, which
+        // are laid out in two columns of four rows
+        0x2800..=0x28ff => {
+            // (column, row) of dots 1 through 8
+            const DOTS: [(isize, isize); 8] = [
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (1, 0),
+                (1, 1),
+                (1, 2),
+                (0, 3),
+                (1, 3),
+            ];
+            let size = (half_x / 2).min(height / 8).max(1);
+            for (bit, &(col, row)) in DOTS.iter().enumerate() {
+                if c as u32 & (1 << bit) != 0 {
+                    let x0 = col * half_x + center(half_x, size);
+                    let y0 = row * height / 4 + center(height / 4, size);
+                    canvas.fill_rect(x0, y0, x0 + size, y0 + size, 1.0);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    canvas
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WIDTH: isize = 10;
+    const HEIGHT: isize = 20;
+    const LIGHT: isize = 2;
+
+    fn draw(c: char) -> Canvas {
+        let key = BlockKey::from_text(&c.to_string()).unwrap();
+        draw_block(key, WIDTH, HEIGHT, LIGHT)
+    }
+
+    fn coverage(canvas: &Canvas, x: isize, y: isize) -> f32 {
+        canvas.coverage[y as usize * canvas.width + x as usize]
+    }
+
+    /// Returns true if every pixel in the half open rectangle has
+    /// the specified coverage
+    fn all(canvas: &Canvas, x0: isize, y0: isize, x1: isize, y1: isize, alpha: f32) -> bool {
+        (y0..y1).all(|y| (x0..x1).all(|x| (coverage(canvas, x, y) - alpha).abs() < 0.01))
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(BlockKey::from_text("\u{2500}"), Some(BlockKey('\u{2500}')));
+        assert_eq!(BlockKey::from_text("\u{28ff}"), Some(BlockKey('\u{28ff}')));
+        assert_eq!(BlockKey::from_text("\u{e0b0}"), Some(BlockKey('\u{e0b0}')));
+        assert_eq!(BlockKey::from_text("a"), None);
+        assert_eq!(BlockKey::from_text("\u{2500}\u{2500}"), None);
+    }
+
+    #[test]
+    fn half_blocks() {
+        let upper = draw('\u{2580}');
+        assert!(all(&upper, 0, 0, WIDTH, HEIGHT / 2, 1.0));
+        assert!(all(&upper, 0, HEIGHT / 2, WIDTH, HEIGHT, 0.0));
+
+        let lower = draw('\u{2584}');
+        assert!(all(&lower, 0, 0, WIDTH, HEIGHT / 2, 0.0));
+        assert!(all(&lower, 0, HEIGHT / 2, WIDTH, HEIGHT, 1.0));
+
+        let left = draw('\u{258c}');
+        assert!(all(&left, 0, 0, WIDTH / 2, HEIGHT, 1.0));
+        assert!(all(&left, WIDTH / 2, 0, WIDTH, HEIGHT, 0.0));
+
+        let right = draw('\u{2590}');
+        assert!(all(&right, 0, 0, WIDTH / 2, HEIGHT, 0.0));
+        assert!(all(&right, WIDTH / 2, 0, WIDTH, HEIGHT, 1.0));
+
+        let full = draw('\u{2588}');
+        assert!(all(&full, 0, 0, WIDTH, HEIGHT, 1.0));
+    }
+
+    #[test]
+    fn shades() {
+        assert!(all(&draw('\u{2591}'), 0, 0, WIDTH, HEIGHT, 0.25));
+        assert!(all(&draw('\u{2592}'), 0, 0, WIDTH, HEIGHT, 0.5));
+        assert!(all(&draw('\u{2593}'), 0, 0, WIDTH, HEIGHT, 0.75));
+    }
+
+    #[test]
+    fn braille() {
+        assert!(all(&draw('\u{2800}'), 0, 0, WIDTH, HEIGHT, 0.0));
+
+        // Dot 1 is in the top left; the dots are 2x2 pixels, centered
+        // in a 5x5 region
+        let dot1 = draw('\u{2801}');
+        assert!(all(&dot1, 1, 1, 3, 3, 1.0));
+        assert_eq!(dot1.coverage.iter().filter(|&&c| c > 0.).count(), 4);
+
+        // Dot 8 is in the bottom right
+        let dot8 = draw('\u{2880}');
+        assert!(all(&dot8, 6, 16, 8, 18, 1.0));
+        assert_eq!(dot8.coverage.iter().filter(|&&c| c > 0.).count(), 4);
+
+        // All 8 dots are distinct
+        let all_dots = draw('\u{28ff}');
+        assert_eq!(all_dots.coverage.iter().filter(|&&c| c > 0.).count(), 32);
+        for (x, y) in &[
+            (1, 1),
+            (1, 6),
+            (1, 11),
+            (1, 16),
+            (6, 1),
+            (6, 6),
+            (6, 11),
+            (6, 16),
+        ] {
+            assert!(all(&all_dots, *x, *y, x + 2, y + 2, 1.0));
+        }
+    }
+
+    #[test]
+    fn box_corners() {
+        // The light lines are centered in the cell
+        let y_start = (HEIGHT - LIGHT) / 2;
+        let x_start = (WIDTH - LIGHT) / 2;
+
+        // Down and right: the horizontal line runs from the center to
+        // the right edge, and the vertical line from the center down to
+        // the bottom edge, joining up in the middle.
+        let corner = draw('\u{250c}');
+        assert!(all(&corner, x_start, y_start, WIDTH, y_start + LIGHT, 1.0));
+        assert!(all(&corner, x_start, y_start, x_start + LIGHT, HEIGHT, 1.0));
+        assert!(all(&corner, 0, 0, WIDTH, y_start, 0.0));
+        assert!(all(&corner, 0, 0, x_start, HEIGHT, 0.0));
+
+        // Up and left is the mirror image
+        let corner = draw('\u{2518}');
+        assert!(all(
+            &corner,
+            0,
+            y_start,
+            x_start + LIGHT,
+            y_start + LIGHT,
+            1.0
+        ));
+        assert!(all(
+            &corner,
+            x_start,
+            0,
+            x_start + LIGHT,
+            y_start + LIGHT,
+            1.0
+        ));
+        assert!(all(&corner, 0, y_start + LIGHT, WIDTH, HEIGHT, 0.0));
+        assert!(all(&corner, x_start + LIGHT, 0, WIDTH, HEIGHT, 0.0));
+
+        // A rounded corner reaches the same edges, but leaves the
+        // corner of the square that it cuts off empty
+        let arc = draw('\u{256d}');
+        assert!(coverage(&arc, WIDTH - 1, y_start) > 0.);
+        assert!(coverage(&arc, WIDTH - 1, y_start + LIGHT - 1) > 0.);
+        assert!(all(&arc, x_start, HEIGHT - 1, x_start + LIGHT, HEIGHT, 1.0));
+        assert!(all(&arc, 0, 0, WIDTH, y_start, 0.0));
+        assert!(all(&arc, 0, 0, x_start, HEIGHT, 0.0));
+        assert!(all(&arc, x_start, y_start, x_start + 1, y_start + 1, 0.0));
+        let square = draw('\u{250c}');
+        assert!(all(
+            &square,
+            x_start,
+            y_start,
+            x_start + 1,
+            y_start + 1,
+            1.0
+        ));
+    }
+}
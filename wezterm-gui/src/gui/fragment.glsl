@@ -3,6 +3,7 @@ precision highp float;
 in float o_has_color;
 in vec2 o_cursor;
 in vec2 o_tex;
+in vec2 o_fg_mask;
 in vec2 o_underline;
 in vec3 o_hsv;
 in vec4 o_bg_color;
@@ -74,7 +75,7 @@ void main() {
       discard;
     }
   } else if (bg_and_line_layer) {
-    if (o_has_color >= 2.0) {
+    if (o_has_color == 2.0 || o_has_color == 3.0) {
       // Don't render the background image on anything other than
       // the window_bg_layer.
       discard;
@@ -104,13 +105,19 @@ void main() {
       color = o_cursor_color;
     }
   } else {
-    if (o_has_color >= 2.0) {
+    if (o_has_color == 2.0 || o_has_color == 3.0) {
       // Don't render the background image on anything other than
       // the window_bg_layer.
       discard;
     } else {
       color = texture(atlas_nearest_sampler, o_tex);
-      if (o_has_color == 0.0) {
+      if (o_has_color == 4.0) {
+        // A color glyph with parts that use the text color; those
+        // parts are black in the glyph texture, and their coverage
+        // is tinted with the fg_color and added here.
+        float mask = texture(atlas_nearest_sampler, o_fg_mask).a;
+        color.rgb += o_fg_color.rgb * mask;
+      } else if (o_has_color == 0.0) {
         // if it's not a color emoji it will be grayscale
         // and we need to tint with the fg_color
         if (o_fg_color == o_bg_color) {
@@ -1,6 +1,7 @@
+use super::customglyph::BlockKey;
 use super::utilsprites::RenderMetrics;
 use ::window::bitmaps::atlas::{Atlas, Sprite};
-use ::window::bitmaps::{Image, Texture2d};
+use ::window::bitmaps::{Image, ImageTexture, Texture2d};
 use ::window::glium::backend::Context as GliumContext;
 use ::window::glium::texture::SrgbTexture2d;
 use ::window::*;
@@ -10,7 +11,8 @@ use euclid::num::Zero;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
-use termwiz::image::ImageData;
+use std::time::{Duration, Instant};
+use termwiz::image::{ImageData, ImageDataType};
 use wezterm_font::units::*;
 use wezterm_font::{FontConfiguration, GlyphInfo};
 use wezterm_term::Underline;
@@ -97,6 +99,9 @@ pub struct CachedGlyph<T: Texture2d> {
     pub bearing_x: PixelLength,
     pub bearing_y: PixelLength,
     pub texture: Option<Sprite<T>>,
+    /// The parts of a color glyph that are tinted with the
+    /// foreground color; the same size as `texture`
+    pub foreground: Option<Sprite<T>>,
     pub scale: f64,
 }
 
@@ -110,10 +115,28 @@ impl<T: Texture2d> std::fmt::Debug for CachedGlyph<T> {
             .field("bearing_y", &self.bearing_y)
             .field("scale", &self.scale)
             .field("texture", &self.texture)
+            .field("foreground", &self.foreground)
             .finish()
     }
 }
 
+/// Tracks the texture for an image and, for animated images,
+/// which of its frames is currently held in that texture
+struct DecodedImage<T: Texture2d> {
+    sprite: Sprite<T>,
+    frame_idx: usize,
+    frame_start: Instant,
+}
+
+/// Converts a frame of RGBA pixels into the BGRA layout of an Image
+fn rgba_frame_to_image(width: u32, height: u32, frame: &[u8]) -> Image {
+    let mut data = frame.to_vec();
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    Image::from_raw(width as usize, height as usize, data)
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 struct LineKey {
     strike_through: bool,
@@ -125,9 +148,13 @@ pub struct GlyphCache<T: Texture2d> {
     glyph_cache: HashMap<GlyphKey, Rc<CachedGlyph<T>>>,
     pub atlas: Atlas<T>,
     fonts: Rc<FontConfiguration>,
-    image_cache: HashMap<usize, Sprite<T>>,
+    image_cache: HashMap<usize, DecodedImage<T>>,
+    /// The earliest time at which one of the animated images that
+    /// we have rendered is due to advance to its next frame
+    pub next_frame_due: Option<Instant>,
     line_glyphs: HashMap<LineKey, Sprite<T>>,
-    metrics: RenderMetrics,
+    pub(crate) block_glyphs: HashMap<BlockKey, Sprite<T>>,
+    pub(crate) metrics: RenderMetrics,
 }
 
 impl GlyphCache<SrgbTexture2d> {
@@ -150,9 +177,11 @@ impl GlyphCache<SrgbTexture2d> {
             fonts: Rc::clone(fonts),
             glyph_cache: HashMap::new(),
             image_cache: HashMap::new(),
+            next_frame_due: None,
             atlas,
             metrics: metrics.clone(),
             line_glyphs: HashMap::new(),
+            block_glyphs: HashMap::new(),
         })
     }
 
@@ -161,6 +190,31 @@ impl GlyphCache<SrgbTexture2d> {
         self.image_cache.clear();
         self.glyph_cache.clear();
         self.line_glyphs.clear();
+        self.block_glyphs.clear();
+    }
+}
+
+impl GlyphCache<ImageTexture> {
+    /// Create a glyph cache whose atlas is held in memory rather
+    /// than on the GPU, for use by the software renderer
+    pub fn new_in_memory(
+        fonts: &Rc<FontConfiguration>,
+        size: usize,
+        metrics: &RenderMetrics,
+    ) -> anyhow::Result<Self> {
+        let surface = Rc::new(ImageTexture::new(size, size));
+        let atlas = Atlas::new(&surface).expect("failed to create new texture atlas");
+
+        Ok(Self {
+            fonts: Rc::clone(fonts),
+            glyph_cache: HashMap::new(),
+            image_cache: HashMap::new(),
+            next_frame_due: None,
+            atlas,
+            metrics: metrics.clone(),
+            line_glyphs: HashMap::new(),
+            block_glyphs: HashMap::new(),
+        })
     }
 }
 
@@ -244,6 +298,7 @@ impl<T: Texture2d> GlyphCache<T> {
             CachedGlyph {
                 has_color: glyph.has_color,
                 texture: None,
+                foreground: None,
                 x_offset: info.x_offset * scale,
                 y_offset: info.y_offset * scale,
                 bearing_x: PixelLength::zero(),
@@ -257,13 +312,21 @@ impl<T: Texture2d> GlyphCache<T> {
                 4 * glyph.width as usize,
                 &glyph.data,
             );
+            let raw_fg = glyph.foreground.as_ref().map(|data| {
+                Image::with_rgba32(
+                    glyph.width as usize,
+                    glyph.height as usize,
+                    4 * glyph.width as usize,
+                    data,
+                )
+            });
 
             let bearing_x = glyph.bearing_x * scale;
             let bearing_y = glyph.bearing_y * scale;
             let x_offset = info.x_offset * scale;
             let y_offset = info.y_offset * scale;
 
-            let (scale, raw_im) = if scale != 1.0 {
+            let (scale, raw_im, raw_fg) = if scale != 1.0 {
                 log::trace!(
                     "physically scaling {:?} by {} bcos {}x{} > {:?}x{:?}",
                     info,
@@ -273,16 +336,25 @@ impl<T: Texture2d> GlyphCache<T> {
                     cell_width,
                     cell_height
                 );
-                (1.0, raw_im.scale_by(scale))
+                (
+                    1.0,
+                    raw_im.scale_by(scale),
+                    raw_fg.map(|im| im.scale_by(scale)),
+                )
             } else {
-                (scale, raw_im)
+                (scale, raw_im, raw_fg)
             };
 
             let tex = self.atlas.allocate(&raw_im)?;
+            let foreground = match raw_fg {
+                Some(im) => Some(self.atlas.allocate(&im)?),
+                None => None,
+            };
 
             let g = CachedGlyph {
                 has_color: glyph.has_color,
                 texture: Some(tex),
+                foreground,
                 x_offset,
                 y_offset,
                 bearing_x,
@@ -307,21 +379,83 @@ impl<T: Texture2d> GlyphCache<T> {
         image_data: &Arc<ImageData>,
         padding: Option<usize>,
     ) -> anyhow::Result<Sprite<T>> {
-        if let Some(sprite) = self.image_cache.get(&image_data.id()) {
-            return Ok(sprite.clone());
+        if let Some(decoded) = self.image_cache.get_mut(&image_data.id()) {
+            if let ImageDataType::AnimRgba8 {
+                width,
+                height,
+                durations,
+                frames,
+                ..
+            } = image_data.data()
+            {
+                let fps = configuration().animation_fps;
+                if fps > 0 && frames.len() > 1 {
+                    let now = Instant::now();
+                    let total: Duration = durations.iter().sum();
+                    if now.duration_since(decoded.frame_start) > total {
+                        // We haven't been rendered in a while; rather than
+                        // fast forwarding through the frames that we missed,
+                        // pick up from the current frame.
+                        decoded.frame_start = now;
+                    }
+
+                    let mut next_due = decoded.frame_start + durations[decoded.frame_idx];
+                    if now >= next_due {
+                        // Skip any frames that we were too slow to show,
+                        // so that the animation runs at its intended speed
+                        while now >= next_due {
+                            decoded.frame_idx = (decoded.frame_idx + 1) % frames.len();
+                            decoded.frame_start = next_due;
+                            next_due = decoded.frame_start + durations[decoded.frame_idx];
+                        }
+                        let image =
+                            rgba_frame_to_image(*width, *height, &frames[decoded.frame_idx]);
+                        decoded.sprite.texture.write(decoded.sprite.coords, &image);
+                    }
+
+                    let next_due = next_due.max(now + Duration::from_secs_f64(1.0 / fps as f64));
+                    self.next_frame_due = Some(match self.next_frame_due {
+                        Some(due) => due.min(next_due),
+                        None => next_due,
+                    });
+                }
+            }
+            return Ok(decoded.sprite.clone());
         }
 
-        let decoded_image = image::load_from_memory(image_data.data())?.to_bgra8();
-        let (width, height) = decoded_image.dimensions();
-        let image = ::window::bitmaps::Image::from_raw(
-            width as usize,
-            height as usize,
-            decoded_image.to_vec(),
-        );
+        let image = match image_data.data() {
+            ImageDataType::EncodedFile(data) => {
+                let decoded_image = image::load_from_memory(data)?.to_bgra8();
+                let (width, height) = decoded_image.dimensions();
+                ::window::bitmaps::Image::from_raw(
+                    width as usize,
+                    height as usize,
+                    decoded_image.to_vec(),
+                )
+            }
+            ImageDataType::AnimRgba8 {
+                width,
+                height,
+                frames,
+                ..
+            } => rgba_frame_to_image(*width, *height, &frames[0]),
+        };
 
         let sprite = self.atlas.allocate_with_padding(&image, padding)?;
 
-        self.image_cache.insert(image_data.id(), sprite.clone());
+        self.image_cache.insert(
+            image_data.id(),
+            DecodedImage {
+                sprite: sprite.clone(),
+                frame_idx: 0,
+                frame_start: Instant::now(),
+            },
+        );
+
+        if image_data.is_animated() {
+            // Make sure that we get scheduled to show the next frame
+            return self.cached_image(image_data, padding);
+        }
 
         Ok(sprite)
     }
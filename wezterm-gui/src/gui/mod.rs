@@ -5,13 +5,16 @@ use mux::{Mux, MuxNotification};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+mod customglyph;
 mod glyphcache;
 mod overlay;
 mod quad;
 mod renderstate;
+mod screenline;
 mod scrollbar;
 mod selection;
 mod shapecache;
+pub mod softrender;
 mod tabbar;
 mod termwindow;
 mod utilsprites;
@@ -62,7 +65,7 @@ impl GuiFrontEnd {
                             mux.kill_window(mux_window_id);
                         }
                     }
-                    MuxNotification::PaneOutput(_) => {}
+                    _ => {}
                 }
                 true
             } else {
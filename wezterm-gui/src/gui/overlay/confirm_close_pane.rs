@@ -1,3 +1,5 @@
+use crate::gui::termwindow::TermWindow;
+use ::window::Window;
 use mux::pane::PaneId;
 use mux::tab::TabId;
 use mux::termwiztermtab::TermWizTerminal;
@@ -8,6 +10,8 @@ use termwiz::color::ColorAttribute;
 use termwiz::input::{InputEvent, KeyCode, KeyEvent, MouseButtons, MouseEvent};
 use termwiz::surface::{Change, CursorVisibility, Position};
 use termwiz::terminal::Terminal;
+use wezterm_client::pane::ClientPane;
+use wezterm_term::ClipboardSelection;
 
 fn run_confirmation_app(message: &str, term: &mut TermWizTerminal) -> anyhow::Result<bool> {
     term.set_raw_mode()?;
@@ -217,6 +221,58 @@ pub fn confirm_close_window(
     Ok(())
 }
 
+pub fn confirm_clipboard_read(
+    pane_id: PaneId,
+    mut term: TermWizTerminal,
+    window: Window,
+    selection: ClipboardSelection,
+) -> anyhow::Result<()> {
+    if run_confirmation_app(
+        "🔐 Allow the program running in this pane to read the clipboard?",
+        &mut term,
+    )? {
+        promise::spawn::spawn_into_main_thread(async move {
+            TermWindow::send_clipboard_to_pane(&window, pane_id, selection);
+        })
+        .detach();
+    }
+
+    Ok(())
+}
+
+pub fn confirm_replay_input(
+    pane_id: PaneId,
+    mut term: TermWizTerminal,
+    num_inputs: usize,
+) -> anyhow::Result<()> {
+    let replay = run_confirmation_app(
+        &format!(
+            "🔌 Reconnected!  Send the {} inputs typed while disconnected?",
+            num_inputs
+        ),
+        &mut term,
+    )?;
+    promise::spawn::spawn_into_main_thread(async move {
+        let mux = Mux::get().unwrap();
+        let pane = match mux.get_pane(pane_id) {
+            Some(pane) => pane,
+            None => return,
+        };
+        if let Some(client_pane) = pane.downcast_ref::<ClientPane>() {
+            if replay {
+                if let Err(err) = client_pane.replay_buffered_input() {
+                    log::error!("replaying input to pane {}: {:#}", pane_id, err);
+                }
+            } else {
+                client_pane.discard_buffered_input();
+            }
+        }
+    })
+    .detach();
+
+    Ok(())
+}
+
 pub fn confirm_quit_program(mut term: TermWizTerminal) -> anyhow::Result<()> {
     if run_confirmation_app("🛑 Really Quit WezTerm?", &mut term)? {
         promise::spawn::spawn_into_main_thread(async move {
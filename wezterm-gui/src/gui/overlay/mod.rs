@@ -12,10 +12,12 @@ mod launcher;
 mod search;
 mod tabnavigator;
 
+pub use confirm_close_pane::confirm_clipboard_read;
 pub use confirm_close_pane::confirm_close_pane;
 pub use confirm_close_pane::confirm_close_tab;
 pub use confirm_close_pane::confirm_close_window;
 pub use confirm_close_pane::confirm_quit_program;
+pub use confirm_close_pane::confirm_replay_input;
 pub use copy::CopyOverlay;
 pub use launcher::launcher;
 pub use search::SearchOverlay;
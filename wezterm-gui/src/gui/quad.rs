@@ -23,6 +23,8 @@ pub struct Vertex {
     pub adjust: (f32, f32),
     // glyph texture
     pub tex: (f32, f32),
+    // texture for the parts of a color glyph that use the text color
+    pub fg_mask: (f32, f32),
     // underline texture
     pub underline: (f32, f32),
     // cursor texture
@@ -44,6 +46,8 @@ pub struct Vertex {
     //        background image of the window
     // 3.0 -> like 2.0, except that instead of an
     //        image, we use the solid bg color
+    // 4.0 -> like 1.0, except that fg_mask is tinted
+    //        with the fg color and added to the glyph
     pub has_color: f32,
 }
 ::window::glium::implement_vertex!(
@@ -51,6 +55,7 @@ pub struct Vertex {
     position,
     adjust,
     tex,
+    fg_mask,
     underline,
     cursor,
     cursor_color,
@@ -74,8 +79,34 @@ pub struct Quads {
     pub background_image: usize,
 }
 
+/// The vertices are either mapped from a vertex buffer on the GPU,
+/// or held in memory for the software renderer
+enum VertexStorage<'a> {
+    Mapped(glium::buffer::Mapping<'a, [Vertex]>),
+    Slice(&'a mut [Vertex]),
+}
+
+impl<'a> std::ops::Deref for VertexStorage<'a> {
+    type Target = [Vertex];
+    fn deref(&self) -> &[Vertex] {
+        match self {
+            Self::Mapped(mapping) => &*mapping,
+            Self::Slice(slice) => &*slice,
+        }
+    }
+}
+
+impl<'a> std::ops::DerefMut for VertexStorage<'a> {
+    fn deref_mut(&mut self) -> &mut [Vertex] {
+        match self {
+            Self::Mapped(mapping) => &mut *mapping,
+            Self::Slice(slice) => &mut *slice,
+        }
+    }
+}
+
 pub struct MappedQuads<'a> {
-    mapping: glium::buffer::Mapping<'a, [Vertex]>,
+    mapping: VertexStorage<'a>,
     quads: Quads,
 }
 
@@ -116,7 +147,15 @@ impl Quads {
     pub fn map<'a>(&self, vb: &'a mut RefMut<VertexBuffer<Vertex>>) -> MappedQuads<'a> {
         let mapping = vb.slice_mut(..).expect("to map vertex buffer").map();
         MappedQuads {
-            mapping,
+            mapping: VertexStorage::Mapped(mapping),
+            quads: self.clone(),
+        }
+    }
+
+    /// Like `map`, but for vertices that are held in memory
+    pub fn map_slice<'a>(&self, vertices: &'a mut [Vertex]) -> MappedQuads<'a> {
+        MappedQuads {
+            mapping: VertexStorage::Slice(vertices),
             quads: self.clone(),
         }
     }
@@ -152,6 +191,19 @@ impl<'a> Quad<'a> {
         }
     }
 
+    /// Mark this quad as a color glyph with parts that use the text
+    /// color, and assign the texture coordinates of those parts.
+    /// Mutually exclusive with set_has_color.
+    pub fn set_fg_mask(&mut self, coords: TextureRect) {
+        self.vert[V_TOP_LEFT].fg_mask = (coords.min_x(), coords.min_y());
+        self.vert[V_TOP_RIGHT].fg_mask = (coords.max_x(), coords.min_y());
+        self.vert[V_BOT_LEFT].fg_mask = (coords.min_x(), coords.max_y());
+        self.vert[V_BOT_RIGHT].fg_mask = (coords.max_x(), coords.max_y());
+        for v in self.vert.iter_mut() {
+            v.has_color = 4.0;
+        }
+    }
+
     /// Mark this quad as a background image.
     /// Mutually exclusive with set_has_color.
     pub fn set_is_background_image(&mut self) {
@@ -130,6 +130,27 @@ impl RenderState {
         width: f32,
         height: f32,
     ) -> anyhow::Result<(VertexBuffer<Vertex>, IndexBuffer<u32>, Quads)> {
+        let (verts, indices, quads) = Self::compute_quads(metrics, width, height);
+
+        Ok((
+            VertexBuffer::dynamic(context, &verts)?,
+            IndexBuffer::new(
+                context,
+                glium::index::PrimitiveType::TrianglesList,
+                &indices,
+            )?,
+            quads,
+        ))
+    }
+
+    /// Compute the vertices, and the indices of the triangles that they
+    /// form, for the quads that comprise the visible portion of the screen.
+    /// This is shared by the OpenGL and the software renderers.
+    pub fn compute_quads(
+        metrics: &RenderMetrics,
+        width: f32,
+        height: f32,
+    ) -> (Vec<Vertex>, Vec<u32>, Quads) {
         let cell_width = metrics.cell_size.width as f32;
         let cell_height = metrics.cell_size.height as f32;
         let mut verts = Vec::new();
@@ -219,15 +240,7 @@ impl RenderState {
         // And a quad for the scrollbar thumb
         quads.scroll_thumb = define_quad(0.0, 0.0, 0.0, 0.0) as usize;
 
-        Ok((
-            VertexBuffer::dynamic(context, &verts)?,
-            IndexBuffer::new(
-                context,
-                glium::index::PrimitiveType::TrianglesList,
-                &indices,
-            )?,
-            quads,
-        ))
+        (verts, indices, quads)
     }
 
     pub fn clear_texture_atlas(&mut self, metrics: &RenderMetrics) -> anyhow::Result<()> {
@@ -0,0 +1,696 @@
+//! Renders the lines of a terminal into quads.
+//! This is shared by the OpenGL renderer in `termwindow` and by the
+//! software renderer in `softrender`, so that both produce the same
+//! output for a given line.
+use super::customglyph::BlockKey;
+use super::glyphcache::GlyphCache;
+use super::quad::*;
+use super::shapecache::*;
+use super::termwindow::{
+    rgbcolor_alpha_to_window_color, rgbcolor_to_window_color, scale_glyph_for_line_size,
+};
+use super::utilsprites::{RenderMetrics, UtilSprites};
+use ::window::bitmaps::atlas::SpriteSlice;
+use ::window::bitmaps::{Texture2d, TextureCoord, TextureRect, TextureSize};
+use ::window::*;
+use config::ConfigHandle;
+use mux::renderable::StableCursorPosition;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+use termwiz::cellcluster::CellCluster;
+use termwiz::color::{ColorAttribute, RgbColor};
+use termwiz::hyperlink::Hyperlink;
+use termwiz::surface::{CursorShape, CursorVisibility};
+use wezterm_font::units::*;
+use wezterm_font::FontConfiguration;
+use wezterm_term::color::ColorPalette;
+use wezterm_term::{CellAttributes, Line, LineSize, StableRowIndex};
+
+pub struct RenderScreenLineParams<'a> {
+    /// The row of quads to render into
+    pub line_idx: usize,
+    /// The offset of the pane within the grid of quads
+    pub left: usize,
+    pub top: usize,
+    pub num_cols: usize,
+    pub stable_line_idx: Option<StableRowIndex>,
+    pub line: &'a Line,
+    pub selection: Range<usize>,
+    pub cursor: &'a StableCursorPosition,
+    pub palette: &'a ColorPalette,
+    pub config: &'a ConfigHandle,
+
+    pub cursor_border_color: Color,
+    pub foreground: Color,
+    pub is_active: bool,
+}
+
+pub struct ComputeCellFgBgParams<'a> {
+    pub stable_line_idx: Option<StableRowIndex>,
+    pub cell_idx: usize,
+    pub cursor: &'a StableCursorPosition,
+    pub selection: &'a Range<usize>,
+    pub fg_color: Color,
+    pub bg_color: Color,
+    pub palette: &'a ColorPalette,
+    pub is_active_pane: bool,
+    pub config: &'a ConfigHandle,
+}
+
+pub struct ComputeCellFgBgResult {
+    pub fg_color: Color,
+    pub bg_color: Color,
+    pub cursor_shape: Option<CursorShape>,
+}
+
+/// Holds the window state that is needed to render a line
+pub struct LineRenderer<'a, T: Texture2d> {
+    pub fonts: &'a FontConfiguration,
+    pub render_metrics: &'a RenderMetrics,
+    pub glyph_cache: &'a RefCell<GlyphCache<T>>,
+    pub util_sprites: &'a UtilSprites<T>,
+    pub shape_cache: &'a RefCell<ShapeCache>,
+    pub bidi_cache: &'a RefCell<BidiCache>,
+    pub window_is_transparent: bool,
+    /// The hyperlink under the mouse, if any
+    pub highlight: Option<&'a Arc<Hyperlink>>,
+    pub focused: bool,
+    /// Used to compute the phase of a blinking cursor
+    pub last_cursor_movement: Instant,
+}
+
+impl<'a, T: Texture2d> LineRenderer<'a, T> {
+    /// "Render" a line of the terminal screen into the vertex buffer.
+    /// This is nominally a matter of setting the fg/bg color and the
+    /// texture coordinates for a given glyph.  There's a little bit
+    /// of extra complexity to deal with multi-cell glyphs.
+    pub fn render_screen_line(
+        &self,
+        params: RenderScreenLineParams,
+        quads: &mut MappedQuads,
+    ) -> anyhow::Result<()> {
+        let num_cols = params.num_cols;
+
+        let hsv = if params.is_active {
+            None
+        } else {
+            Some(params.config.inactive_pane_hsb)
+        };
+
+        let window_is_transparent = self.window_is_transparent;
+
+        let white_space = self.util_sprites.white_space.texture_coords();
+
+        // Lines set via DECDWL/DECDHL render each cell across two quads
+        let line_size = params.line.line_size();
+        let col_scale = if line_size.is_double_width() { 2 } else { 1 };
+
+        // Pre-set the row with the whitespace glyph.
+        // This is here primarily because clustering/shaping can cause the line updates
+        // to skip setting a quad that is logically obscured by a double-wide glyph.
+        // If eg: scrolling the viewport causes the pair of quads to change from two
+        // individual cells to a single double-wide cell then we might leave the second
+        // one of the pair with the glyph from the prior viewport position.
+        for cell_idx in 0..num_cols {
+            let mut quad = match quads.cell(cell_idx + params.left, params.line_idx + params.top) {
+                Ok(quad) => quad,
+                Err(_) => break,
+            };
+
+            quad.set_texture(white_space);
+            quad.set_texture_adjust(0., 0., 0., 0.);
+            quad.set_underline(white_space);
+            quad.set_cursor(white_space);
+        }
+
+        // Break the line into clusters of cells with the same attributes.
+        // When bidi is enabled for the line, the clusters are in visual
+        // order and we need to map between the logical cell positions
+        // (used for the cursor and selection) and the visual position
+        // of the quads that we render into.
+        let bidi = cached_bidi(self.bidi_cache, params.line);
+
+        // Clusters are shaped separately either side of the cursor and
+        // the edges of the selection when ligatures are disabled there,
+        // so that the rest of the line keeps its shape as they move
+        let mut boundaries = vec![];
+        if params.config.disable_ligatures_under_cursor
+            && params.stable_line_idx == Some(params.cursor.y)
+        {
+            boundaries.push(params.cursor.x);
+            boundaries.push(params.cursor.x + 1);
+        }
+        if params.config.disable_ligatures_in_selection && !params.selection.is_empty() {
+            boundaries.push(params.selection.start);
+            boundaries.push(params.selection.end);
+        }
+        let split_clusters: Vec<CellCluster>;
+        let clusters = if boundaries.is_empty() {
+            &bidi.clusters
+        } else {
+            split_clusters = bidi
+                .clusters
+                .iter()
+                .flat_map(|cluster| cluster.clone().split_at_cells(&boundaries))
+                .collect();
+            &split_clusters
+        };
+
+        // Tracks which quads have been assigned a glyph
+        let mut covered = vec![false; num_cols];
+        for cluster in clusters {
+            let attrs = &cluster.attrs;
+            let is_highlited_hyperlink = match (attrs.hyperlink(), self.highlight) {
+                (Some(ref this), Some(highlight)) => Arc::ptr_eq(this, highlight),
+                _ => false,
+            };
+            let style = self.fonts.match_style(params.config, attrs);
+
+            let bg_is_default = attrs.background == ColorAttribute::Default;
+            let bg_color = params.palette.resolve_bg(attrs.background);
+
+            fn resolve_fg_color_attr(
+                attrs: &CellAttributes,
+                fg: &ColorAttribute,
+                params: &RenderScreenLineParams,
+                style: &config::TextStyle,
+            ) -> RgbColor {
+                match fg {
+                    wezterm_term::color::ColorAttribute::Default => {
+                        if let Some(fg) = style.foreground {
+                            fg
+                        } else {
+                            params.palette.resolve_fg(attrs.foreground)
+                        }
+                    }
+                    wezterm_term::color::ColorAttribute::PaletteIndex(idx)
+                        if *idx < 8 && params.config.bold_brightens_ansi_colors =>
+                    {
+                        // For compatibility purposes, switch to a brighter version
+                        // of one of the standard ANSI colors when Bold is enabled.
+                        // This lifts black to dark grey.
+                        let idx = if attrs.intensity() == wezterm_term::Intensity::Bold {
+                            *idx + 8
+                        } else {
+                            *idx
+                        };
+                        params
+                            .palette
+                            .resolve_fg(wezterm_term::color::ColorAttribute::PaletteIndex(idx))
+                    }
+                    _ => params.palette.resolve_fg(*fg),
+                }
+            }
+            let fg_color = resolve_fg_color_attr(&attrs, &attrs.foreground, &params, &style);
+
+            let (fg_color, bg_color, bg_is_default) = {
+                let mut fg = fg_color;
+                let mut bg = bg_color;
+                let mut bg_default = bg_is_default;
+
+                if attrs.reverse() {
+                    std::mem::swap(&mut fg, &mut bg);
+                    bg_default = false;
+                }
+
+                (fg, bg, bg_default)
+            };
+
+            let glyph_color = rgbcolor_to_window_color(fg_color);
+            let underline_color = match attrs.underline_color() {
+                ColorAttribute::Default => fg_color,
+                c => resolve_fg_color_attr(&attrs, &c, &params, &style),
+            };
+            let underline_color = rgbcolor_to_window_color(underline_color);
+
+            let bg_color = rgbcolor_alpha_to_window_color(
+                bg_color,
+                if window_is_transparent && bg_is_default {
+                    0x00
+                } else {
+                    (params.config.text_background_opacity * 255.0) as u8
+                },
+            );
+
+            // Shape the printable text from this cluster
+            let ligatures = !(params.config.disable_ligatures_in_selection
+                && params.selection.contains(&cluster.cell_range().start));
+            let glyph_info = cached_shape(
+                self.shape_cache,
+                self.fonts,
+                BorrowedShapeCacheKey {
+                    style,
+                    text: &cluster.text,
+                    direction: cluster.direction,
+                    ligatures,
+                },
+            )?;
+
+            for info in glyph_info.iter() {
+                let cell_idx = cluster.byte_to_cell_idx[info.cluster as usize];
+                // The leftmost visual position occupied by this glyph;
+                // for right to left text that is the position of its
+                // last logical cell.
+                let visual_idx = (0..info.num_cells.max(1) as usize)
+                    .map(|i| bidi.logical_to_visual(cell_idx + i))
+                    .min()
+                    .unwrap_or(cell_idx);
+
+                if covered
+                    .get(visual_idx * col_scale)
+                    .copied()
+                    .unwrap_or(false)
+                {
+                    // This is a tricky case: if we have a cluster such as
+                    // 1F470 1F3FF 200D 2640 (woman with veil: dark skin tone)
+                    // and the font doesn't define a glyph for it, the shaper
+                    // may give us a sequence of three output clusters, each
+                    // comprising: veil, skin tone and female respectively.
+                    // Those all have the same info.cluster which
+                    // means that they all resolve to the same cell_idx.
+                    // In this case, the cluster is logically a single cell,
+                    // and the best presentation is of the veil, so we pick
+                    // that one and ignore the rest of the glyphs that map to
+                    // this same cell.
+                    // Ideally we'd overlay this with a "something is broken"
+                    // glyph in the corner.
+                    continue;
+                }
+
+                let followed_by_space = match params
+                    .line
+                    .cells()
+                    .get(bidi.visual_to_logical(visual_idx + 1))
+                {
+                    Some(cell) => cell.str() == " ",
+                    None => false,
+                };
+
+                let glyph =
+                    self.glyph_cache
+                        .borrow_mut()
+                        .cached_glyph(info, style, followed_by_space)?;
+
+                // Box drawing and similar glyphs are drawn by us to
+                // fill the cell, rather than taken from the font
+                let block_sprite = if params.config.custom_block_glyphs && info.num_cells == 1 {
+                    match params
+                        .line
+                        .cells()
+                        .get(cell_idx)
+                        .and_then(|cell| BlockKey::from_text(cell.str()))
+                    {
+                        Some(key) => Some(self.glyph_cache.borrow_mut().cached_block(key)?),
+                        None => None,
+                    }
+                } else {
+                    None
+                };
+
+                let left = (glyph.x_offset + glyph.bearing_x).get() as f32;
+                let top = ((PixelLength::new(self.render_metrics.cell_size.height as f64)
+                    + self.render_metrics.descender)
+                    - (glyph.y_offset + glyph.bearing_y))
+                    .get() as f32;
+
+                // underline and strikethrough
+                let underline_tex_rect = self
+                    .glyph_cache
+                    .borrow_mut()
+                    .cached_line_sprite(
+                        is_highlited_hyperlink,
+                        attrs.strikethrough(),
+                        attrs.underline(),
+                        attrs.overline(),
+                    )?
+                    .texture_coords();
+
+                // Iterate each cell that comprises this glyph.  There is usually
+                // a single cell per glyph but combining characters, ligatures
+                // and emoji can be 2 or more cells wide.
+                for glyph_idx in 0..info.num_cells as usize {
+                    let visual_idx = visual_idx + glyph_idx;
+                    let cell_idx = bidi.visual_to_logical(visual_idx);
+
+                    if visual_idx * col_scale >= num_cols {
+                        // terminal line data is wider than the window.
+                        // This happens for example while live resizing the window
+                        // smaller than the terminal.
+                        break;
+                    }
+
+                    for half in 0..col_scale {
+                        if let Some(c) = covered.get_mut(visual_idx * col_scale + half) {
+                            *c = true;
+                        }
+                    }
+
+                    let ComputeCellFgBgResult {
+                        fg_color: glyph_color,
+                        bg_color,
+                        cursor_shape,
+                    } = self.compute_cell_fg_bg(ComputeCellFgBgParams {
+                        stable_line_idx: params.stable_line_idx,
+                        cell_idx,
+                        cursor: params.cursor,
+                        selection: &params.selection,
+                        fg_color: glyph_color,
+                        bg_color,
+                        palette: params.palette,
+                        is_active_pane: params.is_active,
+                        config: params.config,
+                    });
+
+                    if let Some(image) = attrs.image() {
+                        // Render iTerm2 style image attributes
+
+                        let padding = self
+                            .render_metrics
+                            .cell_size
+                            .height
+                            .max(self.render_metrics.cell_size.width)
+                            as usize;
+                        let padding = if padding.is_power_of_two() {
+                            padding
+                        } else {
+                            padding.next_power_of_two()
+                        };
+
+                        let sprite = self
+                            .glyph_cache
+                            .borrow_mut()
+                            .cached_image(image.image_data(), Some(padding))?;
+                        let width = sprite.coords.size.width;
+                        let height = sprite.coords.size.height;
+
+                        let top_left = image.top_left();
+                        let bottom_right = image.bottom_right();
+
+                        // We *could* call sprite.texture.to_texture_coords() here,
+                        // but since that takes integer pixel coordinates, we'd
+                        // lose precision and end up with visual artifacts.
+                        // Instead, we compute the texture coords here in floating point.
+
+                        let texture_width = sprite.texture.width() as f32;
+                        let texture_height = sprite.texture.height() as f32;
+                        let origin = TextureCoord::new(
+                            (sprite.coords.origin.x as f32 + (*top_left.x * width as f32))
+                                / texture_width,
+                            (sprite.coords.origin.y as f32 + (*top_left.y * height as f32))
+                                / texture_height,
+                        );
+
+                        let size = TextureSize::new(
+                            (*bottom_right.x - *top_left.x) * width as f32 / texture_width,
+                            (*bottom_right.y - *top_left.y) * height as f32 / texture_height,
+                        );
+
+                        let texture_rect = TextureRect::new(origin, size);
+
+                        for half in 0..col_scale {
+                            let mut quad = match quads.cell(
+                                visual_idx * col_scale + half + params.left,
+                                params.line_idx + params.top,
+                            ) {
+                                Ok(quad) => quad,
+                                Err(_) => break,
+                            };
+
+                            // On a double width or double height line, each
+                            // quad shows its portion of the scaled image
+                            let (texture_rect, left, top, right, bottom) = if col_scale == 1 {
+                                (texture_rect, 0., 0., 0., 0.)
+                            } else {
+                                match scale_glyph_for_line_size(
+                                    line_size,
+                                    half,
+                                    texture_rect,
+                                    (0., 0., 0., 0.),
+                                    self.render_metrics.cell_size.width as f32,
+                                    self.render_metrics.cell_size.height as f32,
+                                ) {
+                                    Some(scaled) => scaled,
+                                    None => (white_space, 0., 0., 0., 0.),
+                                }
+                            };
+
+                            quad.set_hsv(hsv);
+                            quad.set_fg_color(glyph_color);
+                            quad.set_underline_color(underline_color);
+                            quad.set_bg_color(bg_color);
+                            quad.set_texture(texture_rect);
+                            quad.set_texture_adjust(left, top, right, bottom);
+                            quad.set_underline(white_space);
+                            quad.set_has_color(true);
+                            quad.set_cursor(
+                                self.util_sprites
+                                    .cursor_sprite(cursor_shape)
+                                    .texture_coords(),
+                            );
+                            quad.set_cursor_color(params.cursor_border_color);
+                        }
+
+                        continue;
+                    }
+
+                    let (texture_rect, fg_mask, left, top, right, bottom, has_color) =
+                        match &block_sprite {
+                            Some(sprite) => (sprite.texture_coords(), None, 0., 0., 0., 0., false),
+                            None => {
+                                let texture = glyph
+                                    .texture
+                                    .as_ref()
+                                    .unwrap_or(&self.util_sprites.white_space);
+
+                                let slice = SpriteSlice {
+                                    cell_idx: glyph_idx,
+                                    num_cells: info.num_cells as usize,
+                                    cell_width: self.render_metrics.cell_size.width as usize,
+                                    scale: glyph.scale as f32,
+                                    left_offset: left,
+                                };
+
+                                let pixel_rect = slice.pixel_rect(texture);
+                                let texture_rect = texture.texture.to_texture_coords(pixel_rect);
+                                let fg_mask = glyph.foreground.as_ref().map(|sprite| {
+                                    sprite.texture.to_texture_coords(slice.pixel_rect(sprite))
+                                });
+
+                                let left = if glyph_idx == 0 { left } else { 0.0 };
+                                let bottom = (pixel_rect.size.height as f32 * glyph.scale as f32)
+                                    + top
+                                    - self.render_metrics.cell_size.height as f32;
+                                let right = pixel_rect.size.width as f32 + left
+                                    - self.render_metrics.cell_size.width as f32;
+                                (
+                                    texture_rect,
+                                    fg_mask,
+                                    left,
+                                    top,
+                                    right,
+                                    bottom,
+                                    glyph.has_color,
+                                )
+                            }
+                        };
+
+                    for half in 0..col_scale {
+                        let mut quad = match quads.cell(
+                            visual_idx * col_scale + half + params.left,
+                            params.line_idx + params.top,
+                        ) {
+                            Ok(quad) => quad,
+                            Err(_) => break,
+                        };
+
+                        let (texture_rect, fg_mask, left, top, right, bottom) = if col_scale == 1 {
+                            (texture_rect, fg_mask, left, top, right, bottom)
+                        } else {
+                            let scale = |texture_rect| {
+                                scale_glyph_for_line_size(
+                                    line_size,
+                                    half,
+                                    texture_rect,
+                                    (left, top, right, bottom),
+                                    self.render_metrics.cell_size.width as f32,
+                                    self.render_metrics.cell_size.height as f32,
+                                )
+                            };
+                            match scale(texture_rect) {
+                                Some((texture_rect, left, top, right, bottom)) => (
+                                    texture_rect,
+                                    fg_mask.and_then(scale).map(|scaled| scaled.0),
+                                    left,
+                                    top,
+                                    right,
+                                    bottom,
+                                ),
+                                None => (white_space, None, 0., 0., 0., 0.),
+                            }
+                        };
+
+                        quad.set_fg_color(glyph_color);
+                        quad.set_bg_color(bg_color);
+                        quad.set_texture(texture_rect);
+                        quad.set_texture_adjust(left, top, right, bottom);
+                        quad.set_underline(if line_size == LineSize::DoubleHeightTop {
+                            white_space
+                        } else {
+                            underline_tex_rect
+                        });
+                        quad.set_underline_color(underline_color);
+                        quad.set_hsv(hsv);
+                        match fg_mask {
+                            Some(fg_mask) => quad.set_fg_mask(fg_mask),
+                            None => quad.set_has_color(has_color),
+                        }
+                        quad.set_cursor(
+                            self.util_sprites
+                                .cursor_sprite(cursor_shape)
+                                .texture_coords(),
+                        );
+                        quad.set_cursor_color(params.cursor_border_color);
+                    }
+                }
+            }
+        }
+
+        // Clear any remaining cells that were not covered by the clusters
+        // we found above, otherwise we leave artifacts behind.  The easiest
+        // reproduction for the artifacts is to maximize the window and
+        // open a vim split horizontally.  Backgrounding vim would leave
+        // the right pane with its prior contents instead of showing the
+        // cleared lines from the shell in the main screen.
+
+        for quad_idx in (0..num_cols).filter(|&idx| !covered[idx]) {
+            // Even though we don't have a cell for these, they still
+            // hold the cursor or the selection so we need to compute
+            // the colors in the usual way.
+            let cell_idx = bidi.visual_to_logical(quad_idx / col_scale);
+
+            let ComputeCellFgBgResult {
+                fg_color: glyph_color,
+                bg_color,
+                cursor_shape,
+            } = self.compute_cell_fg_bg(ComputeCellFgBgParams {
+                stable_line_idx: params.stable_line_idx,
+                cell_idx,
+                cursor: params.cursor,
+                selection: &params.selection,
+                fg_color: params.foreground,
+                bg_color: rgbcolor_alpha_to_window_color(
+                    params.palette.resolve_bg(ColorAttribute::Default),
+                    if window_is_transparent {
+                        0x00
+                    } else {
+                        (params.config.text_background_opacity * 255.0) as u8
+                    },
+                ),
+                palette: params.palette,
+                is_active_pane: params.is_active,
+                config: params.config,
+            });
+
+            let mut quad = match quads.cell(quad_idx + params.left, params.line_idx + params.top) {
+                Ok(quad) => quad,
+                Err(_) => break,
+            };
+
+            quad.set_bg_color(bg_color);
+            quad.set_fg_color(glyph_color);
+            quad.set_underline_color(glyph_color);
+            quad.set_texture(white_space);
+            quad.set_texture_adjust(0., 0., 0., 0.);
+            quad.set_underline(white_space);
+            quad.set_has_color(false);
+            quad.set_hsv(hsv);
+            quad.set_cursor(
+                self.util_sprites
+                    .cursor_sprite(cursor_shape)
+                    .texture_coords(),
+            );
+            quad.set_cursor_color(params.cursor_border_color);
+        }
+
+        Ok(())
+    }
+
+    pub fn compute_cell_fg_bg(&self, params: ComputeCellFgBgParams) -> ComputeCellFgBgResult {
+        let selected = params.selection.contains(&params.cell_idx);
+
+        let is_cursor =
+            params.stable_line_idx == Some(params.cursor.y) && params.cursor.x == params.cell_idx;
+
+        let (cursor_shape, visibility) =
+            if is_cursor && params.cursor.visibility == CursorVisibility::Visible {
+                // This logic figures out whether the cursor is visible or not.
+                // If the cursor is explicitly hidden then it is obviously not
+                // visible.
+                // If the cursor is set to a blinking mode then we are visible
+                // depending on the current time.
+                let shape = params
+                    .config
+                    .default_cursor_style
+                    .effective_shape(params.cursor.shape);
+                // Work out the blinking shape if its a blinking cursor and it hasn't been disabled
+                // and the window is focused.
+                let blinking = params.is_active_pane
+                    && shape.is_blinking()
+                    && params.config.cursor_blink_rate != 0
+                    && self.focused;
+                if blinking {
+                    // Divide the time since we last moved by the blink rate.
+                    // If the result is even then the cursor is "on", else it
+                    // is "off"
+                    let now = std::time::Instant::now();
+                    let milli_uptime = now.duration_since(self.last_cursor_movement).as_millis();
+                    let ticks = milli_uptime / params.config.cursor_blink_rate as u128;
+                    (
+                        shape,
+                        if (ticks & 1) == 0 {
+                            CursorVisibility::Visible
+                        } else {
+                            CursorVisibility::Hidden
+                        },
+                    )
+                } else {
+                    (shape, CursorVisibility::Visible)
+                }
+            } else {
+                (params.cursor.shape, CursorVisibility::Hidden)
+            };
+
+        let (fg_color, bg_color) = match (
+            selected,
+            self.focused && params.is_active_pane,
+            cursor_shape,
+            visibility,
+        ) {
+            // Selected text overrides colors
+            (true, _, _, CursorVisibility::Hidden) => (
+                rgbcolor_to_window_color(params.palette.selection_fg),
+                rgbcolor_to_window_color(params.palette.selection_bg),
+            ),
+            // Cursor cell overrides colors
+            (_, true, CursorShape::BlinkingBlock, CursorVisibility::Visible)
+            | (_, true, CursorShape::SteadyBlock, CursorVisibility::Visible) => (
+                rgbcolor_to_window_color(params.palette.cursor_fg),
+                rgbcolor_to_window_color(params.palette.cursor_bg),
+            ),
+            // Normally, render the cell as configured (or if the window is unfocused)
+            _ => (params.fg_color, params.bg_color),
+        };
+
+        ComputeCellFgBgResult {
+            fg_color,
+            bg_color,
+            cursor_shape: if visibility == CursorVisibility::Visible {
+                Some(cursor_shape)
+            } else {
+                None
+            },
+        }
+    }
+}
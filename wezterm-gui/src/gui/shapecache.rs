@@ -1,9 +1,29 @@
+use anyhow::anyhow;
 use config::TextStyle;
+use lru::LruCache;
+use std::cell::RefCell;
+use std::rc::Rc;
+use termwiz::cellcluster::BidiClusters;
+use termwiz::surface::{Line, SequenceNo};
+use wezterm_font::shaper::GlyphInfo;
+use wezterm_font::{Direction, FontConfiguration};
+
+pub type ShapeCache = LruCache<ShapeCacheKey, anyhow::Result<Rc<Vec<GlyphInfo>>>>;
+
+/// Caches the visual order clusters of lines, keyed by the
+/// sequence number of the line
+pub type BidiCache = LruCache<SequenceNo, Rc<BidiClusters>>;
+
+/// The number of lines for which we cache clusters; this is enough
+/// to cover the lines displayed by a large window
+pub const BIDI_CACHE_SIZE: usize = 1024;
 
 #[derive(PartialEq, Eq, Hash)]
 pub struct ShapeCacheKey {
     pub style: TextStyle,
     pub text: String,
+    pub direction: Direction,
+    pub ligatures: bool,
 }
 
 /// We'd like to avoid allocating when resolving from the cache
@@ -15,6 +35,8 @@ pub struct ShapeCacheKey {
 pub struct BorrowedShapeCacheKey<'a> {
     pub style: &'a TextStyle,
     pub text: &'a str,
+    pub direction: Direction,
+    pub ligatures: bool,
 }
 
 impl<'a> BorrowedShapeCacheKey<'a> {
@@ -22,6 +44,8 @@ impl<'a> BorrowedShapeCacheKey<'a> {
         ShapeCacheKey {
             style: self.style.clone(),
             text: self.text.to_owned(),
+            direction: self.direction,
+            ligatures: self.ligatures,
         }
     }
 }
@@ -35,6 +59,8 @@ impl ShapeCacheKeyTrait for ShapeCacheKey {
         BorrowedShapeCacheKey {
             style: &self.style,
             text: &self.text,
+            direction: self.direction,
+            ligatures: self.ligatures,
         }
     }
 }
@@ -71,3 +97,59 @@ impl<'a> std::hash::Hash for (dyn ShapeCacheKeyTrait + 'a) {
         self.key().hash(state)
     }
 }
+
+fn lookup_cached_shape(
+    cache: &RefCell<ShapeCache>,
+    key: &dyn ShapeCacheKeyTrait,
+) -> Option<anyhow::Result<Rc<Vec<GlyphInfo>>>> {
+    match cache.borrow_mut().get(key) {
+        Some(Ok(info)) => Some(Ok(Rc::clone(info))),
+        Some(Err(err)) => Some(Err(anyhow!("cached shaper error: {}", err))),
+        None => None,
+    }
+}
+
+/// Returns the shaped glyphs for the text described by `key`,
+/// shaping and caching them if they were not already cached.
+pub fn cached_shape(
+    cache: &RefCell<ShapeCache>,
+    fonts: &FontConfiguration,
+    key: BorrowedShapeCacheKey,
+) -> anyhow::Result<Rc<Vec<GlyphInfo>>> {
+    if let Some(info) = lookup_cached_shape(cache, &key) {
+        metrics::counter!("shape.cache.hit", 1);
+        return info;
+    }
+    metrics::counter!("shape.cache.miss", 1);
+
+    let font = fonts.resolve_font(key.style)?;
+    let features: &[&str] = if key.ligatures {
+        &[]
+    } else {
+        &["-calt", "-clig", "-dlig", "-liga"]
+    };
+    match font.shape_with_features(key.text, key.direction, features) {
+        Ok(info) => {
+            let info = Rc::new(info);
+            cache.borrow_mut().put(key.to_owned(), Ok(Rc::clone(&info)));
+            Ok(info)
+        }
+        Err(err) => {
+            let res = anyhow!("shaper error: {}", err);
+            cache.borrow_mut().put(key.to_owned(), Err(err));
+            Err(res)
+        }
+    }
+}
+
+/// Returns the clusters of `line` in visual order, computing and
+/// caching them if the line has changed since they were last computed.
+pub fn cached_bidi(cache: &RefCell<BidiCache>, line: &Line) -> Rc<BidiClusters> {
+    let seqno = line.current_seqno();
+    if let Some(bidi) = cache.borrow_mut().get(&seqno) {
+        return Rc::clone(bidi);
+    }
+    let bidi = Rc::new(line.cluster_bidi());
+    cache.borrow_mut().put(seqno, Rc::clone(&bidi));
+    bidi
+}
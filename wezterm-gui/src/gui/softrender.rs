@@ -0,0 +1,616 @@
+//! A CPU implementation of the quad based renderer.
+//! The vertices are produced in exactly the same way as for the OpenGL
+//! renderer, and are then rasterized here using a port of the logic in
+//! `vertex.glsl` and `fragment.glsl`.  This allows rendering without a
+//! GPU, and allows a `Surface` to be rendered to a PNG file so that the
+//! rendering can be regression tested headlessly.
+use super::glyphcache::GlyphCache;
+use super::quad::*;
+use super::renderstate::RenderState;
+use super::screenline::{LineRenderer, RenderScreenLineParams};
+use super::shapecache::{BidiCache, ShapeCache, BIDI_CACHE_SIZE};
+use super::termwindow::{
+    effective_right_padding, load_background_image, rgbcolor_alpha_to_window_color,
+    rgbcolor_to_window_color,
+};
+use super::utilsprites::{RenderMetrics, UtilSprites};
+use ::window::bitmaps::atlas::OutOfTextureSpace;
+use ::window::bitmaps::{BitmapImage, Image, ImageTexture};
+use ::window::*;
+use anyhow::Context;
+use config::ConfigHandle;
+use mux::renderable::StableCursorPosition;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+use termwiz::surface::{CursorShape, CursorVisibility, Line, Surface};
+use wezterm_font::FontConfiguration;
+use wezterm_term::{StableRowIndex, TerminalConfiguration};
+
+/// Selects which of the passes that make up a frame is being drawn.
+/// These correspond to the `window_bg_layer` and `bg_and_line_layer`
+/// uniforms used by the shaders.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Layer {
+    WindowBackground,
+    BackgroundAndLines,
+    Glyphs,
+}
+
+type Rgba = [f32; 4];
+
+/// An RGBA framebuffer with floating point components
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgba>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, clear: Color) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![color_to_rgba(clear); width * height],
+        }
+    }
+
+    /// Draws the vertices in the same three passes that are used by
+    /// the OpenGL renderer.  `atlas` is the texture that holds the
+    /// glyphs, sprites and images referenced by the vertices.
+    pub fn draw_quads(&mut self, vertices: &[Vertex], atlas: &Image) {
+        for layer in &[
+            Layer::WindowBackground,
+            Layer::BackgroundAndLines,
+            Layer::Glyphs,
+        ] {
+            for quad in vertices.chunks_exact(VERTICES_PER_CELL) {
+                self.draw_quad(quad, atlas, *layer);
+            }
+        }
+    }
+
+    #[allow(clippy::float_cmp)]
+    fn draw_quad(&mut self, quad: &[Vertex], atlas: &Image, layer: Layer) {
+        let top_left = &quad[V_TOP_LEFT];
+        let bottom_right = &quad[V_BOT_RIGHT];
+        let has_color = top_left.has_color;
+
+        // The equivalent of the vertex shader: decide where the quad goes,
+        // or whether it is moved off screen for this pass
+        let (left, top, right, bottom) = match layer {
+            Layer::WindowBackground if has_color == 2.0 => (
+                top_left.position.0,
+                top_left.position.1,
+                bottom_right.position.0,
+                bottom_right.position.1,
+            ),
+            Layer::WindowBackground => return,
+            _ if has_color == 2.0 || has_color == 3.0 => return,
+            Layer::BackgroundAndLines => (
+                top_left.position.0,
+                top_left.position.1,
+                bottom_right.position.0,
+                bottom_right.position.1,
+            ),
+            Layer::Glyphs => (
+                top_left.position.0 + top_left.adjust.0,
+                top_left.position.1 + top_left.adjust.1,
+                bottom_right.position.0 + bottom_right.adjust.0,
+                bottom_right.position.1 + bottom_right.adjust.1,
+            ),
+        };
+
+        // The projection places the origin at the center of the window
+        let left = left + self.width as f32 / 2.;
+        let right = right + self.width as f32 / 2.;
+        let top = top + self.height as f32 / 2.;
+        let bottom = bottom + self.height as f32 / 2.;
+        if right <= left || bottom <= top {
+            return;
+        }
+
+        // A pixel is covered when its center lies within the quad
+        let x_range = (left - 0.5).ceil().max(0.) as usize
+            ..((right - 0.5).ceil().max(0.) as usize).min(self.width);
+        let y_range = (top - 0.5).ceil().max(0.) as usize
+            ..((bottom - 0.5).ceil().max(0.) as usize).min(self.height);
+
+        let interpolate = |from: (f32, f32), to: (f32, f32), x: f32, y: f32| {
+            (
+                from.0 + (to.0 - from.0) * (x - left) / (right - left),
+                from.1 + (to.1 - from.1) * (y - top) / (bottom - top),
+            )
+        };
+
+        for y in y_range {
+            let pixel_y = y as f32 + 0.5;
+            for x in x_range.clone() {
+                let pixel_x = x as f32 + 0.5;
+
+                let tex = interpolate(top_left.tex, bottom_right.tex, pixel_x, pixel_y);
+                let color = match layer {
+                    Layer::WindowBackground => {
+                        // We're the window background image
+                        let mut color = sample_linear(atlas, tex);
+                        // Apply window_background_image_opacity
+                        color[3] = top_left.bg_color.3;
+                        color
+                    }
+                    Layer::BackgroundAndLines => {
+                        let underline = interpolate(
+                            top_left.underline,
+                            bottom_right.underline,
+                            pixel_x,
+                            pixel_y,
+                        );
+                        let cursor =
+                            interpolate(top_left.cursor, bottom_right.cursor, pixel_x, pixel_y);
+                        if sample_nearest(atlas, cursor)[3] != 0. {
+                            tuple_to_rgba(top_left.cursor_color)
+                        } else if sample_nearest(atlas, underline)[3] != 0. {
+                            tuple_to_rgba(top_left.underline_color)
+                        } else {
+                            tuple_to_rgba(top_left.bg_color)
+                        }
+                    }
+                    Layer::Glyphs => {
+                        let mut color = sample_nearest(atlas, tex);
+                        if has_color == 4.0 {
+                            // Add the parts that use the text color
+                            let fg_mask = interpolate(
+                                top_left.fg_mask,
+                                bottom_right.fg_mask,
+                                pixel_x,
+                                pixel_y,
+                            );
+                            let mask = sample_nearest(atlas, fg_mask)[3];
+                            let fg_color = tuple_to_rgba(top_left.fg_color);
+                            for (c, fg) in color.iter_mut().zip(fg_color.iter()).take(3) {
+                                *c += fg * mask;
+                            }
+                            color
+                        } else if has_color != 0. {
+                            color
+                        } else if top_left.fg_color == top_left.bg_color {
+                            // A monochrome glyph that would be invisible
+                            [0., 0., 0., 0.]
+                        } else {
+                            multiply(tuple_to_rgba(top_left.fg_color), color)
+                        }
+                    }
+                };
+                let color = apply_hsv(color, top_left.hsv);
+
+                let dest = &mut self.pixels[y * self.width + x];
+                *dest = match layer {
+                    // No blending for the background layer
+                    Layer::WindowBackground => color,
+                    // Blend the color, but keep the destination alpha
+                    Layer::BackgroundAndLines => {
+                        let alpha = color[3];
+                        [
+                            color[0] * alpha + dest[0] * (1. - alpha),
+                            color[1] * alpha + dest[1] * (1. - alpha),
+                            color[2] * alpha + dest[2] * (1. - alpha),
+                            (alpha * alpha + dest[3]).min(1.),
+                        ]
+                    }
+                    // Regular alpha blending
+                    Layer::Glyphs => {
+                        let alpha = color[3];
+                        [
+                            color[0] * alpha + dest[0] * (1. - alpha),
+                            color[1] * alpha + dest[1] * (1. - alpha),
+                            color[2] * alpha + dest[2] * (1. - alpha),
+                            alpha * alpha + dest[3] * (1. - alpha),
+                        ]
+                    }
+                };
+            }
+        }
+    }
+
+    /// Converts the framebuffer into a bgra32 image
+    pub fn to_image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for (dest, src) in image.pixels_mut().iter_mut().zip(self.pixels.iter()) {
+            let component = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+            *dest = Color::rgba(
+                component(src[0]),
+                component(src[1]),
+                component(src[2]),
+                component(src[3]),
+            )
+            .0;
+        }
+        image
+    }
+}
+
+fn color_to_rgba(color: Color) -> Rgba {
+    let (red, green, blue, alpha) = color.as_rgba();
+    [
+        red as f32 / 255.,
+        green as f32 / 255.,
+        blue as f32 / 255.,
+        alpha as f32 / 255.,
+    ]
+}
+
+fn tuple_to_rgba(color: (f32, f32, f32, f32)) -> Rgba {
+    [color.0, color.1, color.2, color.3]
+}
+
+fn texel(atlas: &Image, x: isize, y: isize) -> Rgba {
+    let (width, height) = atlas.image_dimensions();
+    let x = x.max(0).min(width as isize - 1) as usize;
+    let y = y.max(0).min(height as isize - 1) as usize;
+    color_to_rgba(Color(*atlas.pixel(x, y)))
+}
+
+/// Samples the texel nearest to the texture coordinate, clamping
+/// to the edges of the texture
+fn sample_nearest(atlas: &Image, coord: (f32, f32)) -> Rgba {
+    let (width, height) = atlas.image_dimensions();
+    texel(
+        atlas,
+        (coord.0 * width as f32).floor() as isize,
+        (coord.1 * height as f32).floor() as isize,
+    )
+}
+
+/// Bilinear interpolation of the texels around the texture coordinate
+fn sample_linear(atlas: &Image, coord: (f32, f32)) -> Rgba {
+    let (width, height) = atlas.image_dimensions();
+    let x = coord.0 * width as f32 - 0.5;
+    let y = coord.1 * height as f32 - 0.5;
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = x - x0;
+    let fy = y - y0;
+    let (x0, y0) = (x0 as isize, y0 as isize);
+
+    let a = texel(atlas, x0, y0);
+    let b = texel(atlas, x0 + 1, y0);
+    let c = texel(atlas, x0, y0 + 1);
+    let d = texel(atlas, x0 + 1, y0 + 1);
+    let mix = |i: usize| {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        top + (bottom - top) * fy
+    };
+    [mix(0), mix(1), mix(2), mix(3)]
+}
+
+/// Alpha-regulated multiply to colorize the glyph bitmap
+fn multiply(src: Rgba, dst: Rgba) -> Rgba {
+    let inv_src_alpha = 1. - src[3];
+    let inv_dst_alpha = 1. - dst[3];
+    let one = |s: f32, d: f32| (s * d) + (s * inv_dst_alpha) + (d * inv_src_alpha);
+    [
+        one(src[0], dst[0]),
+        one(src[1], dst[1]),
+        one(src[2], dst[2]),
+        dst[3],
+    ]
+}
+
+fn rgb2hsv(c: [f32; 3]) -> [f32; 3] {
+    let (r, g, b) = (c[0], c[1], c[2]);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+    let e = 1.0e-10;
+    let hue = if d <= 0. {
+        0.
+    } else if max == r {
+        ((g - b) / d).rem_euclid(6.) / 6.
+    } else if max == g {
+        ((b - r) / d + 2.) / 6.
+    } else {
+        ((r - g) / d + 4.) / 6.
+    };
+    [hue, d / (max + e), max]
+}
+
+fn hsv2rgb(c: [f32; 3]) -> [f32; 3] {
+    let channel = |k: f32| {
+        let p = ((c[0] + k).fract() * 6. - 3.).abs();
+        c[2] * (1. + ((p - 1.).clamp(0., 1.) - 1.) * c[1])
+    };
+    [channel(1.), channel(2. / 3.), channel(1. / 3.)]
+}
+
+fn apply_hsv(c: Rgba, hsv: (f32, f32, f32)) -> Rgba {
+    let converted = rgb2hsv([c[0], c[1], c[2]]);
+    let rgb = hsv2rgb([
+        converted[0] * hsv.0,
+        converted[1] * hsv.1,
+        converted[2] * hsv.2,
+    ]);
+    [rgb[0], rgb[1], rgb[2], c[3]]
+}
+
+/// Renders the visible lines of a `Surface`, including its cursor,
+/// using the configured fonts, colors and window padding.
+pub fn render_surface(
+    surface: &Surface,
+    config: &ConfigHandle,
+    fonts: &Rc<FontConfiguration>,
+) -> anyhow::Result<Image> {
+    let (cols, _rows) = surface.dimensions();
+    let cursor = if surface.cursor_visibility() == CursorVisibility::Visible {
+        let (x, y) = surface.cursor_position();
+        Some((
+            x,
+            y,
+            surface.cursor_shape().unwrap_or(CursorShape::SteadyBlock),
+        ))
+    } else {
+        None
+    };
+    render_lines(&surface.screen_lines(), cols, cursor, config, fonts)
+}
+
+/// Renders a set of lines into an image that is sized to fit `cols`
+/// columns and one row per line, plus the window padding.
+/// `cursor` holds the column and row of the cursor, if it is visible.
+pub fn render_lines(
+    lines: &[Cow<Line>],
+    cols: usize,
+    cursor: Option<(usize, usize, CursorShape)>,
+    config: &ConfigHandle,
+    fonts: &Rc<FontConfiguration>,
+) -> anyhow::Result<Image> {
+    let mut atlas_size = 1024;
+    loop {
+        match render_lines_with_atlas(lines, cols, cursor, config, fonts, atlas_size) {
+            Ok(image) => return Ok(image),
+            Err(err) => match err.downcast_ref::<OutOfTextureSpace>() {
+                Some(&OutOfTextureSpace { size: Some(size) }) if size > atlas_size => {
+                    atlas_size = size;
+                }
+                _ => return Err(err),
+            },
+        }
+    }
+}
+
+fn render_lines_with_atlas(
+    lines: &[Cow<Line>],
+    cols: usize,
+    cursor: Option<(usize, usize, CursorShape)>,
+    config: &ConfigHandle,
+    fonts: &Rc<FontConfiguration>,
+    atlas_size: usize,
+) -> anyhow::Result<Image> {
+    let metrics = RenderMetrics::new(fonts)?;
+    let mut glyph_cache = GlyphCache::new_in_memory(fonts, atlas_size, &metrics)?;
+    let util_sprites = UtilSprites::new(&mut glyph_cache, &metrics)?;
+    let glyph_cache = RefCell::new(glyph_cache);
+    let palette = config::TermConfig.color_palette();
+
+    let cell_width = metrics.cell_size.width as usize;
+    let cell_height = metrics.cell_size.height as usize;
+    let width = cols * cell_width
+        + config.window_padding.left as usize
+        + effective_right_padding(config, &metrics) as usize;
+    let height = lines.len() * cell_height
+        + config.window_padding.top as usize
+        + config.window_padding.bottom as usize;
+
+    let (mut vertices, _indices, quad_map) =
+        RenderState::compute_quads(&metrics, width as f32, height as f32);
+    let mut quads = quad_map.map_slice(&mut vertices);
+
+    let white_space = util_sprites.white_space.texture_coords();
+    let window_is_transparent =
+        config.window_background_image.is_some() || config.window_background_opacity != 1.0;
+
+    {
+        let mut quad = quads.background_image();
+        quad.set_underline(white_space);
+        quad.set_cursor(white_space);
+
+        let background_image_alpha = (config.window_background_opacity * 255.0) as u8;
+        let color = rgbcolor_alpha_to_window_color(palette.background, background_image_alpha);
+
+        match load_background_image(config) {
+            Some(im) => {
+                let sprite = glyph_cache.borrow_mut().cached_image(&im, None)?;
+                quad.set_texture(sprite.texture_coords());
+                quad.set_is_background_image();
+            }
+            None => {
+                quad.set_texture(white_space);
+                quad.set_is_background();
+            }
+        }
+        quad.set_texture_adjust(0., 0., 0., 0.);
+        quad.set_hsv(config.window_background_image_hsb);
+        quad.set_cursor_color(color);
+        quad.set_fg_color(color);
+        quad.set_underline_color(color);
+        quad.set_bg_color(color);
+    }
+
+    let cursor = match cursor {
+        Some((x, y, shape)) => StableCursorPosition {
+            x,
+            y: y as StableRowIndex,
+            shape,
+            visibility: CursorVisibility::Visible,
+        },
+        None => StableCursorPosition {
+            visibility: CursorVisibility::Hidden,
+            ..Default::default()
+        },
+    };
+    let shape_cache = RefCell::new(ShapeCache::new(config.shape_cache_size.max(1)));
+    let bidi_cache = RefCell::new(BidiCache::new(BIDI_CACHE_SIZE));
+    let renderer = LineRenderer {
+        fonts,
+        render_metrics: &metrics,
+        glyph_cache: &glyph_cache,
+        util_sprites: &util_sprites,
+        shape_cache: &shape_cache,
+        bidi_cache: &bidi_cache,
+        window_is_transparent,
+        highlight: None,
+        // Render as though the window is focused and the cursor has
+        // just moved, so that a blinking cursor is visible
+        focused: true,
+        last_cursor_movement: Instant::now(),
+    };
+
+    for (line_idx, line) in lines.iter().enumerate() {
+        renderer.render_screen_line(
+            RenderScreenLineParams {
+                line_idx,
+                left: 0,
+                top: 0,
+                num_cols: cols,
+                stable_line_idx: Some(line_idx as StableRowIndex),
+                line,
+                selection: 0..0,
+                cursor: &cursor,
+                palette: &palette,
+                config,
+                cursor_border_color: rgbcolor_to_window_color(palette.cursor_border),
+                foreground: rgbcolor_to_window_color(palette.foreground),
+                is_active: true,
+            },
+            &mut quads,
+        )?;
+    }
+
+    drop(quads);
+
+    let background_alpha = (config.window_background_opacity * 255.0) as u8;
+    let mut frame = Framebuffer::new(
+        width,
+        height,
+        rgbcolor_alpha_to_window_color(palette.background, background_alpha),
+    );
+    let atlas = glyph_cache.borrow().atlas.texture();
+    let atlas: &ImageTexture = &atlas;
+    frame.draw_quads(&vertices, &atlas.image.borrow());
+    Ok(frame.to_image())
+}
+
+/// Writes a bgra32 image to a PNG file
+pub fn write_png(image: &Image, path: &Path) -> anyhow::Result<()> {
+    let (width, height) = image.image_dimensions();
+    let mut data = Vec::with_capacity(width * height * 4);
+    for pixel in image.pixels() {
+        let (red, green, blue, alpha) = Color(*pixel).as_rgba();
+        data.extend_from_slice(&[red, green, blue, alpha]);
+    }
+    let png = ::image::RgbaImage::from_raw(width as u32, height as u32, data)
+        .context("image dimensions are inconsistent with its data")?;
+    png.save(path)
+        .with_context(|| format!("saving {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use termwiz::cell::AttributeChange;
+    use termwiz::color::{ColorAttribute, RgbColor};
+    use termwiz::surface::{Change, Position};
+
+    fn rgb(color: RgbColor) -> (u8, u8, u8, u8) {
+        (color.red, color.green, color.blue, 0xff)
+    }
+
+    /// Renders a small surface and compares it against a reference
+    /// image built from the cell geometry and the palette.  The cells
+    /// hold either nothing or a block glyph, which is drawn by us rather
+    /// than taken from a font, so the expected pixels are independent
+    /// of the fonts that are installed.
+    #[test]
+    fn render_surface_matches_reference() {
+        config::use_default_configuration();
+        let config = config::configuration();
+        let fonts = Rc::new(FontConfiguration::new().unwrap());
+        let metrics = RenderMetrics::new(&fonts).unwrap();
+        let palette = config::TermConfig.color_palette();
+
+        let red = RgbColor::new(0xff, 0, 0);
+        let green = RgbColor::new(0, 0xff, 0);
+        let blue = RgbColor::new(0, 0, 0xff);
+
+        let mut surface = Surface::new(4, 2);
+        surface.add_changes(vec![
+            Change::Attribute(AttributeChange::Background(
+                ColorAttribute::TrueColorWithDefaultFallback(red),
+            )),
+            Change::Text(" ".to_string()),
+            Change::AllAttributes(Default::default()),
+            Change::Attribute(AttributeChange::Foreground(
+                ColorAttribute::TrueColorWithDefaultFallback(green),
+            )),
+            Change::Text("\u{2588}".to_string()),
+            Change::AllAttributes(Default::default()),
+            Change::CursorPosition {
+                x: Position::Absolute(1),
+                y: Position::Absolute(1),
+            },
+            Change::Attribute(AttributeChange::Reverse(true)),
+            Change::Attribute(AttributeChange::Foreground(
+                ColorAttribute::TrueColorWithDefaultFallback(blue),
+            )),
+            Change::Text(" ".to_string()),
+            Change::AllAttributes(Default::default()),
+            Change::CursorPosition {
+                x: Position::Absolute(3),
+                y: Position::Absolute(1),
+            },
+            Change::CursorShape(CursorShape::SteadyBlock),
+            Change::CursorVisibility(CursorVisibility::Visible),
+        ]);
+
+        let image = render_surface(&surface, &config, &fonts).unwrap();
+
+        let cell_width = metrics.cell_size.width as usize;
+        let cell_height = metrics.cell_size.height as usize;
+        let left_padding = config.window_padding.left as usize;
+        let top_padding = config.window_padding.top as usize;
+        assert_eq!(
+            image.image_dimensions(),
+            (
+                4 * cell_width + left_padding + effective_right_padding(&config, &metrics) as usize,
+                2 * cell_height + top_padding + config.window_padding.bottom as usize
+            )
+        );
+
+        let background = rgb(palette.background);
+        let expected = [
+            [rgb(red), rgb(green), background, background],
+            [background, rgb(blue), background, rgb(palette.cursor_bg)],
+        ];
+
+        // Compare the interior of each cell; the edges may hold the
+        // outline of the cursor
+        for (row, cells) in expected.iter().enumerate() {
+            for (col, expected) in cells.iter().enumerate() {
+                let x0 = left_padding + col * cell_width;
+                let y0 = top_padding + row * cell_height;
+                for y in y0 + cell_height / 4..y0 + cell_height * 3 / 4 {
+                    for x in x0 + cell_width / 4..x0 + cell_width * 3 / 4 {
+                        let actual = Color(*image.pixel(x, y)).as_rgba();
+                        assert_eq!(
+                            actual, *expected,
+                            "pixel {},{} of cell {},{}",
+                            x, y, col, row
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
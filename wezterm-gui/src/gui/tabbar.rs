@@ -1,4 +1,5 @@
 use config::{ConfigHandle, TabBarColors};
+use mux::tab::TabId;
 use mux::window::Window as MuxWindow;
 use std::cell::Ref;
 use termwiz::cell::unicode_column_width;
@@ -11,6 +12,7 @@ use wezterm_term::Line;
 pub struct TabBarState {
     line: Line,
     items: Vec<TabEntry>,
+    status: String,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -32,6 +34,7 @@ impl TabBarState {
         Self {
             line: Line::with_width(1),
             items: vec![],
+            status: String::new(),
         }
     }
 
@@ -39,16 +42,25 @@ impl TabBarState {
         &self.line
     }
 
+    /// Returns the status text shown at the right of the tab bar
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
     /// Build a new tab bar from the current state
     /// mouse_x is some if the mouse is on the same row as the tab bar.
     /// title_width is the total number of cell columns in the window.
     /// window allows access to the tabs associated with the window.
+    /// has_unseen_bell returns true for tabs that should show the bell marker.
+    /// status is shown at the right hand side, if there is room for it.
     pub fn new(
         title_width: usize,
         mouse_x: Option<usize>,
         window: &Ref<MuxWindow>,
         colors: Option<&TabBarColors>,
         config: &ConfigHandle,
+        has_unseen_bell: impl Fn(TabId) -> bool,
+        status: String,
     ) -> Self {
         // We ultimately want to produce a line looking like this:
         // ` | tab1-title x | tab2-title x |  +      . - X `
@@ -75,6 +87,9 @@ impl TabBarState {
                             title
                         );
                     }
+                    if has_unseen_bell(tab.tab_id()) {
+                        title = format!("\u{1f514} {}", title);
+                    }
                     // We have a preferred soft minimum on tab width to make it
                     // easier to click on tab titles, but we'll still go below
                     // this if there are too many tabs to fit the window at
@@ -91,8 +106,14 @@ impl TabBarState {
         let titles_len: usize = tab_titles.iter().map(|s| unicode_column_width(s)).sum();
         let number_of_tabs = tab_titles.len();
 
-        let available_cells =
-            title_width.saturating_sub((number_of_tabs * per_tab_overhead) + system_overhead);
+        let status_width = if status.is_empty() {
+            0
+        } else {
+            unicode_column_width(&status) + 2
+        };
+
+        let available_cells = title_width
+            .saturating_sub((number_of_tabs * per_tab_overhead) + system_overhead + status_width);
         let tab_width_max = if available_cells >= titles_len {
             // We can render each title with its full width
             usize::max_value()
@@ -131,13 +152,17 @@ impl TabBarState {
             line.set_cell(x, Cell::new(' ', cell_attrs.clone()));
             x += 1;
 
-            for (idx, sub) in tab_title.graphemes(true).enumerate() {
-                if idx >= tab_width_max {
+            let mut title_len = 0;
+            for sub in tab_title.graphemes(true) {
+                let cell = Cell::new_grapheme(sub, cell_attrs.clone());
+                let width = cell.width();
+                if title_len + width > tab_width_max {
                     break;
                 }
 
-                line.set_cell(x, Cell::new_grapheme(sub, cell_attrs.clone()));
-                x += 1;
+                line.set_cell(x, cell);
+                x += width;
+                title_len += width;
             }
 
             line.set_cell(x, Cell::new(' ', cell_attrs));
@@ -184,7 +209,25 @@ impl TabBarState {
             line.set_cell(idx, black_cell.clone());
         }
 
-        Self { line, items }
+        if status_width > 0 && x + status_width <= title_width {
+            let cell_attrs = colors.inactive_tab.as_cell_attributes();
+            let mut x = title_width - status_width;
+            line.set_cell(x, Cell::new(' ', cell_attrs.clone()));
+            x += 1;
+            for sub in status.graphemes(true) {
+                let cell = Cell::new_grapheme(sub, cell_attrs.clone());
+                let width = cell.width();
+                line.set_cell(x, cell);
+                x += width;
+            }
+            line.set_cell(x, Cell::new(' ', cell_attrs));
+        }
+
+        Self {
+            line,
+            items,
+            status,
+        }
     }
 
     /// Determine which component the mouse is over
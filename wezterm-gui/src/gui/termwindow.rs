@@ -3,9 +3,11 @@ use super::quad::*;
 use super::renderstate::*;
 use super::utilsprites::RenderMetrics;
 use crate::gui::overlay::{
-    confirm_close_pane, confirm_close_tab, confirm_close_window, confirm_quit_program, launcher,
-    start_overlay, start_overlay_pane, tab_navigator, CopyOverlay, SearchOverlay,
+    confirm_clipboard_read, confirm_close_pane, confirm_close_tab, confirm_close_window,
+    confirm_quit_program, confirm_replay_input, launcher, start_overlay, start_overlay_pane,
+    tab_navigator, CopyOverlay, SearchOverlay,
 };
+use crate::gui::screenline::*;
 use crate::gui::scrollbar::*;
 use crate::gui::selection::*;
 use crate::gui::shapecache::*;
@@ -28,7 +30,10 @@ use config::keyassignment::{
     ClipboardCopyDestination, ClipboardPasteSource, InputMap, KeyAssignment, MouseEventTrigger,
     SpawnCommand, SpawnTabDomain,
 };
-use config::{configuration, ConfigHandle, WindowCloseConfirmation};
+use config::{
+    configuration, AudibleBell, ClipboardReadAccess, ConfigHandle, VisualBellTarget,
+    WindowCloseConfirmation,
+};
 use lru::LruCache;
 use mux::activity::Activity;
 use mux::domain::{DomainId, DomainState};
@@ -36,28 +41,29 @@ use mux::pane::{Pane, PaneId};
 use mux::renderable::{RenderableDimensions, StableCursorPosition};
 use mux::tab::{PositionedPane, PositionedSplit, SplitDirection, TabId};
 use mux::window::WindowId as MuxWindowId;
-use mux::Mux;
+use mux::{Mux, MuxNotification};
 use portable_pty::{CommandBuilder, PtySize};
 use std::any::Any;
 use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::ops::{Add, Range, Sub};
+use std::io::Write;
+use std::ops::{Add, Sub};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
-use termwiz::color::{ColorAttribute, RgbColor};
+use termwiz::color::RgbColor;
+use termwiz::escape::osc::{OperatingSystemCommand, Selection};
 use termwiz::hyperlink::Hyperlink;
-use termwiz::image::ImageData;
-use termwiz::surface::{CursorShape, CursorVisibility};
+use termwiz::image::{ImageData, ImageDataType};
 use wezterm_font::shaper::GlyphInfo;
 use wezterm_font::units::*;
-use wezterm_font::FontConfiguration;
+use wezterm_font::{Direction, FontConfiguration};
 use wezterm_term::color::ColorPalette;
 use wezterm_term::input::LastMouseClick;
 use wezterm_term::{
-    CellAttributes, ClipboardSelection, Line, StableRowIndex, TerminalConfiguration,
+    Alert, CellAttributes, ClipboardSelection, LineSize, StableRowIndex, TerminalConfiguration,
 };
 
 const ATLAS_SIZE: usize = 128;
@@ -79,40 +85,6 @@ pub enum SpawnWhere {
     SplitPane(SplitDirection),
 }
 
-struct RenderScreenLineOpenGLParams<'a> {
-    line_idx: usize,
-    stable_line_idx: Option<StableRowIndex>,
-    line: &'a Line,
-    selection: Range<usize>,
-    cursor: &'a StableCursorPosition,
-    palette: &'a ColorPalette,
-    dims: &'a RenderableDimensions,
-    config: &'a ConfigHandle,
-    pos: &'a PositionedPane,
-
-    cursor_border_color: Color,
-    foreground: Color,
-    is_active: bool,
-}
-
-struct ComputeCellFgBgParams<'a> {
-    stable_line_idx: Option<StableRowIndex>,
-    cell_idx: usize,
-    cursor: &'a StableCursorPosition,
-    selection: &'a Range<usize>,
-    fg_color: Color,
-    bg_color: Color,
-    palette: &'a ColorPalette,
-    is_active_pane: bool,
-    config: &'a ConfigHandle,
-}
-
-struct ComputeCellFgBgResult {
-    fg_color: Color,
-    bg_color: Color,
-    cursor_shape: Option<CursorShape>,
-}
-
 #[derive(Debug, Clone, Copy)]
 struct RowsAndCols {
     rows: usize,
@@ -205,6 +177,9 @@ pub struct PaneState {
     /// contents, we're overlaying a little internal application
     /// tab.  We'll also route input to it.
     pub overlay: Option<Rc<dyn Pane>>,
+    /// When the bell was most recently rung in this pane;
+    /// used to animate the visual bell
+    bell_start: Option<Instant>,
 }
 
 #[derive(Default, Clone)]
@@ -213,6 +188,9 @@ pub struct TabState {
     /// contents, we're overlaying a little internal application
     /// tab.  We'll also route input to it.
     pub overlay: Option<Rc<dyn Pane>>,
+    /// Set when the bell rings in a tab that isn't active,
+    /// and cleared when the tab is next activated
+    has_unseen_bell: bool,
 }
 
 pub struct TermWindow {
@@ -258,7 +236,8 @@ pub struct TermWindow {
     /// The URL over which we are currently hovering
     current_highlight: Option<Arc<Hyperlink>>,
 
-    shape_cache: RefCell<LruCache<ShapeCacheKey, anyhow::Result<Rc<Vec<GlyphInfo>>>>>,
+    shape_cache: RefCell<ShapeCache>,
+    bidi_cache: RefCell<BidiCache>,
 
     last_blink_paint: Instant,
 
@@ -334,6 +313,71 @@ impl WindowCallbacks for TermWindow {
         }
     }
 
+    fn dropped_files(&mut self, paths: Vec<std::path::PathBuf>, coords: Point) {
+        let config = configuration();
+        let x = (coords.x.sub(config.window_padding.left as isize).max(0)
+            / self.render_metrics.cell_size.width) as usize;
+        let y = (coords.y.sub(config.window_padding.top as isize).max(0)
+            / self.render_metrics.cell_size.height) as usize;
+        let first_line_offset = if self.show_tab_bar { 1 } else { 0 };
+        let y = y.saturating_sub(first_line_offset);
+
+        // Prefer the pane under the drop location, falling back to
+        // the active pane
+        let pane = self
+            .get_panes_to_render()
+            .into_iter()
+            .find(|pos| {
+                y >= pos.top
+                    && y < pos.top + pos.height
+                    && x >= pos.left
+                    && x < pos.left + pos.width
+            })
+            .map(|pos| pos.pane)
+            .or_else(|| self.get_active_pane_no_overlay());
+        let pane = match pane {
+            Some(pane) => pane,
+            None => return,
+        };
+
+        let client_pane = match pane.downcast_ref::<wezterm_client::pane::ClientPane>() {
+            Some(client_pane) => client_pane,
+            None => {
+                let message = format!(
+                    "Unable to upload {} dropped file(s): uploads are only \
+                     supported for panes in a multiplexer domain",
+                    paths.len()
+                );
+                log::error!("{}", message);
+                wezterm_toast_notification::toast_notification("wezterm", &message);
+                return;
+            }
+        };
+
+        let upload = client_pane.upload_files(paths, |local, done, size| {
+            log::debug!("uploading {}: {}/{} bytes", local.display(), done, size);
+        });
+        promise::spawn::spawn(async move {
+            match upload.await {
+                Ok(uploaded) => {
+                    log::info!("uploaded {:?}", uploaded);
+                    wezterm_toast_notification::toast_notification(
+                        "wezterm",
+                        &format!("Uploaded {}", uploaded.join(", ")),
+                    );
+                }
+                Err(err) => {
+                    log::error!("{:#}", err);
+                    wezterm_toast_notification::toast_notification(
+                        "wezterm",
+                        &format!("Upload failed: {:#}", err),
+                    );
+                }
+            }
+        })
+        .detach();
+    }
+
     fn mouse_event(&mut self, event: &MouseEvent, context: &dyn WindowOps) {
         let pane = match self.get_active_pane_or_overlay() {
             Some(pane) => pane,
@@ -733,7 +777,8 @@ impl WindowCallbacks for TermWindow {
             current_mouse_button: self.current_mouse_button.clone(),
             last_mouse_click: self.last_mouse_click.clone(),
             current_highlight: self.current_highlight.clone(),
-            shape_cache: RefCell::new(LruCache::new(65536)),
+            shape_cache: RefCell::new(LruCache::new(configuration().shape_cache_size.max(1))),
+            bidi_cache: RefCell::new(LruCache::new(BIDI_CACHE_SIZE)),
             last_blink_paint: Instant::now(),
         });
         prior_window.close();
@@ -751,6 +796,7 @@ impl WindowCallbacks for TermWindow {
 
             Self::apply_icon(&window)?;
             Self::start_periodic_maintenance(window.clone());
+            Self::subscribe_to_pane_alerts(&window, mux_window_id);
             Self::setup_clipboard(&window, mux_window_id, clipboard_contents);
 
             drop(activity); // Keep the activity outstanding until we get here
@@ -816,6 +862,11 @@ impl WindowCallbacks for TermWindow {
             frame.clear_color_srgb(r, g, b, a);
         }
 
+        // Any animated images that we render will schedule their next frame
+        if let Some(render_state) = self.render_state.as_ref() {
+            render_state.glyph_cache.borrow_mut().next_frame_due.take();
+        }
+
         for pass in 0.. {
             match self.paint_opengl_pass() {
                 Ok(_) => break,
@@ -869,7 +920,7 @@ pub fn effective_right_padding(config: &ConfigHandle, render_metrics: &RenderMet
     }
 }
 
-fn load_background_image(config: &ConfigHandle) -> Option<Arc<ImageData>> {
+pub fn load_background_image(config: &ConfigHandle) -> Option<Arc<ImageData>> {
     match &config.window_background_image {
         Some(p) => match std::fs::read(p) {
             Ok(data) => {
@@ -897,8 +948,10 @@ fn reload_background_image(
         Some(p) => match std::fs::read(p) {
             Ok(data) => {
                 if let Some(existing) = image {
-                    if existing.data() == &*data {
-                        return Some(Arc::clone(existing));
+                    if let ImageDataType::EncodedFile(existing_data) = existing.data() {
+                        if *existing_data == data {
+                            return Some(Arc::clone(existing));
+                        }
                     }
                 }
                 Some(Arc::new(ImageData::with_raw_data(data)))
@@ -1006,13 +1059,15 @@ impl TermWindow {
                 current_mouse_button: None,
                 last_mouse_click: None,
                 current_highlight: None,
-                shape_cache: RefCell::new(LruCache::new(65536)),
+                shape_cache: RefCell::new(LruCache::new(config.shape_cache_size.max(1))),
+                bidi_cache: RefCell::new(LruCache::new(BIDI_CACHE_SIZE)),
                 last_blink_paint: Instant::now(),
             }),
         )?;
 
         Self::apply_icon(&window)?;
         Self::start_periodic_maintenance(window.clone());
+        Self::subscribe_to_pane_alerts(&window, mux_window_id);
         Self::setup_clipboard(&window, mux_window_id, clipboard_contents);
 
         crate::update::start_update_checker();
@@ -1040,6 +1095,234 @@ impl TermWindow {
         }
     }
 
+    /// Arrange to be told about alerts raised by panes in this window
+    fn subscribe_to_pane_alerts(window: &Window, mux_window_id: MuxWindowId) {
+        let window = window.clone();
+        let mux = Mux::get().expect("to be main thread with mux running");
+        mux.subscribe(move |n| {
+            let mux = Mux::get().expect("subscribe to trigger on main thread");
+            if mux.get_window(mux_window_id).is_none() {
+                // The window has gone away; stop listening
+                return false;
+            }
+            match n {
+                MuxNotification::Alert { pane_id, alert } => {
+                    window.apply(move |myself, _window| {
+                        if let Some(myself) = myself.downcast_mut::<Self>() {
+                            myself.mux_pane_alert(pane_id, alert.clone());
+                        }
+                        Ok(())
+                    });
+                }
+                MuxNotification::BufferedInputPending(pane_id) => {
+                    window.apply(move |myself, _window| {
+                        if let Some(myself) = myself.downcast_mut::<Self>() {
+                            myself.prompt_to_replay_input(pane_id);
+                        }
+                        Ok(())
+                    });
+                }
+                _ => {}
+            }
+            true
+        });
+    }
+
+    fn mux_pane_alert(&mut self, pane_id: PaneId, alert: Alert) {
+        let mux = Mux::get().expect("to be called on main thread");
+        let tab_id = match mux.resolve_pane_id(pane_id) {
+            Some((_domain_id, window_id, tab_id)) if window_id == self.mux_window_id => tab_id,
+            _ => return,
+        };
+        let pane = match mux.get_pane(pane_id) {
+            Some(pane) => pane,
+            None => return,
+        };
+
+        match alert {
+            Alert::Bell => {
+                let config = configuration();
+
+                if config.audible_bell == AudibleBell::SystemBeep {
+                    Connection::get().unwrap().beep();
+                }
+
+                if config.visual_bell.fade_in_duration_ms + config.visual_bell.fade_out_duration_ms
+                    > 0
+                {
+                    self.pane_state(pane_id).bell_start.replace(Instant::now());
+                }
+
+                let is_active_tab = mux
+                    .get_active_tab_for_window(self.mux_window_id)
+                    .map(|tab| tab.tab_id() == tab_id)
+                    .unwrap_or(false);
+                if !is_active_tab {
+                    self.tab_state(tab_id).has_unseen_bell = true;
+                    self.update_title();
+                }
+
+                self.emit_window_event("bell", &pane);
+
+                if let Some(window) = self.window.as_ref() {
+                    window.invalidate();
+                }
+            }
+            Alert::ToastNotification { title, body } => {
+                let title = title.unwrap_or_else(|| pane.get_title());
+                wezterm_toast_notification::toast_notification(&title, &body);
+            }
+            Alert::WindowTitleChanged(_) | Alert::IconTitleChanged(_) => {
+                self.update_title();
+                if let Some(window) = self.window.as_ref() {
+                    window.invalidate();
+                }
+            }
+            Alert::QueryClipboard(selection) => {
+                self.answer_clipboard_query(&pane, selection);
+            }
+        }
+    }
+
+    /// Ask the user whether to send the input that was typed into
+    /// the pane while its connection to the mux server was lost
+    fn prompt_to_replay_input(&mut self, pane_id: PaneId) {
+        let mux = Mux::get().expect("to be called on main thread");
+        match mux.resolve_pane_id(pane_id) {
+            Some((_domain_id, window_id, _tab_id)) if window_id == self.mux_window_id => {}
+            _ => return,
+        };
+        let pane = match mux.get_pane(pane_id) {
+            Some(pane) => pane,
+            None => return,
+        };
+        let num_inputs = match pane.downcast_ref::<wezterm_client::pane::ClientPane>() {
+            Some(client_pane) => client_pane.num_buffered_inputs(),
+            None => return,
+        };
+        let (overlay, future) = start_overlay_pane(self, &pane, move |pane_id, term| {
+            confirm_replay_input(pane_id, term, num_inputs)
+        });
+        self.assign_overlay_for_pane(pane_id, overlay);
+        promise::spawn::spawn(future).detach();
+    }
+
+    /// Respond to an OSC 52 request from the pane to read the clipboard,
+    /// subject to the `clipboard_read_access` configuration
+    fn answer_clipboard_query(&mut self, pane: &Rc<dyn Pane>, selection: ClipboardSelection) {
+        let window = match self.window.as_ref() {
+            Some(window) => window.clone(),
+            None => return,
+        };
+        match configuration().clipboard_read_access {
+            ClipboardReadAccess::Deny => {
+                log::warn!(
+                    "pane {} tried to read the clipboard; denied by clipboard_read_access",
+                    pane.pane_id()
+                );
+            }
+            ClipboardReadAccess::Allow => {
+                Self::send_clipboard_to_pane(&window, pane.pane_id(), selection);
+            }
+            ClipboardReadAccess::Prompt => {
+                let (overlay, future) = start_overlay_pane(self, pane, move |pane_id, term| {
+                    confirm_clipboard_read(pane_id, term, window, selection)
+                });
+                self.assign_overlay_for_pane(pane.pane_id(), overlay);
+                promise::spawn::spawn(future).detach();
+            }
+        }
+    }
+
+    /// Read the clipboard and send it to the pane as the response
+    /// to an OSC 52 query
+    pub fn send_clipboard_to_pane(window: &Window, pane_id: PaneId, selection: ClipboardSelection) {
+        let (clipboard, osc_selection) = match selection {
+            ClipboardSelection::Clipboard => (Clipboard::Clipboard, Selection::CLIPBOARD),
+            ClipboardSelection::PrimarySelection => {
+                (Clipboard::PrimarySelection, Selection::PRIMARY)
+            }
+        };
+        let future = window.get_clipboard(clipboard);
+
+        promise::spawn::spawn(async move {
+            let text = future.await?;
+            let mux = Mux::get().unwrap();
+            if let Some(pane) = mux.get_pane(pane_id) {
+                let response = OperatingSystemCommand::SetSelection(osc_selection, text);
+                write!(pane.writer(), "{}", response)?;
+            }
+            Ok::<(), anyhow::Error>(())
+        })
+        .detach();
+    }
+
+    /// Computes the intensity of the visual bell for a pane, in the
+    /// range 0.0 to 1.0.  Returns None if the bell is not active.
+    fn visual_bell_intensity(&self, pane_id: PaneId) -> Option<f32> {
+        let config = configuration();
+        let bell_start = self.pane_state(pane_id).bell_start?;
+        let fade_in = config.visual_bell.fade_in_duration_ms as f32;
+        let fade_out = config.visual_bell.fade_out_duration_ms as f32;
+        let elapsed = bell_start.elapsed().as_millis() as f32;
+
+        if elapsed < fade_in {
+            Some(elapsed / fade_in)
+        } else if elapsed < fade_in + fade_out {
+            Some(1.0 - ((elapsed - fade_in) / fade_out))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the palette for a pane, adjusted for the visual bell
+    fn pane_palette(&self, pane: &Rc<dyn Pane>) -> ColorPalette {
+        let mut palette = pane.palette();
+        if let Some(intensity) = self.visual_bell_intensity(pane.pane_id()) {
+            let target = palette.visual_bell;
+            match configuration().visual_bell.target {
+                VisualBellTarget::BackgroundColor => {
+                    palette.background = mix_colors(palette.background, target, intensity);
+                }
+                VisualBellTarget::CursorColor => {
+                    palette.cursor_bg = mix_colors(palette.cursor_bg, target, intensity);
+                    palette.cursor_border = mix_colors(palette.cursor_border, target, intensity);
+                }
+            }
+        }
+        palette
+    }
+
+    /// Emit a lua event with the window and pane as parameters
+    fn emit_window_event(&mut self, name: &str, pane: &Rc<dyn Pane>) {
+        let window = GuiWin::new(self);
+        let pane = PaneObject::new(pane);
+
+        async fn emit_event(
+            lua: Option<Rc<mlua::Lua>>,
+            name: String,
+            window: GuiWin,
+            pane: PaneObject,
+        ) -> anyhow::Result<()> {
+            if let Some(lua) = lua {
+                let args = lua.pack_multi((window, pane))?;
+                config::lua::emit_event(&lua, (name.clone(), args))
+                    .await
+                    .map_err(|e| {
+                        log::error!("while processing {} event: {:#}", name, e);
+                        e
+                    })?;
+            }
+            Ok(())
+        }
+
+        let name = name.to_string();
+        promise::spawn::spawn(config::with_lua_config_on_main_thread(move |lua| {
+            emit_event(lua, name, window, pane)
+        }))
+        .detach();
+    }
+
     fn apply_icon(window: &Window) -> anyhow::Result<()> {
         let icon_image = image::load_from_memory(ICON_DATA)?;
         let image = icon_image.to_bgra8();
@@ -1105,6 +1388,14 @@ impl TermWindow {
                 }
             }
 
+            // Keep repainting while the visual bell is animating
+            if self.pane_state(pos.pane.pane_id()).bell_start.is_some() {
+                if self.visual_bell_intensity(pos.pane.pane_id()).is_none() {
+                    self.pane_state(pos.pane.pane_id()).bell_start.take();
+                }
+                needs_invalidate = true;
+            }
+
             // If the model is dirty, arrange to re-paint
             let dims = pos.pane.get_dimensions();
             let viewport = self
@@ -1143,12 +1434,27 @@ impl TermWindow {
             }
         }
 
+        // If an animated image is due to show its next frame,
+        // arrange to re-paint
+        if let Some(render_state) = self.render_state.as_ref() {
+            if let Some(due) = render_state.glyph_cache.borrow().next_frame_due {
+                if Instant::now() >= due {
+                    needs_invalidate = true;
+                }
+            }
+        }
+
         if let Some(mut mux_window) = mux.get_window_mut(self.mux_window_id) {
             if mux_window.check_and_reset_invalidated() {
                 needs_invalidate = true;
             }
         }
 
+        // Keep the connection status in the tab bar current
+        if self.connection_status() != self.tab_bar.status() {
+            self.update_title();
+        }
+
         if needs_invalidate {
             self.window.as_ref().unwrap().invalidate();
         }
@@ -1309,6 +1615,9 @@ impl TermWindow {
 
         self.show_scroll_bar = config.enable_scroll_bar;
         self.shape_cache.borrow_mut().clear();
+        self.shape_cache
+            .borrow_mut()
+            .resize(config.shape_cache_size.max(1));
         self.input_map = InputMap::new();
         self.leader_is_down = None;
         let dimensions = self.dimensions;
@@ -1343,6 +1652,33 @@ impl TermWindow {
         }
     }
 
+    /// Returns the text that describes the health of the connection
+    /// to the mux server for the active pane, for display in the
+    /// tab bar.  Returns an empty string for local panes.
+    fn connection_status(&self) -> String {
+        if !configuration().show_connection_status_in_tab_bar {
+            return String::new();
+        }
+        let pane = match self.get_active_pane_no_overlay() {
+            Some(pane) => pane,
+            None => return String::new(),
+        };
+        let client_pane = match pane.downcast_ref::<wezterm_client::pane::ClientPane>() {
+            Some(client_pane) => client_pane,
+            None => return String::new(),
+        };
+        let mux = Mux::get().unwrap();
+        let domain_name = mux
+            .get_domain(pane.domain_id())
+            .map(|domain| domain.domain_name().to_string())
+            .unwrap_or_default();
+        format!(
+            "{}: {}",
+            domain_name,
+            client_pane.connection_health().summary()
+        )
+    }
+
     fn update_title(&mut self) {
         let mux = Mux::get().unwrap();
         let window = match mux.get_window(self.mux_window_id) {
@@ -1351,6 +1687,11 @@ impl TermWindow {
         };
         let config = configuration();
 
+        // The user can see the active tab, so any bell in it has been seen
+        if let Some(tab) = window.get_active() {
+            self.tab_state(tab.tab_id()).has_unseen_bell = false;
+        }
+
         let new_tab_bar = TabBarState::new(
             self.terminal_size.cols as usize,
             if self.last_mouse_coords.1 == 0 {
@@ -1361,6 +1702,8 @@ impl TermWindow {
             &window,
             config.colors.as_ref().and_then(|c| c.tab_bar.as_ref()),
             &config,
+            |tab_id| self.tab_state(tab_id).has_unseen_bell,
+            self.connection_status(),
         );
         if new_tab_bar != self.tab_bar {
             self.tab_bar = new_tab_bar;
@@ -2075,32 +2418,7 @@ impl TermWindow {
                 }
             }
             EmitEvent(name) => {
-                let window = GuiWin::new(self);
-                let pane = PaneObject::new(pane);
-
-                async fn emit_event(
-                    lua: Option<Rc<mlua::Lua>>,
-                    name: String,
-                    window: GuiWin,
-                    pane: PaneObject,
-                ) -> anyhow::Result<()> {
-                    if let Some(lua) = lua {
-                        let args = lua.pack_multi((window, pane))?;
-                        config::lua::emit_event(&lua, (name.clone(), args))
-                            .await
-                            .map_err(|e| {
-                                log::error!("while processing EmitEvent({}): {:#}", name, e);
-                                e
-                            })?;
-                    }
-                    Ok(())
-                }
-
-                let name = name.to_string();
-                promise::spawn::spawn(config::with_lua_config_on_main_thread(move |lua| {
-                    emit_event(lua, name, window, pane)
-                }))
-                .detach();
+                self.emit_window_event(name, pane);
             }
             CompleteSelectionOrOpenLinkAtMouseCursor(dest) => {
                 let text = self.selection_text(pane);
@@ -2457,29 +2775,12 @@ impl TermWindow {
         );
 
         let style = self.fonts.match_style(&config, &CellAttributes::default());
-        let glyph_info = {
-            let key = BorrowedShapeCacheKey { style, text };
-            match self.lookup_cached_shape(&key) {
-                Some(Ok(info)) => info,
-                Some(Err(err)) => return Err(err),
-                None => {
-                    let font = self.fonts.resolve_font(style)?;
-                    match font.shape(text) {
-                        Ok(info) => {
-                            self.shape_cache
-                                .borrow_mut()
-                                .put(key.to_owned(), Ok(Rc::new(info)));
-                            self.lookup_cached_shape(&key).unwrap().unwrap()
-                        }
-                        Err(err) => {
-                            let res = anyhow!("shaper error: {}", err);
-                            self.shape_cache.borrow_mut().put(key.to_owned(), Err(err));
-                            return Err(res);
-                        }
-                    }
-                }
-            }
-        };
+        let glyph_info = self.cached_shape(BorrowedShapeCacheKey {
+            style,
+            text,
+            direction: Direction::LeftToRight,
+            ligatures: true,
+        })?;
         let first_row_offset = if self.show_tab_bar { 1 } else { 0 };
         let not_followed_by_space = false;
 
@@ -2538,7 +2839,12 @@ impl TermWindow {
                 quad.set_texture(texture_rect);
                 quad.set_texture_adjust(left, top, right, bottom);
                 quad.set_underline(underline_tex_rect);
-                quad.set_has_color(glyph.has_color);
+                match &glyph.foreground {
+                    Some(sprite) => {
+                        quad.set_fg_mask(sprite.texture.to_texture_coords(slice.pixel_rect(sprite)))
+                    }
+                    None => quad.set_has_color(glyph.has_color),
+                }
                 quad.set_cursor(underline_tex_rect);
                 quad.set_cursor_color(background);
             }
@@ -2568,7 +2874,7 @@ impl TermWindow {
 
     fn paint_pane_opengl(&mut self, pos: &PositionedPane) -> anyhow::Result<()> {
         let config = configuration();
-        let palette = pos.pane.palette();
+        let palette = self.pane_palette(&pos.pane);
 
         let background_color = palette.resolve_bg(wezterm_term::color::ColorAttribute::Default);
         let first_line_offset = if self.show_tab_bar { 1 } else { 0 };
@@ -2600,24 +2906,35 @@ impl TermWindow {
         let cursor_border_color = rgbcolor_to_window_color(palette.cursor_border);
         let foreground = rgbcolor_to_window_color(palette.foreground);
 
+        let renderer = LineRenderer {
+            fonts: &self.fonts,
+            render_metrics: &self.render_metrics,
+            glyph_cache: &gl_state.glyph_cache,
+            util_sprites: &gl_state.util_sprites,
+            shape_cache: &self.shape_cache,
+            bidi_cache: &self.bidi_cache,
+            window_is_transparent: self.window_background.is_some()
+                || config.window_background_opacity != 1.0,
+            highlight: self.current_highlight.as_ref(),
+            focused: self.focused.is_some(),
+            last_cursor_movement: self.prev_cursor.last_cursor_movement(),
+        };
+
         if self.show_tab_bar && pos.index == 0 {
-            let tab_dims = RenderableDimensions {
-                cols: self.terminal_size.cols as _,
-                ..dims
-            };
-            self.render_screen_line_opengl(
-                RenderScreenLineOpenGLParams {
+            renderer.render_screen_line(
+                RenderScreenLineParams {
                     line_idx: 0,
+                    left: pos.left,
+                    top: pos.top,
+                    num_cols: self.terminal_size.cols as _,
                     stable_line_idx: None,
                     line: self.tab_bar.line(),
                     selection: 0..0,
                     cursor: &cursor,
                     palette: &palette,
-                    dims: &tab_dims,
                     config: &config,
                     cursor_border_color,
                     foreground,
-                    pos,
                     is_active: true,
                 },
                 &mut quads,
@@ -2706,19 +3023,20 @@ impl TermWindow {
                 .map(|sel| sel.cols_for_row(stable_row))
                 .unwrap_or(0..0);
 
-            self.render_screen_line_opengl(
-                RenderScreenLineOpenGLParams {
+            renderer.render_screen_line(
+                RenderScreenLineParams {
                     line_idx: line_idx + first_line_offset,
+                    left: pos.left,
+                    top: pos.top,
+                    num_cols: dims.cols,
                     stable_line_idx: Some(stable_row),
                     line: &line,
                     selection: selrange,
                     cursor: &cursor,
                     palette: &palette,
-                    dims: &dims,
                     config: &config,
                     cursor_border_color,
                     foreground,
-                    pos,
                     is_active: pos.is_active,
                 },
                 &mut quads,
@@ -2844,503 +3162,10 @@ impl TermWindow {
         Ok(())
     }
 
-    fn lookup_cached_shape(
-        &self,
-        key: &dyn ShapeCacheKeyTrait,
-    ) -> Option<anyhow::Result<Rc<Vec<GlyphInfo>>>> {
-        match self.shape_cache.borrow_mut().get(key) {
-            Some(Ok(info)) => Some(Ok(Rc::clone(info))),
-            Some(Err(err)) => Some(Err(anyhow!("cached shaper error: {}", err))),
-            None => None,
-        }
-    }
-
-    /// "Render" a line of the terminal screen into the vertex buffer.
-    /// This is nominally a matter of setting the fg/bg color and the
-    /// texture coordinates for a given glyph.  There's a little bit
-    /// of extra complexity to deal with multi-cell glyphs.
-    fn render_screen_line_opengl(
-        &self,
-        params: RenderScreenLineOpenGLParams,
-        quads: &mut MappedQuads,
-    ) -> anyhow::Result<()> {
-        let gl_state = self.render_state.as_ref().unwrap();
-
-        let num_cols = params.dims.cols;
-
-        let hsv = if params.is_active {
-            None
-        } else {
-            Some(params.config.inactive_pane_hsb)
-        };
-
-        let window_is_transparent =
-            self.window_background.is_some() || params.config.window_background_opacity != 1.0;
-
-        let white_space = gl_state.util_sprites.white_space.texture_coords();
-
-        // Pre-set the row with the whitespace glyph.
-        // This is here primarily because clustering/shaping can cause the line updates
-        // to skip setting a quad that is logically obscured by a double-wide glyph.
-        // If eg: scrolling the viewport causes the pair of quads to change from two
-        // individual cells to a single double-wide cell then we might leave the second
-        // one of the pair with the glyph from the prior viewport position.
-        for cell_idx in 0..num_cols {
-            let mut quad =
-                match quads.cell(cell_idx + params.pos.left, params.line_idx + params.pos.top) {
-                    Ok(quad) => quad,
-                    Err(_) => break,
-                };
-
-            quad.set_texture(white_space);
-            quad.set_texture_adjust(0., 0., 0., 0.);
-            quad.set_underline(white_space);
-            quad.set_cursor(white_space);
-        }
-
-        // Break the line into clusters of cells with the same attributes
-        let cell_clusters = params.line.cluster();
-        let mut last_cell_idx = None;
-        for cluster in &cell_clusters {
-            let attrs = &cluster.attrs;
-            let is_highlited_hyperlink = match (attrs.hyperlink(), &self.current_highlight) {
-                (Some(ref this), &Some(ref highlight)) => Arc::ptr_eq(this, highlight),
-                _ => false,
-            };
-            let style = self.fonts.match_style(params.config, attrs);
-
-            let bg_is_default = attrs.background == ColorAttribute::Default;
-            let bg_color = params.palette.resolve_bg(attrs.background);
-
-            fn resolve_fg_color_attr(
-                attrs: &CellAttributes,
-                fg: &ColorAttribute,
-                params: &RenderScreenLineOpenGLParams,
-                style: &config::TextStyle,
-            ) -> RgbColor {
-                match fg {
-                    wezterm_term::color::ColorAttribute::Default => {
-                        if let Some(fg) = style.foreground {
-                            fg
-                        } else {
-                            params.palette.resolve_fg(attrs.foreground)
-                        }
-                    }
-                    wezterm_term::color::ColorAttribute::PaletteIndex(idx)
-                        if *idx < 8 && params.config.bold_brightens_ansi_colors =>
-                    {
-                        // For compatibility purposes, switch to a brighter version
-                        // of one of the standard ANSI colors when Bold is enabled.
-                        // This lifts black to dark grey.
-                        let idx = if attrs.intensity() == wezterm_term::Intensity::Bold {
-                            *idx + 8
-                        } else {
-                            *idx
-                        };
-                        params
-                            .palette
-                            .resolve_fg(wezterm_term::color::ColorAttribute::PaletteIndex(idx))
-                    }
-                    _ => params.palette.resolve_fg(*fg),
-                }
-            }
-            let fg_color = resolve_fg_color_attr(&attrs, &attrs.foreground, &params, &style);
-
-            let (fg_color, bg_color, bg_is_default) = {
-                let mut fg = fg_color;
-                let mut bg = bg_color;
-                let mut bg_default = bg_is_default;
-
-                if attrs.reverse() {
-                    std::mem::swap(&mut fg, &mut bg);
-                    bg_default = false;
-                }
-
-                (fg, bg, bg_default)
-            };
-
-            let glyph_color = rgbcolor_to_window_color(fg_color);
-            let underline_color = match attrs.underline_color() {
-                ColorAttribute::Default => fg_color,
-                c => resolve_fg_color_attr(&attrs, &c, &params, &style),
-            };
-            let underline_color = rgbcolor_to_window_color(underline_color);
-
-            let bg_color = rgbcolor_alpha_to_window_color(
-                bg_color,
-                if window_is_transparent && bg_is_default {
-                    0x00
-                } else {
-                    (params.config.text_background_opacity * 255.0) as u8
-                },
-            );
-
-            // Shape the printable text from this cluster
-            let glyph_info = {
-                let key = BorrowedShapeCacheKey {
-                    style,
-                    text: &cluster.text,
-                };
-                match self.lookup_cached_shape(&key) {
-                    Some(Ok(info)) => info,
-                    Some(Err(err)) => return Err(err),
-                    None => {
-                        let font = self.fonts.resolve_font(style)?;
-                        match font.shape(&cluster.text) {
-                            Ok(info) => {
-                                self.shape_cache
-                                    .borrow_mut()
-                                    .put(key.to_owned(), Ok(Rc::new(info)));
-                                self.lookup_cached_shape(&key).unwrap().unwrap()
-                            }
-                            Err(err) => {
-                                let res = anyhow!("shaper error: {}", err);
-                                self.shape_cache.borrow_mut().put(key.to_owned(), Err(err));
-                                return Err(res);
-                            }
-                        }
-                    }
-                }
-            };
-
-            for info in glyph_info.iter() {
-                let cell_idx = cluster.byte_to_cell_idx[info.cluster as usize];
-
-                if last_cell_idx.is_some() && cell_idx <= last_cell_idx.unwrap() {
-                    // This is a tricky case: if we have a cluster such as
-                    // 1F470 1F3FF 200D 2640 (woman with veil: dark skin tone)
-                    // and the font doesn't define a glyph for it, the shaper
-                    // may give us a sequence of three output clusters, each
-                    // comprising: veil, skin tone and female respectively.
-                    // Those all have the same info.cluster which
-                    // means that they all resolve to the same cell_idx.
-                    // In this case, the cluster is logically a single cell,
-                    // and the best presentation is of the veil, so we pick
-                    // that one and ignore the rest of the glyphs that map to
-                    // this same cell.
-                    // Ideally we'd overlay this with a "something is broken"
-                    // glyph in the corner.
-                    continue;
-                }
-
-                let followed_by_space = match params.line.cells().get(cell_idx + 1) {
-                    Some(cell) => cell.str() == " ",
-                    None => false,
-                };
-
-                let glyph = gl_state.glyph_cache.borrow_mut().cached_glyph(
-                    info,
-                    style,
-                    followed_by_space,
-                )?;
-
-                let left = (glyph.x_offset + glyph.bearing_x).get() as f32;
-                let top = ((PixelLength::new(self.render_metrics.cell_size.height as f64)
-                    + self.render_metrics.descender)
-                    - (glyph.y_offset + glyph.bearing_y))
-                    .get() as f32;
-
-                // underline and strikethrough
-                let underline_tex_rect = gl_state
-                    .glyph_cache
-                    .borrow_mut()
-                    .cached_line_sprite(
-                        is_highlited_hyperlink,
-                        attrs.strikethrough(),
-                        attrs.underline(),
-                        attrs.overline(),
-                    )?
-                    .texture_coords();
-
-                // Iterate each cell that comprises this glyph.  There is usually
-                // a single cell per glyph but combining characters, ligatures
-                // and emoji can be 2 or more cells wide.
-                for glyph_idx in 0..info.num_cells as usize {
-                    let cell_idx = cell_idx + glyph_idx;
-
-                    if cell_idx >= num_cols {
-                        // terminal line data is wider than the window.
-                        // This happens for example while live resizing the window
-                        // smaller than the terminal.
-                        break;
-                    }
-
-                    last_cell_idx.replace(cell_idx);
-
-                    let ComputeCellFgBgResult {
-                        fg_color: glyph_color,
-                        bg_color,
-                        cursor_shape,
-                    } = self.compute_cell_fg_bg(ComputeCellFgBgParams {
-                        stable_line_idx: params.stable_line_idx,
-                        cell_idx,
-                        cursor: params.cursor,
-                        selection: &params.selection,
-                        fg_color: glyph_color,
-                        bg_color,
-                        palette: params.palette,
-                        is_active_pane: params.pos.is_active,
-                        config: params.config,
-                    });
-
-                    if let Some(image) = attrs.image() {
-                        // Render iTerm2 style image attributes
-
-                        let padding = self
-                            .render_metrics
-                            .cell_size
-                            .height
-                            .max(self.render_metrics.cell_size.width)
-                            as usize;
-                        let padding = if padding.is_power_of_two() {
-                            padding
-                        } else {
-                            padding.next_power_of_two()
-                        };
-
-                        let sprite = gl_state
-                            .glyph_cache
-                            .borrow_mut()
-                            .cached_image(image.image_data(), Some(padding))?;
-                        let width = sprite.coords.size.width;
-                        let height = sprite.coords.size.height;
-
-                        let top_left = image.top_left();
-                        let bottom_right = image.bottom_right();
-
-                        // We *could* call sprite.texture.to_texture_coords() here,
-                        // but since that takes integer pixel coordinates, we'd
-                        // lose precision and end up with visual artifacts.
-                        // Instead, we compute the texture coords here in floating point.
-
-                        let texture_width = sprite.texture.width() as f32;
-                        let texture_height = sprite.texture.height() as f32;
-                        let origin = TextureCoord::new(
-                            (sprite.coords.origin.x as f32 + (*top_left.x * width as f32))
-                                / texture_width,
-                            (sprite.coords.origin.y as f32 + (*top_left.y * height as f32))
-                                / texture_height,
-                        );
-
-                        let size = TextureSize::new(
-                            (*bottom_right.x - *top_left.x) * width as f32 / texture_width,
-                            (*bottom_right.y - *top_left.y) * height as f32 / texture_height,
-                        );
-
-                        let texture_rect = TextureRect::new(origin, size);
-
-                        let mut quad = match quads
-                            .cell(cell_idx + params.pos.left, params.line_idx + params.pos.top)
-                        {
-                            Ok(quad) => quad,
-                            Err(_) => break,
-                        };
-
-                        quad.set_hsv(hsv);
-                        quad.set_fg_color(glyph_color);
-                        quad.set_underline_color(underline_color);
-                        quad.set_bg_color(bg_color);
-                        quad.set_texture(texture_rect);
-                        quad.set_texture_adjust(0., 0., 0., 0.);
-                        quad.set_underline(white_space);
-                        quad.set_has_color(true);
-                        quad.set_cursor(
-                            gl_state
-                                .util_sprites
-                                .cursor_sprite(cursor_shape)
-                                .texture_coords(),
-                        );
-                        quad.set_cursor_color(params.cursor_border_color);
-
-                        continue;
-                    }
-
-                    let texture = glyph
-                        .texture
-                        .as_ref()
-                        .unwrap_or(&gl_state.util_sprites.white_space);
-
-                    let slice = SpriteSlice {
-                        cell_idx: glyph_idx,
-                        num_cells: info.num_cells as usize,
-                        cell_width: self.render_metrics.cell_size.width as usize,
-                        scale: glyph.scale as f32,
-                        left_offset: left,
-                    };
-
-                    let pixel_rect = slice.pixel_rect(texture);
-                    let texture_rect = texture.texture.to_texture_coords(pixel_rect);
-
-                    let left = if glyph_idx == 0 { left } else { 0.0 };
-                    let bottom = (pixel_rect.size.height as f32 * glyph.scale as f32) + top
-                        - self.render_metrics.cell_size.height as f32;
-                    let right = pixel_rect.size.width as f32 + left
-                        - self.render_metrics.cell_size.width as f32;
-
-                    let mut quad = match quads
-                        .cell(cell_idx + params.pos.left, params.line_idx + params.pos.top)
-                    {
-                        Ok(quad) => quad,
-                        Err(_) => break,
-                    };
-
-                    quad.set_fg_color(glyph_color);
-                    quad.set_bg_color(bg_color);
-                    quad.set_texture(texture_rect);
-                    quad.set_texture_adjust(left, top, right, bottom);
-                    quad.set_underline(underline_tex_rect);
-                    quad.set_underline_color(underline_color);
-                    quad.set_hsv(hsv);
-                    quad.set_has_color(glyph.has_color);
-                    quad.set_cursor(
-                        gl_state
-                            .util_sprites
-                            .cursor_sprite(cursor_shape)
-                            .texture_coords(),
-                    );
-                    quad.set_cursor_color(params.cursor_border_color);
-                }
-            }
-        }
-
-        // Clear any remaining cells to the right of the clusters we
-        // found above, otherwise we leave artifacts behind.  The easiest
-        // reproduction for the artifacts is to maximize the window and
-        // open a vim split horizontally.  Backgrounding vim would leave
-        // the right pane with its prior contents instead of showing the
-        // cleared lines from the shell in the main screen.
-
-        for cell_idx in last_cell_idx.unwrap_or(0) + 1..num_cols {
-            // Even though we don't have a cell for these, they still
-            // hold the cursor or the selection so we need to compute
-            // the colors in the usual way.
-
-            let ComputeCellFgBgResult {
-                fg_color: glyph_color,
-                bg_color,
-                cursor_shape,
-            } = self.compute_cell_fg_bg(ComputeCellFgBgParams {
-                stable_line_idx: params.stable_line_idx,
-                cell_idx,
-                cursor: params.cursor,
-                selection: &params.selection,
-                fg_color: params.foreground,
-                bg_color: rgbcolor_alpha_to_window_color(
-                    params.palette.resolve_bg(ColorAttribute::Default),
-                    if window_is_transparent {
-                        0x00
-                    } else {
-                        (params.config.text_background_opacity * 255.0) as u8
-                    },
-                ),
-                palette: params.palette,
-                is_active_pane: params.pos.is_active,
-                config: params.config,
-            });
-
-            let mut quad =
-                match quads.cell(cell_idx + params.pos.left, params.line_idx + params.pos.top) {
-                    Ok(quad) => quad,
-                    Err(_) => break,
-                };
-
-            quad.set_bg_color(bg_color);
-            quad.set_fg_color(glyph_color);
-            quad.set_underline_color(glyph_color);
-            quad.set_texture(white_space);
-            quad.set_texture_adjust(0., 0., 0., 0.);
-            quad.set_underline(white_space);
-            quad.set_has_color(false);
-            quad.set_hsv(hsv);
-            quad.set_cursor(
-                gl_state
-                    .util_sprites
-                    .cursor_sprite(cursor_shape)
-                    .texture_coords(),
-            );
-            quad.set_cursor_color(params.cursor_border_color);
-        }
-
-        Ok(())
-    }
-
-    fn compute_cell_fg_bg(&self, params: ComputeCellFgBgParams) -> ComputeCellFgBgResult {
-        let selected = params.selection.contains(&params.cell_idx);
-
-        let is_cursor =
-            params.stable_line_idx == Some(params.cursor.y) && params.cursor.x == params.cell_idx;
-
-        let (cursor_shape, visibility) =
-            if is_cursor && params.cursor.visibility == CursorVisibility::Visible {
-                // This logic figures out whether the cursor is visible or not.
-                // If the cursor is explicitly hidden then it is obviously not
-                // visible.
-                // If the cursor is set to a blinking mode then we are visible
-                // depending on the current time.
-                let shape = params
-                    .config
-                    .default_cursor_style
-                    .effective_shape(params.cursor.shape);
-                // Work out the blinking shape if its a blinking cursor and it hasn't been disabled
-                // and the window is focused.
-                let blinking = params.is_active_pane
-                    && shape.is_blinking()
-                    && params.config.cursor_blink_rate != 0
-                    && self.focused.is_some();
-                if blinking {
-                    // Divide the time since we last moved by the blink rate.
-                    // If the result is even then the cursor is "on", else it
-                    // is "off"
-                    let now = std::time::Instant::now();
-                    let milli_uptime = now
-                        .duration_since(self.prev_cursor.last_cursor_movement())
-                        .as_millis();
-                    let ticks = milli_uptime / params.config.cursor_blink_rate as u128;
-                    (
-                        shape,
-                        if (ticks & 1) == 0 {
-                            CursorVisibility::Visible
-                        } else {
-                            CursorVisibility::Hidden
-                        },
-                    )
-                } else {
-                    (shape, CursorVisibility::Visible)
-                }
-            } else {
-                (params.cursor.shape, CursorVisibility::Hidden)
-            };
-
-        let (fg_color, bg_color) = match (
-            selected,
-            self.focused.is_some() && params.is_active_pane,
-            cursor_shape,
-            visibility,
-        ) {
-            // Selected text overrides colors
-            (true, _, _, CursorVisibility::Hidden) => (
-                rgbcolor_to_window_color(params.palette.selection_fg),
-                rgbcolor_to_window_color(params.palette.selection_bg),
-            ),
-            // Cursor cell overrides colors
-            (_, true, CursorShape::BlinkingBlock, CursorVisibility::Visible)
-            | (_, true, CursorShape::SteadyBlock, CursorVisibility::Visible) => (
-                rgbcolor_to_window_color(params.palette.cursor_fg),
-                rgbcolor_to_window_color(params.palette.cursor_bg),
-            ),
-            // Normally, render the cell as configured (or if the window is unfocused)
-            _ => (params.fg_color, params.bg_color),
-        };
-
-        ComputeCellFgBgResult {
-            fg_color,
-            bg_color,
-            cursor_shape: if visibility == CursorVisibility::Visible {
-                Some(cursor_shape)
-            } else {
-                None
-            },
-        }
+    /// Returns the shaped glyphs for the text described by `key`,
+    /// shaping and caching them if they were not already cached.
+    fn cached_shape(&self, key: BorrowedShapeCacheKey) -> anyhow::Result<Rc<Vec<GlyphInfo>>> {
+        cached_shape(&self.shape_cache, &self.fonts, key)
     }
 
     pub fn pane_state(&self, pane_id: PaneId) -> RefMut<PaneState> {
@@ -3617,6 +3442,25 @@ impl TermWindow {
         event: &MouseEvent,
         context: &dyn WindowOps,
     ) {
+        // Compute the position of the mouse within the cell, so that
+        // pixel based mouse reporting can be precise.  The split and
+        // pane adjustments made to x and y below are whole cells, so
+        // these remain valid after those adjustments.
+        let config = configuration();
+        let first_line_offset = if self.show_tab_bar { 1 } else { 0 };
+        let x_pixel_offset = event
+            .coords
+            .x
+            .sub(config.window_padding.left as isize)
+            .max(0)
+            - (x as isize * self.render_metrics.cell_size.width);
+        let y_pixel_offset = event
+            .coords
+            .y
+            .sub(config.window_padding.top as isize)
+            .max(0)
+            - ((y + first_line_offset) as isize * self.render_metrics.cell_size.height);
+
         let mut on_split = None;
         if y >= 0 {
             let y = y as usize;
@@ -3687,12 +3531,35 @@ impl TermWindow {
             .unwrap_or(dims.physical_top)
             + y as StableRowIndex;
 
-        self.last_mouse_terminal_coords = (x, stable_row); // FIXME: per-pane
-
         let (top, mut lines) = pane.get_lines(stable_row..stable_row + 1);
+
+        // Each cell of a double width or double height line spans two
+        // columns of the display, so the mouse position needs to be
+        // scaled down to find the cell, and the position within the
+        // cell adjusted to match.
+        let (x, x_pixel_offset) = match lines.get(0) {
+            Some(line) if top == stable_row && line.line_size().is_double_width() => (
+                x / 2,
+                x_pixel_offset + (x % 2) as isize * self.render_metrics.cell_size.width,
+            ),
+            _ => (x, x_pixel_offset),
+        };
+
+        // The mouse position is in terms of the visual column; when the
+        // line has been reordered by the bidi algorithm we need the
+        // logical cell for the purposes of selection and hyperlinks.
+        let logical_x = match lines.get(0) {
+            Some(line) if top == stable_row && line.is_bidi_enabled() => {
+                cached_bidi(&self.bidi_cache, line).visual_to_logical(x)
+            }
+            _ => x,
+        };
+
+        self.last_mouse_terminal_coords = (logical_x, stable_row); // FIXME: per-pane
+
         let new_highlight = if top == stable_row {
             if let Some(line) = lines.get_mut(0) {
-                if let Some(cell) = line.cells().get(x) {
+                if let Some(cell) = line.cells().get(logical_x) {
                     cell.attrs().hyperlink().cloned()
                 } else {
                     None
@@ -3825,6 +3692,8 @@ impl TermWindow {
             },
             x,
             y,
+            x_pixel_offset,
+            y_pixel_offset,
             modifiers: window_mods_to_termwiz_mods(event.modifiers),
         };
 
@@ -3975,14 +3844,83 @@ impl TermWindow {
     }
 }
 
-fn rgbcolor_to_window_color(color: RgbColor) -> Color {
+/// Computes the texture coordinates and bearing adjustment for one of
+/// the quads that comprise a cell on a double width or double height line.
+/// `half` is 0 for the left quad and 1 for the right quad of the cell.
+/// `adjust` is the (left, top, right, bottom) adjustment that would be
+/// used to render the glyph at its normal size.
+/// Returns None if no part of the glyph falls within the quad.
+pub fn scale_glyph_for_line_size(
+    line_size: LineSize,
+    half: usize,
+    texture_rect: TextureRect,
+    adjust: (f32, f32, f32, f32),
+    cell_width: f32,
+    cell_height: f32,
+) -> Option<(TextureRect, f32, f32, f32, f32)> {
+    let (left, top, right, bottom) = adjust;
+
+    // The glyph bounds at 2x horizontal scale, relative to the
+    // top left corner of the first quad
+    let x0 = left * 2.;
+    let x1 = (cell_width + right) * 2.;
+    let (y0, y1) = if line_size.is_double_height() {
+        (top * 2., (cell_height + bottom) * 2.)
+    } else {
+        (top, cell_height + bottom)
+    };
+
+    // The portion of the scaled glyph bounds that is covered by this quad
+    let quad_x = half as f32 * cell_width;
+    let (clip_x0, clip_x1) = if half == 0 {
+        (x0, x1.min(cell_width))
+    } else {
+        (x0.max(cell_width), x1)
+    };
+    let (quad_y, clip_y0, clip_y1) = match line_size {
+        LineSize::DoubleHeightTop => (0., y0, y1.min(cell_height)),
+        LineSize::DoubleHeightBottom => (cell_height, y0.max(cell_height), y1),
+        _ => (0., y0, y1),
+    };
+
+    if clip_x1 <= clip_x0 || clip_y1 <= clip_y0 {
+        return None;
+    }
+
+    let tex_x = |x: f32| texture_rect.min_x() + (x - x0) / (x1 - x0) * texture_rect.width();
+    let tex_y = |y: f32| texture_rect.min_y() + (y - y0) / (y1 - y0) * texture_rect.height();
+    let origin = TextureCoord::new(tex_x(clip_x0), tex_y(clip_y0));
+    let size = TextureSize::new(tex_x(clip_x1) - origin.x, tex_y(clip_y1) - origin.y);
+
+    Some((
+        TextureRect::new(origin, size),
+        clip_x0 - quad_x,
+        clip_y0 - quad_y,
+        clip_x1 - quad_x - cell_width,
+        clip_y1 - quad_y - cell_height,
+    ))
+}
+
+pub fn rgbcolor_to_window_color(color: RgbColor) -> Color {
     rgbcolor_alpha_to_window_color(color, 0xff)
 }
 
-fn rgbcolor_alpha_to_window_color(color: RgbColor, alpha: u8) -> Color {
+pub fn rgbcolor_alpha_to_window_color(color: RgbColor, alpha: u8) -> Color {
     Color::rgba(color.red, color.green, color.blue, alpha)
 }
 
+/// Linearly interpolate between two colors; `t` is in the
+/// range 0.0 (returns `a`) to 1.0 (returns `b`)
+fn mix_colors(a: RgbColor, b: RgbColor, t: f32) -> RgbColor {
+    let t = t.max(0.0).min(1.0);
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    RgbColor::new(
+        mix(a.red, b.red),
+        mix(a.green, b.green),
+        mix(a.blue, b.blue),
+    )
+}
+
 fn window_mods_to_termwiz_mods(modifiers: ::window::Modifiers) -> termwiz::input::Modifiers {
     let mut result = termwiz::input::Modifiers::NONE;
     if modifiers.contains(::window::Modifiers::SHIFT) {
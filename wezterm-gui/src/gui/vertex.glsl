@@ -3,6 +3,7 @@ precision highp float;
 in vec2 position;
 in vec2 adjust;
 in vec2 tex;
+in vec2 fg_mask;
 in vec2 underline;
 in vec4 bg_color;
 in vec4 fg_color;
@@ -20,6 +21,7 @@ uniform bool has_background_image;
 out float o_has_color;
 out vec2 o_cursor;
 out vec2 o_tex;
+out vec2 o_fg_mask;
 out vec2 o_underline;
 out vec3 o_hsv;
 out vec4 o_bg_color;
@@ -37,6 +39,7 @@ vec4 off_screen() {
 
 void main() {
     o_tex = tex;
+    o_fg_mask = fg_mask;
     o_has_color = has_color;
     o_fg_color = fg_color;
     o_bg_color = bg_color;
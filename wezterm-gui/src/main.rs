@@ -60,6 +60,15 @@ enum SubCommand {
 
     #[structopt(name = "connect", about = "Connect to wezterm multiplexer")]
     Connect(ConnectCommand),
+
+    #[structopt(name = "ls-fonts", about = "Display information about fonts")]
+    LsFonts(LsFontsCommand),
+
+    #[structopt(
+        name = "render-png",
+        about = "Render terminal output to a PNG file using the software renderer"
+    )]
+    RenderPng(RenderPngCommand),
 }
 
 async fn async_run_ssh(opts: SshCommand) -> anyhow::Result<()> {
@@ -182,7 +191,7 @@ fn run_mux_client(config: config::ConfigHandle, opts: &ConnectCommand) -> anyhow
             )
         })?;
 
-    let domain: Arc<dyn Domain> = Arc::new(ClientDomain::new(client_config));
+    let domain: Arc<dyn Domain> = Arc::new(ClientDomain::new(client_config, opts.read_only));
     let mux = Rc::new(mux::Mux::new(Some(domain.clone())));
     Mux::set_mux(&mux);
     crate::update::load_last_release_info_and_set_banner();
@@ -250,7 +259,7 @@ async fn async_run_terminal_gui(
         let config = config::configuration();
         for client_config in client_domains(&config) {
             let connect_automatically = client_config.connect_automatically();
-            let dom = record_domain(&mux, ClientDomain::new(client_config))?;
+            let dom = record_domain(&mux, ClientDomain::new(client_config, false))?;
             if connect_automatically {
                 dom.attach().await?;
             }
@@ -440,5 +449,215 @@ fn run() -> anyhow::Result<()> {
         SubCommand::Ssh(ssh) => run_ssh(config, ssh),
         SubCommand::Serial(serial) => run_serial(config, &serial),
         SubCommand::Connect(connect) => run_mux_client(config, &connect),
+        SubCommand::LsFonts(cmd) => run_ls_fonts(config, &cmd),
+        SubCommand::RenderPng(cmd) => run_render_png(config, &cmd),
+    }
+}
+
+fn run_render_png(config: config::ConfigHandle, cmd: &RenderPngCommand) -> anyhow::Result<()> {
+    use std::io::Read;
+    use termwiz::surface::{Change, Position, Surface};
+    use wezterm_font::FontConfiguration;
+    use wezterm_term::{Terminal, TerminalSize};
+
+    let mut data = vec![];
+    match &cmd.input {
+        Some(path) => {
+            data = std::fs::read(path)?;
+        }
+        None => {
+            std::io::stdin().read_to_end(&mut data)?;
+        }
+    }
+
+    // Let the terminal model interpret the escape sequences, then
+    // transfer the result to a Surface for rendering
+    let mut terminal = Terminal::new(
+        TerminalSize {
+            physical_rows: cmd.rows,
+            physical_cols: cmd.cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        },
+        Arc::new(config::TermConfig),
+        "WezTerm",
+        config::wezterm_version(),
+        Box::new(std::io::sink()),
+    );
+    terminal.advance_bytes(&data);
+
+    let mut surface = Surface::new(cmd.cols, cmd.rows);
+    let lines = &terminal.screen().lines;
+    let first_visible = lines.len().saturating_sub(cmd.rows);
+    for (row, line) in lines.iter().skip(first_visible).enumerate() {
+        for (col, cell) in line.visible_cells() {
+            surface.add_changes(vec![
+                Change::CursorPosition {
+                    x: Position::Absolute(col),
+                    y: Position::Absolute(row),
+                },
+                Change::AllAttributes(cell.attrs().clone()),
+                Change::Text(cell.str().to_string()),
+            ]);
+        }
+    }
+    let cursor = terminal.cursor_pos();
+    surface.add_changes(vec![
+        Change::CursorPosition {
+            x: Position::Absolute(cursor.x),
+            y: Position::Absolute(cursor.y.max(0) as usize),
+        },
+        Change::CursorShape(cursor.shape),
+        Change::CursorVisibility(cursor.visibility),
+    ]);
+
+    let fonts = Rc::new(FontConfiguration::new()?);
+    let image = gui::softrender::render_surface(&surface, &config, &fonts)?;
+    gui::softrender::write_png(&image, &cmd.output)
+}
+
+/// Describes the font referenced by a handle, for diagnostic purposes
+fn describe_font_handle(handle: &wezterm_font::locator::FontDataHandle) -> String {
+    use wezterm_font::locator::FontDataHandle;
+    use wezterm_font::parser::ParsedFont;
+
+    let name = match ParsedFont::from_locator(handle) {
+        Ok(parsed) => parsed.names().full_name.clone(),
+        Err(err) => format!("<unable to parse: {:#}>", err),
+    };
+    match handle {
+        FontDataHandle::OnDisk { path, index } => {
+            format!("{}, {}, index={}", name, path.display(), index)
+        }
+        FontDataHandle::Memory { name: source, .. } => {
+            format!("{}, <built-in> {}", name, source)
+        }
+    }
+}
+
+/// Prints the fonts and metrics that were resolved for a text style
+fn print_text_style(
+    font_config: &wezterm_font::FontConfiguration,
+    label: &str,
+    style: &config::TextStyle,
+) -> anyhow::Result<()> {
+    use wezterm_font::parser::ParsedFont;
+
+    println!("{}:", label);
+    for attr in &style.font {
+        println!("  requested: {}", attr);
     }
+
+    let font = font_config.resolve_font(style)?;
+    for (idx, handle) in font.clone_handles().iter().enumerate() {
+        println!("  {:2}: {}", idx, describe_font_handle(handle));
+        match ParsedFont::from_locator(handle) {
+            Ok(parsed) => {
+                let names = parsed.names();
+                println!(
+                    "      family={} sub_family={} weight={} stretch={:?} italic={} \
+                    variable={} source={}",
+                    names.family.as_deref().unwrap_or("?"),
+                    names.sub_family.as_deref().unwrap_or("?"),
+                    parsed.weight(),
+                    parsed.stretch(),
+                    parsed.italic(),
+                    parsed.is_variable(),
+                    font_config.font_source(handle)
+                );
+            }
+            Err(_) => println!("      source={}", font_config.font_source(handle)),
+        }
+    }
+
+    let metrics = font.metrics();
+    println!(
+        "  metrics: cell={}x{} descender={} underline_thickness={} underline_position={}",
+        metrics.cell_width.get(),
+        metrics.cell_height.get(),
+        metrics.descender.get(),
+        metrics.underline_thickness.get(),
+        metrics.underline_position.get()
+    );
+    println!();
+    Ok(())
+}
+
+/// Describes the attributes that a font rule matches
+fn describe_style_rule(rule: &config::StyleRule) -> String {
+    let mut conditions = vec![];
+    macro_rules! cond {
+        ($ident:ident) => {
+            if let Some(value) = &rule.$ident {
+                conditions.push(format!("{}={:?}", stringify!($ident), value));
+            }
+        };
+    }
+    cond!(intensity);
+    cond!(underline);
+    cond!(italic);
+    cond!(blink);
+    cond!(reverse);
+    cond!(strikethrough);
+    cond!(invisible);
+
+    if conditions.is_empty() {
+        "any".to_string()
+    } else {
+        conditions.join(" ")
+    }
+}
+
+fn run_ls_fonts(config: config::ConfigHandle, cmd: &LsFontsCommand) -> anyhow::Result<()> {
+    use wezterm_font::shaper::Direction;
+    use wezterm_font::FontConfiguration;
+
+    let font_config = FontConfiguration::new()?;
+
+    if let Some(text) = &cmd.text {
+        let font = font_config.resolve_font(&config.font)?;
+        let infos = font.shape(text, Direction::LeftToRight)?;
+        // Fallback fonts may have been added while shaping
+        let handles = font.clone_handles();
+
+        for (idx, info) in infos.iter().enumerate() {
+            let start = info.cluster as usize;
+            let end = infos
+                .iter()
+                .skip(idx + 1)
+                .map(|next| next.cluster as usize)
+                .find(|&next| next > start)
+                .unwrap_or_else(|| text.len());
+            let cluster = &text[start..end];
+            let escaped = cluster
+                .chars()
+                .map(|c| format!("U+{:X}", c as u32))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let font = handles
+                .get(info.font_idx)
+                .map(describe_font_handle)
+                .unwrap_or_else(|| format!("<no font at index {}>", info.font_idx));
+            println!(
+                "{:4} {:12} glyph={:<5} {}",
+                cluster, escaped, info.glyph_pos, font
+            );
+        }
+        return Ok(());
+    }
+
+    print_text_style(&font_config, "font (regular text)", &config.font)?;
+    for (idx, rule) in config.font_rules.iter().enumerate() {
+        let label = format!("font_rules[{}] when {}", idx, describe_style_rule(rule));
+        print_text_style(&font_config, &label, &rule.font)?;
+    }
+
+    if cmd.list_system {
+        println!("Fonts found by the system font locator:");
+        for handle in font_config.list_system_fonts()? {
+            println!("  {}", describe_font_handle(&handle));
+        }
+    }
+
+    Ok(())
 }
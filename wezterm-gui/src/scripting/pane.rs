@@ -5,6 +5,8 @@ use mlua::{UserData, UserDataMethods};
 use mux::pane::{Pane, PaneId};
 use mux::Mux;
 use std::rc::Rc;
+use wezterm_client::health::ConnectionState;
+use wezterm_client::pane::ClientPane;
 
 #[derive(Clone)]
 pub struct PaneObject {
@@ -49,6 +51,38 @@ impl UserData for PaneObject {
             Ok(this.pane()?.get_dimensions())
         });
 
+        // Returns a table describing the health of the connection to
+        // the multiplexer server, or nil if this is a local pane
+        methods.add_method("get_connection_status", |lua, this, _: ()| {
+            let pane = this.pane()?;
+            let client_pane = match pane.downcast_ref::<ClientPane>() {
+                Some(client_pane) => client_pane,
+                None => return Ok(None),
+            };
+            let health = client_pane.connection_health();
+            let status = lua.create_table()?;
+            match health.state {
+                ConnectionState::Connected => {
+                    status.set("state", "Connected")?;
+                }
+                ConnectionState::Reconnecting { attempt, reason } => {
+                    status.set("state", "Reconnecting")?;
+                    status.set("attempt", attempt)?;
+                    status.set("reason", reason)?;
+                }
+                ConnectionState::Disconnected => {
+                    status.set("state", "Disconnected")?;
+                }
+            }
+            if let Some(latency) = health.latency {
+                status.set("latency_ms", latency.as_millis() as u64)?;
+            }
+            status.set("seconds_in_state", health.since.elapsed().as_secs())?;
+            status.set("buffered_inputs", client_pane.num_buffered_inputs())?;
+            status.set("summary", health.summary())?;
+            Ok(Some(status))
+        });
+
         // When called with no arguments, returns the lines from the
         // viewport as plain text (no escape sequences).
         // When called with an optional integer argument, returns the
@@ -11,6 +11,7 @@ static ENABLE_STAT_PRINT: AtomicBool = AtomicBool::new(true);
 
 struct Inner {
     histograms: HashMap<Key, Histogram<u64>>,
+    counters: HashMap<Key, u64>,
 }
 
 fn pctile_latency(histogram: &Histogram<u64>, p: f64) -> Duration {
@@ -39,6 +40,16 @@ impl Inner {
                 alignment: Alignment::Left,
             },
         ];
+        let counter_cols = vec![
+            Column {
+                name: "COUNTER".to_string(),
+                alignment: Alignment::Left,
+            },
+            Column {
+                name: "VALUE".to_string(),
+                alignment: Alignment::Right,
+            },
+        ];
 
         loop {
             std::thread::sleep(Duration::from_secs(10));
@@ -79,6 +90,17 @@ impl Inner {
                 data.sort_by(|a, b| a[0].cmp(&b[0]));
                 eprintln!();
                 tabulate_output(&cols, &data, &mut std::io::stderr().lock()).ok();
+
+                if !inner.counters.is_empty() {
+                    let mut data: Vec<Vec<String>> = inner
+                        .counters
+                        .iter()
+                        .map(|(key, value)| vec![key.to_string(), value.to_string()])
+                        .collect();
+                    data.sort_by(|a, b| a[0].cmp(&b[0]));
+                    eprintln!();
+                    tabulate_output(&counter_cols, &data, &mut std::io::stderr().lock()).ok();
+                }
                 last_print = Instant::now();
             }
         }
@@ -94,6 +116,7 @@ impl Stats {
         Self {
             inner: Arc::new(Mutex::new(Inner {
                 histograms: HashMap::new(),
+                counters: HashMap::new(),
             })),
         }
     }
@@ -124,6 +147,8 @@ impl Recorder for Stats {
 
     fn increment_counter(&self, key: Key, value: u64) {
         log::trace!("counter '{}' -> {}", key, value);
+        let mut inner = self.inner.lock().unwrap();
+        *inner.counters.entry(key).or_insert(0) += value;
     }
 
     fn update_gauge(&self, key: Key, value: GaugeValue) {
@@ -0,0 +1,29 @@
+//! Records connections to the mux server, and what they authenticated
+//! as, so that the owner of a shared host can review who has used it
+use crate::auth::PeerInfo;
+use config::configuration;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Log `event` for `peer`, and append it to the file specified
+/// by the `mux_audit_log` option, if any
+pub fn record(peer: &PeerInfo, event: &str) {
+    log::info!("{}: {}", peer.description, event);
+
+    let path = match configuration().mux_audit_log.clone() {
+        Some(path) => path,
+        None => return,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{} {}: {}", now, peer.description, event));
+    if let Err(err) = result {
+        log::error!("writing to audit log {}: {}", path.display(), err);
+    }
+}
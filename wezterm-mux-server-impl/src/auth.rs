@@ -0,0 +1,351 @@
+//! Manages the tokens that clients can use to authenticate with
+//! the mux server.  Only a hash of each token is stored; the token
+//! itself is revealed once, when it is created or rotated.
+use anyhow::{anyhow, bail, Context as _};
+use codec::AuthTokenInfo;
+use config::configuration;
+use mux::client::AuthPermission;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TOKEN_PREFIX: &str = "wzt_";
+const TOKEN_BYTES: usize = 32;
+/// `last_used` is only written back to the token file when it is
+/// older than this many seconds, so that a busy server doesn't
+/// rewrite the file every time a client connects
+const LAST_USED_RESOLUTION: u64 = 60 * 60;
+/// The longest that a connection has to wait between failed
+/// attempts to authenticate
+const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Describes the other end of a connection to the server
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    /// Identifies the peer in the audit log; for example, the
+    /// uid and pid of a local process or the address of a TLS client
+    pub description: String,
+    /// If true, the peer must authenticate before it can make
+    /// any other request
+    pub require_auth_token: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct StoredToken {
+    name: String,
+    permission: AuthPermission,
+    /// The hex encoded sha256 hash of the token
+    hash: String,
+    /// Seconds since the unix epoch
+    created: u64,
+    last_used: Option<u64>,
+}
+
+impl StoredToken {
+    fn info(&self) -> AuthTokenInfo {
+        AuthTokenInfo {
+            name: self.name.clone(),
+            permission: self.permission,
+            created: from_epoch_secs(self.created),
+            last_used: self.last_used.map(from_epoch_secs),
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct TokenFile {
+    #[serde(default)]
+    tokens: Vec<StoredToken>,
+}
+
+fn epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn from_epoch_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Returns how long a connection must wait before it may try to
+/// authenticate again, after `failures` consecutive failed attempts.
+/// The delay doubles with each failure, so that guessing tokens over
+/// a single connection is impractical.
+pub fn failure_backoff(failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::from_secs(0);
+    }
+    Duration::from_secs(1u64 << (failures - 1).min(16)).min(MAX_FAILURE_BACKOFF)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_token(token: &str) -> String {
+    to_hex(&openssl::sha::sha256(token.as_bytes()))
+}
+
+fn generate_token() -> anyhow::Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    openssl::rand::rand_bytes(&mut bytes).context("generating token")?;
+    Ok(format!("{}{}", TOKEN_PREFIX, to_hex(&bytes)))
+}
+
+/// The tokens are kept in a file so that they persist across
+/// restarts of the server and can be managed by `wezterm cli`.
+/// The file is re-read for each operation so that changes made
+/// to it while the server is running take effect immediately.
+pub struct TokenStore {
+    path: PathBuf,
+}
+
+impl TokenStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Use the file specified by the `mux_auth_tokens_file` option
+    pub fn from_config() -> Self {
+        Self::new(configuration().mux_auth_tokens_file())
+    }
+
+    fn load(&self) -> anyhow::Result<TokenFile> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(data) => {
+                toml::from_str(&data).with_context(|| format!("parsing {}", self.path.display()))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(TokenFile::default()),
+            Err(err) => Err(err).with_context(|| format!("reading {}", self.path.display())),
+        }
+    }
+
+    fn save(&self, file: &TokenFile) -> anyhow::Result<()> {
+        let data = toml::to_string(file)?;
+        if let Some(dir) = self.path.parent() {
+            config::create_user_owned_dirs(dir)?;
+        }
+
+        // Write to a temporary file and rename it into place, so
+        // that a reader never observes a partially written file
+        let temp = self.path.with_extension("tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut f = options
+            .open(&temp)
+            .with_context(|| format!("creating {}", temp.display()))?;
+        f.write_all(data.as_bytes())?;
+        drop(f);
+        std::fs::rename(&temp, &self.path)
+            .with_context(|| format!("replacing {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Check `token` against the stored tokens, returning the name
+    /// and permission of the matching token
+    pub fn authenticate(&self, token: &str) -> anyhow::Result<(String, AuthPermission)> {
+        let mut file = self.load()?;
+        let hash = hash_token(token);
+        let entry = file
+            .tokens
+            .iter_mut()
+            .find(|entry| {
+                entry.hash.len() == hash.len()
+                    && openssl::memcmp::eq(entry.hash.as_bytes(), hash.as_bytes())
+            })
+            .ok_or_else(|| anyhow!("invalid token"))?;
+        let result = (entry.name.clone(), entry.permission);
+        let now = epoch_secs();
+        let stale = entry
+            .last_used
+            .map(|last_used| now.saturating_sub(last_used) >= LAST_USED_RESOLUTION)
+            .unwrap_or(true);
+        if stale {
+            entry.last_used.replace(now);
+            if let Err(err) = self.save(&file) {
+                log::warn!("recording token use: {:#}", err);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Create a token named `name`, returning the token
+    pub fn create(&self, name: &str, permission: AuthPermission) -> anyhow::Result<String> {
+        if name.is_empty() {
+            bail!("the token name must not be empty");
+        }
+        let mut file = self.load()?;
+        if file.tokens.iter().any(|entry| entry.name == name) {
+            bail!("a token named {} already exists", name);
+        }
+        let token = generate_token()?;
+        file.tokens.push(StoredToken {
+            name: name.to_string(),
+            permission,
+            hash: hash_token(&token),
+            created: epoch_secs(),
+            last_used: None,
+        });
+        self.save(&file)?;
+        Ok(token)
+    }
+
+    /// Replace the token named `name` with a new one that has the
+    /// same permission, returning the new token
+    pub fn rotate(&self, name: &str) -> anyhow::Result<String> {
+        let mut file = self.load()?;
+        let entry = file
+            .tokens
+            .iter_mut()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| anyhow!("no token named {}", name))?;
+        let token = generate_token()?;
+        entry.hash = hash_token(&token);
+        entry.created = epoch_secs();
+        entry.last_used = None;
+        self.save(&file)?;
+        Ok(token)
+    }
+
+    pub fn revoke(&self, name: &str) -> anyhow::Result<()> {
+        let mut file = self.load()?;
+        let len = file.tokens.len();
+        file.tokens.retain(|entry| entry.name != name);
+        if file.tokens.len() == len {
+            bail!("no token named {}", name);
+        }
+        self.save(&file)
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<AuthTokenInfo>> {
+        Ok(self.load()?.tokens.iter().map(StoredToken::info).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A token file in a directory that is removed when dropped
+    struct TempStore {
+        dir: PathBuf,
+        store: TokenStore,
+    }
+
+    impl TempStore {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "wezterm-auth-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let store = TokenStore::new(dir.join("tokens.toml"));
+            Self { dir, store }
+        }
+
+        fn modified(&self) -> SystemTime {
+            std::fs::metadata(&self.store.path)
+                .unwrap()
+                .modified()
+                .unwrap()
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    #[test]
+    fn create_and_authenticate() {
+        let temp = TempStore::new();
+        let store = &temp.store;
+
+        assert!(store.list().unwrap().is_empty());
+
+        let token = store.create("laptop", AuthPermission::Full).unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert!(store.create("laptop", AuthPermission::Full).is_err());
+        assert!(store.create("", AuthPermission::Full).is_err());
+
+        let (name, permission) = store.authenticate(&token).unwrap();
+        assert_eq!(name, "laptop");
+        assert_eq!(permission, AuthPermission::Full);
+
+        assert!(store.authenticate("wzt_nope").is_err());
+        assert!(store.authenticate("").is_err());
+
+        // Only the hash is persisted
+        let data = std::fs::read_to_string(&store.path).unwrap();
+        assert!(!data.contains(&token));
+
+        let tokens = store.list().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].name, "laptop");
+        assert!(tokens[0].last_used.is_some());
+    }
+
+    #[test]
+    fn authenticate_doesnt_rewrite_file() {
+        let temp = TempStore::new();
+        let store = &temp.store;
+        let token = store.create("ci", AuthPermission::NoSpawn).unwrap();
+
+        // The first use is recorded
+        store.authenticate(&token).unwrap();
+        let modified = temp.modified();
+        let data = std::fs::read_to_string(&store.path).unwrap();
+
+        // but subsequent uses within LAST_USED_RESOLUTION are not
+        std::thread::sleep(Duration::from_millis(20));
+        for _ in 0..3 {
+            store.authenticate(&token).unwrap();
+        }
+        assert_eq!(temp.modified(), modified);
+        assert_eq!(std::fs::read_to_string(&store.path).unwrap(), data);
+    }
+
+    #[test]
+    fn rotate_and_revoke() {
+        let temp = TempStore::new();
+        let store = &temp.store;
+        let old = store.create("watcher", AuthPermission::ReadOnly).unwrap();
+        let new = store.rotate("watcher").unwrap();
+        assert_ne!(old, new);
+
+        assert!(store.authenticate(&old).is_err());
+        assert_eq!(
+            store.authenticate(&new).unwrap(),
+            ("watcher".to_string(), AuthPermission::ReadOnly)
+        );
+
+        assert!(store.rotate("nobody").is_err());
+        assert!(store.revoke("nobody").is_err());
+        store.revoke("watcher").unwrap();
+        assert!(store.authenticate(&new).is_err());
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn backoff() {
+        assert_eq!(failure_backoff(0), Duration::from_secs(0));
+        assert_eq!(failure_backoff(1), Duration::from_secs(1));
+        assert_eq!(failure_backoff(2), Duration::from_secs(2));
+        assert_eq!(failure_backoff(4), Duration::from_secs(8));
+        assert_eq!(failure_backoff(7), MAX_FAILURE_BACKOFF);
+        assert_eq!(failure_backoff(u32::max_value()), MAX_FAILURE_BACKOFF);
+    }
+}
@@ -1,8 +1,9 @@
+use crate::auth::PeerInfo;
 use crate::sessionhandler::{PduSender, SessionHandler};
 use crate::UnixStream;
 use anyhow::Context;
 use async_ossl::AsyncSslStream;
-use codec::{DecodedPdu, Pdu};
+use codec::{CodecState, DecodedPdu, Pdu};
 use futures::FutureExt;
 use mux::{Mux, MuxNotification};
 use smol::prelude::*;
@@ -23,7 +24,7 @@ enum Item {
     Readable,
 }
 
-pub async fn process<T>(stream: T) -> anyhow::Result<()>
+pub async fn process<T>(stream: T, peer: PeerInfo) -> anyhow::Result<()>
 where
     T: 'static,
     T: std::io::Read,
@@ -32,10 +33,10 @@ where
     T: std::fmt::Debug,
 {
     let stream = smol::Async::new(stream)?;
-    process_async(stream).await
+    process_async(stream, peer).await
 }
 
-pub async fn process_async<T>(mut stream: Async<T>) -> anyhow::Result<()>
+pub async fn process_async<T>(mut stream: Async<T>, peer: PeerInfo) -> anyhow::Result<()>
 where
     T: 'static,
     T: std::io::Read,
@@ -54,7 +55,8 @@ where
                 .map_err(|e| anyhow::anyhow!("{:?}", e))
         }
     });
-    let mut handler = SessionHandler::new(pdu_sender);
+    let mut handler = SessionHandler::new(pdu_sender, peer);
+    let mut codec = CodecState::new();
 
     {
         let mux = Mux::get().expect("to be running on gui thread");
@@ -68,20 +70,33 @@ where
 
         match smol::future::or(rx_msg, wait_for_read).await {
             Ok(Item::Readable) => {
-                let decoded = Pdu::decode_async(&mut stream).await?;
+                let decoded = Pdu::decode_async_with(&mut stream, &mut codec).await?;
                 handler.process_one(decoded);
+                handler.record_compression_stats(codec.stats());
             }
             Ok(Item::WritePdu(decoded)) => {
                 decoded
                     .pdu
-                    .encode_async(&mut stream, decoded.serial)
+                    .encode_async_with(&mut stream, decoded.serial, &mut codec)
                     .await?;
                 stream.flush().await.context("flushing PDU to client")?;
+                handler.record_compression_stats(codec.stats());
             }
             Ok(Item::Notif(MuxNotification::PaneOutput(pane_id))) => {
                 handler.schedule_pane_push(pane_id);
             }
             Ok(Item::Notif(MuxNotification::WindowCreated(_window_id))) => {}
+            Ok(Item::Notif(MuxNotification::AuthTokenRevoked(name))) => {
+                if handler.auth_token_name() == Some(name.as_str()) {
+                    return Ok(());
+                }
+            }
+            Ok(Item::Notif(MuxNotification::PortForwardRemoved(forward_id))) => {
+                handler.remove_port_forward(forward_id);
+            }
+            Ok(Item::Notif(notification)) => {
+                handler.schedule_topology_push(notification);
+            }
             Err(err) => {
                 log::error!("process_async Err {}", err);
                 return Ok(());
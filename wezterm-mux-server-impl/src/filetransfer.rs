@@ -0,0 +1,286 @@
+use anyhow::{anyhow, ensure, Context};
+use codec::FileTransferMode;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A file that a client is in the process of uploading or downloading.
+/// Uploads are written to a temporary file alongside the destination,
+/// which is renamed into place once the client indicates that the
+/// transfer is complete, so that an interrupted upload doesn't leave
+/// a truncated file behind.
+pub enum FileTransfer {
+    Upload {
+        file: Option<File>,
+        temp_path: PathBuf,
+        path: PathBuf,
+        size: u64,
+        written: u64,
+    },
+    Download {
+        file: File,
+        size: u64,
+    },
+}
+
+fn resolve_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else if let Ok(rest) = path.strip_prefix("~") {
+        config::HOME_DIR.join(rest)
+    } else {
+        config::HOME_DIR.join(path)
+    }
+}
+
+impl FileTransfer {
+    pub fn open(path: &str, mode: FileTransferMode) -> anyhow::Result<Self> {
+        let path = resolve_path(path);
+        match mode {
+            FileTransferMode::Upload { size } => {
+                let file_name = path
+                    .file_name()
+                    .ok_or_else(|| anyhow!("{} is not a file name", path.display()))?;
+                let mut temp_name = std::ffi::OsString::from(".");
+                temp_name.push(file_name);
+                temp_name.push(".wezterm-upload");
+                let temp_path = path.with_file_name(temp_name);
+                let file = File::create(&temp_path)
+                    .with_context(|| format!("creating {}", temp_path.display()))?;
+                Ok(Self::Upload {
+                    file: Some(file),
+                    temp_path,
+                    path,
+                    size,
+                    written: 0,
+                })
+            }
+            FileTransferMode::Download => {
+                let file =
+                    File::open(&path).with_context(|| format!("opening {}", path.display()))?;
+                let meta = file.metadata()?;
+                ensure!(meta.is_file(), "{} is not a regular file", path.display());
+                Ok(Self::Download {
+                    file,
+                    size: meta.len(),
+                })
+            }
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Upload { size, .. } | Self::Download { size, .. } => *size,
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Self::Upload {
+                file: Some(file),
+                size,
+                written,
+                ..
+            } => {
+                ensure!(
+                    *written + data.len() as u64 <= *size,
+                    "upload exceeds its declared size of {} bytes",
+                    size
+                );
+                file.write_all(data)?;
+                *written += data.len() as u64;
+                Ok(())
+            }
+            _ => Err(anyhow!("transfer is not an upload")),
+        }
+    }
+
+    pub fn read(&mut self, max_len: usize) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Download { file, .. } => {
+                let mut data = vec![0u8; max_len.min(codec::FILE_TRANSFER_CHUNK_SIZE)];
+                let len = file.read(&mut data)?;
+                data.truncate(len);
+                Ok(data)
+            }
+            _ => Err(anyhow!("transfer is not a download")),
+        }
+    }
+
+    /// Finish the transfer.  For an upload that is `complete`, the
+    /// uploaded data replaces the destination file.  Otherwise, the
+    /// partial upload is removed when the transfer is dropped.
+    pub fn close(&mut self, complete: bool) -> anyhow::Result<()> {
+        if let Self::Upload {
+            file,
+            temp_path,
+            path,
+            size,
+            written,
+        } = self
+        {
+            if let Some(file) = file.take() {
+                if complete {
+                    ensure!(
+                        *written == *size,
+                        "upload is incomplete: received {} of {} bytes",
+                        written,
+                        size
+                    );
+                    file.sync_all()?;
+                    drop(file);
+                    std::fs::rename(&temp_path, &path).with_context(|| {
+                        format!("renaming {} to {}", temp_path.display(), path.display())
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileTransfer {
+    fn drop(&mut self) {
+        // Clean up after an upload that was abandoned or that failed
+        if let Self::Upload { temp_path, .. } = self {
+            if temp_path.exists() {
+                std::fs::remove_file(&temp_path).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A directory that is removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "wezterm-filetransfer-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).to_str().unwrap().to_string()
+        }
+
+        fn entries(&self) -> Vec<String> {
+            let mut entries: Vec<String> = std::fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            entries.sort();
+            entries
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn upload(path: &str, size: u64) -> FileTransfer {
+        FileTransfer::open(path, FileTransferMode::Upload { size }).unwrap()
+    }
+
+    #[test]
+    fn resolve() {
+        let home = &*config::HOME_DIR;
+        assert_eq!(resolve_path("~"), *home);
+        assert_eq!(resolve_path("~/foo/bar"), home.join("foo/bar"));
+        assert_eq!(resolve_path("foo/bar"), home.join("foo/bar"));
+        let absolute = std::env::temp_dir().join("foo");
+        assert_eq!(resolve_path(absolute.to_str().unwrap()), absolute);
+    }
+
+    #[test]
+    fn upload_renames_on_complete() {
+        let dir = TempDir::new();
+        let path = dir.path("file.txt");
+        std::fs::write(&path, b"old").unwrap();
+
+        let mut transfer = upload(&path, 11);
+        transfer.write(b"hello ").unwrap();
+        transfer.write(b"world").unwrap();
+        // The destination is untouched until the upload completes
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert_eq!(dir.entries(), vec![".file.txt.wezterm-upload", "file.txt"]);
+
+        transfer.close(true).unwrap();
+        drop(transfer);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert_eq!(dir.entries(), vec!["file.txt"]);
+    }
+
+    #[test]
+    fn upload_rejects_overflow() {
+        let dir = TempDir::new();
+        let path = dir.path("file.txt");
+
+        let mut transfer = upload(&path, 4);
+        transfer.write(b"abc").unwrap();
+        assert!(transfer.write(b"de").is_err());
+        transfer.write(b"d").unwrap();
+        assert!(transfer.write(b"e").is_err());
+        transfer.close(true).unwrap();
+        drop(transfer);
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcd");
+    }
+
+    #[test]
+    fn incomplete_upload_is_removed() {
+        let dir = TempDir::new();
+        let path = dir.path("file.txt");
+
+        // Abandoned without being closed, as happens when the
+        // client disconnects part way through
+        let mut transfer = upload(&path, 10);
+        transfer.write(b"abc").unwrap();
+        drop(transfer);
+        assert!(dir.entries().is_empty());
+
+        // Closed as complete, but with data missing
+        let mut transfer = upload(&path, 10);
+        transfer.write(b"abc").unwrap();
+        assert!(transfer.close(true).is_err());
+        drop(transfer);
+        assert!(dir.entries().is_empty());
+
+        // Cancelled by the client
+        let mut transfer = upload(&path, 10);
+        transfer.write(b"abc").unwrap();
+        transfer.close(false).unwrap();
+        drop(transfer);
+        assert!(dir.entries().is_empty());
+    }
+
+    #[test]
+    fn download() {
+        let dir = TempDir::new();
+        let path = dir.path("file.txt");
+        std::fs::write(&path, b"hello world").unwrap();
+
+        let mut transfer = FileTransfer::open(&path, FileTransferMode::Download).unwrap();
+        assert_eq!(transfer.size(), 11);
+        assert!(transfer.write(b"nope").is_err());
+        assert_eq!(transfer.read(6).unwrap(), b"hello ");
+        assert_eq!(transfer.read(100).unwrap(), b"world");
+        assert_eq!(transfer.read(100).unwrap(), b"");
+        transfer.close(true).unwrap();
+
+        assert!(FileTransfer::open(&dir.path("missing"), FileTransferMode::Download).is_err());
+        assert!(FileTransfer::open(dir.0.to_str().unwrap(), FileTransferMode::Download).is_err());
+    }
+}
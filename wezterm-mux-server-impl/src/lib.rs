@@ -3,9 +3,13 @@ use std::os::unix::net::{UnixListener, UnixStream};
 #[cfg(windows)]
 use uds_windows::{UnixListener, UnixStream};
 
+pub mod audit;
+pub mod auth;
 pub mod dispatch;
+pub mod filetransfer;
 pub mod local;
 pub mod pki;
+pub mod portforward;
 pub mod sessionhandler;
 
 lazy_static::lazy_static! {
@@ -1,28 +1,41 @@
-use crate::UnixListener;
+use crate::auth::PeerInfo;
+use crate::{UnixListener, UnixStream};
 use anyhow::{anyhow, Context as _};
 use config::{create_user_owned_dirs, UnixDomain};
 use promise::spawn::spawn_into_main_thread;
 
 pub struct LocalListener {
     listener: UnixListener,
+    require_auth_token: bool,
 }
 
 impl LocalListener {
     pub fn new(listener: UnixListener) -> Self {
-        Self { listener }
+        Self {
+            listener,
+            require_auth_token: false,
+        }
     }
 
     pub fn with_domain(unix_dom: &UnixDomain) -> anyhow::Result<Self> {
         let listener = safely_create_sock_path(unix_dom)?;
-        Ok(Self::new(listener))
+        Ok(Self {
+            listener,
+            require_auth_token: unix_dom.require_auth_token,
+        })
     }
 
     pub fn run(&mut self) {
         for stream in self.listener.incoming() {
             match stream {
                 Ok(stream) => {
+                    let peer = PeerInfo {
+                        description: describe_peer(&stream),
+                        require_auth_token: self.require_auth_token,
+                    };
+                    crate::audit::record(&peer, "connected");
                     spawn_into_main_thread(async move {
-                        crate::dispatch::process(stream).await.map_err(|e| {
+                        crate::dispatch::process(stream, peer).await.map_err(|e| {
                             log::error!("{:#}", e);
                             e
                         })
@@ -38,6 +51,46 @@ impl LocalListener {
     }
 }
 
+/// Identify the process on the other end of the socket
+/// for the audit log
+#[cfg(target_os = "linux")]
+fn describe_peer(stream: &UnixStream) -> String {
+    use std::os::unix::io::AsRawFd;
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res == 0 {
+        format!("local uid={} pid={}", cred.uid, cred.pid)
+    } else {
+        format!("local peer ({})", std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn describe_peer(stream: &UnixStream) -> String {
+    use std::os::unix::io::AsRawFd;
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == 0 {
+        format!("local uid={}", uid)
+    } else {
+        format!("local peer ({})", std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn describe_peer(_stream: &UnixStream) -> String {
+    "local peer".to_string()
+}
+
 /// Take care when setting up the listener socket;
 /// we need to be sure that the directory that we create it in
 /// is owned by the user and has appropriate file permissions
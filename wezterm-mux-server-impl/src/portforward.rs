@@ -0,0 +1,175 @@
+use crate::sessionhandler::PduSender;
+use anyhow::{anyhow, ensure};
+use codec::*;
+use config::{PortForward, PortForwardDirection};
+use mux::portforward::{alloc_port_forward_id, alloc_stream_id, TcpForwardListener, TcpRelay};
+use std::collections::HashMap;
+use std::net::TcpStream;
+
+struct ServerForward {
+    forward: PortForward,
+    /// Accepts connections for RemoteToLocal forwards
+    _listener: Option<TcpForwardListener>,
+}
+
+/// The server side of the port forwards owned by a client session
+pub struct PortForwards {
+    sender: PduSender,
+    forwards: HashMap<u64, ServerForward>,
+    relay: TcpRelay,
+}
+
+fn relay_stream(
+    relay: &TcpRelay,
+    sender: PduSender,
+    stream_id: u64,
+    stream: TcpStream,
+) -> anyhow::Result<()> {
+    let data_sender = sender.clone();
+    let ack_sender = sender.clone();
+    relay.add(
+        stream_id,
+        stream,
+        move |data| {
+            data_sender.send(DecodedPdu {
+                serial: 0,
+                pdu: Pdu::TcpStreamData(TcpStreamData { stream_id, data }),
+            })
+        },
+        move || {
+            sender
+                .send(DecodedPdu {
+                    serial: 0,
+                    pdu: Pdu::CloseTcpStream(CloseTcpStream { stream_id }),
+                })
+                .ok();
+        },
+        move |len| {
+            ack_sender
+                .send(DecodedPdu {
+                    serial: 0,
+                    pdu: Pdu::TcpStreamAck(TcpStreamAck {
+                        stream_id,
+                        len: len as u64,
+                    }),
+                })
+                .ok();
+        },
+    )
+}
+
+impl PortForwards {
+    pub fn new(sender: PduSender) -> Self {
+        Self {
+            sender,
+            forwards: HashMap::new(),
+            relay: TcpRelay::default(),
+        }
+    }
+
+    pub fn add(&mut self, forward: PortForward) -> anyhow::Result<u64> {
+        let forward_id = alloc_port_forward_id();
+        let listener = match forward.direction {
+            PortForwardDirection::LocalToRemote => None,
+            PortForwardDirection::RemoteToLocal => {
+                let sender = self.sender.clone();
+                let relay = self.relay.clone();
+                Some(TcpForwardListener::spawn(&forward.remote, move |stream| {
+                    let stream_id = alloc_stream_id(false);
+                    // The client needs to know about the stream before
+                    // it receives any data for it
+                    let opened = sender.send(DecodedPdu {
+                        serial: 0,
+                        pdu: Pdu::TcpStreamOpened(TcpStreamOpened {
+                            forward_id,
+                            stream_id,
+                        }),
+                    });
+                    if let Err(err) =
+                        opened.and_then(|_| relay_stream(&relay, sender.clone(), stream_id, stream))
+                    {
+                        log::error!("forwarding connection for {}: {:#}", forward_id, err);
+                    }
+                })?)
+            }
+        };
+        self.forwards.insert(
+            forward_id,
+            ServerForward {
+                forward,
+                _listener: listener,
+            },
+        );
+        Ok(forward_id)
+    }
+
+    /// Stop listening for connections for the forward.
+    /// Connections that are already established are left open.
+    /// Returns false if this session doesn't own the forward.
+    pub fn remove(&mut self, forward_id: u64) -> bool {
+        self.forwards.remove(&forward_id).is_some()
+    }
+
+    /// Connect to the remote address of a LocalToRemote forward on behalf
+    /// of the client, which has accepted a connection as `stream_id`.
+    /// The connection is made on a background thread; `send_response`
+    /// is called once it has been established, or has failed.
+    pub fn open_stream<SND>(
+        &self,
+        forward_id: u64,
+        stream_id: u64,
+        send_response: SND,
+    ) -> anyhow::Result<()>
+    where
+        SND: Fn(anyhow::Result<Pdu>) + Send + 'static,
+    {
+        let forward = &self
+            .forwards
+            .get(&forward_id)
+            .ok_or_else(|| anyhow!("no such port forward {}", forward_id))?
+            .forward;
+        ensure!(
+            forward.direction == PortForwardDirection::LocalToRemote,
+            "port forward {} does not accept connections from the client",
+            forward_id
+        );
+
+        // Queue up anything that the client sends while we connect
+        self.relay.reserve(stream_id);
+
+        let remote = forward.remote.clone();
+        let relay = self.relay.clone();
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let result = TcpStream::connect(&remote)
+                .map_err(|err| anyhow!("connecting to {}: {}", remote, err))
+                .and_then(|stream| relay_stream(&relay, sender, stream_id, stream));
+            match result {
+                Ok(()) => send_response(Ok(Pdu::UnitResponse(UnitResponse {}))),
+                Err(err) => {
+                    relay.abort(stream_id);
+                    send_response(Err(err));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn write(&self, stream_id: u64, data: Vec<u8>) {
+        self.relay.write(stream_id, data);
+    }
+
+    pub fn ack(&self, stream_id: u64, len: u64) {
+        self.relay.ack(stream_id, len as usize);
+    }
+
+    pub fn finish_writing(&self, stream_id: u64) {
+        self.relay.finish_writing(stream_id);
+    }
+}
+
+impl Drop for PortForwards {
+    fn drop(&mut self) {
+        self.relay.close_all();
+    }
+}
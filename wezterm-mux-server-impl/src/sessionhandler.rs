@@ -1,11 +1,16 @@
+use crate::auth::{PeerInfo, TokenStore};
+use crate::filetransfer::FileTransfer;
+use crate::portforward::PortForwards;
 use crate::PKI;
-use anyhow::anyhow;
+use anyhow::{anyhow, ensure};
 use codec::*;
 use config::keyassignment::SpawnTabDomain;
+use config::{configuration, MuxClientSizePolicy};
+use mux::client::{AuthPermission, ClientId, CompressionStats};
 use mux::pane::{Pane, PaneId};
 use mux::renderable::{RenderableDimensions, StableCursorPosition};
 use mux::tab::TabId;
-use mux::Mux;
+use mux::{Mux, MuxNotification};
 use portable_pty::PtySize;
 use promise::spawn::spawn_into_main_thread;
 use rangeset::RangeSet;
@@ -14,7 +19,7 @@ use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use url::Url;
-use wezterm_term::terminal::{Clipboard, ClipboardSelection};
+use wezterm_term::terminal::{Alert, Clipboard, ClipboardSelection};
 use wezterm_term::StableRowIndex;
 
 #[derive(Clone)]
@@ -43,6 +48,8 @@ struct PerPane {
     dimensions: RenderableDimensions,
     dirty_lines: RangeSet<StableRowIndex>,
     mouse_grabbed: bool,
+    alt_screen_active: bool,
+    baselines: LineBaselines,
 }
 
 impl PerPane {
@@ -57,6 +64,11 @@ impl PerPane {
             changed = true;
         }
 
+        let alt_screen_active = pane.is_alt_screen_active();
+        if alt_screen_active != self.alt_screen_active {
+            changed = true;
+        }
+
         let dims = pane.get_dimensions();
         if dims != self.dimensions {
             changed = true;
@@ -114,12 +126,19 @@ impl PerPane {
         self.dimensions = dims;
         self.dirty_lines = all_dirty_lines;
         self.mouse_grabbed = mouse_grabbed;
+        self.alt_screen_active = alt_screen_active;
+
+        // Lines in the scrollback are unlikely to change again, so there
+        // is little value in retaining them to compute deltas
+        self.baselines
+            .retain(dims.physical_top..dims.physical_top + dims.viewport_rows as StableRowIndex);
 
         let dirty_lines = dirty_delta.iter().cloned().collect();
-        let bonus_lines = bonus_lines.into();
+        let bonus_lines = self.baselines.serialize(bonus_lines);
         Some(GetPaneRenderChangesResponse {
             pane_id: pane.pane_id(),
             mouse_grabbed,
+            alt_screen_active,
             dirty_lines,
             dimensions: dims,
             cursor_position,
@@ -150,18 +169,152 @@ fn maybe_push_pane_changes(
     Ok(())
 }
 
+fn tab_tree_pdu(mux: &Rc<Mux>, tab_id: TabId) -> Option<Pdu> {
+    let tab = mux.get_tab(tab_id)?;
+    let root = tab.codec_pane_tree();
+    let (window_id, tab_id) = root.window_and_tab_ids()?;
+    Some(Pdu::TabTreeChanged(TabTreeChanged {
+        tab_id,
+        window_id,
+        root,
+    }))
+}
+
+/// Resize the pane to the size arbitrated between the sizes
+/// requested by each of the attached clients
+fn apply_client_pane_size(pane_id: PaneId) -> anyhow::Result<()> {
+    let mux = Mux::get().unwrap();
+    let policy = configuration().mux_client_size_policy;
+    let size = match mux.arbitrate_pane_size(pane_id, policy) {
+        Some(size) => size,
+        None => return Ok(()),
+    };
+    let pane = mux
+        .get_pane(pane_id)
+        .ok_or_else(|| anyhow!("no such pane {}", pane_id))?;
+    pane.resize(size)?;
+    if let Some((_domain_id, _window_id, tab_id)) = mux.resolve_pane_id(pane_id) {
+        if let Some(tab) = mux.get_tab(tab_id) {
+            tab.rebuild_splits_sizes_from_contained_panes();
+        }
+    }
+    Ok(())
+}
+
+/// Record that the client sent some input to `pane_id`.  With the
+/// LatestActive size policy, the panes are resized to suit the client
+/// that has just become the most recently active.
+fn note_client_input(client_id: &Option<ClientId>, pane_id: PaneId) {
+    if let Some(client_id) = client_id {
+        let mux = Mux::get().unwrap();
+        if mux.client_had_input(client_id, pane_id)
+            && configuration().mux_client_size_policy == MuxClientSizePolicy::LatestActive
+        {
+            for pane_id in mux.client_pane_ids(client_id) {
+                if let Err(err) = apply_client_pane_size(pane_id) {
+                    log::error!("resizing pane {}: {:#}", pane_id, err);
+                }
+            }
+        }
+    }
+}
+
 pub struct SessionHandler {
     to_write_tx: PduSender,
     per_pane: HashMap<TabId, Arc<Mutex<PerPane>>>,
+    client_id: Option<ClientId>,
+    read_only: bool,
+    file_transfers: Arc<Mutex<HashMap<u64, Arc<Mutex<FileTransfer>>>>>,
+    next_transfer_id: u64,
+    port_forwards: PortForwards,
+    peer: PeerInfo,
+    /// The name of the token that the client authenticated with
+    auth_token: Option<String>,
+    permission: AuthPermission,
+    /// The number of consecutive failed Authenticate requests
+    auth_failures: u32,
+    /// Authenticate requests are rejected until this time, to slow
+    /// down attempts to guess a token
+    auth_retry_after: Option<Instant>,
+}
+
+impl Drop for SessionHandler {
+    fn drop(&mut self) {
+        crate::audit::record(&self.peer, "disconnected");
+        if let Some(client_id) = self.client_id.take() {
+            spawn_into_main_thread(async move {
+                let mux = Mux::get().unwrap();
+                for pane_id in mux.unregister_client(&client_id) {
+                    apply_client_pane_size(pane_id).ok();
+                }
+            })
+            .detach();
+        }
+    }
+}
+
+/// Returns true if a client that authenticated with a token that
+/// has `permission` may make the request `pdu`.
+/// Starting processes, reading and writing files and relaying network
+/// connections are all equivalent to running arbitrary commands on
+/// the host, so they require a `Full` token.
+fn permission_allows(permission: AuthPermission, pdu: &Pdu) -> bool {
+    match pdu {
+        Pdu::CreateAuthToken(_)
+        | Pdu::RotateAuthToken(_)
+        | Pdu::RevokeAuthToken(_)
+        | Pdu::ListAuthTokens(_)
+        | Pdu::GetTlsCreds(_)
+        | Pdu::Spawn(_)
+        | Pdu::SplitPane(_)
+        | Pdu::OpenFileTransfer(_)
+        | Pdu::AddPortForward(_)
+        | Pdu::RemovePortForward(_)
+        | Pdu::OpenTcpStream(_) => permission == AuthPermission::Full,
+        Pdu::WriteToPane(_)
+        | Pdu::SendPaste(_)
+        | Pdu::SendKeyDown(_)
+        | Pdu::SendMouseEvent(_)
+        | Pdu::SetPaneZoomed(_) => permission != AuthPermission::ReadOnly,
+        _ => true,
+    }
 }
 
 impl SessionHandler {
-    pub fn new(to_write_tx: PduSender) -> Self {
+    pub fn new(to_write_tx: PduSender, peer: PeerInfo) -> Self {
         Self {
+            port_forwards: PortForwards::new(to_write_tx.clone()),
             to_write_tx,
             per_pane: HashMap::new(),
+            client_id: None,
+            read_only: false,
+            file_transfers: Arc::new(Mutex::new(HashMap::new())),
+            next_transfer_id: 0,
+            peer,
+            auth_token: None,
+            permission: AuthPermission::Full,
+            auth_failures: 0,
+            auth_retry_after: None,
         }
     }
+
+    /// Returns the name of the token that the client authenticated with
+    pub fn auth_token_name(&self) -> Option<&str> {
+        self.auth_token.as_deref()
+    }
+
+    /// Returns an error if the token that the client authenticated
+    /// with doesn't permit the request
+    fn check_permission(&self, pdu: &Pdu) -> anyhow::Result<()> {
+        ensure!(
+            permission_allows(self.permission, pdu),
+            "the {} token {} does not permit {:?}",
+            self.permission,
+            self.auth_token.as_deref().unwrap_or_default(),
+            pdu
+        );
+        Ok(())
+    }
     fn per_pane(&mut self, pane_id: PaneId) -> Arc<Mutex<PerPane>> {
         Arc::clone(
             self.per_pane
@@ -170,6 +323,25 @@ impl SessionHandler {
         )
     }
 
+    fn file_transfer(&self, transfer_id: u64) -> anyhow::Result<Arc<Mutex<FileTransfer>>> {
+        self.file_transfers
+            .lock()
+            .unwrap()
+            .get(&transfer_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("no such file transfer {}", transfer_id))
+    }
+
+    /// Publish the traffic stats for this connection so that they
+    /// show up in the client list
+    pub fn record_compression_stats(&self, stats: CompressionStats) {
+        if let Some(client_id) = &self.client_id {
+            if let Some(mux) = Mux::get() {
+                mux.record_client_compression_stats(client_id, stats);
+            }
+        }
+    }
+
     pub fn schedule_pane_push(&mut self, pane_id: PaneId) {
         let sender = self.to_write_tx.clone();
         let per_pane = self.per_pane(pane_id);
@@ -184,6 +356,104 @@ impl SessionHandler {
         .detach();
     }
 
+    /// A port forward was cancelled; if it belongs to this session,
+    /// stop forwarding it and let the client know.
+    pub fn remove_port_forward(&mut self, forward_id: u64) {
+        if !self.port_forwards.remove(forward_id) {
+            return;
+        }
+        log::info!(
+            "client {:?} port forward {} removed",
+            self.client_id,
+            forward_id
+        );
+        self.to_write_tx
+            .send(DecodedPdu {
+                serial: 0,
+                pdu: Pdu::PortForwardRemoved(PortForwardRemoved { forward_id }),
+            })
+            .ok();
+        if let Some(client_id) = self.client_id.clone() {
+            spawn_into_main_thread(async move {
+                let mux = Mux::get().unwrap();
+                mux.forget_client_port_forward(&client_id, forward_id);
+            })
+            .detach();
+        }
+    }
+
+    /// Translate a change to the mux topology, or an alert raised by
+    /// a pane, into a unilateral PDU for the client, so that it can
+    /// apply the change without having to poll the full pane list.
+    /// A clipboard query is only passed to the one client that is
+    /// expected to answer it; see `Mux::clipboard_query_target`.
+    pub fn schedule_topology_push(&mut self, notification: MuxNotification) {
+        if let MuxNotification::PaneRemoved(pane_id) = &notification {
+            self.per_pane.remove(pane_id);
+        }
+        let sender = self.to_write_tx.clone();
+        let client_id = self.client_id.clone();
+        spawn_into_main_thread(async move {
+            let mux = Mux::get().unwrap();
+            let pdu = match notification {
+                MuxNotification::PaneAdded(pane_id) => match mux.resolve_pane_id(pane_id) {
+                    Some((_domain_id, _window_id, tab_id)) => tab_tree_pdu(&mux, tab_id),
+                    None => None,
+                },
+                MuxNotification::TabAddedToWindow { tab_id, .. }
+                | MuxNotification::TabLayoutChanged(tab_id) => tab_tree_pdu(&mux, tab_id),
+                MuxNotification::TabRemoved(tab_id) => Some(Pdu::TabRemoved(TabRemoved { tab_id })),
+                MuxNotification::PaneRemoved(pane_id) => {
+                    Some(Pdu::PaneRemoved(PaneRemoved { pane_id }))
+                }
+                MuxNotification::PaneMetadataChanged(pane_id) => {
+                    mux.get_pane(pane_id).map(|pane| {
+                        Pdu::PaneMetadataChanged(PaneMetadataChanged {
+                            pane_id,
+                            title: pane.get_title(),
+                            working_dir: pane.get_current_working_dir().map(Into::into),
+                        })
+                    })
+                }
+                MuxNotification::ActivePaneChanged { tab_id, pane_id } => {
+                    Some(Pdu::ActivePaneChanged(ActivePaneChanged {
+                        tab_id,
+                        pane_id,
+                    }))
+                }
+                MuxNotification::TabZoomChanged {
+                    tab_id,
+                    pane_id,
+                    zoomed,
+                } => Some(Pdu::PaneZoomChanged(PaneZoomChanged {
+                    tab_id,
+                    pane_id,
+                    zoomed,
+                })),
+                MuxNotification::Alert {
+                    pane_id,
+                    alert: Alert::QueryClipboard(_),
+                } if client_id.is_none() || mux.clipboard_query_target(pane_id) != client_id => {
+                    None
+                }
+                MuxNotification::Alert { pane_id, alert } => {
+                    Some(Pdu::NotifyAlert(NotifyAlert { pane_id, alert }))
+                }
+                MuxNotification::PaneOutput(_)
+                | MuxNotification::WindowCreated(_)
+                | MuxNotification::WindowRemoved(_)
+                | MuxNotification::PortForwardRemoved(_)
+                | MuxNotification::BufferedInputPending(_)
+                | MuxNotification::AuthTokenRevoked(_) => None,
+            };
+            if let Some(pdu) = pdu {
+                sender.send(DecodedPdu { pdu, serial: 0 })?;
+            }
+            Ok::<(), anyhow::Error>(())
+        })
+        .detach();
+    }
+
     pub fn process_one(&mut self, decoded: DecodedPdu) {
         let start = Instant::now();
         let sender = self.to_write_tx.clone();
@@ -208,6 +478,63 @@ impl SessionHandler {
             send_response(f());
         }
 
+        /// Like catch, but runs `f` on a thread that is allowed to
+        /// block, so that file I/O doesn't hold up the main thread
+        fn catch_blocking<F, SND>(f: F, send_response: SND)
+        where
+            F: FnOnce() -> anyhow::Result<Pdu> + Send + 'static,
+            SND: Fn(anyhow::Result<Pdu>) + Send + 'static,
+        {
+            smol::unblock(move || send_response(f())).detach();
+        }
+
+        if self.peer.require_auth_token && self.auth_token.is_none() {
+            match &decoded.pdu {
+                Pdu::Authenticate(_) | Pdu::GetCodecVersion(_) => {}
+                _ => {
+                    send_response(Err(anyhow!(
+                        "this server requires authentication; rejecting {:?}",
+                        decoded.pdu
+                    )));
+                    return;
+                }
+            }
+        }
+
+        if let Err(err) = self.check_permission(&decoded.pdu) {
+            send_response(Err(err));
+            return;
+        }
+
+        if self.read_only {
+            match &decoded.pdu {
+                Pdu::WriteToPane(_)
+                | Pdu::SendPaste(_)
+                | Pdu::SendKeyDown(_)
+                | Pdu::SendMouseEvent(_)
+                | Pdu::SetPaneZoomed(_)
+                | Pdu::Spawn(_)
+                | Pdu::SplitPane(_)
+                | Pdu::OpenFileTransfer(_)
+                | Pdu::AddPortForward(_)
+                | Pdu::RemovePortForward(_)
+                | Pdu::OpenTcpStream(_) => {
+                    send_response(Err(anyhow!(
+                        "client is attached read-only; rejecting {:?}",
+                        decoded.pdu
+                    )));
+                    return;
+                }
+                // Observers don't get a say in the size of the panes
+                Pdu::Resize(_) => {
+                    send_response(Ok(Pdu::UnitResponse(UnitResponse {})));
+                    return;
+                }
+                _ => {}
+            }
+        }
+        let client_id = self.client_id.clone();
+
         match decoded.pdu {
             Pdu::Ping(Ping {}) => send_response(Ok(Pdu::Pong(Pong {}))),
             Pdu::ListPanes(ListPanes {}) => {
@@ -241,6 +568,7 @@ impl SessionHandler {
                             let pane = mux
                                 .get_pane(pane_id)
                                 .ok_or_else(|| anyhow!("no such pane {}", pane_id))?;
+                            note_client_input(&client_id, pane_id);
                             pane.writer().write_all(&data)?;
                             maybe_push_pane_changes(&pane, sender, per_pane)?;
                             Ok(Pdu::UnitResponse(UnitResponse {}))
@@ -260,6 +588,7 @@ impl SessionHandler {
                             let pane = mux
                                 .get_pane(pane_id)
                                 .ok_or_else(|| anyhow!("no such pane {}", pane_id))?;
+                            note_client_input(&client_id, pane_id);
                             pane.send_paste(&data)?;
                             maybe_push_pane_changes(&pane, sender, per_pane)?;
                             Ok(Pdu::UnitResponse(UnitResponse {}))
@@ -331,11 +660,16 @@ impl SessionHandler {
                             let pane = mux
                                 .get_pane(pane_id)
                                 .ok_or_else(|| anyhow!("no such pane {}", pane_id))?;
-                            pane.resize(size)?;
-                            let tab = mux
-                                .get_tab(containing_tab_id)
-                                .ok_or_else(|| anyhow!("no such tab {}", containing_tab_id))?;
-                            tab.rebuild_splits_sizes_from_contained_panes();
+                            if let Some(client_id) = &client_id {
+                                mux.record_client_pane_size(client_id, pane_id, size);
+                                apply_client_pane_size(pane_id)?;
+                            } else {
+                                pane.resize(size)?;
+                                let tab = mux
+                                    .get_tab(containing_tab_id)
+                                    .ok_or_else(|| anyhow!("no such tab {}", containing_tab_id))?;
+                                tab.rebuild_splits_sizes_from_contained_panes();
+                            }
                             Ok(Pdu::UnitResponse(UnitResponse {}))
                         },
                         send_response,
@@ -358,6 +692,7 @@ impl SessionHandler {
                             let pane = mux
                                 .get_pane(pane_id)
                                 .ok_or_else(|| anyhow!("no such pane {}", pane_id))?;
+                            note_client_input(&client_id, pane_id);
                             pane.key_down(event.key, event.modifiers)?;
 
                             // For a key press, we want to always send back the
@@ -388,6 +723,7 @@ impl SessionHandler {
                             let pane = mux
                                 .get_pane(pane_id)
                                 .ok_or_else(|| anyhow!("no such pane {}", pane_id))?;
+                            note_client_input(&client_id, pane_id);
                             pane.mouse_event(event)?;
                             maybe_push_pane_changes(&pane, sender, per_pane)?;
                             Ok(Pdu::UnitResponse(UnitResponse {}))
@@ -461,7 +797,7 @@ impl SessionHandler {
                             }
                             Ok(Pdu::GetLinesResponse(GetLinesResponse {
                                 pane_id,
-                                lines: lines_and_indices.into(),
+                                lines: per_pane.baselines.serialize_full(lines_and_indices),
                             }))
                         },
                         send_response,
@@ -470,6 +806,186 @@ impl SessionHandler {
                 .detach();
             }
 
+            Pdu::SetClientId(SetClientId {
+                client_id,
+                read_only,
+            }) => {
+                let read_only = read_only || self.permission == AuthPermission::ReadOnly;
+                self.client_id.replace(client_id.clone());
+                self.read_only = read_only;
+                let auth_token = self.auth_token.clone();
+                spawn_into_main_thread(async move {
+                    catch(
+                        move || {
+                            let mux = Mux::get().unwrap();
+                            mux.register_client(client_id, read_only, auth_token);
+                            Ok(Pdu::UnitResponse(UnitResponse {}))
+                        },
+                        send_response,
+                    )
+                })
+                .detach();
+            }
+
+            Pdu::GetClientList(GetClientList {}) => {
+                spawn_into_main_thread(async move {
+                    catch(
+                        move || {
+                            let mux = Mux::get().unwrap();
+                            let clients = mux.iter_clients();
+                            Ok(Pdu::GetClientListResponse(GetClientListResponse {
+                                clients,
+                            }))
+                        },
+                        send_response,
+                    )
+                })
+                .detach();
+            }
+
+            Pdu::NegotiateCompression(NegotiateCompression { compression }) => {
+                let compression =
+                    negotiate_compression(&compression, &configuration().mux_compression);
+                send_response(Ok(Pdu::NegotiateCompressionResponse(
+                    NegotiateCompressionResponse { compression },
+                )))
+            }
+
+            // The codec has already applied this
+            Pdu::SetCompressionDictionary(_) => {}
+
+            Pdu::OpenFileTransfer(OpenFileTransfer { path, mode }) => {
+                self.next_transfer_id += 1;
+                let transfer_id = self.next_transfer_id;
+                let file_transfers = Arc::clone(&self.file_transfers);
+                catch_blocking(
+                    move || {
+                        let transfer = FileTransfer::open(&path, mode)?;
+                        let size = transfer.size();
+                        file_transfers
+                            .lock()
+                            .unwrap()
+                            .insert(transfer_id, Arc::new(Mutex::new(transfer)));
+                        log::info!("client {:?} opened {:?} of {}", client_id, mode, path);
+                        Ok(Pdu::OpenFileTransferResponse(OpenFileTransferResponse {
+                            transfer_id,
+                            size,
+                        }))
+                    },
+                    send_response,
+                )
+            }
+
+            Pdu::WriteFileChunk(WriteFileChunk { transfer_id, data }) => {
+                match self.file_transfer(transfer_id) {
+                    Ok(transfer) => catch_blocking(
+                        move || {
+                            transfer.lock().unwrap().write(&data)?;
+                            Ok(Pdu::UnitResponse(UnitResponse {}))
+                        },
+                        send_response,
+                    ),
+                    Err(err) => send_response(Err(err)),
+                }
+            }
+
+            Pdu::ReadFileChunk(ReadFileChunk {
+                transfer_id,
+                max_len,
+            }) => match self.file_transfer(transfer_id) {
+                Ok(transfer) => catch_blocking(
+                    move || {
+                        let data = transfer.lock().unwrap().read(max_len)?;
+                        Ok(Pdu::ReadFileChunkResponse(ReadFileChunkResponse { data }))
+                    },
+                    send_response,
+                ),
+                Err(err) => send_response(Err(err)),
+            },
+
+            Pdu::CloseFileTransfer(CloseFileTransfer {
+                transfer_id,
+                complete,
+            }) => {
+                let transfer = self.file_transfers.lock().unwrap().remove(&transfer_id);
+                match transfer {
+                    // Dropping the transfer removes the temporary file of an
+                    // incomplete upload, so let that happen on the worker too
+                    Some(transfer) => catch_blocking(
+                        move || {
+                            transfer.lock().unwrap().close(complete)?;
+                            Ok(Pdu::UnitResponse(UnitResponse {}))
+                        },
+                        send_response,
+                    ),
+                    None => send_response(Err(anyhow!("no such file transfer {}", transfer_id))),
+                }
+            }
+
+            Pdu::AddPortForward(AddPortForward { forward }) => {
+                match self.port_forwards.add(forward.clone()) {
+                    Ok(forward_id) => {
+                        log::info!("client {:?} forwarding {}", client_id, forward);
+                        spawn_into_main_thread(async move {
+                            if let Some(client_id) = &client_id {
+                                let mux = Mux::get().unwrap();
+                                mux.record_client_port_forward(client_id, forward_id, forward);
+                            }
+                            send_response(Ok(Pdu::AddPortForwardResponse(AddPortForwardResponse {
+                                forward_id,
+                            })))
+                        })
+                        .detach();
+                    }
+                    Err(err) => send_response(Err(err)),
+                }
+            }
+
+            Pdu::RemovePortForward(RemovePortForward { forward_id }) => {
+                spawn_into_main_thread(async move {
+                    catch(
+                        move || {
+                            let mux = Mux::get().unwrap();
+                            ensure!(
+                                mux.has_port_forward(forward_id),
+                                "no such port forward {}",
+                                forward_id
+                            );
+                            mux.notify(MuxNotification::PortForwardRemoved(forward_id));
+                            Ok(Pdu::UnitResponse(UnitResponse {}))
+                        },
+                        send_response,
+                    )
+                })
+                .detach();
+            }
+
+            Pdu::OpenTcpStream(OpenTcpStream {
+                forward_id,
+                stream_id,
+            }) => {
+                if let Err(err) =
+                    self.port_forwards
+                        .open_stream(forward_id, stream_id, send_response.clone())
+                {
+                    send_response(Err(err));
+                }
+            }
+
+            // The relayed stream PDUs are unilateral; the client
+            // doesn't wait for a response to them
+            Pdu::TcpStreamData(TcpStreamData { stream_id, data }) => {
+                self.port_forwards.write(stream_id, data);
+            }
+
+            Pdu::TcpStreamAck(TcpStreamAck { stream_id, len }) => {
+                self.port_forwards.ack(stream_id, len);
+            }
+
+            Pdu::CloseTcpStream(CloseTcpStream { stream_id }) => {
+                self.port_forwards.finish_writing(stream_id);
+            }
+
             Pdu::GetCodecVersion(_) => {
                 send_response(Ok(Pdu::GetCodecVersionResponse(GetCodecVersionResponse {
                     codec_vers: CODEC_VERSION,
@@ -491,6 +1007,108 @@ impl SessionHandler {
                 );
             }
 
+            Pdu::Authenticate(Authenticate { token }) => {
+                if let Some(retry_after) = self.auth_retry_after {
+                    if Instant::now() < retry_after {
+                        crate::audit::record(
+                            &self.peer,
+                            "authentication attempt rejected; too many failures",
+                        );
+                        send_response(Err(anyhow!(
+                            "too many failed attempts to authenticate; try again in {:?}",
+                            retry_after.saturating_duration_since(Instant::now())
+                        )));
+                        return;
+                    }
+                }
+                match TokenStore::from_config().authenticate(&token) {
+                    Ok((name, permission)) => {
+                        self.auth_failures = 0;
+                        self.auth_retry_after = None;
+                        crate::audit::record(
+                            &self.peer,
+                            &format!("authenticated with token {} ({})", name, permission),
+                        );
+                        if permission == AuthPermission::ReadOnly {
+                            self.read_only = true;
+                        }
+                        self.auth_token.replace(name.clone());
+                        self.permission = permission;
+                        send_response(Ok(Pdu::AuthenticateResponse(AuthenticateResponse {
+                            name,
+                            permission,
+                        })));
+                    }
+                    Err(err) => {
+                        crate::audit::record(
+                            &self.peer,
+                            &format!("failed to authenticate: {:#}", err),
+                        );
+                        self.auth_failures = self.auth_failures.saturating_add(1);
+                        self.auth_retry_after =
+                            Some(Instant::now() + crate::auth::failure_backoff(self.auth_failures));
+                        send_response(Err(err));
+                    }
+                }
+            }
+
+            Pdu::CreateAuthToken(CreateAuthToken { name, permission }) => {
+                let peer = self.peer.clone();
+                catch(
+                    move || {
+                        let token = TokenStore::from_config().create(&name, permission)?;
+                        crate::audit::record(
+                            &peer,
+                            &format!("created token {} ({})", name, permission),
+                        );
+                        Ok(Pdu::AuthTokenResponse(AuthTokenResponse { name, token }))
+                    },
+                    send_response,
+                );
+            }
+
+            Pdu::RotateAuthToken(RotateAuthToken { name }) => {
+                let peer = self.peer.clone();
+                catch(
+                    move || {
+                        let token = TokenStore::from_config().rotate(&name)?;
+                        crate::audit::record(&peer, &format!("rotated token {}", name));
+                        Ok(Pdu::AuthTokenResponse(AuthTokenResponse { name, token }))
+                    },
+                    send_response,
+                );
+            }
+
+            Pdu::RevokeAuthToken(RevokeAuthToken { name }) => {
+                let peer = self.peer.clone();
+                spawn_into_main_thread(async move {
+                    catch(
+                        move || {
+                            TokenStore::from_config().revoke(&name)?;
+                            crate::audit::record(&peer, &format!("revoked token {}", name));
+                            // Deferred so that the response is sent before
+                            // this client is disconnected, in case it used
+                            // the revoked token itself
+                            let mux = Mux::get().unwrap();
+                            mux.notify_later(MuxNotification::AuthTokenRevoked(name));
+                            Ok(Pdu::UnitResponse(UnitResponse {}))
+                        },
+                        send_response,
+                    )
+                })
+                .detach();
+            }
+
+            Pdu::ListAuthTokens(ListAuthTokens {}) => catch(
+                move || {
+                    let tokens = TokenStore::from_config().list()?;
+                    Ok(Pdu::ListAuthTokensResponse(ListAuthTokensResponse {
+                        tokens,
+                    }))
+                },
+                send_response,
+            ),
+
             Pdu::Invalid { .. } => send_response(Err(anyhow!("invalid PDU {:?}", decoded.pdu))),
             Pdu::Pong { .. }
             | Pdu::ListPanesResponse { .. }
@@ -503,6 +1121,23 @@ impl SessionHandler {
             | Pdu::GetLinesResponse { .. }
             | Pdu::GetCodecVersionResponse { .. }
             | Pdu::GetTlsCredsResponse { .. }
+            | Pdu::TabTreeChanged { .. }
+            | Pdu::TabRemoved { .. }
+            | Pdu::PaneRemoved { .. }
+            | Pdu::PaneMetadataChanged { .. }
+            | Pdu::ActivePaneChanged { .. }
+            | Pdu::PaneZoomChanged { .. }
+            | Pdu::NotifyAlert { .. }
+            | Pdu::OpenFileTransferResponse { .. }
+            | Pdu::ReadFileChunkResponse { .. }
+            | Pdu::AddPortForwardResponse { .. }
+            | Pdu::PortForwardRemoved { .. }
+            | Pdu::TcpStreamOpened { .. }
+            | Pdu::GetClientListResponse { .. }
+            | Pdu::NegotiateCompressionResponse { .. }
+            | Pdu::AuthenticateResponse { .. }
+            | Pdu::AuthTokenResponse { .. }
+            | Pdu::ListAuthTokensResponse { .. }
             | Pdu::ErrorResponse { .. } => {
                 send_response(Err(anyhow!("expected a request, got {:?}", decoded.pdu)))
             }
@@ -639,3 +1274,98 @@ async fn domain_spawn(spawn: Spawn, sender: PduSender) -> anyhow::Result<Pdu> {
         size: tab.get_size(),
     }))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn permission_table() {
+        use AuthPermission::*;
+
+        // (pdu, full, no-spawn, read-only)
+        let cases = vec![
+            (Pdu::Ping(Ping {}), true, true, true),
+            (Pdu::ListPanes(ListPanes {}), true, true, true),
+            (
+                Pdu::WriteToPane(WriteToPane {
+                    pane_id: 0,
+                    data: b"ls\n".to_vec(),
+                }),
+                true,
+                true,
+                false,
+            ),
+            (
+                Pdu::SendPaste(SendPaste {
+                    pane_id: 0,
+                    data: "ls".to_string(),
+                }),
+                true,
+                true,
+                false,
+            ),
+            (
+                Pdu::Spawn(Spawn {
+                    domain_id: 0,
+                    window_id: None,
+                    command: None,
+                    command_dir: None,
+                    size: PtySize::default(),
+                }),
+                true,
+                false,
+                false,
+            ),
+            (
+                Pdu::OpenFileTransfer(OpenFileTransfer {
+                    path: "~/.ssh/id_rsa".to_string(),
+                    mode: FileTransferMode::Download,
+                }),
+                true,
+                false,
+                false,
+            ),
+            (
+                Pdu::RemovePortForward(RemovePortForward { forward_id: 1 }),
+                true,
+                false,
+                false,
+            ),
+            (
+                Pdu::OpenTcpStream(OpenTcpStream {
+                    forward_id: 1,
+                    stream_id: 1,
+                }),
+                true,
+                false,
+                false,
+            ),
+            (Pdu::ListAuthTokens(ListAuthTokens {}), true, false, false),
+            (
+                Pdu::RevokeAuthToken(RevokeAuthToken {
+                    name: "ci".to_string(),
+                }),
+                true,
+                false,
+                false,
+            ),
+        ];
+
+        for (pdu, full, no_spawn, read_only) in cases {
+            assert_eq!(permission_allows(Full, &pdu), full, "full {:?}", pdu);
+            assert_eq!(
+                permission_allows(NoSpawn, &pdu),
+                no_spawn,
+                "no-spawn {:?}",
+                pdu
+            );
+            assert_eq!(
+                permission_allows(ReadOnly, &pdu),
+                read_only,
+                "read-only {:?}",
+                pdu
+            );
+        }
+    }
+}
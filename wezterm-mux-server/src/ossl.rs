@@ -7,18 +7,21 @@ use promise::spawn::spawn_into_main_thread;
 use std::net::TcpListener;
 use std::path::Path;
 use std::sync::Arc;
+use wezterm_mux_server_impl::auth::PeerInfo;
 use wezterm_mux_server_impl::PKI;
 
 struct OpenSSLNetListener {
     acceptor: Arc<SslAcceptor>,
     listener: TcpListener,
+    require_auth_token: bool,
 }
 
 impl OpenSSLNetListener {
-    pub fn new(listener: TcpListener, acceptor: SslAcceptor) -> Self {
+    pub fn new(listener: TcpListener, acceptor: SslAcceptor, require_auth_token: bool) -> Self {
         Self {
             listener,
             acceptor: Arc::new(acceptor),
+            require_auth_token,
         }
     }
 
@@ -75,6 +78,13 @@ impl OpenSSLNetListener {
                 Ok(stream) => {
                     stream.set_nodelay(true).ok();
                     let acceptor = self.acceptor.clone();
+                    let peer = PeerInfo {
+                        description: match stream.peer_addr() {
+                            Ok(addr) => format!("tls {}", addr),
+                            Err(_) => "tls peer".to_string(),
+                        },
+                        require_auth_token: self.require_auth_token,
+                    };
 
                     match acceptor.accept(stream) {
                         Ok(stream) => {
@@ -82,11 +92,13 @@ impl OpenSSLNetListener {
                                 log::error!("problem with peer cert: {}", err);
                                 break;
                             }
+                            wezterm_mux_server_impl::audit::record(&peer, "connected");
                             spawn_into_main_thread(async move {
                                 log::error!("Making new AsyncSslStream");
-                                wezterm_mux_server_impl::dispatch::process(AsyncSslStream::new(
-                                    stream,
-                                ))
+                                wezterm_mux_server_impl::dispatch::process(
+                                    AsyncSslStream::new(stream),
+                                    peer,
+                                )
                                 .await
                                 .map_err(|e| {
                                     log::error!("process: {:?}", e);
@@ -180,6 +192,7 @@ pub fn spawn_tls_listener(tls_server: &TlsDomainServer) -> Result<(), Error> {
             )
         })?,
         acceptor,
+        tls_server.require_auth_token,
     );
     std::thread::spawn(move || {
         net_listener.run();
@@ -76,3 +76,44 @@ pub fn persistent_toast_notification(title: &str, message: &str) {
         });
     }
 }
+
+/// Show a notification that is dismissed after the system's usual
+/// timeout, rather than remaining until the user dismisses it
+pub fn toast_notification(title: &str, message: &str) {
+    #[cfg(target_os = "macos")]
+    {
+        macos::show_notif(title, message, None);
+    }
+
+    #[cfg(all(not(target_os = "macos"), not(windows)))]
+    {
+        let mut notif = notify_rust::Notification::new();
+        notif
+            .appname("wezterm")
+            .summary(title)
+            .body(message)
+            .icon("org.wezfurlong.wezterm")
+            .show()
+            .ok();
+    }
+
+    #[cfg(windows)]
+    {
+        let title = title.to_owned();
+        let message = message.to_owned();
+
+        // We need to be in a different thread from the caller
+        // in case we get called in the guts of a windows message
+        // loop dispatch and are unable to pump messages
+        std::thread::spawn(move || {
+            use winrt_notification::Toast;
+
+            Toast::new(Toast::POWERSHELL_APP_ID)
+                .title(&title)
+                .text1(&message)
+                .duration(winrt_notification::Duration::Short)
+                .show()
+                .ok();
+        });
+    }
+}
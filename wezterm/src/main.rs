@@ -1,12 +1,14 @@
 use anyhow::{anyhow, Context};
 use config::wezterm_version;
 use mux::activity::Activity;
+use mux::client::AuthPermission;
 use mux::pane::PaneId;
 use mux::tab::SplitDirection;
 use mux::Mux;
 use portable_pty::cmdbuilder::CommandBuilder;
 use std::ffi::OsString;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use structopt::StructOpt;
 use tabout::{tabulate_output, Alignment, Column};
@@ -56,6 +58,15 @@ enum SubCommand {
     #[structopt(name = "connect", about = "Connect to wezterm multiplexer")]
     Connect(ConnectCommand),
 
+    #[structopt(name = "ls-fonts", about = "Display information about fonts")]
+    LsFonts(LsFontsCommand),
+
+    #[structopt(
+        name = "render-png",
+        about = "Render terminal output to a PNG file using the software renderer"
+    )]
+    RenderPng(RenderPngCommand),
+
     #[structopt(name = "cli", about = "Interact with experimental mux server")]
     Cli(CliCommand),
 
@@ -85,9 +96,155 @@ enum CliSubCommand {
     #[structopt(name = "list", about = "list windows, tabs and panes")]
     List,
 
+    #[structopt(
+        name = "list-clients",
+        about = "list clients that are attached to the mux"
+    )]
+    ListClients,
+
     #[structopt(name = "proxy", about = "start rpc proxy pipe")]
     Proxy,
 
+    #[structopt(name = "upload", about = "copy a local file to the mux server host")]
+    Upload {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// The local file to upload
+        #[structopt(parse(from_os_str))]
+        local: PathBuf,
+
+        /// The destination path on the server host.
+        /// Relative paths are relative to the home directory.
+        /// The default is the name of the local file.
+        remote: Option<String>,
+    },
+
+    #[structopt(
+        name = "download",
+        about = "copy a file from the mux server host to the local machine"
+    )]
+    Download {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// The path of the file on the server host.
+        /// Relative paths are relative to the home directory.
+        remote: String,
+
+        /// Where to save the file.
+        /// The default is the name of the remote file, in the
+        /// current directory.
+        #[structopt(parse(from_os_str))]
+        local: Option<PathBuf>,
+    },
+
+    #[structopt(
+        name = "forward",
+        about = "forward a TCP port over the mux connection until interrupted"
+    )]
+    Forward {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// Listen on the remote address on the mux server host and
+        /// forward connections to the local port, rather than
+        /// the other way around
+        #[structopt(long = "reverse")]
+        reverse: bool,
+
+        /// The port on the local machine
+        local: u16,
+
+        /// The host:port on the mux server side
+        remote: String,
+    },
+
+    #[structopt(
+        name = "list-forwards",
+        about = "list the TCP ports that clients are forwarding"
+    )]
+    ListForwards {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+    },
+
+    #[structopt(name = "remove-forward", about = "stop forwarding a TCP port")]
+    RemoveForward {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// The id of the forward, as shown by list-forwards
+        forward_id: u64,
+    },
+
+    #[structopt(
+        name = "create-token",
+        about = "create a token that clients can authenticate with.
+Outputs the token on success; it cannot be retrieved later"
+    )]
+    CreateToken {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// What clients that use the token may do:
+        /// full, read-only or no-spawn
+        #[structopt(long = "permission", default_value = "full")]
+        permission: AuthPermission,
+
+        /// A name to identify the token
+        name: String,
+    },
+
+    #[structopt(name = "list-tokens", about = "list the authentication tokens")]
+    ListTokens {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+    },
+
+    #[structopt(
+        name = "rotate-token",
+        about = "replace an authentication token with a new one.
+Outputs the new token on success"
+    )]
+    RotateToken {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// The name of the token
+        name: String,
+    },
+
+    #[structopt(
+        name = "revoke-token",
+        about = "delete an authentication token and disconnect the clients using it"
+    )]
+    RevokeToken {
+        /// Connect to the named unix, tls or ssh domain rather than
+        /// the default unix domain
+        #[structopt(long = "domain")]
+        domain: Option<String>,
+
+        /// The name of the token
+        name: String,
+    },
+
     #[structopt(name = "tlscreds", about = "obtain tls credentials")]
     TlsCreds,
 
@@ -253,7 +410,9 @@ fn run() -> anyhow::Result<()> {
         SubCommand::Start(_)
         | SubCommand::Ssh(_)
         | SubCommand::Serial(_)
-        | SubCommand::Connect(_) => delegate_to_gui(saver),
+        | SubCommand::Connect(_)
+        | SubCommand::LsFonts(_)
+        | SubCommand::RenderPng(_) => delegate_to_gui(saver),
         SubCommand::ImageCat(cmd) => cmd.run(),
         SubCommand::SetCwd(cmd) => cmd.run(),
         SubCommand::Cli(cli) => run_cli(config, cli),
@@ -305,7 +464,33 @@ fn delegate_to_gui(saver: UmaskSaver) -> anyhow::Result<()> {
 async fn run_cli_async(config: config::ConfigHandle, cli: CliCommand) -> anyhow::Result<()> {
     let initial = true;
     let mut ui = mux::connui::ConnectionUI::new_headless();
-    let client = Client::new_default_unix_domain(initial, &mut ui)?;
+    let client = match &cli.sub {
+        CliSubCommand::Upload {
+            domain: Some(name), ..
+        }
+        | CliSubCommand::Download {
+            domain: Some(name), ..
+        }
+        | CliSubCommand::Forward {
+            domain: Some(name), ..
+        }
+        | CliSubCommand::ListForwards { domain: Some(name) }
+        | CliSubCommand::RemoveForward {
+            domain: Some(name), ..
+        }
+        | CliSubCommand::CreateToken {
+            domain: Some(name), ..
+        }
+        | CliSubCommand::ListTokens { domain: Some(name) }
+        | CliSubCommand::RotateToken {
+            domain: Some(name), ..
+        }
+        | CliSubCommand::RevokeToken {
+            domain: Some(name), ..
+        } => Client::new_for_domain_name(name, &mut ui)?,
+        _ => Client::new_default_unix_domain(initial, &mut ui)?,
+    };
+    client.authenticate().await?;
     match cli.sub {
         CliSubCommand::List => {
             let cols = vec![
@@ -365,6 +550,131 @@ async fn run_cli_async(config: config::ConfigHandle, cli: CliCommand) -> anyhow:
 
             tabulate_output(&cols, &data, &mut std::io::stdout().lock())?;
         }
+        CliSubCommand::ListClients => {
+            let cols = vec![
+                Column {
+                    name: "USER".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "HOST".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "PID".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "CONNECTED".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "IDLE".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "MODE".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "TOKEN".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "SENT".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "RATIO".to_string(),
+                    alignment: Alignment::Right,
+                },
+            ];
+            let mut clients = client.list_clients().await?.clients;
+            clients.sort_by_key(|info| info.connected_at);
+
+            fn bytes(n: u64) -> String {
+                if n >= 1024 * 1024 {
+                    format!("{:.1}MiB", n as f64 / (1024. * 1024.))
+                } else if n >= 1024 {
+                    format!("{:.1}KiB", n as f64 / 1024.)
+                } else {
+                    format!("{}B", n)
+                }
+            }
+
+            let data: Vec<Vec<String>> = clients
+                .into_iter()
+                .map(|info| {
+                    vec![
+                        info.client_id.username,
+                        info.client_id.hostname,
+                        info.client_id.pid.to_string(),
+                        elapsed(info.connected_at),
+                        elapsed(info.last_input),
+                        if info.read_only {
+                            "read-only"
+                        } else {
+                            "interactive"
+                        }
+                        .to_string(),
+                        info.auth_token.unwrap_or_else(|| "-".to_string()),
+                        bytes(info.compression.compressed_bytes_sent),
+                        format!("{:.1}x", info.compression.send_ratio()),
+                    ]
+                })
+                .collect();
+
+            tabulate_output(&cols, &data, &mut std::io::stdout().lock())?;
+        }
+        CliSubCommand::ListForwards { .. } => {
+            let cols = vec![
+                Column {
+                    name: "ID".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "USER".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "HOST".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "PID".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "DIRECTION".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "LOCAL".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "REMOTE".to_string(),
+                    alignment: Alignment::Left,
+                },
+            ];
+            let mut data = vec![];
+            for info in client.list_clients().await?.clients {
+                for (forward_id, forward) in info.port_forwards {
+                    data.push(vec![
+                        forward_id.to_string(),
+                        info.client_id.username.clone(),
+                        info.client_id.hostname.clone(),
+                        info.client_id.pid.to_string(),
+                        format!("{:?}", forward.direction),
+                        forward.local.to_string(),
+                        forward.remote,
+                    ]);
+                }
+            }
+            data.sort_by_key(|row| row[0].parse::<u64>().unwrap_or(0));
+
+            tabulate_output(&cols, &data, &mut std::io::stdout().lock())?;
+        }
         CliSubCommand::SplitPane {
             pane_id,
             cwd,
@@ -439,10 +749,148 @@ async fn run_cli_async(config: config::ConfigHandle, cli: CliCommand) -> anyhow:
             let creds = client.get_tls_creds().await?;
             codec::Pdu::GetTlsCredsResponse(creds).encode(std::io::stdout().lock(), 0)?;
         }
+        CliSubCommand::Upload { local, remote, .. } => {
+            let remote = match remote {
+                Some(remote) => remote,
+                None => local
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| anyhow!("{} has no usable file name", local.display()))?
+                    .to_string(),
+            };
+            client
+                .upload_file(&local, &remote, |sent, size| {
+                    report_transfer_progress(&remote, sent, size)
+                })
+                .await?;
+            eprintln!();
+        }
+        CliSubCommand::Download { remote, local, .. } => {
+            let local = match local {
+                Some(local) => local,
+                None => PathBuf::from(
+                    Path::new(&remote)
+                        .file_name()
+                        .ok_or_else(|| anyhow!("{} has no file name", remote))?,
+                ),
+            };
+            client
+                .download_file(&remote, &local, |received, size| {
+                    report_transfer_progress(&remote, received, size)
+                })
+                .await?;
+            eprintln!();
+        }
+        CliSubCommand::Forward {
+            reverse,
+            local,
+            remote,
+            ..
+        } => {
+            let forward = config::PortForward {
+                local,
+                remote,
+                direction: if reverse {
+                    config::PortForwardDirection::RemoteToLocal
+                } else {
+                    config::PortForwardDirection::LocalToRemote
+                },
+            };
+            // The server records forwards against the client that made
+            // them, which is how list-forwards and remove-forward find them
+            client
+                .set_client_id(codec::SetClientId {
+                    client_id: mux::client::ClientId::new(),
+                    read_only: false,
+                })
+                .await?;
+            let forward_id = client.add_port_forward(forward.clone()).await?;
+            println!("{}", forward_id);
+            eprintln!("Forwarding {}; press CTRL-C to stop", forward);
+            client.wait_for_port_forwards().await?;
+        }
+        CliSubCommand::RemoveForward { forward_id, .. } => {
+            client
+                .remove_port_forward(codec::RemovePortForward { forward_id })
+                .await?;
+        }
+        CliSubCommand::CreateToken {
+            name, permission, ..
+        } => {
+            let created = client
+                .create_auth_token(codec::CreateAuthToken { name, permission })
+                .await?;
+            println!("{}", created.token);
+        }
+        CliSubCommand::ListTokens { .. } => {
+            let cols = vec![
+                Column {
+                    name: "NAME".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "PERMISSION".to_string(),
+                    alignment: Alignment::Left,
+                },
+                Column {
+                    name: "CREATED".to_string(),
+                    alignment: Alignment::Right,
+                },
+                Column {
+                    name: "LAST USED".to_string(),
+                    alignment: Alignment::Right,
+                },
+            ];
+            let mut tokens = client.list_auth_tokens().await?.tokens;
+            tokens.sort_by(|a, b| a.name.cmp(&b.name));
+            let data: Vec<Vec<String>> = tokens
+                .into_iter()
+                .map(|info| {
+                    vec![
+                        info.name,
+                        info.permission.to_string(),
+                        elapsed(info.created),
+                        info.last_used
+                            .map(elapsed)
+                            .unwrap_or_else(|| "never".to_string()),
+                    ]
+                })
+                .collect();
+
+            tabulate_output(&cols, &data, &mut std::io::stdout().lock())?;
+        }
+        CliSubCommand::RotateToken { name, .. } => {
+            let rotated = client
+                .rotate_auth_token(codec::RotateAuthToken { name })
+                .await?;
+            println!("{}", rotated.token);
+        }
+        CliSubCommand::RevokeToken { name, .. } => {
+            client
+                .revoke_auth_token(codec::RevokeAuthToken { name })
+                .await?;
+        }
     }
     Ok(())
 }
 
+/// Format the time since `since` compactly, for tabulated output
+fn elapsed(since: std::time::SystemTime) -> String {
+    let secs = since.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+fn report_transfer_progress(name: &str, done: u64, size: u64) {
+    let percent = if size == 0 { 100 } else { done * 100 / size };
+    eprint!("\r{}: {}/{} bytes ({}%)", name, done, size, percent);
+}
+
 fn run_cli(config: config::ConfigHandle, cli: CliCommand) -> anyhow::Result<()> {
     let executor = promise::spawn::SimpleExecutor::new();
     promise::spawn::spawn(async move {
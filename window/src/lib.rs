@@ -121,6 +121,11 @@ pub trait WindowCallbacks: Any {
         context.set_cursor(Some(MouseCursor::Arrow));
     }
 
+    /// Called when files are dragged from another application and
+    /// dropped onto the window.  `coords` is the position of the drop,
+    /// in the same coordinate space as `MouseEvent::coords`.
+    fn dropped_files(&mut self, paths: Vec<std::path::PathBuf>, coords: Point) {}
+
     /// Called when the window is created and allows the embedding
     /// app to reference the window and operate upon it.
     fn created(
@@ -11,9 +11,9 @@ use crate::{
 use anyhow::{anyhow, bail, ensure};
 use cocoa::appkit::{
     self, NSApplication, NSApplicationActivateIgnoringOtherApps, NSApplicationPresentationOptions,
-    NSBackingStoreBuffered, NSEvent, NSEventModifierFlags, NSOpenGLContext, NSOpenGLPixelFormat,
-    NSRunningApplication, NSScreen, NSView, NSViewHeightSizable, NSViewWidthSizable, NSWindow,
-    NSWindowStyleMask,
+    NSBackingStoreBuffered, NSEvent, NSEventModifierFlags, NSFilenamesPboardType, NSOpenGLContext,
+    NSOpenGLPixelFormat, NSRunningApplication, NSScreen, NSView, NSViewHeightSizable,
+    NSViewWidthSizable, NSWindow, NSWindowStyleMask,
 };
 use cocoa::base::*;
 use cocoa::foundation::NSAutoreleasePool;
@@ -31,6 +31,7 @@ use promise::Future;
 use std::any::Any;
 use std::cell::RefCell;
 use std::ffi::c_void;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::time::Instant;
@@ -436,6 +437,9 @@ impl Window {
             window.setContentView_(*view);
             window.setDelegate_(*view);
 
+            let dragged_types = NSArray::arrayWithObject(nil, NSFilenamesPboardType);
+            let () = msg_send![*view, registerForDraggedTypes: dragged_types];
+
             let frame = NSView::frame(*view);
             let backing_frame = NSView::convertRectToBacking(*view, frame);
             let width = backing_frame.size.width;
@@ -1498,6 +1502,43 @@ impl WindowView {
         Self::mouse_common(this, nsevent, MouseEventKind::Move);
     }
 
+    extern "C" fn dragging_entered(_this: &mut Object, _sel: Sel, _sender: id) -> NSUInteger {
+        // NSDragOperationCopy
+        1
+    }
+
+    extern "C" fn perform_drag_operation(this: &mut Object, _sel: Sel, sender: id) -> BOOL {
+        let view = this as id;
+        let mut paths = vec![];
+        let coords;
+        unsafe {
+            let pboard: id = msg_send![sender, draggingPasteboard];
+            let filenames: id = msg_send![pboard, propertyListForType: NSFilenamesPboardType];
+            if filenames == nil {
+                return NO;
+            }
+            for idx in 0..NSArray::count(filenames) {
+                let name = NSArray::objectAtIndex(filenames, idx);
+                paths.push(PathBuf::from(nsstring_to_str(name)));
+            }
+
+            let location: NSPoint = msg_send![sender, draggingLocation];
+            let point = NSView::convertPoint_fromView_(view, location, nil);
+            let rect = NSRect::new(NSPoint::new(0., 0.), NSSize::new(point.x, point.y));
+            let backing_rect = NSView::convertRectToBacking(view, rect);
+            coords = Point::new(
+                backing_rect.size.width as isize,
+                backing_rect.size.height as isize,
+            );
+        }
+
+        if let Some(myself) = Self::get_this(this) {
+            let mut inner = myself.inner.borrow_mut();
+            inner.callbacks.dropped_files(paths, coords);
+        }
+        YES
+    }
+
     fn key_common(this: &mut Object, nsevent: id, key_is_down: bool) {
         // let is_a_repeat = unsafe { nsevent.isARepeat() == YES };
         let chars = unsafe { nsstring_to_str(nsevent.characters()) };
@@ -1852,6 +1893,14 @@ impl WindowView {
                 sel!(rightMouseDragged:),
                 Self::mouse_moved_or_dragged as extern "C" fn(&mut Object, Sel, id),
             );
+            cls.add_method(
+                sel!(draggingEntered:),
+                Self::dragging_entered as extern "C" fn(&mut Object, Sel, id) -> NSUInteger,
+            );
+            cls.add_method(
+                sel!(performDragOperation:),
+                Self::perform_drag_operation as extern "C" fn(&mut Object, Sel, id) -> BOOL,
+            );
             cls.add_method(
                 sel!(mouseDown:),
                 Self::mouse_down as extern "C" fn(&mut Object, Sel, id),
@@ -1,7 +1,13 @@
 use super::copy_and_paste::*;
+use super::window::read_pipe_with_timeout;
+use crate::connection::ConnectionOps;
+use crate::os::paths_from_uri_list;
 use crate::os::wayland::connection::WaylandConnection;
+use anyhow::Error;
+use filedescriptor::Pipe;
 use smithay_client_toolkit as toolkit;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::io::AsRawFd;
 use std::sync::{Arc, Mutex};
 use toolkit::reexports::client::protocol::wl_data_device::{
     Event as DataDeviceEvent, WlDataDevice,
@@ -13,17 +19,33 @@ use toolkit::reexports::client::protocol::wl_pointer::{
 use toolkit::reexports::client::protocol::wl_surface::WlSurface;
 use toolkit::seat::pointer::{ThemeManager, ThemeSpec, ThemedPointer};
 use wayland_client::protocol::wl_compositor::WlCompositor;
-use wayland_client::protocol::wl_data_device_manager::WlDataDeviceManager;
+use wayland_client::protocol::wl_data_device_manager::{DndAction, WlDataDeviceManager};
 use wayland_client::protocol::wl_seat::WlSeat;
 use wayland_client::protocol::wl_shm::WlShm;
 use wayland_client::{Attached, Main};
 use wezterm_input_types::*;
 
+const URI_MIME_TYPE: &str = "text/uri-list";
+
+/// A drag and drop operation from another application that is
+/// over one of our windows
+struct DragAndDrop {
+    offer: WlDataOffer,
+    window_id: usize,
+    x: f64,
+    y: f64,
+}
+
 #[derive(Default)]
 struct Inner {
     active_surface_id: u32,
     surface_to_pending: HashMap<u32, Arc<Mutex<PendingMouse>>>,
     serial: u32,
+    /// The ids of the data offers that are able to provide a list of URIs
+    uri_list_offers: HashSet<u32>,
+    drag_and_drop: Option<DragAndDrop>,
+    /// Offers that have been dropped and whose data is being read
+    dropped_offers: HashMap<u32, WlDataOffer>,
 }
 
 impl Inner {
@@ -55,6 +77,11 @@ impl Inner {
     }
 
     fn route_data_offer(&mut self, event: DataOfferEvent, offer: WlDataOffer) {
+        if let DataOfferEvent::Offer { mime_type } = &event {
+            if mime_type == URI_MIME_TYPE {
+                self.uri_list_offers.insert(offer.as_ref().id());
+            }
+        }
         if let Some(copy_and_paste) = self.resolve_copy_and_paste() {
             copy_and_paste
                 .lock()
@@ -74,10 +101,54 @@ impl Inner {
                     }
                 });
             }
-            DataDeviceEvent::Enter { .. }
-            | DataDeviceEvent::Leave { .. }
-            | DataDeviceEvent::Motion { .. }
-            | DataDeviceEvent::Drop => {}
+            DataDeviceEvent::Enter {
+                serial,
+                surface,
+                x,
+                y,
+                id,
+            } => {
+                if let Some(offer) = id {
+                    let window_id = self
+                        .surface_to_pending
+                        .get(&surface.as_ref().id())
+                        .map(|pending| pending.lock().unwrap().window_id);
+                    match window_id {
+                        Some(window_id) if self.uri_list_offers.contains(&offer.as_ref().id()) => {
+                            offer.accept(serial, Some(URI_MIME_TYPE.to_string()));
+                            if offer.as_ref().version() >= 3 {
+                                offer.set_actions(DndAction::Copy, DndAction::Copy);
+                            }
+                            self.drag_and_drop = Some(DragAndDrop {
+                                offer,
+                                window_id,
+                                x,
+                                y,
+                            });
+                        }
+                        _ => offer.accept(serial, None),
+                    }
+                }
+            }
+            DataDeviceEvent::Motion { x, y, .. } => {
+                if let Some(dnd) = self.drag_and_drop.as_mut() {
+                    dnd.x = x;
+                    dnd.y = y;
+                }
+            }
+            DataDeviceEvent::Leave { .. } => {
+                if let Some(dnd) = self.drag_and_drop.take() {
+                    dnd.offer.destroy();
+                }
+                self.uri_list_offers.clear();
+            }
+            DataDeviceEvent::Drop => {
+                if let Some(dnd) = self.drag_and_drop.take() {
+                    if let Err(err) = self.receive_drop(dnd) {
+                        log::error!("while receiving dropped files: {:#}", err);
+                    }
+                }
+            }
 
             DataDeviceEvent::Selection { id } => {
                 if let Some(offer) = id {
@@ -89,6 +160,46 @@ impl Inner {
             _ => {}
         }
     }
+
+    /// Read the list of URIs from a dropped offer and deliver
+    /// the files to the window that they were dropped on
+    fn receive_drop(&mut self, dnd: DragAndDrop) -> anyhow::Result<()> {
+        let pipe = Pipe::new().map_err(Error::msg)?;
+        dnd.offer
+            .receive(URI_MIME_TYPE.to_string(), pipe.write.as_raw_fd());
+        let read = pipe.read;
+
+        let DragAndDrop {
+            offer,
+            window_id,
+            x,
+            y,
+        } = dnd;
+        let offer_id = offer.as_ref().id();
+        self.dropped_offers.insert(offer_id, offer);
+
+        std::thread::spawn(move || {
+            let result = read_pipe_with_timeout(read);
+            promise::spawn::spawn_into_main_thread(async move {
+                let conn = crate::Connection::get().unwrap().wayland();
+                conn.pointer.finish_drop(offer_id);
+                match result {
+                    Ok(list) => {
+                        let paths = paths_from_uri_list(&list);
+                        if paths.is_empty() {
+                            return;
+                        }
+                        if let Some(handle) = conn.window_by_id(window_id) {
+                            handle.borrow_mut().dropped_files(paths, x, y);
+                        }
+                    }
+                    Err(err) => log::error!("while reading dropped files: {:#}", err),
+                }
+            })
+            .detach();
+        });
+        Ok(())
+    }
 }
 
 pub struct PointerDispatcher {
@@ -250,6 +361,16 @@ impl PointerDispatcher {
             .insert(surface.as_ref().id(), Arc::clone(pending));
     }
 
+    /// Let the source of a drop know that we're done with its data
+    fn finish_drop(&self, offer_id: u32) {
+        if let Some(offer) = self.inner.lock().unwrap().dropped_offers.remove(&offer_id) {
+            if offer.as_ref().version() >= 3 {
+                offer.finish();
+            }
+            offer.destroy();
+        }
+    }
+
     pub fn set_cursor(&self, name: &str, serial: Option<u32>) {
         let inner = self.inner.lock().unwrap();
         let serial = serial.unwrap_or(inner.serial);
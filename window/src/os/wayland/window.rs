@@ -412,6 +412,15 @@ impl WaylandWindowInner {
         }
     }
 
+    /// Deliver files dropped at surface coordinates `x`, `y`
+    pub(crate) fn dropped_files(&mut self, paths: Vec<std::path::PathBuf>, x: f64, y: f64) {
+        let coords = Point::new(
+            self.surface_to_pixels(x as i32) as isize,
+            self.surface_to_pixels(y as i32) as isize,
+        );
+        self.callbacks.dropped_files(paths, coords);
+    }
+
     fn get_dpi_factor(&self) -> i32 {
         self.dimensions.dpi as i32 / crate::DEFAULT_DPI as i32
     }
@@ -727,7 +736,7 @@ fn write_pipe_with_timeout(mut file: FileDescriptor, data: &[u8]) -> anyhow::Res
     Ok(())
 }
 
-fn read_pipe_with_timeout(mut file: FileDescriptor) -> anyhow::Result<String> {
+pub(crate) fn read_pipe_with_timeout(mut file: FileDescriptor) -> anyhow::Result<String> {
     let mut result = Vec::new();
 
     file.set_non_blocking(true)?;
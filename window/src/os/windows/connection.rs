@@ -28,6 +28,12 @@ impl ConnectionOps for Connection {
         }
     }
 
+    fn beep(&self) {
+        unsafe {
+            MessageBeep(MB_OK);
+        }
+    }
+
     fn run_message_loop(&self) -> anyhow::Result<()> {
         let mut msg: MSG = unsafe { std::mem::zeroed() };
         loop {
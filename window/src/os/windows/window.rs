@@ -16,6 +16,7 @@ use std::convert::TryInto;
 use std::ffi::OsString;
 use std::io::{self, Error as IoError};
 use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
 use std::ptr::{null, null_mut};
 use std::rc::Rc;
 use winapi::shared::minwindef::*;
@@ -23,6 +24,7 @@ use winapi::shared::ntdef::*;
 use winapi::shared::windef::*;
 use winapi::um::imm::*;
 use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::shellapi::{DragAcceptFiles, DragFinish, DragQueryFileW, DragQueryPoint, HDROP};
 use winapi::um::winuser::*;
 use winreg::{enums::HKEY_CURRENT_USER, RegKey};
 
@@ -319,6 +321,9 @@ impl Window {
 
         enable_dark_mode(hwnd.0);
         enable_blur_behind(hwnd.0);
+        unsafe {
+            DragAcceptFiles(hwnd.0, TRUE);
+        }
 
         Connection::get()
             .expect("Connection::init was not called")
@@ -707,6 +712,36 @@ unsafe fn wm_kill_focus(
     None
 }
 
+unsafe fn wm_drop_files(
+    hwnd: HWND,
+    _msg: UINT,
+    wparam: WPARAM,
+    _lparam: LPARAM,
+) -> Option<LRESULT> {
+    let hdrop = wparam as HDROP;
+    let count = DragQueryFileW(hdrop, 0xffff_ffff, null_mut(), 0);
+    let mut paths = vec![];
+    for idx in 0..count {
+        let len = DragQueryFileW(hdrop, idx, null_mut(), 0) as usize;
+        let mut buf = vec![0u16; len + 1];
+        DragQueryFileW(hdrop, idx, buf.as_mut_ptr(), buf.len() as UINT);
+        buf.truncate(len);
+        paths.push(PathBuf::from(OsString::from_wide(&buf)));
+    }
+    let mut point = POINT { x: 0, y: 0 };
+    DragQueryPoint(hdrop, &mut point);
+    DragFinish(hdrop);
+
+    if let Some(inner) = rc_from_hwnd(hwnd) {
+        let inner = inner.borrow();
+        inner
+            .callbacks
+            .borrow_mut()
+            .dropped_files(paths, Point::new(point.x as isize, point.y as isize));
+    }
+    Some(0)
+}
+
 unsafe fn wm_paint(hwnd: HWND, _msg: UINT, _wparam: WPARAM, _lparam: LPARAM) -> Option<LRESULT> {
     if let Some(inner) = rc_from_hwnd(hwnd) {
         let inner = inner.borrow();
@@ -1723,6 +1758,7 @@ unsafe fn do_wnd_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) ->
         WM_WINDOWPOSCHANGED => wm_windowposchanged(hwnd, msg, wparam, lparam),
         WM_SETFOCUS => wm_set_focus(hwnd, msg, wparam, lparam),
         WM_KILLFOCUS => wm_kill_focus(hwnd, msg, wparam, lparam),
+        WM_DROPFILES => wm_drop_files(hwnd, msg, wparam, lparam),
         WM_DEADCHAR | WM_KEYDOWN | WM_KEYUP | WM_SYSCHAR | WM_CHAR | WM_IME_CHAR | WM_SYSKEYUP
         | WM_SYSKEYDOWN => key(hwnd, msg, wparam, lparam),
         WM_IME_COMPOSITION => ime_composition(hwnd, msg, wparam, lparam),
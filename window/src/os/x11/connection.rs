@@ -15,6 +15,42 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use xcb_util::ffi::keysyms::{xcb_key_symbols_alloc, xcb_key_symbols_free, xcb_key_symbols_t};
 
+/// The atoms used by the XDND drag and drop protocol
+pub struct XdndAtoms {
+    pub aware: xcb::Atom,
+    pub enter: xcb::Atom,
+    pub position: xcb::Atom,
+    pub status: xcb::Atom,
+    pub leave: xcb::Atom,
+    pub drop: xcb::Atom,
+    pub finished: xcb::Atom,
+    pub selection: xcb::Atom,
+    pub type_list: xcb::Atom,
+    pub action_copy: xcb::Atom,
+    pub uri_list: xcb::Atom,
+}
+
+impl XdndAtoms {
+    fn new(conn: &xcb::Connection) -> anyhow::Result<Self> {
+        let atom = |name: &str| -> anyhow::Result<xcb::Atom> {
+            Ok(xcb::intern_atom(conn, false, name).get_reply()?.atom())
+        };
+        Ok(Self {
+            aware: atom("XdndAware")?,
+            enter: atom("XdndEnter")?,
+            position: atom("XdndPosition")?,
+            status: atom("XdndStatus")?,
+            leave: atom("XdndLeave")?,
+            drop: atom("XdndDrop")?,
+            finished: atom("XdndFinished")?,
+            selection: atom("XdndSelection")?,
+            type_list: atom("XdndTypeList")?,
+            action_copy: atom("XdndActionCopy")?,
+            uri_list: atom("text/uri-list")?,
+        })
+    }
+}
+
 pub struct XConnection {
     pub conn: xcb_util::ewmh::Connection,
     pub screen_num: i32,
@@ -28,6 +64,7 @@ pub struct XConnection {
     pub atom_xsel_data: xcb::Atom,
     pub atom_targets: xcb::Atom,
     pub atom_clipboard: xcb::Atom,
+    pub xdnd: XdndAtoms,
     keysyms: *mut xcb_key_symbols_t,
     pub(crate) windows: RefCell<HashMap<xcb::xproto::Window, Arc<Mutex<XWindowInner>>>>,
     should_terminate: RefCell<bool>,
@@ -176,6 +213,11 @@ impl ConnectionOps for XConnection {
         *self.should_terminate.borrow_mut() = true;
     }
 
+    fn beep(&self) {
+        xcb::bell(&self.conn, 0);
+        self.conn.flush();
+    }
+
     fn run_message_loop(&self) -> anyhow::Result<()> {
         self.conn.flush();
 
@@ -339,6 +381,7 @@ impl XConnection {
         let atom_clipboard = xcb::intern_atom(&conn, false, "CLIPBOARD")
             .get_reply()?
             .atom();
+        let xdnd = XdndAtoms::new(&conn)?;
 
         let keysyms = unsafe { xcb_key_symbols_alloc((*conn).get_raw_conn()) };
 
@@ -405,6 +448,7 @@ impl XConnection {
             atom_utf8_string,
             atom_xsel_data,
             atom_targets,
+            xdnd,
             windows: RefCell::new(HashMap::new()),
             should_terminate: RefCell::new(false),
             shm_available,
@@ -2,7 +2,7 @@ use super::*;
 use crate::bitmaps::*;
 use crate::connection::ConnectionOps;
 use crate::os::xkeysyms;
-use crate::os::{Connection, Window};
+use crate::os::{paths_from_uri_list, Connection, Window};
 use crate::{
     Clipboard, Dimensions, MouseButtons, MouseCursor, MouseEvent, MouseEventKind, MousePress,
     Point, Rect, ScreenPoint, Size, WindowCallbacks, WindowOps, WindowOpsMut,
@@ -61,6 +61,16 @@ impl CopyAndPaste {
     }
 }
 
+/// A drag and drop operation from another application that is
+/// over the window, following the XDND protocol.
+/// <https://www.freedesktop.org/wiki/Specifications/XDND/>
+struct DragAndDrop {
+    source: xcb::xproto::Window,
+    /// Whether the source is able to provide a list of URIs
+    has_uri_list: bool,
+    coords: Point,
+}
+
 pub(crate) struct XWindowInner {
     window_id: xcb::xproto::Window,
     conn: Weak<XConnection>,
@@ -72,6 +82,7 @@ pub(crate) struct XWindowInner {
     cursor: Option<MouseCursor>,
     cursors: HashMap<Option<MouseCursor>, XcbCursor>,
     copy_and_paste: CopyAndPaste,
+    drag_and_drop: Option<DragAndDrop>,
     gl_state: Option<Rc<glium::backend::Context>>,
 }
 
@@ -342,6 +353,9 @@ impl XWindowInner {
             }
             xcb::CLIENT_MESSAGE => {
                 let msg: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(event) };
+                if self.xdnd_message(msg)? {
+                    return Ok(());
+                }
                 if msg.data().data32()[0] == conn.atom_delete() && self.callbacks.can_close() {
                     xcb::destroy_window(conn.conn(), self.window_id);
                 }
@@ -514,9 +528,158 @@ impl XWindowInner {
         Ok(())
     }
 
+    /// Send an XDND message to the source of a drag and drop operation
+    fn send_xdnd_message(&self, source: xcb::xproto::Window, kind: xcb::Atom, data: [u32; 5]) {
+        xcb::xproto::send_event(
+            &self.conn(),
+            false,
+            source,
+            xcb::xproto::EVENT_MASK_NO_EVENT,
+            &xcb::xproto::ClientMessageEvent::new(
+                32,
+                source,
+                kind,
+                xcb::ClientMessageData::from_data32(data),
+            ),
+        );
+    }
+
+    /// Handle the XDND client messages for a drag and drop operation
+    /// over the window.  Returns false if `msg` isn't one of them.
+    fn xdnd_message(&mut self, msg: &xcb::ClientMessageEvent) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let atoms = &conn.xdnd;
+        let data = msg.data().data32();
+        let kind = msg.type_();
+
+        if kind == atoms.enter {
+            let source = data[0];
+            // If the source offers more than three types, they are
+            // listed in a property of its window
+            let types = if data[1] & 1 != 0 {
+                xcb::get_property(
+                    &conn,
+                    false,
+                    source,
+                    atoms.type_list,
+                    xcb::xproto::ATOM_ATOM,
+                    0,
+                    1024,
+                )
+                .get_reply()?
+                .value::<xcb::Atom>()
+                .to_vec()
+            } else {
+                data[2..5].to_vec()
+            };
+            self.drag_and_drop = Some(DragAndDrop {
+                source,
+                has_uri_list: types.contains(&atoms.uri_list),
+                coords: Point::new(0, 0),
+            });
+        } else if kind == atoms.position {
+            let window_id = self.window_id;
+            if let Some(dnd) = self.drag_and_drop.as_mut() {
+                // The position is in root window coordinates
+                let (x, y) = ((data[2] >> 16) as i16, data[2] as i16);
+                let reply =
+                    xcb::translate_coordinates(&conn, conn.root, window_id, x, y).get_reply()?;
+                dnd.coords = Point::new(reply.dst_x() as isize, reply.dst_y() as isize);
+
+                let (source, accept) = (dnd.source, dnd.has_uri_list);
+                let action = if accept { atoms.action_copy } else { xcb::NONE };
+                self.send_xdnd_message(
+                    source,
+                    atoms.status,
+                    [window_id, accept as u32, 0, 0, action],
+                );
+            }
+        } else if kind == atoms.leave {
+            self.drag_and_drop.take();
+        } else if kind == atoms.drop {
+            match self.drag_and_drop.as_ref() {
+                Some(dnd) if dnd.has_uri_list => {
+                    // The drop completes when the selection is delivered
+                    // to us in selection_notify
+                    xcb::convert_selection(
+                        &conn,
+                        self.window_id,
+                        atoms.selection,
+                        atoms.uri_list,
+                        atoms.selection,
+                        data[2],
+                    );
+                }
+                Some(dnd) => {
+                    self.send_xdnd_message(
+                        dnd.source,
+                        atoms.finished,
+                        [self.window_id, 0, xcb::NONE, 0, 0],
+                    );
+                    self.drag_and_drop.take();
+                }
+                None => {}
+            }
+        } else {
+            return Ok(false);
+        }
+
+        conn.flush();
+        Ok(true)
+    }
+
+    /// Deliver the URIs of a drop to the callbacks and let the
+    /// source know that we're done with them
+    fn xdnd_selection_notify(
+        &mut self,
+        selection: &xcb::SelectionNotifyEvent,
+    ) -> anyhow::Result<()> {
+        let conn = self.conn();
+        let dnd = match self.drag_and_drop.take() {
+            Some(dnd) => dnd,
+            None => return Ok(()),
+        };
+
+        let mut paths = vec![];
+        if selection.property() != xcb::NONE {
+            let reply = xcb::get_property(
+                &conn,
+                true,
+                self.window_id,
+                selection.property(),
+                xcb::xproto::ATOM_ANY,
+                0,
+                u32::max_value() / 4,
+            )
+            .get_reply()?;
+            paths = paths_from_uri_list(&String::from_utf8_lossy(reply.value::<u8>()));
+        }
+
+        let accepted = !paths.is_empty();
+        if accepted {
+            self.callbacks.dropped_files(paths, dnd.coords);
+        }
+        let action = if accepted {
+            conn.xdnd.action_copy
+        } else {
+            xcb::NONE
+        };
+        self.send_xdnd_message(
+            dnd.source,
+            conn.xdnd.finished,
+            [self.window_id, accepted as u32, action, 0, 0],
+        );
+        conn.flush();
+        Ok(())
+    }
+
     fn selection_notify(&mut self, selection: &xcb::SelectionNotifyEvent) -> anyhow::Result<()> {
         let conn = self.conn();
 
+        if selection.selection() == conn.xdnd.selection {
+            return self.xdnd_selection_notify(selection);
+        }
+
         log::trace!(
             "SELECTION_NOTIFY received selection={} (prim={} clip={}) target={} property={}",
             selection.selection(),
@@ -764,6 +927,7 @@ impl XWindow {
                 expose: VecDeque::new(),
                 paint_all: true,
                 copy_and_paste: CopyAndPaste::default(),
+                drag_and_drop: None,
                 cursor: None,
                 cursors: HashMap::new(),
                 gl_state: None,
@@ -785,6 +949,18 @@ impl XWindow {
             &[conn.atom_delete],
         );
 
+        // Accept files dropped from other applications; this is the
+        // version of the XDND protocol that we support
+        xcb::change_property(
+            &*conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            window_id,
+            conn.xdnd.aware,
+            xcb::ATOM_ATOM,
+            32,
+            &[5u32],
+        );
+
         // window.lock().unwrap().disable_decorations()?;
 
         let window_handle = Window::X11(XWindow::from_id(window_id));
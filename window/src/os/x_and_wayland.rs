@@ -10,6 +10,9 @@ use crate::os::x11::window::XWindow;
 use crate::{config, Clipboard, MouseCursor, ScreenPoint, WindowCallbacks, WindowOps};
 use promise::*;
 use std::any::Any;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 pub enum Connection {
@@ -94,6 +97,14 @@ impl ConnectionOps for Connection {
             Self::Wayland(w) => w.run_message_loop(),
         }
     }
+
+    fn beep(&self) {
+        match self {
+            Self::X11(x) => x.beep(),
+            #[cfg(feature = "wayland")]
+            Self::Wayland(w) => w.beep(),
+        }
+    }
     fn schedule_timer<F: FnMut() + 'static>(&self, interval: std::time::Duration, callback: F) {
         match self {
             Self::X11(x) => x.schedule_timer(interval, callback),
@@ -228,3 +239,29 @@ impl WindowOps for Window {
         }
     }
 }
+
+/// Returns the local paths named by the `file:` URIs in a
+/// `text/uri-list`, which is how files are dropped onto a window
+/// on both X11 and Wayland.
+pub(crate) fn paths_from_uri_list(list: &str) -> Vec<PathBuf> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|uri| {
+            let path = uri.strip_prefix("file://")?;
+            // Skip the host name, which is usually empty or localhost
+            let path = &path[path.find('/')?..];
+            let mut bytes = vec![];
+            let mut iter = path.bytes();
+            while let Some(b) = iter.next() {
+                if b == b'%' {
+                    let hex = [iter.next()?, iter.next()?];
+                    bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+                } else {
+                    bytes.push(b);
+                }
+            }
+            Some(PathBuf::from(OsString::from_vec(bytes)))
+        })
+        .collect()
+}